@@ -0,0 +1,54 @@
+use crate::internal::install::install;
+use shared::args::PackageManager;
+use shared::cleanup;
+use shared::exec::RunSpec;
+use shared::files;
+use shared::returncode_eval::files_eval;
+use shared::{info, warn};
+use std::time::Duration;
+
+// Lets the build user run pacman through the helper without a password prompt, removed after the builds,
+// on a crash, and by the cleanup of the next run if the installer is killed
+const SUDOERS_DROPIN: &str = "/mnt/etc/sudoers.d/10-aegis-aur";
+// Some AUR packages compile for a long time, but a stuck build must not hang the install
+const BUILD_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Installs the AUR helper, prebuilt in the Chaotic-AUR repository enabled on every Athena Arch install.
+pub fn install_helper(helper: &str) {
+    info!("Installing AUR helper : {}", helper);
+    install(PackageManager::Pacman, vec![helper]);
+}
+
+/// Builds and installs AUR packages with the helper as `username`, makepkg refuses to run as root.
+/// A package that fails is logged and skipped, the others are still installed.
+pub fn install_packages(helper: &str, username: &str, packages: &[String]) {
+    cleanup::track_file(SUDOERS_DROPIN);
+    let dropin = files::SecretFile::new(SUDOERS_DROPIN);
+    files_eval(
+        std::fs::write(SUDOERS_DROPIN, format!("{} ALL=(ALL:ALL) NOPASSWD: ALL\n", username)),
+        "Allow the AUR build user to install packages",
+    );
+    let options: &[&str] = match helper {
+        "yay" => &["--answerdiff", "None", "--answerclean", "None", "--removemake"],
+        _ => &["--skipreview", "--removemake"],
+    };
+    let mut failed = Vec::new();
+    for package in packages {
+        info!("Building AUR package : {}", package);
+        let result = RunSpec::new("arch-chroot")
+            .args(["/mnt", "sudo", "-u", username, "-H", helper, "-S", "--noconfirm", "--needed"])
+            .args(options)
+            .arg(package)
+            .prefix(helper)
+            .timeout(BUILD_TIMEOUT)
+            .run();
+        if let Err(e) = result {
+            warn!("AUR package {} was not installed: {}", package, e);
+            failed.push(package.as_str());
+        }
+    }
+    drop(dropin);
+    if !failed.is_empty() {
+        warn!("AUR packages not installed: {}", failed.join(", "));
+    }
+}
@@ -1,16 +1,24 @@
+use crate::functions::initramfs;
 use crate::internal::hardware;
 use crate::internal::install::install;
 use crate::internal::services::enable_service;
-use shared::args::PackageManager;
+use shared::args::{Encryption, PackageManager};
 use shared::exec::exec;
 use shared::exec::exec_chroot;
-use shared::encrypt::find_luks_partitions;
+use shared::exec::RunSpec;
+use shared::encrypt::{self, find_luks_partitions};
 use shared::files;
+use shared::partition;
+use shared::secureboot;
 use shared::{info, warn};
+use shared::services::{self, VirtualizationPackages};
 use shared::returncode_eval::exec_eval;
 use shared::returncode_eval::files_eval;
+use shared::returncode_eval::run_eval;
 use shared::strings::crash;
+use std::time::Duration;
 use std::path::PathBuf;
+use std::process::Command;
 
 pub fn install_packages(kernel: String, mut packages: Vec<&str>) {
 
@@ -82,18 +90,8 @@ pub fn install_packages(kernel: String, mut packages: Vec<&str>) {
         );
     }
 
-    exec_eval(
-        exec( // Using exec instead of exec_chroot because in exec_chroot, these sed arguments need some chars to be escaped
-            "sed",
-            vec![
-                String::from("-i"),
-                String::from("-e"),
-                String::from("s/^HOOKS=.*/HOOKS=(base systemd autodetect modconf kms keyboard sd-vconsole block sd-encrypt lvm2 filesystems fsck)/g"),
-                String::from("/mnt/etc/mkinitcpio.conf"),
-            ],
-        ),
-        "Set mkinitcpio hooks",
-    );
+    // Ready for any layout, the config install rewrites it for the features it uses
+    initramfs::Features { encrypted: true, ..Default::default() }.write();
     
     files::copy_file("/etc/skel/.bashrc", "/mnt/etc/skel/.bashrc");
     files::copy_file("/mnt/usr/lib/os-release-athena", "/mnt/usr/lib/os-release");
@@ -118,19 +116,6 @@ pub fn install_packages(kernel: String, mut packages: Vec<&str>) {
     );
 }
 
-pub fn preset_process() {
-    // mkinitcpio -P must be run after all the edits on /etc/mkinitcpio.conf file
-    exec_eval(
-        exec_chroot(
-            "mkinitcpio",
-            vec![
-                String::from("-P"),
-            ],
-        ),
-        "run mkinitcpio presets processing",
-    );
-}
-
 fn init_keyrings_mirrors() {
     info!("Upgrade keyrings on the host");
     exec_eval(
@@ -196,19 +181,45 @@ fn init_keyrings_mirrors() {
 }
 
 pub fn genfstab() {
-    exec_eval(
-        exec(
-            "bash",
-            vec![
-                String::from("-c"),
-                String::from("genfstab -U /mnt >> /mnt/etc/fstab"),
-            ],
-        ),
+    run_eval(
+        RunSpec::new("bash")
+            .args(["-c", "genfstab -U /mnt >> /mnt/etc/fstab"])
+            .prefix("genfstab")
+            .timeout(Duration::from_secs(120))
+            .run(),
         "Generate fstab",
     );
 }
 
-fn setting_grub_parameters(encrypt_check: bool) {
+/// Replaces the swap entries written by genfstab with plain dm-crypt swaps keyed from /dev/urandom at every boot.
+/// There is no persistent label or UUID to mount by, the crypttab entries use the PARTUUID.
+pub fn configure_random_swap() {
+    let swaps = encrypt::swap_partitions();
+    if swaps.is_empty() {
+        warn!("No swap partition found, swap encryption skipped");
+        return;
+    }
+    files_eval(
+        files::sed_file("/mnt/etc/fstab", r"(?m)^(# .*\n)?\S+\s+none\s+swap\s.*\n", ""),
+        "remove the plain swap entries from fstab",
+    );
+    for (i, (device, partuuid)) in swaps.iter().enumerate() {
+        info!("Encrypting swap {} with a random key", device);
+        files_eval(
+            files::append_file(
+                "/mnt/etc/crypttab",
+                &format!("swap{} PARTUUID={} /dev/urandom swap,cipher=aes-xts-plain64,size=512", i, partuuid),
+            ),
+            "add the swap to crypttab",
+        );
+        files_eval(
+            files::append_file("/mnt/etc/fstab", &format!("/dev/mapper/swap{} none swap defaults 0 0", i)),
+            "add the encrypted swap to fstab",
+        );
+    }
+}
+
+fn setting_grub_parameters(encryption: Encryption, os_prober: bool) {
     let mut luks_param = String::new();
     files_eval(
         files::sed_file(
@@ -218,7 +229,7 @@ fn setting_grub_parameters(encrypt_check: bool) {
         ),
         "set distributor name",
     );
-    if encrypt_check {
+    if encryption != Encryption::None {
         /*Set UUID of encrypted partition as kernel parameter*/
         let luks_partitions = find_luks_partitions();
         let mut cryptlabel = String::new();
@@ -228,7 +239,12 @@ fn setting_grub_parameters(encrypt_check: bool) {
             cryptlabel = format!("{}crypted", device_path.trim_start_matches("/dev/")); // i.e., sda3crypted
             luks_param.push_str(&format!("rd.luks.name={}={} ", uuid, cryptlabel));
         }
-        luks_param.push_str(&format!("root=/dev/mapper/{} ", cryptlabel));
+        if encryption == Encryption::AllButEsp {
+            // root is a logical volume inside the LUKS container
+            luks_param.push_str(&format!("root=/dev/{}/root ", partition::VOLUME_GROUP));
+        } else {
+            luks_param.push_str(&format!("root=/dev/mapper/{} ", cryptlabel));
+        }
         // NOTE: in case of multiple LUKS encryted partitions, the encrypted system will work ONLY if the root partition is the last one in the disk
 
         files_eval(
@@ -248,17 +264,33 @@ fn setting_grub_parameters(encrypt_check: bool) {
         ),
         "set kernel parameters",
     );
+    if os_prober {
+        files_eval(
+            files::sed_file(
+                "/mnt/etc/default/grub",
+                "#GRUB_DISABLE_OS_PROBER=.*",
+                "GRUB_DISABLE_OS_PROBER=false",
+            ),
+            "enable os prober",
+        );
+    }
+}
+
+/// Adds the sd-encrypt parameters unlocking the LUKS partitions with the keyfile on `key_partition`
+/// and/or the TPM2, before grub.cfg is generated. The passphrase keeps working as a fallback.
+pub fn configure_luks_unlock(key_partition: Option<&str>, tpm2: bool) {
+    let unlock_params = encrypt::unlock_kernel_params(key_partition, tpm2);
     files_eval(
         files::sed_file(
             "/mnt/etc/default/grub",
-            "#GRUB_DISABLE_OS_PROBER=.*",
-            "GRUB_DISABLE_OS_PROBER=false",
+            "GRUB_CMDLINE_LINUX=.*",
+            &format!("GRUB_CMDLINE_LINUX=\"{}\"", unlock_params.trim_end()),
         ),
-        "enable os prober",
+        "set LUKS unlock kernel parameters",
     );
 }
 
-pub fn configure_bootloader_efi(efidir: PathBuf, encrypt_check: bool) {
+pub fn configure_bootloader_efi(efidir: PathBuf, encryption: Encryption, os_prober: bool) {
 
     let efidir = std::path::Path::new("/mnt").join(&efidir);
     let efi_str = efidir.to_str().unwrap();
@@ -267,6 +299,9 @@ pub fn configure_bootloader_efi(efidir: PathBuf, encrypt_check: bool) {
     if !std::path::Path::new(&format!("/mnt{efi_str}")).exists() {
         crash(format!("The efidir {efidir:?} doesn't exist"), 1);
     }
+
+    // GRUB_ENABLE_CRYPTODISK must be set before grub-install to embed the modules reading an encrypted /boot
+    setting_grub_parameters(encryption, os_prober);
     
     exec_eval(
         exec_chroot(
@@ -292,8 +327,6 @@ pub fn configure_bootloader_efi(efidir: PathBuf, encrypt_check: bool) {
         ),
         "install grub as efi without --removable",
     );
-
-    setting_grub_parameters(encrypt_check);
     
     exec_eval(
         exec_chroot(
@@ -304,7 +337,188 @@ pub fn configure_bootloader_efi(efidir: PathBuf, encrypt_check: bool) {
     );
 }
 
-pub fn configure_bootloader_legacy(device: PathBuf, encrypt_check: bool) {
+/// Signs the bootloader and kernels for Secure Boot, after configure_bootloader_efi.
+/// sbctl enrolls custom keys (firmware in setup mode only), shim keeps the Microsoft keys and needs a MOK enrollment at first boot.
+pub fn configure_secure_boot(efidir: PathBuf, mode: &str) {
+    let efi_str = efidir.to_string_lossy().to_string();
+    let state = secureboot::state();
+    warn!("Configuring Secure Boot with {} (firmware state: {})", mode, state);
+    warn!("Enrolling keys can make some firmware refuse to boot, keep a way to reset the firmware keys at hand");
+
+    // Unsigned kernels and the GRUB EFI binaries written by grub-install
+    let mut binaries: Vec<String> = std::fs::read_dir("/mnt/boot")
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| name.starts_with("vmlinuz-"))
+                .map(|name| format!("/boot/{}", name))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    match mode {
+        "sbctl" => {
+            // Without shim, GRUB must embed its modules and skip the shim_lock verifier
+            exec_eval(
+                exec_chroot(
+                    "grub-install",
+                    vec![
+                        String::from("--target=x86_64-efi"),
+                        format!("--efi-directory={}", efi_str),
+                        String::from("--bootloader-id=GRUB"),
+                        String::from("--modules=\"tpm\""),
+                        String::from("--disable-shim-lock"),
+                    ],
+                ),
+                "install grub for sbctl secure boot",
+            );
+            binaries.push(format!("{}/EFI/GRUB/grubx64.efi", efi_str));
+            binaries.push(format!("{}/EFI/BOOT/BOOTX64.EFI", efi_str));
+            exec_eval(
+                exec_chroot("sbctl", vec![String::from("create-keys")]),
+                "create secure boot keys",
+            );
+            if state == secureboot::State::SetupMode {
+                // Keep the Microsoft keys, GPU and NIC option ROMs are signed with them
+                exec_eval(
+                    exec_chroot("sbctl", vec![String::from("enroll-keys"), String::from("--microsoft")]),
+                    "enroll secure boot keys",
+                );
+            } else {
+                warn!("The firmware is not in setup mode, keys are created but not enrolled. Clear the platform key in the firmware setup, then run 'sbctl enroll-keys --microsoft'");
+            }
+            for binary in &binaries {
+                // -s saves the file in the sbctl database, so the pacman hook signs it again on updates
+                exec_eval(
+                    exec_chroot("sbctl", vec![String::from("sign"), String::from("-s"), binary.clone()]),
+                    &format!("sign {} for secure boot", binary),
+                );
+            }
+        }
+        "shim" => {
+            exec_eval(
+                exec_chroot("mkdir", vec![String::from("-p"), String::from("/etc/secureboot")]),
+                "create MOK directory",
+            );
+            exec_eval(
+                exec_chroot(
+                    "openssl",
+                    vec![
+                        String::from("req -newkey rsa:2048 -nodes -x509 -days 3650 -subj '/CN=Athena OS Machine Owner Key/'"),
+                        String::from("-keyout /etc/secureboot/MOK.key -out /etc/secureboot/MOK.crt"),
+                    ],
+                ),
+                "create machine owner key",
+            );
+            exec_eval(
+                exec_chroot(
+                    "openssl",
+                    vec![String::from("x509 -outform DER -in /etc/secureboot/MOK.crt -out /etc/secureboot/MOK.cer")],
+                ),
+                "export machine owner key certificate",
+            );
+            // shim 15.3+ refuses a GRUB without an SBAT section, and the shim_lock verifier refuses the modules
+            // GRUB would load from /boot, so they are embedded in the image
+            exec_eval(
+                exec_chroot(
+                    "grub-install",
+                    vec![
+                        String::from("--target=x86_64-efi"),
+                        format!("--efi-directory={}", efi_str),
+                        String::from("--bootloader-id=GRUB"),
+                        format!("--modules=\"{}\"", SHIM_GRUB_MODULES),
+                        String::from("--sbat"),
+                        String::from("/usr/share/grub/sbat.csv"),
+                    ],
+                ),
+                "install grub with an SBAT section for shim",
+            );
+            binaries.push(format!("{}/EFI/GRUB/grubx64.efi", efi_str));
+            for binary in &binaries {
+                exec_eval(
+                    exec_chroot(
+                        "sbsign",
+                        vec![
+                            String::from("--key /etc/secureboot/MOK.key --cert /etc/secureboot/MOK.crt"),
+                            format!("--output {} {}", binary, binary),
+                        ],
+                    ),
+                    &format!("sign {} for secure boot", binary),
+                );
+            }
+            // shim is started by the firmware, loads grubx64.efi and MokManager (mmx64.efi) from its own directory.
+            // The GRUB directory and the removable path (used when the NVRAM entry is lost) both get shim.
+            exec_eval(
+                exec_chroot("mkdir", vec![String::from("-p"), format!("{}/EFI/BOOT", efi_str)]),
+                "create the removable EFI boot directory",
+            );
+            for (source, target) in [
+                ("/usr/share/shim-signed/shimx64.efi", "EFI/GRUB/shimx64.efi"),
+                ("/usr/share/shim-signed/mmx64.efi", "EFI/GRUB/mmx64.efi"),
+                ("/usr/share/shim-signed/shimx64.efi", "EFI/BOOT/BOOTX64.EFI"),
+                ("/usr/share/shim-signed/mmx64.efi", "EFI/BOOT/mmx64.efi"),
+            ] {
+                exec_eval(
+                    exec_chroot("cp", vec![String::from(source), format!("{}/{}", efi_str, target)]),
+                    &format!("install {} as {}", source, target),
+                );
+            }
+            exec_eval(
+                exec_chroot("cp", vec![format!("{}/EFI/GRUB/grubx64.efi", efi_str), format!("{}/EFI/BOOT/grubx64.efi", efi_str)]),
+                "install the signed grub on the removable path",
+            );
+            shim_boot_entry(&efi_str);
+            exec_eval(
+                exec_chroot("cp", vec![String::from("/etc/secureboot/MOK.cer"), format!("{}/MOK.cer", efi_str)]),
+                "copy machine owner key certificate to the ESP",
+            );
+            warn!("At first boot MokManager starts: choose 'Enroll key from disk' and select MOK.cer on the EFI partition");
+        }
+        _ => info!("Secure Boot not configured"),
+    }
+}
+
+// Modules of the Arch wiki shim setup, GRUB cannot load any other one once shim verifies it
+const SHIM_GRUB_MODULES: &str = "all_video boot btrfs cat chain configfile echo efifwsetup efinet ext2 fat font gettext gfxmenu gfxterm gfxterm_background gzio halt help hfsplus iso9660 jpeg keystatus loadenv loopback linux ls lsefi lsefimmap lsefisystab lssal memdisk minicmd normal ntfs part_apple part_msdos part_gpt password_pbkdf2 png probe reboot regexp search search_fs_uuid search_fs_file search_label sleep smbios squash4 test true video xfs zfs zfscrypt zfsinfo cpuid play tpm cryptodisk luks luks2 lvm gcry_rijndael gcry_sha256 gcry_sha512";
+
+// The NVRAM entry written by grub-install starts grubx64.efi, which the firmware does not trust: boot shim instead
+fn shim_boot_entry(efi_str: &str) {
+    let output = |program: &str, args: &[&str]| {
+        Command::new(program)
+            .args(args)
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .unwrap_or_default()
+    };
+    let esp = output("findmnt", &["-no", "SOURCE", &format!("/mnt{}", efi_str)]);
+    let disk = output("lsblk", &["-no", "PKNAME", &esp]);
+    let number = std::fs::read_to_string(format!("/sys/class/block/{}/partition", esp.trim_start_matches("/dev/"))).unwrap_or_default();
+    if esp.is_empty() || disk.is_empty() || number.trim().is_empty() {
+        warn!("Unable to find the EFI partition mounted at {}, shim still boots from the removable path", efi_str);
+        return;
+    }
+    exec_eval(
+        exec(
+            "efibootmgr",
+            vec![
+                String::from("--unicode"),
+                String::from("--disk"),
+                format!("/dev/{}", disk),
+                String::from("--part"),
+                number.trim().to_string(),
+                String::from("--create"),
+                String::from("--label"),
+                String::from("Athena OS (shim)"),
+                String::from("--loader"),
+                String::from("\\EFI\\GRUB\\shimx64.efi"),
+            ],
+        ),
+        "create the shim boot entry",
+    );
+}
+
+pub fn configure_bootloader_legacy(device: PathBuf, encryption: Encryption, os_prober: bool) {
 
     if !device.exists() {
         crash(format!("The device {device:?} does not exist"), 1);
@@ -313,6 +527,8 @@ pub fn configure_bootloader_legacy(device: PathBuf, encrypt_check: bool) {
     let device_str = device.to_string_lossy().to_string();
     info!("Legacy bootloader installing at {}", device_str);
 
+    setting_grub_parameters(encryption, os_prober);
+
     exec_eval(
         exec_chroot(
             "grub-install",
@@ -320,8 +536,6 @@ pub fn configure_bootloader_legacy(device: PathBuf, encrypt_check: bool) {
         ),
         "install grub as legacy",
     );
-
-    setting_grub_parameters(encrypt_check);
     
     exec_eval(
         exec_chroot(
@@ -332,58 +546,113 @@ pub fn configure_bootloader_legacy(device: PathBuf, encrypt_check: bool) {
     );
 }
 
-/*
-pub fn setup_snapper() {
-    install(PackageManager::Pacman, vec![
-        "btrfs-assistant", "btrfs-progs", "btrfsmaintenance", "grub-btrfs", "inotify-tools", "snap-pac", "snap-pac-grub", "snapper-support",
-    ]);
-    files_eval(
-        files::sed_file(
-            "/mnt/etc/default/grub-btrfs/config",
-            "#GRUB_BTRFS_LIMIT=.*",
-            "GRUB_BTRFS_LIMIT=\"5\"",
-        ),
-        "Set Grub Btrfs limit",
+/// Sets up timeline snapshots of the root subvolume, keeping the last `hourly` and `daily` ones.
+/// grub-btrfs lists the snapshots in the GRUB menu, the grub-btrfs-overlayfs hook makes them bootable.
+pub fn setup_snapshots(tool: &str, hourly: u32, daily: u32) {
+    match tool {
+        "snapper" => setup_snapper(hourly, daily),
+        "timeshift" => setup_timeshift(hourly, daily),
+        _ => return,
+    }
+    enable_service("grub-btrfsd");
+}
+
+fn setup_snapper(hourly: u32, daily: u32) {
+    // snap-pac takes a pre and post snapshot around every pacman transaction
+    install(PackageManager::Pacman, vec!["snapper", "snap-pac", "grub-btrfs", "inotify-tools"]);
+    // The EraseDisk layout mounts @snapshots there, else snapper keeps them in a nested subvolume of @
+    if !std::path::Path::new("/mnt/.snapshots").exists() {
+        exec_eval(
+            exec_chroot(
+                "btrfs",
+                vec![
+                    String::from("subvolume"),
+                    String::from("create"),
+                    String::from("/.snapshots"),
+                ],
+            ),
+            "create /.snapshots as btrfs subvolume",
+        );
+    }
+    exec_eval(
+        exec_chroot("chmod", vec![String::from("750"), String::from("/.snapshots")]),
+        "restrict /.snapshots to root",
     );
+    // snapper create-config refuses an existing /.snapshots, the config is written from the template instead
+    files::copy_file("/mnt/usr/share/snapper/config-templates/default", "/mnt/etc/snapper/configs/root");
+    let limits = [("HOURLY", hourly), ("DAILY", daily), ("WEEKLY", 0), ("MONTHLY", 0), ("YEARLY", 0)];
+    for (period, count) in limits {
+        files_eval(
+            files::sed_file(
+                "/mnt/etc/snapper/configs/root",
+                &format!("(?m)^TIMELINE_LIMIT_{}=.*$", period),
+                &format!("TIMELINE_LIMIT_{}=\"{}\"", period, count),
+            ),
+            &format!("Keep {} {} snapshots", count, period.to_lowercase()),
+        );
+    }
     files_eval(
-        files::sed_file(
-            "/mnt/etc/default/grub-btrfs/config",
-            "#GRUB_BTRFS_SHOW_SNAPSHOTS_FOUND=.*",
-            "GRUB_BTRFS_SHOW_SNAPSHOTS_FOUND=\"false\"",
-        ),
-        "Not show Grub Btrfs snapshots found",
+        files::sed_file("/mnt/etc/conf.d/snapper", "(?m)^SNAPPER_CONFIGS=.*$", "SNAPPER_CONFIGS=\"root\""),
+        "Register the snapper root config",
     );
-    files_eval(
-        files::sed_file(
-            "/mnt/etc/default/grub-btrfs/config",
-            "#GRUB_BTRFS_SHOW_TOTAL_SNAPSHOTS_FOUND=.*",
-            "GRUB_BTRFS_SHOW_TOTAL_SNAPSHOTS_FOUND=\"false\"",
-        ),
-        "Not show the total number of Grub Btrfs snapshots found",
+    enable_service("snapper-timeline.timer");
+    enable_service("snapper-cleanup.timer");
+}
+
+fn setup_timeshift(hourly: u32, daily: u32) {
+    // Timeshift schedules its snapshots with cron
+    install(PackageManager::Pacman, vec!["timeshift", "cronie", "grub-btrfs", "inotify-tools"]);
+    let output = Command::new("findmnt")
+        .args(["-no", "UUID", "/mnt"])
+        .output()
+        .unwrap_or_else(|e| crash(format!("Read the UUID of /mnt  ERROR: {}", e), 1));
+    let uuid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || uuid.is_empty() {
+        crash("Read the UUID of /mnt  ERROR: findmnt found no filesystem UUID for the timeshift backup device", 1);
+    }
+    let schedule = |count: u32| if count > 0 { "true" } else { "false" };
+    let settings = format!(
+        r#"{{
+  "backup_device_uuid" : "{uuid}",
+  "parent_device_uuid" : "",
+  "do_first_run" : "false",
+  "btrfs_mode" : "true",
+  "include_btrfs_home_for_backup" : "false",
+  "include_btrfs_home_for_restore" : "false",
+  "stop_cron_emails" : "true",
+  "schedule_monthly" : "false",
+  "schedule_weekly" : "false",
+  "schedule_daily" : "{}",
+  "schedule_hourly" : "{}",
+  "schedule_boot" : "false",
+  "count_monthly" : "0",
+  "count_weekly" : "0",
+  "count_daily" : "{daily}",
+  "count_hourly" : "{hourly}",
+  "count_boot" : "0",
+  "exclude" : [],
+  "exclude-apps" : []
+}}
+"#,
+        schedule(daily),
+        schedule(hourly),
     );
+    files_eval(files::create_directory("/mnt/etc/timeshift"), "create /mnt/etc/timeshift");
+    files_eval(std::fs::write("/mnt/etc/timeshift/timeshift.json", settings), "Write the timeshift config");
+    // grub-btrfsd watches /.snapshots by default, timeshift keeps its snapshots elsewhere
     files_eval(
-        files::sed_file(
-            "/mnt/etc/conf.d/snapper",
-            "SNAPPER_CONFIGS=.*",
-            "SNAPPER_CONFIGS=\"root\"",
-        ),
-        "Not show the total number of Grub Btrfs snapshots found",
+        files::create_directory("/mnt/etc/systemd/system/grub-btrfsd.service.d"),
+        "create /mnt/etc/systemd/system/grub-btrfsd.service.d",
     );
-    exec_eval(
-        exec_chroot(
-            "btrfs",
-            vec![
-                String::from("subvolume"),
-                String::from("create"),
-                String::from("/.snapshots"),
-            ],
+    files_eval(
+        std::fs::write(
+            "/mnt/etc/systemd/system/grub-btrfsd.service.d/timeshift.conf",
+            "[Service]\nExecStart=\nExecStart=/usr/bin/grub-btrfsd --syslog --timeshift-auto\n",
         ),
-        "create /.snapshots as btrfs subvolume",
+        "Point grub-btrfsd to the timeshift snapshots",
     );
-    files::copy_file("/mnt/etc/snapper/config-templates/garuda", "/mnt/etc/snapper/configs/root");
-    enable_service("grub-btrfsd");
+    enable_service("cronie");
 }
-*/
 
 pub fn configure_flatpak() {
     exec_eval(
@@ -421,3 +690,43 @@ pub fn enable_system_services() {
     //enable_service("nohang");
     //enable_service("cups");
 }
+
+/// Packages of the audio stack, and the packages of the other stack that conflict with it.
+/// PipeWire itself stays with PulseAudio, the desktops use it for screen sharing.
+pub fn audio_packages(stack: &str) -> (Vec<&'static str>, Vec<&'static str>) {
+    match stack {
+        "pulseaudio" => (
+            vec!["pulseaudio", "pulseaudio-alsa", "pulseaudio-bluetooth"],
+            vec!["pipewire-pulse", "pipewire-alsa", "pipewire-jack"],
+        ),
+        _ => (
+            vec!["pipewire", "pipewire-pulse", "pipewire-alsa", "pipewire-jack", "wireplumber"],
+            vec!["pulseaudio", "pulseaudio-alsa", "pulseaudio-bluetooth", "pulseaudio-jack", "jack2"],
+        ),
+    }
+}
+
+/// Enables the user units of the audio server for every user.
+pub fn enable_audio_services(stack: &str) {
+    let units = match stack {
+        "pulseaudio" => vec!["pulseaudio.socket"],
+        _ => vec!["pipewire.socket", "pipewire-pulse.socket", "wireplumber.service"],
+    };
+    let mut args = vec![String::from("--global"), String::from("enable")];
+    args.extend(units.iter().map(|unit| unit.to_string()));
+    exec_eval(exec_chroot("systemctl", args), "Enable audio user services");
+}
+
+// dnsmasq serves the default NAT network of libvirt, edk2-ovmf boots UEFI guests
+const VIRTUALIZATION_PACKAGES: VirtualizationPackages = VirtualizationPackages {
+    docker: &["docker", "docker-compose"],
+    libvirt: &["libvirt", "qemu-desktop", "virt-manager", "dnsmasq", "edk2-ovmf"],
+};
+
+pub fn install_virtualization(docker: bool, podman: bool, libvirt: bool) {
+    services::install_virtualization(&VIRTUALIZATION_PACKAGES, docker, podman, libvirt, |packages| install(PackageManager::Pacman, packages));
+}
+
+pub fn configure_power_management(service: &str) {
+    services::configure_power_management(service, |packages| install(PackageManager::Pacman, packages));
+}
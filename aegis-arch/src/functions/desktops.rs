@@ -1,6 +1,7 @@
 use shared::args::DesktopSetup;
 use shared::debug;
 use shared::files;
+use shared::validation;
 
 pub fn install_desktop_setup(desktop_setup: DesktopSetup) -> Vec<&'static str> {
     debug!("Selecting {:?}", desktop_setup);
@@ -210,6 +211,18 @@ fn install_onyx() -> Vec<&'static str> {
 
 /**********************************/
 
+/// The Bluetooth stack and the applet of each selected desktop.
+pub fn bluetooth_packages(desktops: &[String]) -> Vec<&'static str> {
+    let mut packages = vec!["bluez", "bluez-utils"];
+    for applet in validation::bluetooth_applets(desktops) {
+        packages.push(match applet {
+            "gnome-bluetooth" => "gnome-bluetooth-3.0",
+            applet => applet,
+        });
+    }
+    packages
+}
+
 pub fn configure_gnome() {
     disable_xsession("gnome.desktop");
     disable_xsession("gnome-classic.desktop");
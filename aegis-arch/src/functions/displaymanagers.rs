@@ -10,8 +10,10 @@ pub fn install_dm_setup(dm_setup: DMSetup) -> Vec<&'static str> {
 
     match dm_setup {
         DMSetup::Gdm => install_gdm(),
+        DMSetup::LightDM => install_lightdm(),
         DMSetup::LightDMNeon => install_lightdm_neon(),
         DMSetup::Sddm => install_sddm(),
+        DMSetup::Greetd => install_greetd(),
         DMSetup::None => {
             debug!("No display manager setup selected");
             Vec::new() // Return empty vector if no DM setup is selected
@@ -27,6 +29,15 @@ fn install_gdm() -> Vec<&'static str> {
     packages
 }
 
+fn install_lightdm() -> Vec<&'static str> {
+    let packages = vec![
+        "lightdm",
+        "lightdm-gtk-greeter",
+    ];
+
+    packages
+}
+
 fn install_lightdm_neon() -> Vec<&'static str> {
     let packages = vec![
         "athena-lightdm-neon-theme",
@@ -43,6 +54,15 @@ fn install_sddm() -> Vec<&'static str> {
     packages
 }
 
+fn install_greetd() -> Vec<&'static str> {
+    let packages = vec![
+        "greetd",
+        "greetd-tuigreet",
+    ];
+
+    packages
+}
+
 /**********************************/
 
 pub fn configure_gdm(desktop: &str) {
@@ -67,12 +87,13 @@ pub fn configure_gdm(desktop: &str) {
     enable_service("gdm"); 
 }
 
-pub fn configure_lightdm_neon(desktop: &str) {
-    lightdm_set_session(desktop);
+pub fn configure_lightdm(session: &str) {
+    lightdm_set_session(session);
     enable_service("lightdm");
 }
 
-pub fn configure_sddm() {
+// theme is one of the variants shipped by sddm-astronaut-theme, i.e. "hyprland kath"
+pub fn configure_sddm(theme: &str) {
     // File creation and configuration can still happen here if needed
     files::create_file("/mnt/etc/sddm.conf");
     files_eval(
@@ -82,40 +103,48 @@ pub fn configure_sddm() {
         ),
         "Add astronaut theme",
     );
-    enable_service("sddm");
-}
-
-/**********************************/
-
-fn lightdm_set_session(setdesktop: &str) {
-    if setdesktop.contains("gnome") {
-        files_eval(
-            files::sed_file(
-                "/mnt/etc/lightdm/lightdm.conf",
-                "#user-session=.*",
-                "user-session=gnome-xorg",
-            ),
-            "Apply GNOME User Session on LightDM",
-        );
-    }
-    if setdesktop.contains("xfce") {
-        files_eval(
-            files::sed_file(
-                "/mnt/etc/lightdm/lightdm.conf",
-                "#user-session=.*",
-                "user-session=xfce",
-            ),
-            "Apply XFCE User Session on LightDM",
-        );
-    }
-    if setdesktop == "hyprland" {
+    if !theme.is_empty() {
         files_eval(
             files::sed_file(
-                "/mnt/etc/lightdm/lightdm.conf",
-                "#user-session=.*",
-                "user-session=Hyprland",
+                "/mnt/usr/share/sddm/themes/sddm-astronaut-theme/metadata.desktop",
+                "ConfigFile=.*",
+                &format!("ConfigFile=Themes/{}.conf", theme.replace(' ', "_")),
             ),
-            "Apply Hyprland User Session on LightDM",
+            "Select SDDM astronaut theme variant",
         );
     }
+    enable_service("sddm");
+}
+
+// tuigreet lists the installed X11 and Wayland sessions by itself
+pub fn configure_greetd() {
+    files_eval(
+        files::sed_file(
+            "/mnt/etc/greetd/config.toml",
+            "command = .*",
+            "command = \"tuigreet --time --remember --remember-session --asterisks\"",
+        ),
+        "Set tuigreet as greetd greeter",
+    );
+    enable_service("greetd");
+}
+
+/**********************************/
+
+// session is the first desktop of the config, the one LightDM starts by default
+fn lightdm_set_session(session: &str) {
+    let (user_session, name) = match session {
+        "gnome" => ("gnome-xorg", "GNOME"),
+        "xfce refined" | "xfce picom" => ("xfce", "XFCE"),
+        "hyprland" => ("Hyprland", "Hyprland"),
+        _ => return,
+    };
+    files_eval(
+        files::sed_file(
+            "/mnt/etc/lightdm/lightdm.conf",
+            "#user-session=.*",
+            &format!("user-session={}", user_session),
+        ),
+        &format!("Apply {} User Session on LightDM", name),
+    );
 }
\ No newline at end of file
@@ -0,0 +1,97 @@
+use crate::internal::hardware;
+use shared::exec::exec_chroot;
+use shared::files;
+use shared::info;
+use shared::returncode_eval::{exec_eval, files_eval};
+use std::path::Path;
+
+const MKINITCPIO_CONF: &str = "/mnt/etc/mkinitcpio.conf";
+// Loaded early so the NVIDIA driver sets the mode instead of nouveau or simpledrm
+const NVIDIA_MODULES: [&str; 4] = ["nvidia", "nvidia_modeset", "nvidia_uvm", "nvidia_drm"];
+// Extra hooks running after the root filesystem is checked, the others go before filesystems
+const LATE_HOOKS: [&str; 3] = ["grub-btrfs-overlayfs", "shutdown", "sd-shutdown"];
+
+/// What the initramfs must handle. The HOOKS and MODULES lines are built from it in a fixed order,
+/// so the same install always gets the same mkinitcpio.conf.
+/// Hibernation needs no resume hook, the systemd hook resumes from the swap on its own.
+#[derive(Default)]
+pub struct Features {
+    // LUKS partitions to unlock, with the LVM volumes of the encrypted /boot layout
+    pub encrypted: bool,
+    pub lvm: bool,
+    pub plymouth: bool,
+    pub extra_hooks: Vec<String>,
+    pub extra_modules: Vec<String>,
+}
+
+impl Features {
+    pub fn hooks(&self) -> Vec<String> {
+        let mut hooks = vec!["base", "systemd"];
+        if self.plymouth {
+            hooks.push("plymouth");
+        }
+        hooks.extend(["autodetect", "modconf"]);
+        // The kms hook would load nouveau next to the NVIDIA driver
+        if !nvidia_installed() {
+            hooks.push("kms");
+        }
+        hooks.extend(["keyboard", "sd-vconsole", "block"]);
+        if self.encrypted {
+            hooks.push("sd-encrypt");
+        }
+        if self.encrypted || self.lvm {
+            hooks.push("lvm2");
+        }
+        let mut hooks: Vec<String> = hooks.into_iter().map(String::from).collect();
+        let (late, early): (Vec<&String>, Vec<&String>) =
+            self.extra_hooks.iter().partition(|hook| LATE_HOOKS.contains(&hook.as_str()));
+        add_missing(&mut hooks, early);
+        hooks.extend(["filesystems", "fsck"].map(String::from));
+        add_missing(&mut hooks, late);
+        hooks
+    }
+
+    pub fn modules(&self) -> Vec<String> {
+        let mut modules: Vec<String> = hardware::virt_modules().into_iter().map(String::from).collect();
+        if nvidia_installed() {
+            modules.extend(NVIDIA_MODULES.map(String::from));
+        }
+        add_missing(&mut modules, self.extra_modules.iter());
+        modules
+    }
+
+    /// Writes the HOOKS and MODULES lines of mkinitcpio.conf, mkinitcpio -P must run afterwards.
+    pub fn write(&self) {
+        let hooks = self.hooks().join(" ");
+        let modules = self.modules().join(" ");
+        info!("Initramfs hooks : {}", hooks);
+        info!("Initramfs modules : {}", if modules.is_empty() { "none" } else { &modules });
+        files_eval(
+            files::sed_file(MKINITCPIO_CONF, r"(?m)^HOOKS=.*$", &format!("HOOKS=({})", hooks)),
+            "Set mkinitcpio hooks",
+        );
+        files_eval(
+            files::sed_file(MKINITCPIO_CONF, r"(?m)^MODULES=.*$", &format!("MODULES=({})", modules)),
+            "Set mkinitcpio modules",
+        );
+    }
+}
+
+fn add_missing<'a>(list: &mut Vec<String>, entries: impl IntoIterator<Item = &'a String>) {
+    for entry in entries {
+        if !list.contains(entry) {
+            list.push(entry.clone());
+        }
+    }
+}
+
+// nvidia-utils ships nvidia-smi with every NVIDIA driver flavour, open or proprietary
+fn nvidia_installed() -> bool {
+    Path::new("/mnt/usr/bin/nvidia-smi").exists()
+}
+
+/// Builds the initramfs of every installed kernel, once all the edits of mkinitcpio.conf are done.
+pub fn generate() {
+    info!("Generating the initramfs images");
+    exec_eval(exec_chroot("mkinitcpio", vec![String::from("-P")]), "run mkinitcpio presets processing");
+}
@@ -1,7 +1,11 @@
 use std::fs::File;
 use std::io::Write;
+use crate::internal::install::install;
+use crate::internal::services::{disable_service, enable_service};
+use shared::args::PackageManager;
 use shared::exec::exec_chroot;
 use shared::files;
+use shared::language::Language;
 use shared::returncode_eval::exec_eval;
 use shared::returncode_eval::files_eval;
 
@@ -23,6 +27,14 @@ pub fn set_timezone(timezone: &str) {
     );
 }
 
+/// Keeps the hardware clock in local time, as Windows does, instead of UTC.
+pub fn set_local_rtc() {
+    exec_eval(
+        exec_chroot("hwclock", vec!["--systohc".to_string(), "--localtime".to_string()]),
+        "Set hardware clock to local time",
+    );
+}
+
 pub fn set_locale(locale: String) {
     files::create_file("/mnt/etc/locale.conf");
     files_eval(
@@ -97,3 +109,56 @@ EndSection
 
     Ok(())
 }
+
+// Translations only exist for what gets installed, a language pack would otherwise pull in the whole application
+pub fn language_packages(language: &Language, firefox: bool, libreoffice: bool) -> Vec<String> {
+    let mut packages = Vec::new();
+    if firefox && language.firefox != "en-US" {
+        packages.push(format!("firefox-i18n-{}", language.firefox.to_lowercase()));
+    }
+    if let (true, Some(suffix)) = (libreoffice, language.libreoffice) {
+        packages.push(format!("libreoffice-fresh-{}", suffix));
+    }
+    if let Some(suffix) = language.hunspell {
+        packages.push(String::from("hunspell"));
+        packages.push(format!("hunspell-{}", suffix));
+    }
+    packages
+}
+
+/// Sets up the NTP client once the system services are enabled, systemd-timesyncd is the default one.
+pub fn configure_ntp(client: &str, servers: &[String]) {
+    match client {
+        "chrony" => {
+            install(PackageManager::Pacman, vec!["chrony"]);
+            if !servers.is_empty() {
+                set_chrony_servers(servers);
+            }
+            disable_service("systemd-timesyncd");
+            enable_service("chronyd");
+        }
+        "none" => disable_service("systemd-timesyncd"),
+        _ if !servers.is_empty() => {
+            files_eval(files::create_directory("/mnt/etc/systemd/timesyncd.conf.d"), "Create timesyncd.conf.d");
+            files_eval(
+                std::fs::write("/mnt/etc/systemd/timesyncd.conf.d/10-aegis.conf", format!("[Time]\nNTP={}\n", servers.join(" "))),
+                "Set NTP servers",
+            );
+        }
+        _ => {}
+    }
+}
+
+// Custom servers replace the pool of the distribution
+fn set_chrony_servers(servers: &[String]) {
+    files_eval(
+        files::sed_file("/mnt/etc/chrony.conf", r"(?m)^(pool|server) ", "#$0"),
+        "Comment default NTP servers",
+    );
+    for server in servers {
+        files_eval(
+            files::append_file("/mnt/etc/chrony.conf", &format!("server {} iburst", server)),
+            "Add NTP server",
+        );
+    }
+}
@@ -1,9 +1,12 @@
+pub mod aur;
 pub mod base;
 pub mod browsers;
 pub mod desktops;
 pub mod displaymanagers;
+pub mod initramfs;
 pub mod locale;
 pub mod network;
+pub mod roles;
 pub mod shells;
 pub mod terminals;
 pub mod themes;
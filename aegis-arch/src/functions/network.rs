@@ -1,8 +1,13 @@
+use crate::internal::install::install;
+use crate::internal::services::enable_service;
+use shared::args::PackageManager;
 use shared::files;
+use shared::netstorage;
+use shared::services;
 use shared::returncode_eval::files_eval;
 
 pub fn set_hostname(hostname: &str) {
-    println!("Setting hostname to {}", hostname);
+    eprintln!("Setting hostname to {}", hostname);
     files::create_file("/mnt/etc/hostname");
     files_eval(
         files::append_file("/mnt/etc/hostname", hostname),
@@ -24,3 +29,32 @@ pub fn enable_ipv6() {
         "add ipv6 localhost",
     );
 }
+
+pub fn configure_firewall(backend: &str, deny_incoming: bool, tcp_ports: &[String], udp_ports: &[String]) {
+    services::configure_firewall(backend, deny_incoming, tcp_ports, udp_ports, |packages| install(PackageManager::Pacman, packages));
+}
+
+/// Logs in to the network storage targets again at boot, with the node records and identity of the live system.
+pub fn configure_network_storage(targets: &[(String, &str, &str)]) {
+    let mut packages = Vec::new();
+    if targets.iter().any(|(protocol, _, _)| protocol == "iscsi") {
+        packages.push("open-iscsi");
+    }
+    if targets.iter().any(|(protocol, _, _)| protocol.starts_with("nvme")) {
+        packages.push("nvme-cli");
+    }
+    if packages.is_empty() {
+        return;
+    }
+    install(PackageManager::Pacman, packages.clone());
+    for (protocol, portal, target) in targets {
+        netstorage::persist(protocol, portal, target);
+    }
+    if packages.contains(&"open-iscsi") {
+        enable_service("iscsid");
+        enable_service("iscsi");
+    }
+    if packages.contains(&"nvme-cli") {
+        enable_service("nvmf-autoconnect");
+    }
+}
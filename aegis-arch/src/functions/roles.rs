@@ -0,0 +1,64 @@
+use shared::args::RoleSetup;
+use shared::debug;
+
+// Roles pull package groups of the BlackArch fork shipped by the Athena repository
+pub fn install_role_setup(role_setup: RoleSetup) -> Vec<&'static str> {
+    debug!("Selecting {:?}", role_setup);
+
+    match role_setup {
+        RoleSetup::RedTeam => install_red_team(),
+        RoleSetup::BlueTeam => install_blue_team(),
+        RoleSetup::Osint => install_osint(),
+        RoleSetup::Forensics => install_forensics(),
+        RoleSetup::Student => install_student(),
+        RoleSetup::None => {
+            debug!("No role setup selected");
+            Vec::new()
+        }
+    }
+}
+
+fn install_red_team() -> Vec<&'static str> {
+    vec![
+        "blackarch-exploitation",
+        "blackarch-recon",
+        "blackarch-scanner",
+        "blackarch-webapp",
+        "blackarch-cracker",
+    ]
+}
+
+fn install_blue_team() -> Vec<&'static str> {
+    vec![
+        "blackarch-defensive",
+        "blackarch-ids",
+        "blackarch-networking",
+        "blackarch-sniffer",
+    ]
+}
+
+fn install_osint() -> Vec<&'static str> {
+    vec![
+        "blackarch-recon",
+        "blackarch-social",
+    ]
+}
+
+fn install_forensics() -> Vec<&'static str> {
+    vec![
+        "blackarch-forensic",
+        "blackarch-binary",
+        "blackarch-reversing",
+    ]
+}
+
+fn install_student() -> Vec<&'static str> {
+    vec![
+        "nmap",
+        "wireshark-qt",
+        "burpsuite",
+        "metasploit",
+        "john",
+        "gobuster",
+    ]
+}
@@ -1,8 +1,11 @@
+use shared::crypt;
 use shared::exec::exec_chroot;
 use shared::files;
 use shared::returncode_eval::exec_eval;
 use shared::returncode_eval::files_eval;
-use std::process::Command;
+use shared::strings::crash;
+use shared::warn;
+use std::path::Path;
 
 pub fn new_user(username: &str, hasroot: bool, password: &str, do_hash_pass: bool, shell: &str) {
     let shell: &str = shell;
@@ -10,8 +13,7 @@ pub fn new_user(username: &str, hasroot: bool, password: &str, do_hash_pass: boo
     // Username cannot contain any space
     let sanitized_username = username.replace(' ', "");
     if do_hash_pass {
-        let hashed_pass = hash_pass(password).stdout;
-        _password = String::from_utf8_lossy(&hashed_pass).into_owned();
+        _password = hash_pass(password);
     }
     else {
         _password = password.to_string();
@@ -77,13 +79,50 @@ pub fn new_user(username: &str, hasroot: bool, password: &str, do_hash_pass: boo
     }
 }
 
-pub fn hash_pass(password: &str) -> std::process::Output {
-    let output = Command::new("openssl")
-        .args(["passwd", "-6", password])
-        .output()
-        .expect("Failed to hash password");
+// Athena ships its avatars in the faces directory, AccountsService reads the user icon from its own directory
+pub fn set_avatar(username: &str, avatar: &str) {
+    let sanitized_username = username.replace(' ', "");
+    let source = format!("/mnt/usr/share/pixmaps/faces/{}", avatar);
+    if !Path::new(&source).exists() {
+        warn!("Avatar {} is not installed, {} keeps the default one", avatar, sanitized_username);
+        return;
+    }
+    files_eval(
+        files::create_directory("/mnt/var/lib/AccountsService/icons/"),
+        "Create /mnt/var/lib/AccountsService/icons",
+    );
+    files::copy_file(&source, &format!("/mnt/var/lib/AccountsService/icons/{}", sanitized_username));
+    let icon = format!("Icon=/var/lib/AccountsService/icons/{}", sanitized_username);
+    let user_file = format!("/mnt/var/lib/AccountsService/users/{}", sanitized_username);
+    if Path::new(&user_file).exists() {
+        files_eval(
+            files::sed_file(&user_file, "Icon=.*", &icon),
+            format!("Set avatar of user {}", sanitized_username).as_str(),
+        );
+    } else {
+        files_eval(
+            files::create_directory("/mnt/var/lib/AccountsService/users/"),
+            "Create /mnt/var/lib/AccountsService",
+        );
+        files::create_file(&user_file);
+        files_eval(
+            files::append_file(&user_file, &format!("[User]\n{}", icon)),
+            format!("Set avatar of user {}", sanitized_username).as_str(),
+        );
+    }
+}
 
-    output
+pub fn hash_pass(password: &str) -> String {
+    crypt::hash_password(password).unwrap_or_else(|e| crash(format!("Hash password ERROR: {}", e), 1))
+}
+
+/// Adds an existing user to supplementary groups.
+pub fn add_groups(username: &str, groups: &[&str]) {
+    let sanitized_username = username.replace(' ', "");
+    exec_eval(
+        exec_chroot("usermod", vec![String::from("-aG"), groups.join(","), sanitized_username.clone()]),
+        format!("Add user {} to groups {}", sanitized_username, groups.join(",")).as_str(),
+    );
 }
 
 pub fn root_pass(root_pass: &str) {
@@ -99,3 +138,10 @@ pub fn root_pass(root_pass: &str) {
         "set root password",
     );
 }
+
+pub fn lock_root() {
+    exec_eval(
+        exec_chroot("usermod", vec![String::from("--lock"), String::from("root")]),
+        "lock the root account",
+    );
+}
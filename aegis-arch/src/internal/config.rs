@@ -1,14 +1,33 @@
 use crate::internal::install::install;
 //use crate::internal::secure;
 use crate::functions::*;
-use shared::args::{self, DesktopSetup, ThemeSetup, DMSetup, ShellSetup, BrowserSetup, TerminalSetup, PackageManager, PartitionMode};
-use shared::{debug, info};
-use shared::files;
+use shared::args::{self, BaseSystem, DesktopSetup, ThemeSetup, RoleSetup, DMSetup, ShellSetup, BrowserSetup, TerminalSetup, Encryption, PackageManager, PartitionMode, WipeMethod};
+use shared::{debug, error, info, warn};
+use shared::clap::ValueEnum;
+use shared::checkpoint::{self, Checkpoint};
+use shared::cleanup;
+use shared::config_input;
+use shared::encrypt;
+use shared::failure::{self, Category};
+use shared::firewall;
+use shared::language;
+use shared::logging;
+use shared::netstorage;
+use shared::osdetect;
+use shared::oem;
 use shared::partition;
-use shared::returncode_eval::files_eval;
+use shared::post_install;
+use shared::progress;
+use shared::proxy;
+use shared::secureboot;
+use shared::steps::{self, Step};
 use shared::serde::{self, Deserialize, Serialize};
 use shared::serde_json;
-use shared::strings::crash;
+use shared::strings::{self, crash};
+use shared::template;
+use shared::tuning;
+use shared::validation::{self, Kind, Schema};
+use std::collections::BTreeMap;
 use std::path::{PathBuf};
 
 
@@ -17,20 +36,56 @@ use std::path::{PathBuf};
 struct Config {
     partition: Partition,
     bootloader: Bootloader,
+    #[serde(default)]
+    secure_boot: SecureBoot,
+    #[serde(default)]
+    language: String,
     locale: Locale,
+    #[serde(default)]
+    time: Time,
     networking: Networking,
+    #[serde(default)]
+    firewall: Firewall,
+    #[serde(default)]
     users: Vec<Users>,
+    #[serde(default)]
     rootpass: String,
-    desktop: String,
+    #[serde(default)]
+    root_locked: bool,
+    #[serde(deserialize_with = "config_input::one_or_many")]
+    desktop: Vec<String>,
     theme: String,
+    #[serde(default)]
+    role: String,
     displaymanager: String,
+    #[serde(default)]
+    displaymanager_theme: String,
     browser: String,
     terminal: String,
-    //snapper: bool,
+    #[serde(default)]
+    snapshots: Snapshots,
     flatpak: bool,
     zramd: bool,
+    #[serde(default)]
+    bluetooth: bool,
+    #[serde(default)]
+    power_management: String,
+    #[serde(default)]
+    tuning: Tuning,
+    #[serde(default)]
+    virtualization: Virtualization,
+    #[serde(default)]
+    audio: String,
     //hardened: bool,
     extra_packages: Vec<String>,
+    #[serde(default)]
+    aur_helper: String,
+    #[serde(default)]
+    aur_packages: Vec<String>,
+    #[serde(default)]
+    post_install_script: String,
+    #[serde(default)]
+    initramfs: Initramfs,
     kernel: String,
 }
 
@@ -40,10 +95,37 @@ struct Partition {
     device: String,
     mode: PartitionMode,
     encrypt_check: bool,
+    #[serde(default)]
+    encrypt_boot: bool,
+    #[serde(default)]
+    wipe: String,
+    #[serde(default)]
+    swap_random_key: bool,
     efi: bool,
     swap: bool,
     swap_size: String,
     partitions: Vec<String>,
+    #[serde(default)]
+    encryption: LuksUnlock,
+    #[serde(default)]
+    network_storage: Vec<NetworkStorage>,
+}
+
+// iSCSI or NVMe over Fabrics target logged in before partitioning, its disks can then be used like local ones
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct NetworkStorage {
+    protocol: String,
+    portal: String,
+    target: String,
+}
+
+// Extra ways to unlock the LUKS partitions, the passphrase keyslot always stays as a fallback
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct LuksUnlock {
+    keyfile_device: String,
+    tpm2: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,6 +133,14 @@ struct Partition {
 struct Bootloader {
     r#type: String,
     location: String,
+    #[serde(default)]
+    os_prober: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct SecureBoot {
+    mode: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -62,6 +152,49 @@ struct Locale {
     timezone: String,
 }
 
+// Container and virtual machine stacks, the first user joins their groups
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Virtualization {
+    docker: bool,
+    podman: bool,
+    libvirt: bool,
+}
+
+// Timeline snapshots of the btrfs root, hourly and daily are the number of snapshots kept
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Snapshots {
+    tool: String,
+    hourly: u32,
+    daily: u32,
+}
+
+// Additions to the generated mkinitcpio HOOKS and MODULES lines
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Initramfs {
+    extra_hooks: Vec<String>,
+    extra_modules: Vec<String>,
+}
+
+// Kernel tunables: a preset of sysctl values and IO scheduler rules, with sysctl overrides applied on top
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Tuning {
+    preset: String,
+    sysctl: BTreeMap<String, String>,
+}
+
+// Clock synchronisation, an empty ntp keeps the NTP client of the base system
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Time {
+    ntp: String,
+    ntp_servers: Vec<String>,
+    local_rtc: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "self::serde")] // must be below the derive attribute
 struct Networking {
@@ -69,6 +202,16 @@ struct Networking {
     ipv6: bool,
 }
 
+// Incoming traffic filter of the installed system, ports are single ports or "first-last" ranges
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Firewall {
+    backend: String,
+    deny_incoming: bool,
+    tcp_ports: Vec<String>,
+    udp_ports: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "self::serde")] // must be below the derive attribute
 struct Users {
@@ -76,9 +219,225 @@ struct Users {
     password: String,
     hasroot: bool,
     shell: String,
+    #[serde(default)]
+    fullname: String,
+    #[serde(default)]
+    avatar: String,
+}
+
+const SCHEMA: Schema = Schema {
+    required: &[
+        ("/partition", Kind::Object),
+        ("/partition/device", Kind::String),
+        ("/partition/mode", Kind::String),
+        ("/partition/encrypt_check", Kind::Bool),
+        ("/partition/efi", Kind::Bool),
+        ("/partition/swap", Kind::Bool),
+        ("/partition/swap_size", Kind::String),
+        ("/partition/partitions", Kind::Array),
+        ("/bootloader/type", Kind::String),
+        ("/bootloader/location", Kind::String),
+        ("/locale/locale", Kind::Array),
+        ("/locale/virtkeymap", Kind::String),
+        ("/locale/x11keymap", Kind::String),
+        ("/locale/timezone", Kind::String),
+        ("/networking/hostname", Kind::String),
+        ("/networking/ipv6", Kind::Bool),
+        ("/users", Kind::Array),
+        ("/rootpass", Kind::String),
+        ("/desktop", Kind::StringList),
+        ("/theme", Kind::String),
+        ("/displaymanager", Kind::String),
+        ("/browser", Kind::String),
+        ("/terminal", Kind::String),
+        ("/flatpak", Kind::Bool),
+        ("/zramd", Kind::Bool),
+        ("/extra_packages", Kind::Array),
+        ("/kernel", Kind::String),
+    ],
+    choices: &[
+        ("/partition/mode", &["erasedisk", "replace", "manual"]),
+        ("/bootloader/type", &["grub-efi", "grub-legacy"]),
+        ("/audio", &["pipewire", "pulseaudio"]),
+        ("/power_management", &["tlp", "power-profiles-daemon", "none"]),
+        ("/tuning/preset", &tuning::PRESETS),
+        ("/time/ntp", &["timesyncd", "chrony", "none"]),
+        ("/snapshots/tool", &["snapper", "timeshift", "none"]),
+        ("/firewall/backend", &["nftables", "firewalld", "none"]),
+        ("/partition/wipe", &["none", "quick", "discard", "secure"]),
+        ("/secure_boot/mode", &["sbctl", "shim", "none"]),
+        ("/desktop", &[
+            "onyx", "kde plasma", "mate", "gnome", "cinnamon", "xfce refined", "xfce picom", "budgie",
+            "enlightenment", "lxqt", "sway", "i3", "herbstluftwm", "awesome", "bspwm", "hyprland", "none",
+        ]),
+        ("/theme", &["akame", "cyborg", "graphite", "hackthebox", "samurai", "sweet", "temple", "none"]),
+        ("/role", &["red team", "blue team", "osint", "forensics", "student", "none"]),
+        ("/displaymanager", &["gdm", "lightdm", "lightdm neon", "sddm", "greetd", "none"]),
+        ("/displaymanager_theme", &[
+            "astronaut", "black hole", "cyberpunk", "hyprland kath", "jake the dog", "japanese aesthetic",
+            "pixel sakura", "post-apocalyptic hacker", "purple leaves",
+        ]),
+        ("/browser", &["firefox", "brave", "none"]),
+        ("/aur_helper", &["paru", "yay", "none"]),
+        ("/terminal", &[
+            "alacritty", "cool retro term", "foot", "gnome terminal", "kitty", "konsole", "terminator",
+            "terminology", "urxvt", "xfce", "xterm", "none",
+        ]),
+        ("/kernel", &[
+            "linux", "linux lts", "linux zen", "linux hardened", "linux real-time", "linux real-time lts",
+            "linux liquorix", "linux xanmod",
+        ]),
+    ],
+};
+
+// Example written by generate-config. Every key of Config is present, "_comment" keys are ignored by the parser.
+// It must keep passing SCHEMA, except for the placeholder password hashes.
+const EXAMPLE_CONFIG: &str = r#"{
+  "_comment": "Aegis unattended config for Athena Arch. Run 'aegis-arch config <file> --dry' to validate it.",
+  "_comment_templates": "${NAME} in any value is replaced by a --set NAME=value, ${dmi:product_serial} and the like, or the NAME environment variable (see list-template-vars). $${NAME} is a literal ${NAME}. post_install_script is passed to the shell unchanged",
+  "partition": {
+    "_comment": "mode: EraseDisk (automatic layout on device), Replace or Manual (use the partitions list)",
+    "device": "sda",
+    "mode": "EraseDisk",
+    "encrypt_check": false,
+    "_comment_encrypt_boot": "EraseDisk with encrypt_check and efi only: encrypt /boot and swap too, the ESP is mounted at /efi (set bootloader location to /efi)",
+    "encrypt_boot": false,
+    "_comment_wipe": "EraseDisk only. wipe: none (new partition table only), quick (erase signatures), discard (SSD blkdiscard) or secure (zero the whole drive, slow)",
+    "wipe": "none",
+    "efi": true,
+    "swap": false,
+    "_comment_swap_size": "size like 8GiB, 8G, 8.5 GiB, 8GB (SI) or a percentage of the drive like 5%",
+    "swap_size": "8GiB",
+    "_comment_swap_random_key": "Encrypt the swap with a new random key at every boot (no hibernation), not with encrypt_boot",
+    "swap_random_key": false,
+    "_comment_partitions": "Manual/Replace only, entries are mountpoint:blockdevice:filesystem:encrypt[:options], options being mount options like noatime,compress=zstd (EraseDisk uses noatime for root and umask=0077 for the ESP)",
+    "partitions": [
+      "/mnt/boot:/dev/sda1:vfat:false:umask=0077",
+      "/mnt:/dev/sda2:btrfs:false:noatime,compress=zstd"
+    ],
+    "encryption": {
+      "_comment": "Optional unlocking of the encrypted partitions, the passphrase always remains as a fallback. keyfile_device: removable partition (i.e. /dev/sdb1) receiving a generated keyfile, empty for none. tpm2: unlock with the TPM2 when present (not with encrypt_boot)",
+      "keyfile_device": "",
+      "tpm2": false
+    },
+    "_comment_network_storage": "iSCSI and NVMe over Fabrics targets to log in to before partitioning, entries are {\"protocol\": \"iscsi\", \"nvme-tcp\" or \"nvme-rdma\", \"portal\": \"host[:port]\", \"target\": IQN or NQN}. The root filesystem must stay on a local disk",
+    "network_storage": []
+  },
+  "bootloader": {
+    "_comment": "type: grub-efi (location is the EFI directory) or grub-legacy (location is the disk). os_prober: add the other installed operating systems (see detect-os) to the boot menu",
+    "type": "grub-efi",
+    "location": "/boot",
+    "os_prober": false
+  },
+  "secure_boot": {
+    "_comment": "grub-efi only. mode: sbctl (custom keys, enrolled when the firmware is in setup mode), shim (Microsoft signed shim and a Machine Owner Key enrolled at first boot) or none",
+    "mode": "none"
+  },
+  "_comment_language": "installer language, i.e. Italiano or it: adds its language packs and fills the locale and keymaps left empty",
+  "language": "English",
+  "locale": {
+    "_comment": "locale entries are lines of /etc/locale.gen",
+    "locale": ["en_US.UTF-8 UTF-8"],
+    "virtkeymap": "us",
+    "x11keymap": "us",
+    "timezone": "Europe/Zurich"
+  },
+  "_comment_time": "ntp: timesyncd, chrony or none. ntp_servers replace the default servers. local_rtc keeps the hardware clock in local time, for dual boot with Windows",
+  "time": {
+    "ntp": "timesyncd",
+    "ntp_servers": [],
+    "local_rtc": false
+  },
+  "networking": {
+    "hostname": "athenaos",
+    "ipv6": false
+  },
+  "_comment_firewall": "backend: nftables, firewalld or none. With deny_incoming, only tcp_ports and udp_ports are let in, as single ports (22) or ranges (6000-6010)",
+  "firewall": {
+    "backend": "nftables",
+    "deny_incoming": true,
+    "tcp_ports": [],
+    "udp_ports": []
+  },
+  "_comment_users": "password is a crypt hash, generate it with 'openssl passwd -6'. The first user's shell is the default one. fullname (shown by the display manager) and avatar (an image of /usr/share/pixmaps/faces) are optional",
+  "users": [
+    {
+      "name": "athena",
+      "password": "REPLACE_WITH_HASH",
+      "hasroot": true,
+      "shell": "fish",
+      "fullname": "Athena User",
+      "avatar": ""
+    }
+  ],
+  "rootpass": "REPLACE_WITH_HASH",
+  "_comment_root_locked": "Lock the root account instead of setting rootpass (which can then be left out), administration goes through the users with hasroot",
+  "root_locked": false,
+  "_comment_desktop": "one desktop or an array of several, i.e. [\"gnome\", \"hyprland\"]: onyx, kde plasma, mate, gnome, cinnamon, xfce refined, xfce picom, budgie, enlightenment, lxqt, sway, i3, herbstluftwm, awesome, bspwm, hyprland or none",
+  "desktop": "gnome",
+  "_comment_theme": "akame, cyborg, graphite, hackthebox, samurai, sweet, temple or none",
+  "theme": "hackthebox",
+  "_comment_role": "tools bundle added on top of extra_packages: red team, blue team, osint, forensics, student or none",
+  "role": "none",
+  "_comment_displaymanager": "gdm, lightdm, lightdm neon, sddm, greetd or none",
+  "displaymanager": "gdm",
+  "_comment_displaymanager_theme": "sddm only: astronaut, black hole, cyberpunk, hyprland kath, jake the dog, japanese aesthetic, pixel sakura, post-apocalyptic hacker or purple leaves. Empty for the default",
+  "displaymanager_theme": "",
+  "_comment_browser": "firefox, brave or none",
+  "browser": "firefox",
+  "_comment_terminal": "alacritty, cool retro term, foot, gnome terminal, kitty, konsole, terminator, terminology, urxvt, xfce, xterm or none",
+  "terminal": "kitty",
+  "_comment_snapshots": "btrfs root only. tool: snapper, timeshift or none, keeping the last hourly and daily snapshots. The snapshots are listed in the GRUB menu",
+  "snapshots": {
+    "tool": "none",
+    "hourly": 5,
+    "daily": 7
+  },
+  "flatpak": false,
+  "zramd": true,
+  "_comment_bluetooth": "Installs the Bluetooth stack with the applet of the selected desktop",
+  "bluetooth": false,
+  "_comment_power_management": "tlp, power-profiles-daemon or none, for laptops. GNOME and KDE Plasma expect power-profiles-daemon",
+  "power_management": "none",
+  "_comment_tuning": "preset: default (kernel defaults), low-latency, vm-guest or server. The presets set swappiness, cache pressure and network sysctls and pick the IO scheduler of each drive type. sysctl holds overrides, i.e., {\"vm.swappiness\": \"20\"}",
+  "tuning": {
+    "preset": "default",
+    "sysctl": {}
+  },
+  "_comment_virtualization": "Container and virtual machine stacks. The first user joins the docker and libvirt groups",
+  "virtualization": {
+    "docker": false,
+    "podman": false,
+    "libvirt": false
+  },
+  "_comment_audio": "pipewire or pulseaudio, the audio server. Extra packages of the other stack are left out",
+  "audio": "pipewire",
+  "extra_packages": [],
+  "_comment_aur_helper": "paru, yay or none. aur_packages are built with it as the first user after the install, a package that fails to build is skipped",
+  "aur_helper": "none",
+  "aur_packages": [],
+  "_comment_post_install_script": "Shell script run as root inside the installed system as the last step: the script itself, an http(s) URL or a file path on the live system. A failing script does not fail the install",
+  "post_install_script": "",
+  "_comment_kernel": "linux, linux lts, linux zen, linux hardened, linux real-time, linux real-time lts, linux liquorix or linux xanmod",
+  "kernel": "linux lts",
+  "_comment_initramfs": "Advanced: mkinitcpio hooks and kernel modules added to the ones the install picks (encryption, early KMS, virtual machine drivers)",
+  "initramfs": {
+    "extra_hooks": [],
+    "extra_modules": []
+  }
+}
+"#;
+
+pub fn generate_config(base: Option<BaseSystem>, output: Option<PathBuf>) -> i32 {
+    if base.is_some_and(|base| base != BaseSystem::Arch) {
+        error!("This backend generates Athena Arch configs, use aegis-nix or aegis-fedora generate-config for the other bases");
+        return 1;
+    }
+    config_input::write_example(EXAMPLE_CONFIG, output.as_deref());
+    0
 }
 
-pub fn read_config(configpath: PathBuf) -> i32 {
+pub fn read_config(configpath: PathBuf, overrides: &[(String, String)], dry: bool, resume: bool, oem: bool, jobs: usize) -> i32 {
     let mut package_set: Vec<&str> = vec![
         "linux-firmware",
         "systemd-sysvcompat",
@@ -230,27 +589,7 @@ pub fn read_config(configpath: PathBuf) -> i32 {
     let data = std::fs::read_to_string(&configpath);
     match &data {
         Ok(_) => {
-            files_eval(
-                files::sed_file(
-                    configpath.to_str().unwrap(),
-                    "\"password\":.*",
-                    "\"password\": \"*REDACTED*\",",
-                ),
-                "Redact user information",
-            );
-            files_eval(
-                files::sed_file(
-                    configpath.to_str().unwrap(),
-                    "\"rootpass\":.*",
-                    "\"rootpass\": \"*REDACTED*\",",
-                ),
-                "Redact root information",
-            );
-            // Re-read the redacted file content
-            let redacted_data = std::fs::read_to_string(&configpath).expect("Failed to read config file after redaction");
-            info!("Configuration set:\n{}", redacted_data);
-
-            debug!("[ \x1b[2;1;32mOK\x1b[0m ] Read and redacted config file {configpath:?}");
+            debug!("[ \x1b[2;1;32mOK\x1b[0m ] Read config file {configpath:?}");
         }
         Err(e) => {
             crash(
@@ -259,80 +598,214 @@ pub fn read_config(configpath: PathBuf) -> i32 {
             );
         }
     }
-    let config: std::result::Result<Config, serde_json::Error> =
+    let config: std::result::Result<serde_json::Value, serde_json::Error> =
         serde_json::from_str(&data.unwrap());
     match &config {
-        Ok(_) => {
+        Ok(config) => {
+            info!("Configuration set:\n{}", serde_json::to_string_pretty(&config_input::redact(config)).unwrap());
             debug!("[ \x1b[2;1;32mOK\x1b[0m ] Parse config file {configpath:?}",);
         }
         Err(e) => {
             crash(format!("Parse config file {configpath:?}  ERROR: {}", e), 1);
         }
     }
-    /*    PARTITIONING    */
-    let config: Config = config.unwrap();
-    info!("Block device to use : {}", config.partition.device);
-    info!("Partitioning mode : {:?}", config.partition.mode);
-    info!("Partitioning for EFI : {}", config.partition.efi);
-    info!("Swap partition : {}", config.partition.swap);
+    let mut config = config.unwrap();
+    let mut problems = template::substitute(&mut config, overrides);
+    problems.extend(validation::validate(&config, &SCHEMA));
+    if oem {
+        oem::skip_credentials(&mut problems);
+    }
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("{}", problem);
+        }
+        error!("Config file {configpath:?} has {} problem(s)", problems.len());
+        return failure::report("Invalid config file", 1, Some(Category::InvalidConfig));
+    }
+    for warning in validation::warnings(&config) {
+        warn!("{}", warning);
+    }
+    let config_hash = checkpoint::fingerprint(&config.to_string());
+    let mut config: Config = match serde_json::from_value(config) {
+        Ok(config) => config,
+        Err(e) => crash(format!("Parse config file {configpath:?}  ERROR: {}", e), 1),
+    };
     let mut partitions: Vec<args::Partition> = Vec::new();
-    for partition in config.partition.partitions {
-        let to_encrypt: bool = partition.split(':').collect::<Vec<&str>>()[3].parse().map_err(|_| "Invalid boolean value").expect("Unable to get encrypt boolean value.");
-        partitions.push(args::Partition::new(
-            partition.split(':').collect::<Vec<&str>>()[0].to_string(),
-            partition.split(':').collect::<Vec<&str>>()[1].to_string(),
-            partition.split(':').collect::<Vec<&str>>()[2].to_string(),
-            to_encrypt,
-        ));
+    for partition in &config.partition.partitions {
+        partitions.push(
+            args::parse_partitions(partition)
+                .unwrap_or_else(|e| crash(format!("Partition \"{}\": {}", partition, e), 1)),
+        );
+    }
+    let language = language::lookup(&config.language);
+    if let Some(language) = language {
+        language::fill_defaults(language, &mut config.locale.locale, &mut config.locale.virtkeymap, &mut config.locale.x11keymap);
     }
     let device = PathBuf::from("/dev/").join(config.partition.device.as_str());
-    partition::partition(
-        device,
-        config.partition.mode,
-        config.partition.encrypt_check,
-        config.partition.efi,
-        config.partition.swap,
-        config.partition.swap_size,
-        &mut partitions,
-    );
-    println!();
+    let encryption = Encryption::from_flags(config.partition.encrypt_check, config.partition.encrypt_boot);
+    let wipe = WipeMethod::from_str(&config.partition.wipe, true).unwrap_or(WipeMethod::None);
+    // Spell out what is about to be destroyed, the dry run shows it as well
+    for line in partition::destructive_summary(&device, config.partition.mode, encryption, wipe, &partitions) {
+        warn!("{}", line);
+    }
+    let network_storage: Vec<(String, &str, &str)> = config
+        .partition
+        .network_storage
+        .iter()
+        .map(|storage| (storage.protocol.to_lowercase(), storage.portal.as_str(), storage.target.as_str()))
+        .collect();
+    for (protocol, portal, target) in &network_storage {
+        info!("Network storage : {} target {} at {}", protocol, target, portal);
+    }
+    let firewall_backend = config.firewall.backend.to_lowercase();
+    info!("{}", firewall::summary(&firewall_backend, config.firewall.deny_incoming, &config.firewall.tcp_ports, &config.firewall.udp_ports));
+    let bluetooth_packages = if config.bluetooth { desktops::bluetooth_packages(&config.desktop) } else { Vec::new() };
+    info!("Bluetooth : {}", if config.bluetooth { bluetooth_packages.join(", ") } else { String::from("false") });
+    let audio = if config.audio.is_empty() { String::from("pipewire") } else { config.audio.to_lowercase() };
+    info!("Audio : {}", audio);
+    let (audio_packages, audio_conflicts) = base::audio_packages(&audio);
+    // The audio stack is chosen separately, conflicting extra packages would make pacman fail
+    for package in config.extra_packages.iter().filter(|package| audio_conflicts.contains(&package.as_str())) {
+        warn!("Extra package {} conflicts with the {} audio stack, it is left out", package, audio);
+    }
+    let snapshots = if config.snapshots.tool.is_empty() { String::from("none") } else { config.snapshots.tool.to_lowercase() };
+    if snapshots == "none" {
+        info!("Snapshots : none");
+    } else {
+        info!("Snapshots : {}, keeping {} hourly and {} daily", snapshots, config.snapshots.hourly, config.snapshots.daily);
+    }
+    let virtualization = &config.virtualization;
+    info!("Virtualization : docker {}, podman {}, libvirt {}", virtualization.docker, virtualization.podman, virtualization.libvirt);
+    let mut virtualization_groups = Vec::new();
+    if virtualization.docker {
+        virtualization_groups.push("docker");
+    }
+    if virtualization.libvirt {
+        virtualization_groups.push("libvirt");
+    }
+    let group_user = config.users.first().filter(|_| !oem).map(|user| user.name.as_str());
+    if !virtualization_groups.is_empty() {
+        match group_user {
+            Some(user) => info!("User {} joins the groups {}", user, virtualization_groups.join(", ")),
+            None => warn!("No user is created by the install, nobody joins the groups {}", virtualization_groups.join(", ")),
+        }
+    }
+    let power_management = config.power_management.to_lowercase();
+    info!("Power management : {}", if power_management.is_empty() { "none" } else { &power_management });
+    if (power_management.is_empty() || power_management == "none") && osdetect::is_laptop() {
+        warn!("This machine runs on a battery, set power_management to tlp or power-profiles-daemon to manage it");
+    }
+    let tuning_preset = if config.tuning.preset.is_empty() { String::from("default") } else { config.tuning.preset.to_lowercase() };
+    let tuning_values = tuning::sysctl_values(&tuning_preset, &config.tuning.sysctl);
+    for line in tuning::summary(&tuning_preset, &tuning_values) {
+        info!("{}", line);
+    }
+    if dry {
+        info!("Config file {configpath:?} is valid");
+        return 0;
+    }
+    // os-prober mounts every partition to look for other systems, a dry run leaves the disks alone
+    if !config.time.local_rtc {
+        if let Some(windows) = osdetect::find_windows() {
+            warn!("{} found on {}, it keeps the hardware clock in local time: set time/local_rtc to avoid a clock offset between the systems", windows.name, windows.device);
+        }
+    }
+    let mut checkpoint = Checkpoint::start(config_hash, resume);
+    progress::phase(5, "Partitioning drives");
+    if checkpoint.should_run("partitioning", false) {
+        info!("Block device to use : {}", config.partition.device);
+        info!("Partitioning mode : {:?}", config.partition.mode);
+        info!("Partitioning for EFI : {}", config.partition.efi);
+        info!("Swap partition : {}", config.partition.swap);
+        // Release whatever a previous failed install left mounted or opened on the drives
+        cleanup::cleanup();
+        for (protocol, portal, target) in &network_storage {
+            if let Err(e) = netstorage::connect(protocol, portal, target) {
+                error!("{}", e);
+                return failure::report(&e, 1, Some(Category::Partitioning));
+            }
+        }
+        // mkinitcpio has no hook logging in to the targets, the root could not be mounted at boot
+        if let Some(transport) = netstorage::root_transport(&device, config.partition.mode, &partitions) {
+            let message = format!("The root filesystem is on {} storage, Athena Arch can only boot from a local disk", transport);
+            error!("{}", message);
+            return failure::report(&message, 1, Some(Category::InvalidConfig));
+        }
+        if config.partition.mode == PartitionMode::EraseDisk {
+            partition::wipe_device(&device, wipe);
+        }
+        partition::partition(
+            device,
+            config.partition.mode,
+            encryption,
+            config.partition.efi,
+            config.partition.swap,
+            config.partition.swap_size,
+            false,
+            &mut partitions,
+        );
+        if !config.partition.encryption.keyfile_device.is_empty() {
+            encrypt::enroll_keyfile(&config.partition.encryption.keyfile_device);
+        }
+        if config.partition.encryption.tpm2 {
+            encrypt::enroll_tpm2();
+        }
+        encrypt::forget_passphrase();
+        checkpoint.complete("partitioning");
+    }
+    eprintln!();
 
     /* BOOTLOADER PACKAGE SET */
+    progress::phase(15, "Selecting packages");
     let boot_packages = vec![
         "grub",
-        "os-prober",
         "athena-grub-theme",
     ];
     package_set.extend(boot_packages);
+    if config.bootloader.os_prober {
+        package_set.push("os-prober");
+    }
     if config.bootloader.r#type == "grub-efi" {
         package_set.push("efibootmgr");
     }
+    package_set.extend(bluetooth_packages);
+    if config.partition.encryption.tpm2 {
+        package_set.push("tpm2-tss"); // pulled in the initramfs by sd-encrypt
+    }
+    info!("Secure Boot : {} (firmware state: {})", config.secure_boot.mode, secureboot::state());
+    match config.secure_boot.mode.to_lowercase().as_str() {
+        "sbctl" => package_set.push("sbctl"),
+        "shim" => package_set.extend(["shim-signed", "sbsigntools", "mokutil"]),
+        _ => {}
+    }
     /**************************/
-    println!();
+    eprintln!();
     /*        DESKTOP         */
     info!("Selected desktop : {:?}", config.desktop);
     /*if let Some(desktop) = &config.desktop {
         desktops::install_desktop_setup(*desktop);
     }*/
-    match config.desktop.to_lowercase().as_str() {
-        "onyx" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Onyx)),
-        "kde plasma" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Kde)), //Note that the value on this match statement must fit the name in desktops.py of aegis-gui (then they are lowercase transformed)
-        "mate" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Mate)),
-        "gnome" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Gnome)),
-        "cinnamon" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Cinnamon)),
-        "xfce refined" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::XfceRefined)),
-        "xfce picom" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::XfcePicom)),
-        "budgie" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Budgie)),
-        "enlightenment" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Enlightenment)),
-        "lxqt" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Lxqt)),
-        "sway" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Sway)),
-        "i3" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::I3)),
-        "herbstluftwm" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Herbstluftwm)),
-        "awesome" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Awesome)),
-        "bspwm" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Bspwm)),
-        "hyprland" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Hyprland)),
-        "none" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::None)),
-        _ => info!("No desktop setup selected!"),
+    for desktop in &config.desktop {
+        match desktop.to_lowercase().as_str() {
+            "onyx" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Onyx)),
+            "kde plasma" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Kde)), //Note that the value on this match statement must fit the name in desktops.py of aegis-gui (then they are lowercase transformed)
+            "mate" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Mate)),
+            "gnome" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Gnome)),
+            "cinnamon" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Cinnamon)),
+            "xfce refined" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::XfceRefined)),
+            "xfce picom" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::XfcePicom)),
+            "budgie" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Budgie)),
+            "enlightenment" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Enlightenment)),
+            "lxqt" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Lxqt)),
+            "sway" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Sway)),
+            "i3" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::I3)),
+            "herbstluftwm" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Herbstluftwm)),
+            "awesome" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Awesome)),
+            "bspwm" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Bspwm)),
+            "hyprland" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::Hyprland)),
+            "none" => package_set.extend(desktops::install_desktop_setup(DesktopSetup::None)),
+            _ => info!("No desktop setup selected!"),
+        }
     }
     /**************************/
 
@@ -340,12 +813,14 @@ pub fn read_config(configpath: PathBuf) -> i32 {
     info!("Selected display manager : {:?}", config.displaymanager);
     match config.displaymanager.to_lowercase().as_str() {
         "gdm" => package_set.extend(displaymanagers::install_dm_setup(DMSetup::Gdm)),
+        "lightdm" => package_set.extend(displaymanagers::install_dm_setup(DMSetup::LightDM)),
         "lightdm neon" => package_set.extend(displaymanagers::install_dm_setup(DMSetup::LightDMNeon)),
         "sddm" => package_set.extend(displaymanagers::install_dm_setup(DMSetup::Sddm)),
+        "greetd" => package_set.extend(displaymanagers::install_dm_setup(DMSetup::Greetd)),
         _ => info!("No display manager setup selected!"),
     }
     /**************************/
-    println!();
+    eprintln!();
     /* BROWSER PACKAGE SET */
     info!("Selected browser : {:?}", config.browser);
     match config.browser.to_lowercase().as_str() {
@@ -358,7 +833,7 @@ pub fn read_config(configpath: PathBuf) -> i32 {
         _ => info!("No browser setup selected!"),
     }
     /**************************/
-    println!();
+    eprintln!();
     /*        TERMINAL       */
     info!("Selected terminal : {:?}", config.terminal);
     let mut terminal_choice = String::new();
@@ -410,7 +885,7 @@ pub fn read_config(configpath: PathBuf) -> i32 {
         _ => info!("No terminal setup selected!"),
     }
     /**************************/
-    println!();
+    eprintln!();
     /*         THEME         */
     info!("Selected theme : {:?}", config.theme);
     match config.theme.to_lowercase().as_str() {
@@ -424,9 +899,36 @@ pub fn read_config(configpath: PathBuf) -> i32 {
         _ => info!("No theme setup selected!"),
     }
     /**************************/
-    println!();
+    eprintln!();
+    /*          ROLE         */
+    info!("Selected role : {:?}", config.role);
+    match config.role.to_lowercase().as_str() {
+        "red team" => package_set.extend(roles::install_role_setup(RoleSetup::RedTeam)),
+        "blue team" => package_set.extend(roles::install_role_setup(RoleSetup::BlueTeam)),
+        "osint" => package_set.extend(roles::install_role_setup(RoleSetup::Osint)),
+        "forensics" => package_set.extend(roles::install_role_setup(RoleSetup::Forensics)),
+        "student" => package_set.extend(roles::install_role_setup(RoleSetup::Student)),
+        _ => info!("No role setup selected!"),
+    }
+    /**************************/
+    eprintln!();
+    /*        LANGUAGE       */
+    let language_packages = match language {
+        Some(language) => locale::language_packages(
+            language,
+            config.browser.eq_ignore_ascii_case("firefox"),
+            config.extra_packages.iter().any(|package| package == "libreoffice-fresh"),
+        ),
+        None => Vec::new(),
+    };
+    info!("Selected language packs : {:?}", language_packages);
+    package_set.extend(language_packages.iter().map(String::as_str));
+    /**************************/
+    eprintln!();
     /*          MISC         */
 
+    package_set.retain(|package| !audio_conflicts.contains(package));
+    package_set.extend(audio_packages);
     if config.zramd {
         info!("Selecting zramd : {}", config.zramd);
         package_set.push("zram-generator");
@@ -436,7 +938,7 @@ pub fn read_config(configpath: PathBuf) -> i32 {
         package_set.push("flatpak");
     }
     /**************************/
-    println!();
+    eprintln!();
     /*         USERS         */
     for i in 0..config.users.len() {
         match config.users[i].shell.to_lowercase().as_str() {
@@ -447,130 +949,220 @@ pub fn read_config(configpath: PathBuf) -> i32 {
         }
     }
     /**************************/
-    println!();
+    eprintln!();
     /********** INSTALLATION **********/
-
-    base::install_packages(config.kernel, package_set);
+    progress::phase(20, "Installing packages");
+    if checkpoint.should_run("packages", true) {
+        base::install_packages(config.kernel, package_set);
+        checkpoint.complete("packages");
+    }
 
     /**************************/
-    println!();
+    eprintln!();
     /********** CONFIGURATION **********/
-
-    base::genfstab();
+    progress::phase(60, "Generating fstab");
+    if checkpoint.should_run("fstab", true) {
+        base::genfstab();
+        if config.partition.swap_random_key {
+            base::configure_random_swap();
+        }
+        if !network_storage.is_empty() {
+            netstorage::add_netdev_options("/mnt/etc/fstab");
+        }
+        checkpoint.complete("fstab");
+    }
 
     /*    BOOTLOADER CONFIG     */
-    info!("Configuring bootloader : {}", config.bootloader.r#type);
-    info!("Configuring bootloader to : {}", config.bootloader.location);
-    if config.bootloader.r#type == "grub-efi" {
-        base::configure_bootloader_efi(PathBuf::from(config.bootloader.location), config.partition.encrypt_check);
-    } else if config.bootloader.r#type == "grub-legacy" {
-        base::configure_bootloader_legacy(PathBuf::from(config.bootloader.location), config.partition.encrypt_check);
+    progress::phase(62, "Configuring bootloader");
+    if checkpoint.should_run("bootloader", true) {
+        let unlock = &config.partition.encryption;
+        if !unlock.keyfile_device.is_empty() || unlock.tpm2 {
+            base::configure_luks_unlock(Some(unlock.keyfile_device.as_str()).filter(|device| !device.is_empty()), unlock.tpm2);
+        }
+        info!("Configuring bootloader : {}", config.bootloader.r#type);
+        info!("Configuring bootloader to : {}", config.bootloader.location);
+        if config.bootloader.r#type == "grub-efi" {
+            base::configure_bootloader_efi(PathBuf::from(&config.bootloader.location), encryption, config.bootloader.os_prober);
+            base::configure_secure_boot(PathBuf::from(&config.bootloader.location), &config.secure_boot.mode.to_lowercase());
+        } else if config.bootloader.r#type == "grub-legacy" {
+            base::configure_bootloader_legacy(PathBuf::from(&config.bootloader.location), encryption, config.bootloader.os_prober);
+        }
+        checkpoint.complete("bootloader");
     }
     /**************************/
-    println!();
+    eprintln!();
     /*         LOCALES        */
-    // Set locales at the beginning to prevent some warning messages about "Setting locale failed"
-    info!("Adding Locales : {:?}", config.locale.locale);
-    locale::set_locale(config.locale.locale.join(" "));
-    info!("Using console keymap : {}", config.locale.virtkeymap);
-    info!("Using x11 keymap : {}", config.locale.x11keymap);
-    locale::set_keyboard(config.locale.virtkeymap.as_str(), config.locale.x11keymap.as_str())
-        .unwrap_or_else(|e| {
-            eprintln!("Error setting keyboard configuration: {}", e);
-        });
-    info!("Setting timezone : {}", config.locale.timezone);
-    locale::set_timezone(config.locale.timezone.as_str());
-    /**************************/
-    info!("Processing all presets.");
-    base::preset_process();
-    println!();
-    info!("Hostname : {}", config.networking.hostname);
-    network::set_hostname(config.networking.hostname.as_str());
-    network::create_hosts();
-    /**************************/
-    println!();
-    /*     DESKTOP CONFIG     */
-    info!("Configuring desktop : {:?}", config.desktop);
-    match config.desktop.to_lowercase().as_str() {
-        "gnome" => desktops::configure_gnome(),
-        _ => info!("No desktop configuration needed."),
+    progress::phase(66, "Configuring locales");
+    if checkpoint.should_run("locales", true) {
+        // Set locales at the beginning to prevent some warning messages about "Setting locale failed"
+        info!("Adding Locales : {:?}", config.locale.locale);
+        locale::set_locale(config.locale.locale.join(" "));
+        info!("Using console keymap : {}", config.locale.virtkeymap);
+        info!("Using x11 keymap : {}", config.locale.x11keymap);
+        locale::set_keyboard(config.locale.virtkeymap.as_str(), config.locale.x11keymap.as_str())
+            .unwrap_or_else(|e| {
+                eprintln!("Error setting keyboard configuration: {}", e);
+            });
+        info!("Setting timezone : {}", config.locale.timezone);
+        locale::set_timezone(config.locale.timezone.as_str());
+        if config.time.local_rtc {
+            info!("Hardware clock in local time");
+            locale::set_local_rtc();
+        }
+        checkpoint.complete("locales");
     }
-    /**************************/
-    println!();
-    /* DISPLAY MANAGER CONFIG */
-    info!("Configuring display manager : {:?}", config.displaymanager);
-    match config.displaymanager.to_lowercase().as_str() {
-        "gdm" => displaymanagers::configure_gdm(&config.desktop),
-        "lightdm neon" => displaymanagers::configure_lightdm_neon(&config.desktop),
-        "sddm" => displaymanagers::configure_sddm(),
-        _ => info!("No display manager configuration needed."),
+    eprintln!();
+    /*        SNAPSHOTS       */
+    if snapshots != "none" {
+        progress::phase(67, "Configuring snapshots");
+        if checkpoint.should_run("snapshots", true) {
+            base::setup_snapshots(&snapshots, config.snapshots.hourly, config.snapshots.daily);
+            checkpoint.complete("snapshots");
+        }
     }
-    /**************************/
-    println!();
-    /*     BROWSER CONFIG     */
-    info!("Configuring browser : {:?}", config.browser);
-    match config.browser.to_lowercase().as_str() {
-        "firefox" => browsers::configure_firefox(&config.desktop),
-        "brave" => browsers::configure_brave(&config.desktop),
-        _ => info!("No browser configuration needed."),
+    eprintln!();
+    /*        INITRAMFS       */
+    progress::phase(68, "Generating the initramfs");
+    if checkpoint.should_run("initramfs", true) {
+        let mut features = initramfs::Features {
+            encrypted: encryption != Encryption::None || partitions.iter().any(|partition| partition.encrypt),
+            lvm: encryption == Encryption::AllButEsp,
+            plymouth: config.extra_packages.iter().any(|package| package == "plymouth"),
+            extra_hooks: config.initramfs.extra_hooks.clone(),
+            extra_modules: config.initramfs.extra_modules.clone(),
+        };
+        if snapshots != "none" {
+            // Read-only snapshots booted from the GRUB menu get a writable overlay
+            features.extra_hooks.push(String::from("grub-btrfs-overlayfs"));
+        }
+        features.write();
+        initramfs::generate();
+        checkpoint.complete("initramfs");
+    }
+    eprintln!();
+    progress::phase(70, "Configuring network");
+    if checkpoint.should_run("network", true) {
+        info!("Hostname : {}", config.networking.hostname);
+        network::set_hostname(config.networking.hostname.as_str());
+        network::create_hosts();
+        proxy::persist();
+        checkpoint.complete("network");
     }
     /**************************/
-    println!();
-    /*    TERMINAL CONFIG    */
-    info!("Configuring terminal : {}", config.terminal);
-    terminals::configure_terminal(terminal_choice, &config.desktop);
-    /**************************/
-    println!();
-    /*      THEME CONFIG     */
-    info!("Configuring theme : {:?}", config.theme);
-    match config.theme.to_lowercase().as_str() {
-        "akame" => themes::configure_akame(),
-        "cyborg" => themes::configure_cyborg(),
-        "graphite" => themes::configure_graphite(),
-        "hackthebox" => themes::configure_hackthebox(),
-        "samurai" => themes::configure_samurai(),
-        "sweet" => themes::configure_sweet(),
-        "temple" => themes::configure_temple(),
-        _ => info!("No theme configuration needed."),
+    eprintln!();
+    /*     DESKTOP CONFIG     */
+    progress::phase(72, "Configuring desktop environment");
+    if checkpoint.should_run("desktop", true) {
+        // Steps touching the same files are chained: sessions (desktop, display manager),
+        // dconf-shell.ini (browser, terminal)
+        // Every listed desktop is installed, the gnome checks look for it anywhere in the list
+        let desktop = config.desktop.join(" ");
+        let desktop = desktop.as_str();
+        // The first listed desktop is the default session
+        let session = config.desktop.first().map(|desktop| desktop.to_lowercase()).unwrap_or_default();
+        steps::run_steps(
+            vec![
+                Step::new("desktop", &[], || {
+                    info!("Configuring desktop : {:?}", config.desktop);
+                    for desktop in &config.desktop {
+                        match desktop.to_lowercase().as_str() {
+                            "gnome" => desktops::configure_gnome(),
+                            _ => info!("No desktop configuration needed."),
+                        }
+                    }
+                }),
+                Step::new("display manager", &["desktop"], || {
+                    info!("Configuring display manager : {:?}", config.displaymanager);
+                    match config.displaymanager.to_lowercase().as_str() {
+                        "gdm" => displaymanagers::configure_gdm(desktop),
+                        "lightdm" | "lightdm neon" => displaymanagers::configure_lightdm(&session),
+                        "sddm" => displaymanagers::configure_sddm(&config.displaymanager_theme.to_lowercase()),
+                        "greetd" => displaymanagers::configure_greetd(),
+                        _ => info!("No display manager configuration needed."),
+                    }
+                }),
+                Step::new("browser", &[], || {
+                    info!("Configuring browser : {:?}", config.browser);
+                    match config.browser.to_lowercase().as_str() {
+                        "firefox" => browsers::configure_firefox(desktop),
+                        "brave" => browsers::configure_brave(desktop),
+                        _ => info!("No browser configuration needed."),
+                    }
+                }),
+                Step::new("terminal", &["browser"], move || {
+                    info!("Configuring terminal : {}", config.terminal);
+                    terminals::configure_terminal(terminal_choice, desktop);
+                }),
+                Step::new("theme", &[], || {
+                    info!("Configuring theme : {:?}", config.theme);
+                    match config.theme.to_lowercase().as_str() {
+                        "akame" => themes::configure_akame(),
+                        "cyborg" => themes::configure_cyborg(),
+                        "graphite" => themes::configure_graphite(),
+                        "hackthebox" => themes::configure_hackthebox(),
+                        "samurai" => themes::configure_samurai(),
+                        "sweet" => themes::configure_sweet(),
+                        "temple" => themes::configure_temple(),
+                        _ => info!("No theme configuration needed."),
+                    }
+                }),
+            ],
+            jobs,
+        );
+        checkpoint.complete("desktop");
     }
     /**************************/
-    println!();
+    eprintln!();
 
-    /*info!("Installing snapper : {}", config.snapper);
-    if config.snapper {
-        base::setup_snapper();
-    }*/
-    
     /*    EXTRA PACKAGES    */
-    info!("Extra packages : {:?}", config.extra_packages);
-    let mut extra_packages: Vec<&str> = Vec::new();
-    for i in 0..config.extra_packages.len() {
-        extra_packages.push(config.extra_packages[i].as_str());
+    progress::phase(78, "Installing extra packages");
+    if checkpoint.should_run("extra_packages", true) {
+        info!("Extra packages : {:?}", config.extra_packages);
+        let mut extra_packages: Vec<&str> = Vec::new();
+        for i in 0..config.extra_packages.len() {
+            extra_packages.push(config.extra_packages[i].as_str());
+        }
+        extra_packages.retain(|package| !audio_conflicts.contains(package));
+        install(PackageManager::Pacman, extra_packages);
+        checkpoint.complete("extra_packages");
     }
-    install(PackageManager::Pacman, extra_packages);
     /**************************/
-    println!();
+    eprintln!();
     /*     SHELL CONFIG     */
-    // The shell of the first created user will be applied on shell.desktop and on SHELL variable
-    match config.users[0].shell.to_lowercase().as_str() {
-        "fish" => shells::configure_fish(),
-        "zsh" => shells::configure_zsh(),
-        _ => info!("No shell configuration needed."),
-    }
-    /**************************/
-    println!();
-    /*          MISC         */
-    info!("Enabling ipv6 : {}", config.networking.ipv6);
-    if config.networking.ipv6 {
-        network::enable_ipv6();
-    }
-    if config.zramd {
-        info!("Enabling zramd : {}", config.zramd);
-        base::configure_zram();
-    }
-    if config.flatpak {
-        info!("Configuring flatpak : {}", config.flatpak);
-        base::configure_flatpak();
+    progress::phase(85, "Configuring shell and system extras");
+    if checkpoint.should_run("system_extras", true) {
+        steps::run_steps(
+            vec![
+                Step::new("shell", &[], || {
+                    // The shell of the first created user will be applied on shell.desktop and on SHELL variable
+                    match config.users.first().map(|user| user.shell.to_lowercase()).unwrap_or_default().as_str() {
+                        "fish" => shells::configure_fish(),
+                        "zsh" => shells::configure_zsh(),
+                        _ => info!("No shell configuration needed."),
+                    }
+                }),
+                Step::new("ipv6", &[], || {
+                    info!("Enabling ipv6 : {}", config.networking.ipv6);
+                    if config.networking.ipv6 {
+                        network::enable_ipv6();
+                    }
+                }),
+                Step::new("zramd", &[], || {
+                    if config.zramd {
+                        info!("Enabling zramd : {}", config.zramd);
+                        base::configure_zram();
+                    }
+                }),
+                Step::new("flatpak", &[], || {
+                    if config.flatpak {
+                        info!("Configuring flatpak : {}", config.flatpak);
+                        base::configure_flatpak();
+                    }
+                }),
+            ],
+            jobs,
+        );
+        checkpoint.complete("system_extras");
     }
     /*info!("Hardening system : {}", config.hardened);
     if config.hardened {
@@ -578,39 +1170,102 @@ pub fn read_config(configpath: PathBuf) -> i32 {
         secure::secure_ssh_config();
     }*/
     /**************************/
-    println!();
+    eprintln!();
     /*      USER CONFIG      */
-    for i in 0..config.users.len() {
-        info!("Creating user : {}", config.users[i].name);
-        //info!("Setting user password : {}", config.users[i].password);
-        info!("Enabling root for user : {}", config.users[i].hasroot);
-        info!("Setting user shell : {}", config.users[i].shell);
+    progress::phase(90, "Creating users");
+    if checkpoint.should_run("users", true) {
+        if oem {
+            oem::install_firstboot();
+        } else {
+            for i in 0..config.users.len() {
+                info!("Creating user : {}", config.users[i].name);
+                //info!("Setting user password : {}", config.users[i].password);
+                info!("Enabling root for user : {}", config.users[i].hasroot);
+                info!("Setting user shell : {}", config.users[i].shell);
 
-        users::new_user(
-            config.users[i].name.as_str(),
-            config.users[i].hasroot,
-            config.users[i].password.as_str(),
-            false,
-            "bash", //config.users[i].shell.as_str(), // Use bash because it must be the shell associated to the user in order to source the initial .sh files at login time
-        );
+                users::new_user(
+                    config.users[i].name.as_str(),
+                    config.users[i].hasroot,
+                    config.users[i].password.as_str(),
+                    false,
+                    "bash", //config.users[i].shell.as_str(), // Use bash because it must be the shell associated to the user in order to source the initial .sh files at login time
+                );
+                if !config.users[i].fullname.is_empty() {
+                    strings::set_full_name(config.users[i].name.as_str(), config.users[i].fullname.as_str());
+                }
+                if !config.users[i].avatar.is_empty() {
+                    users::set_avatar(config.users[i].name.as_str(), config.users[i].avatar.as_str());
+                }
+            }
+            //info!("Setting root password : {}", config.rootpass);
+            if config.root_locked {
+                users::lock_root();
+            } else {
+                users::root_pass(config.rootpass.as_str());
+            }
+        }
+        checkpoint.complete("users");
     }
-    //info!("Setting root password : {}", config.rootpass);
-    users::root_pass(config.rootpass.as_str());
     /**************************/
-    println!();
+    eprintln!();
+    /*     AUR PACKAGES     */
+    let aur_helper = config.aur_helper.to_lowercase();
+    if !aur_helper.is_empty() && aur_helper != "none" {
+        progress::phase(93, "Installing AUR packages");
+        if checkpoint.should_run("aur", true) {
+            aur::install_helper(&aur_helper);
+            match group_user {
+                Some(user) if !config.aur_packages.is_empty() => {
+                    aur::install_packages(&aur_helper, &user.replace(' ', ""), &config.aur_packages);
+                }
+                None if !config.aur_packages.is_empty() => {
+                    warn!("AUR packages need a user to be built, none is created by the install");
+                }
+                _ => {}
+            }
+            checkpoint.complete("aur");
+        }
+    }
+    /**************************/
+    eprintln!();
     /*    ENABLE SERVICES    */
-    info!("Enabling system services...");
-    base::enable_system_services();
+    progress::phase(95, "Enabling system services");
+    if checkpoint.should_run("services", true) {
+        info!("Enabling system services...");
+        base::enable_system_services();
+        base::enable_audio_services(&audio);
+        base::configure_power_management(&power_management);
+        tuning::write_config(&tuning_values, tuning::io_scheduler(&tuning_preset));
+        base::install_virtualization(virtualization.docker, virtualization.podman, virtualization.libvirt);
+        if let Some(user) = group_user.filter(|_| !virtualization_groups.is_empty()) {
+            users::add_groups(user, &virtualization_groups);
+        }
+        locale::configure_ntp(&config.time.ntp.to_lowercase(), &config.time.ntp_servers);
+        network::configure_network_storage(&network_storage);
+        network::configure_firewall(&firewall_backend, config.firewall.deny_incoming, &config.firewall.tcp_ports, &config.firewall.udp_ports);
+        checkpoint.complete("services");
+    }
+    /**************************/
+    eprintln!();
+    /*    POST-INSTALL SCRIPT    */
+    if !config.post_install_script.trim().is_empty() {
+        progress::phase(96, "Running the post-install script");
+        if checkpoint.should_run("post_install", true) {
+            post_install::run(&config.post_install_script, &["arch-chroot", "/mnt"]);
+            checkpoint.complete("post_install");
+        }
+    }
     /**************************/
-    println!();
-    info!("Installation log file copied to /var/log/aegis.log");
-    files_eval(files::create_directory("/mnt/var/log"), "create /mnt/var/log");
-    files::copy_file("/tmp/aegis.log", "/mnt/var/log/aegis.log");
+    eprintln!();
+    progress::phase(98, "Finalizing installation");
+    checkpoint.finish();
+    logging::persist_log("/mnt/var/log/aegis-install.log");
     if config.bootloader.r#type == "grub-efi" {
         partition::umount("/mnt/boot");
     }
     partition::umount("/mnt/home");
     partition::umount("/mnt");
-    println!("Installation finished! You may reboot now!");
+    progress::phase(100, "Installation finished");
+    eprintln!("Installation finished! You may reboot now!");
     0
 }
@@ -8,13 +8,26 @@ type Packages = Vec<&'static str>;
 type Services = Vec<&'static str>;
 type SetParams = Vec<(String, Vec<String>)>;
 
-pub fn virt_check() -> (Packages, Services, SetParams) {
+fn detect_virt() -> String {
     let output = Command::new("systemd-detect-virt")
         .output()
         .expect("Failed to run systemd-detect-virt");
 
     let mut result = String::from_utf8_lossy(&output.stdout).to_string();
     result.pop(); //Removing the \n char from string
+    result
+}
+
+/// Kernel modules the hypervisor needs in the initramfs.
+pub fn virt_modules() -> Vec<&'static str> {
+    match detect_virt().as_str() {
+        "vmware" => vec!["vsock", "vmw_vsock_vmci_transport", "vmw_balloon", "vmw_vmci", "vmwgfx"],
+        _ => Vec::new(),
+    }
+}
+
+pub fn virt_check() -> (Packages, Services, SetParams) {
+    let result = detect_virt();
 
     let mut packages = Vec::new();
     let mut services = Vec::new();
@@ -26,19 +39,7 @@ pub fn virt_check() -> (Packages, Services, SetParams) {
     } else if result == "vmware" {
         packages.extend(vec!["open-vm-tools", "xf86-video-vmware"]);
         services.extend(vec!["vmware-vmblock-fuse", "vmtoolsd"]);
-
-        // Add the file change for vmware modules
-        set_params.push((
-            "Set vmware modules".to_string(),
-            vec![
-                "-i".to_string(),
-                "-e".to_string(),
-                "/^MODULES=()/ s/()/(vsock vmw_vsock_vmci_transport vmw_balloon vmw_vmci vmwgfx)/".to_string(),
-                "-e".to_string(),
-                "/^MODULES=([^)]*)/ {/vsock vmw_vsock_vmci_transport vmw_balloon vmw_vmci vmwgfx/! s/)/ vsock vmw_vsock_vmci_transport vmw_balloon vmw_vmci vmwgfx)/".to_string(),
-                "/mnt/etc/mkinitcpio.conf".to_string(),
-            ],
-        ));
+        // The vmware modules are added by the initramfs configuration
     } else if result == "qemu" || result == "kvm" {
         packages.extend(vec!["qemu-guest-agent", "spice-vdagent"]);
         services.push("qemu-guest-agent");
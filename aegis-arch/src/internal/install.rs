@@ -1,136 +1,95 @@
 use shared::regex::Regex;
 use shared::args::PackageManager;
+use shared::exec::{Classifier, RunError, RunSpec};
+use shared::failure;
 use shared::{debug, error, info};
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufRead, BufReader, Write};
-use std::process::{Command, Stdio};
-use std::sync::{Arc, Mutex};
-use std::thread;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
 
-pub fn install(pkgmanager: PackageManager, pkgs: Vec<&str>) {
-    // Create an Arc<Mutex<bool>> for the retry flag
-    let mut retry = Arc::new(Mutex::new(true)); // Just to enter the first time in the while loop
-
-    let mut retry_counter = 0; // Initialize retry counter
-    while *retry.lock().unwrap() && retry_counter < 15 {
-        retry = Arc::new(Mutex::new(false));
-        let retry_clone = Arc::clone(&retry); // Clone for use in the thread
-        let mut pkgmanager_cmd = Command::new("true")
-            .spawn()
-            .expect("Failed to initialize by 'true'"); // 'true' in bash is like a NOP command
-        let mut pkgmanager_name = String::new();
-        match pkgmanager {
-            PackageManager::Pacman => {
-                pkgmanager_cmd = Command::new("arch-chroot")
-                    .arg("/mnt")
-                    .arg("pacman")
-                    .arg("-Syyu")
-                    .arg("--needed")
-                    .arg("--noconfirm")
-                    .args(&pkgs)
-                    .stdout(Stdio::piped()) // Capture stdout
-                    .stderr(Stdio::piped()) // Capture stderr
-                    .spawn()
-                    .expect("Failed to start pacman");
-                pkgmanager_name = String::from("pacman");
-            },
-            PackageManager::Pacstrap => {
-                pkgmanager_cmd = Command::new("pacstrap")
-                    .arg("/mnt")
-                    .args(&pkgs)
-                    .stdout(Stdio::piped()) // Capture stdout
-                    .stderr(Stdio::piped()) // Capture stderr
-                    .spawn()
-                    .expect("Failed to start pacstrap");
-                pkgmanager_name = String::from("pacstrap");
-            },
-            PackageManager::None => debug!("No package manager selected"),
-        };
-
-        let stdout_handle = pkgmanager_cmd.stdout.take().unwrap();
-        let stderr_handle = pkgmanager_cmd.stderr.take().unwrap();
-
-        // Wrap the stdout and stderr in BufReader to satisfy the BufRead trait
-        let stdout_thread = spawn_log_thread(BufReader::new(stdout_handle), retry_clone.clone(), &pkgmanager_name);
-        let stderr_thread = spawn_log_thread(BufReader::new(stderr_handle), retry_clone.clone(), &pkgmanager_name);
+// A hung mirror connection must not freeze the install forever
+const INSTALL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+const INSTALL_RETRIES: u32 = 3;
 
-        let exit_status = pkgmanager_cmd.wait().expect("Failed to wait for the package manager");
-
-        stdout_thread.join().expect("stdout thread panicked");
-        stderr_thread.join().expect("stderr thread panicked");
-
-        if !exit_status.success() {
-            error!("The package manager failed with exit code: {}", exit_status.code().unwrap_or(-1));
+pub fn install(pkgmanager: PackageManager, pkgs: Vec<&str>) {
+    let (spec, pkgmanager_name) = match pkgmanager {
+        PackageManager::Pacman => (
+            RunSpec::new("arch-chroot")
+                .args(["/mnt", "pacman", "-Syyu", "--needed", "--noconfirm"])
+                .args(&pkgs),
+            "pacman",
+        ),
+        PackageManager::Pacstrap => (RunSpec::new("pacstrap").arg("/mnt").args(&pkgs), "pacstrap"),
+        PackageManager::None => {
+            debug!("No package manager selected");
+            return;
         }
-
-        retry_counter += 1;
+    };
+
+    // Failures caused by a bad mirror are retried once the mirror has been moved to the end of the list
+    let classifier: Classifier = Arc::new(move |line: &str| rotate_failing_mirror(line, pkgmanager_name));
+    let result = spec
+        .prefix(pkgmanager_name)
+        .timeout(INSTALL_TIMEOUT)
+        .retries(INSTALL_RETRIES, Duration::from_secs(5))
+        .retry_when(classifier)
+        .run();
+
+    if let Err(e) = result {
+        if let RunError::Timeout(_) = e {
+            failure::command_timed_out();
+        }
+        error!("The package manager failed: {}", e);
     }
 }
 
-// Helper function to handle both stdout and stderr
-fn spawn_log_thread<R: BufRead + Send + 'static>(
-    reader_handle: R,
-    retry_clone: Arc<Mutex<bool>>,
-    pkgmanager_name: &str,
-) -> thread::JoinHandle<()> {
-    let pkgmanager_name = pkgmanager_name.to_string();
-    thread::spawn(move || {
-        let reader = BufReader::new(reader_handle);
-        for line in reader.lines() {
-            let line = line.expect("Failed to read line");
-            info!("{}", line);
-
-            // Check if the retry flag is already set
-            if *retry_clone.lock().unwrap() {
-                break; // Exit the loop early if *retry is true
-            }
-            // Error handling logic
-            if line.contains("failed retrieving file") && line.contains("from") {
-                if let Some(mirror_name) = extract_mirror_name(&line) {
-                    if let Some(mirrorlist_file) = find_mirrorlist_file(&mirror_name, &pkgmanager_name) {
-                        if let Err(err) = move_server_line(&mirrorlist_file, &mirror_name) {
-                            error!("Failed to move 'Server' line in {}: {}", mirrorlist_file, err);
-                        } else {
-                            info!("Detected unstable mirror: {}. Retrying by a new one...", mirror_name);
-                            let mut retry = retry_clone.lock().unwrap();
-                            *retry = true;
-                        }
-                    }
-                }
-            } else if (line.contains("File") && line.contains("is corrupted")) || line.contains("invalid key") {
-                let package_name = extract_package_name(&line);
-                let repository = get_repository_name(&package_name);
-                let mut mirrorlist_filename = String::new();
-                if pkgmanager_name == "pacstrap" {
-                    if repository == "core" || repository == "extra" || repository == "community" || repository == "multilib" {
-                        mirrorlist_filename = String::from("/etc/pacman.d/mirrorlist");
-                    }
-                    if repository == "chaotic-aur" {
-                        mirrorlist_filename = String::from("/etc/pacman.d/chaotic-mirrorlist");
-                    }
-                } else if pkgmanager_name == "pacman" {
-                    if repository == "core" || repository == "extra" || repository == "community" || repository == "multilib" {
-                        mirrorlist_filename = String::from("/mnt/etc/pacman.d/mirrorlist");
-                    }
-                    if repository == "chaotic-aur" {
-                        mirrorlist_filename = String::from("/mnt/etc/pacman.d/chaotic-mirrorlist");
-                    }
+// Move the mirror responsible for an error line to the end of its mirrorlist, returns whether it did
+fn rotate_failing_mirror(line: &str, pkgmanager_name: &str) -> bool {
+    if line.contains("failed retrieving file") && line.contains("from") {
+        if let Some(mirror_name) = extract_mirror_name(line) {
+            if let Some(mirrorlist_file) = find_mirrorlist_file(&mirror_name, pkgmanager_name) {
+                if let Err(err) = move_server_line(&mirrorlist_file, &mirror_name) {
+                    error!("Failed to move 'Server' line in {}: {}", mirrorlist_file, err);
+                } else {
+                    info!("Detected unstable mirror: {}. Retrying by a new one...", mirror_name);
+                    return true;
                 }
-                match get_first_mirror_name(&mirrorlist_filename) {
-                    Ok(mirror_name) => {
-                        if let Err(err) = move_server_line(&mirrorlist_filename, &mirror_name) {
-                            error!("Failed to move 'Server' line in {}: {}", mirrorlist_filename, err);
-                        } else {
-                            info!("Detected issue on mirror: {}. Retrying by a new one...", mirror_name);
-                            let mut retry = retry_clone.lock().unwrap();
-                            *retry = true;
-                        }
-                    }
-                    Err(err) => eprintln!("Error: {}", err),
+            }
+        }
+    } else if (line.contains("File") && line.contains("is corrupted")) || line.contains("invalid key") {
+        let package_name = extract_package_name(line);
+        let repository = get_repository_name(&package_name);
+        let mut mirrorlist_filename = String::new();
+        if pkgmanager_name == "pacstrap" {
+            if repository == "core" || repository == "extra" || repository == "community" || repository == "multilib" {
+                mirrorlist_filename = String::from("/etc/pacman.d/mirrorlist");
+            }
+            if repository == "chaotic-aur" {
+                mirrorlist_filename = String::from("/etc/pacman.d/chaotic-mirrorlist");
+            }
+        } else if pkgmanager_name == "pacman" {
+            if repository == "core" || repository == "extra" || repository == "community" || repository == "multilib" {
+                mirrorlist_filename = String::from("/mnt/etc/pacman.d/mirrorlist");
+            }
+            if repository == "chaotic-aur" {
+                mirrorlist_filename = String::from("/mnt/etc/pacman.d/chaotic-mirrorlist");
+            }
+        }
+        match get_first_mirror_name(&mirrorlist_filename) {
+            Ok(mirror_name) => {
+                if let Err(err) = move_server_line(&mirrorlist_filename, &mirror_name) {
+                    error!("Failed to move 'Server' line in {}: {}", mirrorlist_filename, err);
+                } else {
+                    info!("Detected issue on mirror: {}. Retrying by a new one...", mirror_name);
+                    return true;
                 }
             }
+            Err(err) => eprintln!("Error: {}", err),
         }
-    })
+    }
+    false
 }
 
 // Function to extract the mirror name from the error message
@@ -8,4 +8,12 @@ pub fn enable_service(dm: &str) {
         exec_chroot("systemctl", vec![String::from("enable"), String::from(dm)]),
         format!("Enable {}", dm).as_str(),
     );
-}
\ No newline at end of file
+}
+
+pub fn disable_service(service: &str) {
+    debug!("Disabling {}", service);
+    exec_eval(
+        exec_chroot("systemctl", vec![String::from("disable"), String::from(service)]),
+        format!("Disable {}", service).as_str(),
+    );
+}
@@ -2,32 +2,52 @@ mod functions;
 mod internal;
 //use crate::internal::secure;
 use crate::functions::*;
-use shared::args::{BootloaderSubcommand, Command, Cli, UsersSubcommand};
+use shared::args::{BootloaderSubcommand, Command, Cli, Encryption, PartitionMode, UsersSubcommand};
+use shared::cleanup;
 use shared::clap::Parser;
+use shared::config_input;
+use shared::encrypt;
+use shared::{debug, error};
 use shared::exec::check_if_root;
 use shared::human_panic;
 use shared::logging;
+use shared::osdetect;
 use shared::partition;
+use shared::progress;
+use shared::proxy;
+use shared::serde_json;
+use shared::template;
+use std::process::ExitCode;
 
-fn main() -> Result<(), i32> {
-    check_if_root();
+fn main() -> ExitCode {
     human_panic::setup_panic!();
     let cli = Cli::parse();
-    println!("verbose: {}", cli.verbose);
-    let log_file_path = "/tmp/aegis";
-    logging::init(cli.verbose, log_file_path);
+    check_if_root(&cli);
+    let log_file_path = logging::resolve_log_file(cli.log_file);
+    logging::init(logging::level(cli.verbose, cli.quiet), cli.log_format, &log_file_path);
+    debug!("verbose: {}", cli.verbose);
+    proxy::apply(&cli.proxy);
+    if cli.show_all_disks {
+        partition::show_all_disks();
+    }
     match cli.command {
         Command::Partition(args) => {
+            cleanup::cleanup();
+            if args.mode == PartitionMode::EraseDisk {
+                partition::wipe_device(&args.device, args.wipe);
+            }
             let mut partitions = args.partitions;
             partition::partition(
                 args.device,
                 args.mode,
-                args.encrypt_check,
+                Encryption::from_flags(args.encrypt_check, args.encrypt_boot),
                 args.efi,
                 args.swap,
                 args.swap_size,
+                false,
                 &mut partitions,
             );
+            encrypt::forget_passphrase();
         }
         Command::InstallPackages(args) => {
             let package_set: Vec<&str> = Vec::new();
@@ -36,13 +56,12 @@ fn main() -> Result<(), i32> {
         Command::GenFstab => {
             base::genfstab();
         }
-        //Command::SetupSnapper => base::setup_snapper(),
         Command::Bootloader { subcommand } => match subcommand {
-            BootloaderSubcommand::GrubEfi { efidir } => {
-                base::configure_bootloader_efi(efidir,false);
+            BootloaderSubcommand::GrubEfi { efidir, os_prober } => {
+                base::configure_bootloader_efi(efidir, Encryption::None, os_prober);
             }
-            BootloaderSubcommand::GrubLegacy { device } => {
-                base::configure_bootloader_legacy(device,false);
+            BootloaderSubcommand::GrubLegacy { device, os_prober } => {
+                base::configure_bootloader_legacy(device, Encryption::None, os_prober);
             }
         }
         Command::Locale(args) => {
@@ -90,18 +109,35 @@ fn main() -> Result<(), i32> {
             println!("{} {}", args.cores, args.jobs); //Just to delete the warning about unused args variable
             //todo!()
         }
-        Command::Config { config } => {
-            let exit_code = internal::config::read_config(config);
+        Command::Config { config, config_sha256, fetch_timeout, overrides, dry, progress_json, resume, oem, jobs } => {
+            if progress_json {
+                progress::enable_json();
+            }
+            let (config, _downloaded) = config_input::resolve(&config, config_sha256.as_deref(), fetch_timeout);
+            let exit_code = internal::config::read_config(config, &overrides, dry, resume, oem, jobs.into());
+            progress::finish(exit_code);
+            if exit_code != 0 {
+                return ExitCode::from(exit_code as u8);
+            }
+        }
+        Command::GenerateConfig { base, output } => {
+            let exit_code = internal::config::generate_config(base, output);
             if exit_code != 0 {
-                return Err(exit_code);
+                return ExitCode::from(exit_code as u8);
             }
         }
+        Command::ListTemplateVars { overrides } => {
+            template::list_vars(&overrides);
+        }
         Command::Desktops { desktop } => {
             desktops::install_desktop_setup(desktop);
         }
         Command::Themes { theme } => {
             themes::install_theme_setup(theme);
         }
+        Command::Roles { role } => {
+            roles::install_role_setup(role);
+        }
         Command::DisplayManagers { displaymanager } => {
             displaymanagers::install_dm_setup(displaymanager);
         }
@@ -117,7 +153,20 @@ fn main() -> Result<(), i32> {
         Command::EnableServices => {
             base::enable_system_services();
         }
+        Command::DetectOs => {
+            println!("{}", serde_json::to_string_pretty(&osdetect::detect()).unwrap());
+        }
+        Command::ImportSettings { device } => match osdetect::import_settings(&device) {
+            Ok(settings) => println!("{}", serde_json::to_string_pretty(&settings).unwrap()),
+            Err(e) => {
+                error!("{}", e);
+                return ExitCode::from(1);
+            }
+        },
+        Command::Cleanup => {
+            cleanup::cleanup();
+        }
         _ => todo!() //Do nothing for all those Command:: specified in shared/args.rs but not specifically implemented in athena-nix (because useless)
     }
-    Ok(())
+    ExitCode::SUCCESS
 }
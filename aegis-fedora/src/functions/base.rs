@@ -0,0 +1,192 @@
+use crate::internal::install::install;
+use crate::internal::services::enable_service;
+use shared::exec::exec_chroot;
+use shared::exec::missing_programs;
+use shared::exec::RunSpec;
+use shared::files;
+use shared::info;
+use shared::services::{self, VirtualizationPackages};
+use shared::returncode_eval::exec_eval;
+use shared::returncode_eval::files_eval;
+use shared::returncode_eval::run_eval;
+use shared::strings::crash;
+use std::time::Duration;
+use std::path::PathBuf;
+
+// exec_chroot and genfstab come from arch-install-scripts, which Fedora packages as well
+const HOST_TOOLS: [&str; 2] = ["arch-chroot", "genfstab"];
+
+/// Checks that the live system has the tools working on /mnt, before anything is written to the disks.
+pub fn check_host_tools() -> Result<(), String> {
+    let missing = missing_programs(&HOST_TOOLS);
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(format!("{} not found on the live system, install arch-install-scripts", missing.join(", ")))
+}
+
+/// Bootstraps /mnt with the kernel, the core group and everything the bootloader and the disk layout need.
+pub fn install_base_packages() {
+    files_eval(files::create_directory("/mnt/etc"), "create /mnt/etc");
+    install(vec![
+        // Kernel
+        "kernel",
+        "kernel-devel",
+        "linux-firmware",
+        // Base Fedora
+        "@core",
+        "fedora-release",
+        "glibc-all-langpacks", // Prebuilt locales, Fedora has no locale-gen
+        "dracut",
+        // Bootloader and disks
+        "grub2-pc",
+        "grub2-pc-modules",
+        "grub2-efi-x64",
+        "grub2-efi-x64-modules",
+        "grub2-tools",
+        "efibootmgr",
+        "btrfs-progs",
+        "dosfstools",
+        "cryptsetup",
+        "lvm2",
+    ]);
+
+    // The installroot is populated outside of SELinux, every file must be labelled at first boot
+    files::create_file("/mnt/.autorelabel");
+}
+
+pub fn install_packages(mut packages: Vec<&str>) {
+    let mut base_packages: Vec<&str> = vec![
+        "NetworkManager",
+        "NetworkManager-wifi",
+        "chrony",
+        "sudo",
+        "passwd",
+        "nano",
+        "curl",
+        "man-db",
+        "man-pages",
+        ];
+
+    packages.append(&mut base_packages);
+    install(packages);
+}
+
+pub fn genfstab() {
+    run_eval(
+        RunSpec::new("bash")
+            .args(["-c", "genfstab -U /mnt >> /mnt/etc/fstab"])
+            .prefix("genfstab")
+            .timeout(Duration::from_secs(120))
+            .run(),
+        "Generate fstab",
+    );
+}
+
+// Fedora reads the kernels from the Boot Loader Specification entries written by kernel-install
+fn setting_grub_parameters(os_prober: bool) {
+    files::create_file("/mnt/etc/default/grub");
+    files_eval(
+        files::append_file(
+            "/mnt/etc/default/grub",
+            "GRUB_TIMEOUT=5\n\
+             GRUB_DISTRIBUTOR=\"Athena OS\"\n\
+             GRUB_DEFAULT=saved\n\
+             GRUB_DISABLE_SUBMENU=true\n\
+             GRUB_TERMINAL_OUTPUT=\"console\"\n\
+             GRUB_CMDLINE_LINUX=\"rhgb quiet\"\n\
+             GRUB_DISABLE_RECOVERY=true\n\
+             GRUB_ENABLE_BLSCFG=true",
+        ),
+        "write /etc/default/grub",
+    );
+    if os_prober {
+        files_eval(
+            files::append_file("/mnt/etc/default/grub", "GRUB_DISABLE_OS_PROBER=false"),
+            "enable os-prober",
+        );
+    }
+}
+
+pub fn configure_bootloader_efi(efidir: PathBuf, os_prober: bool) {
+
+    let efi_str = efidir.to_str().unwrap();
+    info!("EFI bootloader installing at {}", efi_str);
+
+    // efidir is a path of the installed system, absolute like /boot/efi
+    let target_efidir = std::path::Path::new("/mnt").join(efidir.strip_prefix("/").unwrap_or(&efidir));
+    if !target_efidir.exists() {
+        crash(format!("The efidir {target_efidir:?} doesn't exist"), 1);
+    }
+
+    setting_grub_parameters(os_prober);
+
+    exec_eval(
+        exec_chroot(
+            "grub2-install",
+            vec![
+                String::from("--target=x86_64-efi"),
+                format!("--efi-directory={}", efi_str),
+                String::from("--bootloader-id=GRUB"),
+                String::from("--removable"),
+                String::from("--force"), // grub2-install refuses EFI targets on Fedora, which expects the signed shim
+            ],
+        ),
+        "install grub as efi with --removable",
+    );
+
+    exec_eval(
+        exec_chroot(
+            "grub2-mkconfig",
+            vec![String::from("-o"), String::from("/boot/grub2/grub.cfg")],
+        ),
+        "create grub.cfg",
+    );
+}
+
+pub fn configure_bootloader_legacy(device: PathBuf, os_prober: bool) {
+
+    if !device.exists() {
+        crash(format!("The device {device:?} does not exist"), 1);
+    }
+
+    let device_str = device.to_string_lossy().to_string();
+    info!("Legacy bootloader installing at {}", device_str);
+
+    setting_grub_parameters(os_prober);
+
+    exec_eval(
+        exec_chroot(
+            "grub2-install",
+            vec![String::from("--target=i386-pc"), device_str],
+        ),
+        "install grub as legacy",
+    );
+
+    exec_eval(
+        exec_chroot(
+            "grub2-mkconfig",
+            vec![String::from("-o"), String::from("/boot/grub2/grub.cfg")],
+        ),
+        "create grub.cfg",
+    );
+}
+
+pub fn enable_system_services() {
+    enable_service("NetworkManager");
+    enable_service("chronyd");
+}
+
+// Fedora packages Docker as moby-engine
+const VIRTUALIZATION_PACKAGES: VirtualizationPackages = VirtualizationPackages {
+    docker: &["moby-engine", "docker-compose"],
+    libvirt: &["libvirt", "qemu-kvm", "virt-manager"],
+};
+
+pub fn install_virtualization(docker: bool, podman: bool, libvirt: bool) {
+    services::install_virtualization(&VIRTUALIZATION_PACKAGES, docker, podman, libvirt, install);
+}
+
+pub fn configure_power_management(service: &str) {
+    services::configure_power_management(service, install);
+}
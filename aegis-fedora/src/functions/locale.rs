@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::Write;
+use crate::internal::services::disable_service;
+use shared::exec::exec_chroot;
+use shared::files;
+use shared::returncode_eval::exec_eval;
+use shared::returncode_eval::files_eval;
+
+pub fn set_timezone(timezone: &str) {
+    exec_eval(
+        exec_chroot(
+            "ln",
+            vec![
+                "-sf".to_string(),
+                format!("/usr/share/zoneinfo/{}", timezone),
+                "/etc/localtime".to_string(),
+            ],
+        ),
+        "Set timezone",
+    );
+    exec_eval(
+        exec_chroot("hwclock", vec!["--systohc".to_string()]),
+        "Set system clock",
+    );
+}
+
+/// Keeps the hardware clock in local time, as Windows does, instead of UTC.
+pub fn set_local_rtc() {
+    exec_eval(
+        exec_chroot("hwclock", vec!["--systohc".to_string(), "--localtime".to_string()]),
+        "Set hardware clock to local time",
+    );
+}
+
+// Locales are prebuilt by glibc-all-langpacks, only the first one of "locale charset" pairs becomes LANG
+pub fn set_locale(locale: String) {
+    let lang = locale.split(' ').next().filter(|lang| !lang.is_empty()).unwrap_or("en_US.UTF-8");
+    files::create_file("/mnt/etc/locale.conf");
+    files_eval(
+        files::append_file("/mnt/etc/locale.conf", &format!("LANG={}", lang)),
+        "edit locale.conf",
+    );
+}
+
+pub fn set_keyboard(virtkeyboard: &str, x11keyboard: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // Setting keyboard layout for virtual console (TTY)
+    files::create_file("/mnt/etc/vconsole.conf");
+    files_eval(
+        files::append_file(
+            "/mnt/etc/vconsole.conf",
+            format!("KEYMAP={}", virtkeyboard).as_str(),
+        ),
+        "set keyboard layout for virtual console",
+    );
+    // Setting keyboard layout for X (GUI) environment (note: Wayland keyboard layout is managed by the used compositors)
+    files_eval(files::create_directory("/mnt/etc/X11/xorg.conf.d"), "create /mnt/etc/X11/xorg.conf.d directory");
+    let conf_content = format!(
+        r#"# Written by systemd-localed(8), read by systemd-localed and Xorg. It's
+# probably wise not to edit this file manually. Use localectl(1) to
+# instruct systemd-localed to update it.
+Section "InputClass"
+        Identifier "system-keyboard"
+        MatchIsKeyboard "on"
+        Option "XkbLayout" "{}"
+        Option "XkbModel" "pc105+inet"
+        Option "XkbOptions" "terminate:ctrl_alt_bksp"
+EndSection
+"#,
+        x11keyboard
+    );
+    let mut file = File::create("/mnt/etc/X11/xorg.conf.d/00-keyboard.conf")?;
+    file.write_all(conf_content.as_bytes())?;
+
+    Ok(())
+}
+
+/// Sets up chronyd, the NTP client of Fedora, once the system services are enabled.
+pub fn configure_ntp(client: &str, servers: &[String]) {
+    if client == "none" {
+        disable_service("chronyd");
+    } else if !servers.is_empty() {
+        set_chrony_servers(servers);
+    }
+}
+
+// Custom servers replace the pool of the distribution
+fn set_chrony_servers(servers: &[String]) {
+    files_eval(
+        files::sed_file("/mnt/etc/chrony.conf", r"(?m)^(pool|server) ", "#$0"),
+        "Comment default NTP servers",
+    );
+    for server in servers {
+        files_eval(
+            files::append_file("/mnt/etc/chrony.conf", &format!("server {} iburst", server)),
+            "Add NTP server",
+        );
+    }
+}
@@ -0,0 +1,4 @@
+pub mod base;
+pub mod locale;
+pub mod network;
+pub mod users;
@@ -0,0 +1,91 @@
+use crate::internal::install::install;
+use crate::internal::services::enable_service;
+use shared::exec::exec_chroot;
+use shared::files;
+use shared::info;
+use shared::netstorage;
+use shared::services;
+use shared::returncode_eval::{exec_eval, files_eval};
+
+pub fn set_hostname(hostname: &str) {
+    eprintln!("Setting hostname to {}", hostname);
+    files::create_file("/mnt/etc/hostname");
+    files_eval(
+        files::append_file("/mnt/etc/hostname", hostname),
+        "set hostname",
+    );
+}
+
+pub fn create_hosts() {
+    files::create_file("/mnt/etc/hosts");
+    files_eval(
+        files::append_file("/mnt/etc/hosts", "127.0.0.1     localhost"),
+        "create /etc/hosts",
+    );
+}
+
+pub fn enable_ipv6() {
+    files_eval(
+        files::append_file("/mnt/etc/hosts", "::1 localhost"),
+        "add ipv6 localhost",
+    );
+}
+
+pub fn configure_firewall(backend: &str, deny_incoming: bool, tcp_ports: &[String], udp_ports: &[String]) {
+    services::configure_firewall(backend, deny_incoming, tcp_ports, udp_ports, install);
+}
+
+/// Logs in to the network storage targets again at boot, with the node records and identity of the live system.
+/// With the root on one of them, dracut logs in from the initramfs: its network module and the iscsi or nvmf one
+/// are added, and the targets are passed on the kernel command line.
+pub fn configure_network_storage(targets: &[(String, &str, &str)], root_transport: Option<&str>) {
+    let mut packages = Vec::new();
+    if targets.iter().any(|(protocol, _, _)| protocol == "iscsi") {
+        packages.push("iscsi-initiator-utils");
+    }
+    if targets.iter().any(|(protocol, _, _)| protocol.starts_with("nvme")) {
+        packages.push("nvme-cli");
+    }
+    if packages.is_empty() {
+        return;
+    }
+    install(packages.clone());
+    for (protocol, portal, target) in targets {
+        netstorage::persist(protocol, portal, target);
+    }
+    if packages.contains(&"iscsi-initiator-utils") {
+        enable_service("iscsid");
+        enable_service("iscsi");
+    }
+    if packages.contains(&"nvme-cli") {
+        enable_service("nvmf-autoconnect");
+    }
+    let Some(transport) = root_transport else {
+        return;
+    };
+    let module = if transport == "iscsi" { "iscsi" } else { "nvmf" };
+    files_eval(files::create_directory("/mnt/etc/dracut.conf.d"), "create /mnt/etc/dracut.conf.d");
+    files_eval(
+        std::fs::write("/mnt/etc/dracut.conf.d/network-storage.conf", format!("add_dracutmodules+=\" network {} \"\n", module)),
+        "Add the dracut network storage modules",
+    );
+    let mut params = vec![String::from("rd.neednet=1"), String::from("ip=dhcp")];
+    for (protocol, portal, target) in targets.iter().filter(|(protocol, _, _)| protocol.starts_with(transport)) {
+        params.extend(netstorage::dracut_args(protocol, portal, target));
+    }
+    let params = params.join(" ");
+    info!("Kernel parameters for the root on {} storage : {}", transport, params);
+    exec_eval(
+        exec_chroot("grubby", vec![String::from("--update-kernel=ALL"), format!("--args={}", params)]),
+        "Add the network root parameters to the boot entries",
+    );
+    // Kernels installed later take their parameters from there
+    files_eval(
+        files::sed_file("/mnt/etc/default/grub", r#"(?m)^GRUB_CMDLINE_LINUX="(.*)"$"#, &format!("GRUB_CMDLINE_LINUX=\"$1 {}\"", params)),
+        "Add the network root parameters to GRUB_CMDLINE_LINUX",
+    );
+    exec_eval(
+        exec_chroot("dracut", vec![String::from("--regenerate-all"), String::from("--force")]),
+        "Regenerate the initramfs with the network storage modules",
+    );
+}
@@ -0,0 +1,70 @@
+use shared::exec::exec_chroot;
+use shared::returncode_eval::exec_eval;
+
+// Fedora's sudoers already grants the wheel group, membership is all a root user needs
+pub fn new_user(username: &str, hasroot: bool, password: &str, shell: &str) {
+    // Username cannot contain any space
+    let sanitized_username = username.replace(' ', "");
+    let shell_path = match shell {
+        "fish" => "/usr/bin/fish",
+        "zsh" => "/usr/bin/zsh",
+        &_ => "/usr/bin/bash",
+    };
+    exec_eval(
+        exec_chroot(
+            "useradd",
+            vec![
+                String::from("-m"),
+                String::from("-s"),
+                String::from(shell_path),
+                String::from("-p"),
+                format!("'{}'", password.replace('\n', "")),
+                sanitized_username.clone(),
+            ],
+        ),
+        format!("Create user {}", sanitized_username).as_str(),
+    );
+    if hasroot {
+        exec_eval(
+            exec_chroot(
+                "usermod",
+                vec![
+                    String::from("-aG"),
+                    String::from("wheel"),
+                    sanitized_username.clone(),
+                ],
+            ),
+            format!("Add user {} to wheel group", sanitized_username).as_str(),
+        );
+    }
+}
+
+/// Adds an existing user to supplementary groups.
+pub fn add_groups(username: &str, groups: &[&str]) {
+    let sanitized_username = username.replace(' ', "");
+    exec_eval(
+        exec_chroot("usermod", vec![String::from("-aG"), groups.join(","), sanitized_username.clone()]),
+        format!("Add user {} to groups {}", sanitized_username, groups.join(",")).as_str(),
+    );
+}
+
+pub fn root_pass(root_pass: &str) {
+    exec_eval(
+        exec_chroot(
+            "usermod",
+            vec![
+                String::from("--password"),
+                format!("'{}'", root_pass.replace('\n', "")),
+                String::from("root"),
+            ],
+        ),
+        "set root password",
+    );
+}
+
+pub fn lock_root() {
+    exec_eval(
+        exec_chroot("usermod", vec![String::from("--lock"), String::from("root")]),
+        "lock the root account",
+    );
+}
@@ -0,0 +1,607 @@
+use crate::internal::install::install;
+use crate::internal::services::enable_service;
+use crate::functions::*;
+use shared::args::{self, BaseSystem, Encryption, PartitionMode, WipeMethod};
+use shared::{debug, error, info, warn};
+use shared::clap::ValueEnum;
+use shared::checkpoint::{self, Checkpoint};
+use shared::cleanup;
+use shared::config_input;
+use shared::failure::{self, Category};
+use shared::firewall;
+use shared::logging;
+use shared::netstorage;
+use shared::osdetect;
+use shared::oem;
+use shared::partition;
+use shared::post_install;
+use shared::progress;
+use shared::proxy;
+use shared::serde::{self, Deserialize, Serialize};
+use shared::serde_json;
+use shared::strings::{self, crash};
+use shared::template;
+use shared::tuning;
+use shared::validation::{self, Kind, Schema};
+use std::collections::BTreeMap;
+use std::path::{PathBuf};
+
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Config {
+    partition: Partition,
+    bootloader: Bootloader,
+    locale: Locale,
+    #[serde(default)]
+    time: Time,
+    networking: Networking,
+    #[serde(default)]
+    firewall: Firewall,
+    #[serde(default)]
+    users: Vec<Users>,
+    #[serde(default)]
+    rootpass: String,
+    #[serde(default)]
+    root_locked: bool,
+    #[serde(default)]
+    bluetooth: bool,
+    #[serde(default)]
+    power_management: String,
+    #[serde(default)]
+    tuning: Tuning,
+    #[serde(default)]
+    virtualization: Virtualization,
+    extra_packages: Vec<String>,
+    #[serde(default)]
+    post_install_script: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Partition {
+    device: String,
+    mode: PartitionMode,
+    encrypt_check: bool,
+    #[serde(default)]
+    wipe: String,
+    efi: bool,
+    swap: bool,
+    swap_size: String,
+    partitions: Vec<String>,
+    #[serde(default)]
+    network_storage: Vec<NetworkStorage>,
+}
+
+// iSCSI or NVMe over Fabrics target logged in before partitioning, its disks can then be used like local ones
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct NetworkStorage {
+    protocol: String,
+    portal: String,
+    target: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Bootloader {
+    r#type: String,
+    location: String,
+    #[serde(default)]
+    os_prober: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Locale {
+    locale: Vec<String>,
+    virtkeymap: String,
+    x11keymap: String,
+    timezone: String,
+}
+
+// Container and virtual machine stacks, the first user joins their groups
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Virtualization {
+    docker: bool,
+    podman: bool,
+    libvirt: bool,
+}
+
+// Kernel tunables: a preset of sysctl values and IO scheduler rules, with sysctl overrides applied on top
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Tuning {
+    preset: String,
+    sysctl: BTreeMap<String, String>,
+}
+
+// Clock synchronisation, an empty ntp keeps the NTP client of the base system
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Time {
+    ntp: String,
+    ntp_servers: Vec<String>,
+    local_rtc: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Networking {
+    hostname: String,
+    ipv6: bool,
+}
+
+// Incoming traffic filter of the installed system, ports are single ports or "first-last" ranges
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Firewall {
+    backend: String,
+    deny_incoming: bool,
+    tcp_ports: Vec<String>,
+    udp_ports: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Users {
+    name: String,
+    password: String,
+    hasroot: bool,
+    shell: String,
+    #[serde(default)]
+    fullname: String,
+    #[serde(default)]
+    avatar: String,
+}
+
+const SCHEMA: Schema = Schema {
+    required: &[
+        ("/partition", Kind::Object),
+        ("/partition/device", Kind::String),
+        ("/partition/mode", Kind::String),
+        ("/partition/encrypt_check", Kind::Bool),
+        ("/partition/efi", Kind::Bool),
+        ("/partition/swap", Kind::Bool),
+        ("/partition/swap_size", Kind::String),
+        ("/partition/partitions", Kind::Array),
+        ("/bootloader/type", Kind::String),
+        ("/bootloader/location", Kind::String),
+        ("/locale/locale", Kind::Array),
+        ("/locale/virtkeymap", Kind::String),
+        ("/locale/x11keymap", Kind::String),
+        ("/locale/timezone", Kind::String),
+        ("/networking/hostname", Kind::String),
+        ("/networking/ipv6", Kind::Bool),
+        ("/users", Kind::Array),
+        ("/rootpass", Kind::String),
+        ("/extra_packages", Kind::Array),
+    ],
+    choices: &[
+        ("/partition/mode", &["erasedisk", "replace", "manual"]),
+        ("/bootloader/type", &["grub-efi", "grub-legacy"]),
+        ("/power_management", &["tlp", "power-profiles-daemon", "none"]),
+        ("/tuning/preset", &tuning::PRESETS),
+        ("/time/ntp", &["chrony", "none"]),
+        ("/firewall/backend", &["firewalld", "nftables", "none"]),
+        ("/partition/wipe", &["none", "quick", "discard", "secure"]),
+    ],
+};
+
+// Example written by generate-config. Every key of Config is present, "_comment" keys are ignored by the parser.
+// It must keep passing SCHEMA, except for the placeholder password hashes.
+const EXAMPLE_CONFIG: &str = r#"{
+  "_comment": "Aegis unattended config for Athena Fedora. Run 'aegis-fedora config <file> --dry' to validate it.",
+  "_comment_templates": "${NAME} in any value is replaced by a --set NAME=value, ${dmi:product_serial} and the like, or the NAME environment variable (see list-template-vars). $${NAME} is a literal ${NAME}. post_install_script is passed to the shell unchanged",
+  "partition": {
+    "_comment": "mode: EraseDisk (automatic layout on device), Replace or Manual (use the partitions list)",
+    "device": "sda",
+    "mode": "EraseDisk",
+    "_comment_encrypt_check": "Encryption is not supported on Athena Fedora yet, keep it false",
+    "encrypt_check": false,
+    "_comment_wipe": "EraseDisk only. wipe: none (new partition table only), quick (erase signatures), discard (SSD blkdiscard) or secure (zero the whole drive, slow)",
+    "wipe": "none",
+    "efi": true,
+    "swap": false,
+    "_comment_swap_size": "size like 8GiB, 8G, 8.5 GiB, 8GB (SI) or a percentage of the drive like 5%",
+    "swap_size": "8GiB",
+    "_comment_partitions": "Manual/Replace only, entries are mountpoint:blockdevice:filesystem:encrypt[:options], options being mount options like noatime,compress=zstd (EraseDisk uses noatime for root and umask=0077 for the ESP)",
+    "partitions": [
+      "/mnt/boot:/dev/sda1:vfat:false:umask=0077",
+      "/mnt:/dev/sda2:btrfs:false:noatime,compress=zstd"
+    ],
+    "_comment_network_storage": "iSCSI and NVMe over Fabrics targets to log in to before partitioning, entries are {\"protocol\": \"iscsi\", \"nvme-tcp\" or \"nvme-rdma\", \"portal\": \"host[:port]\", \"target\": IQN or NQN}. With the root on one of them, the initramfs logs in at boot",
+    "network_storage": []
+  },
+  "bootloader": {
+    "_comment": "type: grub-efi (location is the EFI directory) or grub-legacy (location is the disk). os_prober: add the other installed operating systems (see detect-os) to the boot menu",
+    "type": "grub-efi",
+    "location": "/boot",
+    "os_prober": false
+  },
+  "locale": {
+    "_comment": "locale entries are \"locale charset\" pairs, the first locale becomes LANG (all locales are prebuilt on Fedora)",
+    "locale": ["en_US.UTF-8 UTF-8"],
+    "virtkeymap": "us",
+    "x11keymap": "us",
+    "timezone": "Europe/Zurich"
+  },
+  "_comment_time": "ntp: chrony or none. ntp_servers replace the default servers. local_rtc keeps the hardware clock in local time, for dual boot with Windows",
+  "time": {
+    "ntp": "chrony",
+    "ntp_servers": [],
+    "local_rtc": false
+  },
+  "networking": {
+    "hostname": "athenaos",
+    "ipv6": false
+  },
+  "_comment_firewall": "backend: firewalld, nftables or none. With deny_incoming, only tcp_ports and udp_ports are let in, as single ports (22) or ranges (6000-6010)",
+  "firewall": {
+    "backend": "firewalld",
+    "deny_incoming": true,
+    "tcp_ports": [],
+    "udp_ports": []
+  },
+  "_comment_users": "password is a crypt hash, generate it with 'openssl passwd -6'. fullname (shown by the display manager) is optional",
+  "users": [
+    {
+      "name": "athena",
+      "password": "REPLACE_WITH_HASH",
+      "hasroot": true,
+      "shell": "bash",
+      "fullname": "Athena User"
+    }
+  ],
+  "rootpass": "REPLACE_WITH_HASH",
+  "_comment_root_locked": "Lock the root account instead of setting rootpass (which can then be left out), administration goes through the users with hasroot",
+  "root_locked": false,
+  "_comment_bluetooth": "Installs and enables the Bluetooth stack",
+  "bluetooth": false,
+  "_comment_power_management": "tlp, power-profiles-daemon or none, for laptops. GNOME and KDE Plasma expect power-profiles-daemon",
+  "power_management": "none",
+  "_comment_tuning": "preset: default (kernel defaults), low-latency, vm-guest or server. The presets set swappiness, cache pressure and network sysctls and pick the IO scheduler of each drive type. sysctl holds overrides, i.e., {\"vm.swappiness\": \"20\"}",
+  "tuning": {
+    "preset": "default",
+    "sysctl": {}
+  },
+  "_comment_virtualization": "Container and virtual machine stacks. The first user joins the docker and libvirt groups",
+  "virtualization": {
+    "docker": false,
+    "podman": false,
+    "libvirt": false
+  },
+  "_comment_extra_packages": "Fedora package names, installed with dnf",
+  "extra_packages": [],
+  "_comment_post_install_script": "Shell script run as root inside the installed system as the last step: the script itself, an http(s) URL or a file path on the live system. A failing script does not fail the install",
+  "post_install_script": ""
+}
+"#;
+
+pub fn generate_config(base: Option<BaseSystem>, output: Option<PathBuf>) -> i32 {
+    if base.is_some_and(|base| base != BaseSystem::Fedora) {
+        error!("This backend generates Athena Fedora configs, use aegis-arch or aegis-nix generate-config for the other bases");
+        return 1;
+    }
+    config_input::write_example(EXAMPLE_CONFIG, output.as_deref());
+    0
+}
+
+pub fn read_config(configpath: PathBuf, overrides: &[(String, String)], dry: bool, resume: bool, oem: bool) -> i32 {
+    let data = std::fs::read_to_string(&configpath);
+    match &data {
+        Ok(_) => {
+            debug!("[ \x1b[2;1;32mOK\x1b[0m ] Read config file {configpath:?}");
+        }
+        Err(e) => {
+            crash(
+                format!("Read config file {configpath:?}  ERROR: {}", e),
+                e.raw_os_error().unwrap(),
+            );
+        }
+    }
+    let config: std::result::Result<serde_json::Value, serde_json::Error> =
+        serde_json::from_str(&data.unwrap());
+    match &config {
+        Ok(config) => {
+            info!("Configuration set:\n{}", serde_json::to_string_pretty(&config_input::redact(config)).unwrap());
+            debug!("[ \x1b[2;1;32mOK\x1b[0m ] Parse config file {configpath:?}",);
+        }
+        Err(e) => {
+            crash(format!("Parse config file {configpath:?}  ERROR: {}", e), 1);
+        }
+    }
+    let mut config = config.unwrap();
+    let mut problems = template::substitute(&mut config, overrides);
+    problems.extend(validation::validate(&config, &SCHEMA));
+    if oem {
+        oem::skip_credentials(&mut problems);
+    }
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("{}", problem);
+        }
+        error!("Config file {configpath:?} has {} problem(s)", problems.len());
+        return failure::report("Invalid config file", 1, Some(Category::InvalidConfig));
+    }
+    for warning in validation::warnings(&config) {
+        warn!("{}", warning);
+    }
+    let config_hash = checkpoint::fingerprint(&config.to_string());
+    let config: Config = match serde_json::from_value(config) {
+        Ok(config) => config,
+        Err(e) => crash(format!("Parse config file {configpath:?}  ERROR: {}", e), 1),
+    };
+    let mut partitions: Vec<args::Partition> = Vec::new();
+    for partition in &config.partition.partitions {
+        partitions.push(
+            args::parse_partitions(partition)
+                .unwrap_or_else(|e| crash(format!("Partition \"{}\": {}", partition, e), 1)),
+        );
+    }
+    // The initramfs and bootloader setup for LUKS is only written for the Arch and Nix bases so far
+    if config.partition.encrypt_check || partitions.iter().any(|partition| partition.encrypt) {
+        error!("Encryption is not supported on Athena Fedora yet");
+        return failure::report("Encryption is not supported on Athena Fedora yet", 1, Some(Category::InvalidConfig));
+    }
+    let device = PathBuf::from("/dev/").join(config.partition.device.as_str());
+    let wipe = WipeMethod::from_str(&config.partition.wipe, true).unwrap_or(WipeMethod::None);
+    // Spell out what is about to be destroyed, the dry run shows it as well
+    for line in partition::destructive_summary(&device, config.partition.mode, Encryption::None, wipe, &partitions) {
+        warn!("{}", line);
+    }
+    let network_storage: Vec<(String, &str, &str)> = config
+        .partition
+        .network_storage
+        .iter()
+        .map(|storage| (storage.protocol.to_lowercase(), storage.portal.as_str(), storage.target.as_str()))
+        .collect();
+    for (protocol, portal, target) in &network_storage {
+        info!("Network storage : {} target {} at {}", protocol, target, portal);
+    }
+    let firewall_backend = config.firewall.backend.to_lowercase();
+    info!("{}", firewall::summary(&firewall_backend, config.firewall.deny_incoming, &config.firewall.tcp_ports, &config.firewall.udp_ports));
+    info!("Bluetooth : {}", if config.bluetooth { "bluez" } else { "false" });
+    let virtualization = &config.virtualization;
+    info!("Virtualization : docker {}, podman {}, libvirt {}", virtualization.docker, virtualization.podman, virtualization.libvirt);
+    let mut virtualization_groups = Vec::new();
+    if virtualization.docker {
+        virtualization_groups.push("docker");
+    }
+    if virtualization.libvirt {
+        virtualization_groups.push("libvirt");
+    }
+    let group_user = config.users.first().filter(|_| !oem).map(|user| user.name.as_str());
+    if !virtualization_groups.is_empty() {
+        match group_user {
+            Some(user) => info!("User {} joins the groups {}", user, virtualization_groups.join(", ")),
+            None => warn!("No user is created by the install, nobody joins the groups {}", virtualization_groups.join(", ")),
+        }
+    }
+    let power_management = config.power_management.to_lowercase();
+    info!("Power management : {}", if power_management.is_empty() { "none" } else { &power_management });
+    if (power_management.is_empty() || power_management == "none") && osdetect::is_laptop() {
+        warn!("This machine runs on a battery, set power_management to tlp or power-profiles-daemon to manage it");
+    }
+    let tuning_preset = if config.tuning.preset.is_empty() { String::from("default") } else { config.tuning.preset.to_lowercase() };
+    let tuning_values = tuning::sysctl_values(&tuning_preset, &config.tuning.sysctl);
+    for line in tuning::summary(&tuning_preset, &tuning_values) {
+        info!("{}", line);
+    }
+    let host_tools = base::check_host_tools();
+    if dry {
+        if let Err(e) = &host_tools {
+            warn!("{}", e);
+        }
+        info!("Config file {configpath:?} is valid");
+        return 0;
+    }
+    if let Err(e) = host_tools {
+        error!("{}", e);
+        return failure::report(&e, 1, Some(Category::Other));
+    }
+    // os-prober mounts every partition to look for other systems, a dry run leaves the disks alone
+    if !config.time.local_rtc {
+        if let Some(windows) = osdetect::find_windows() {
+            warn!("{} found on {}, it keeps the hardware clock in local time: set time/local_rtc to avoid a clock offset between the systems", windows.name, windows.device);
+        }
+    }
+    let mut checkpoint = Checkpoint::start(config_hash, resume);
+    progress::phase(5, "Partitioning drives");
+    if checkpoint.should_run("partitioning", false) {
+        info!("Block device to use : {}", config.partition.device);
+        info!("Partitioning mode : {:?}", config.partition.mode);
+        info!("Partitioning for EFI : {}", config.partition.efi);
+        info!("Swap partition : {}", config.partition.swap);
+        // Release whatever a previous failed install left mounted or opened on the drives
+        cleanup::cleanup();
+        for (protocol, portal, target) in &network_storage {
+            if let Err(e) = netstorage::connect(protocol, portal, target) {
+                error!("{}", e);
+                return failure::report(&e, 1, Some(Category::Partitioning));
+            }
+        }
+        if config.partition.mode == PartitionMode::EraseDisk {
+            partition::wipe_device(&device, wipe);
+        }
+        partition::partition(
+            device.clone(),
+            config.partition.mode,
+            Encryption::None,
+            config.partition.efi,
+            config.partition.swap,
+            config.partition.swap_size,
+            false,
+            &mut partitions,
+        );
+        checkpoint.complete("partitioning");
+    }
+    eprintln!();
+
+    /********** INSTALLATION **********/
+    progress::phase(15, "Installing base system");
+    if checkpoint.should_run("base", true) {
+        base::install_base_packages();
+        checkpoint.complete("base");
+    }
+    progress::phase(30, "Installing packages");
+    if checkpoint.should_run("packages", true) {
+        let mut package_set: Vec<&str> = Vec::new();
+        if config.bootloader.os_prober {
+            package_set.push("os-prober");
+        }
+        for user in &config.users {
+            match user.shell.to_lowercase().as_str() {
+                "fish" => package_set.push("fish"),
+                "zsh" => package_set.push("zsh"),
+                _ => {}
+            }
+        }
+        if config.bluetooth {
+            package_set.push("bluez");
+        }
+        base::install_packages(package_set);
+        checkpoint.complete("packages");
+    }
+
+    /**************************/
+    eprintln!();
+    /********** CONFIGURATION **********/
+    progress::phase(60, "Generating fstab");
+    if checkpoint.should_run("fstab", true) {
+        base::genfstab();
+        if !network_storage.is_empty() {
+            netstorage::add_netdev_options("/mnt/etc/fstab");
+        }
+        checkpoint.complete("fstab");
+    }
+
+    /*    BOOTLOADER CONFIG     */
+    progress::phase(62, "Configuring bootloader");
+    if checkpoint.should_run("bootloader", true) {
+        info!("Configuring bootloader : {}", config.bootloader.r#type);
+        info!("Configuring bootloader to : {}", config.bootloader.location);
+        if config.bootloader.r#type == "grub-efi" {
+            base::configure_bootloader_efi(PathBuf::from(&config.bootloader.location), config.bootloader.os_prober);
+        } else if config.bootloader.r#type == "grub-legacy" {
+            base::configure_bootloader_legacy(PathBuf::from(&config.bootloader.location), config.bootloader.os_prober);
+        }
+        checkpoint.complete("bootloader");
+    }
+    /**************************/
+    eprintln!();
+    /*         LOCALES        */
+    progress::phase(66, "Configuring locales");
+    if checkpoint.should_run("locales", true) {
+        info!("Adding Locales : {:?}", config.locale.locale);
+        locale::set_locale(config.locale.locale.join(" "));
+        info!("Using console keymap : {}", config.locale.virtkeymap);
+        info!("Using x11 keymap : {}", config.locale.x11keymap);
+        locale::set_keyboard(config.locale.virtkeymap.as_str(), config.locale.x11keymap.as_str())
+            .unwrap_or_else(|e| {
+                eprintln!("Error setting keyboard configuration: {}", e);
+            });
+        info!("Setting timezone : {}", config.locale.timezone);
+        locale::set_timezone(config.locale.timezone.as_str());
+        if config.time.local_rtc {
+            info!("Hardware clock in local time");
+            locale::set_local_rtc();
+        }
+        checkpoint.complete("locales");
+    }
+    eprintln!();
+    progress::phase(70, "Configuring network");
+    if checkpoint.should_run("network", true) {
+        info!("Hostname : {}", config.networking.hostname);
+        network::set_hostname(config.networking.hostname.as_str());
+        network::create_hosts();
+        info!("Enabling ipv6 : {}", config.networking.ipv6);
+        if config.networking.ipv6 {
+            network::enable_ipv6();
+        }
+        proxy::persist();
+        checkpoint.complete("network");
+    }
+    /**************************/
+    eprintln!();
+    /*    EXTRA PACKAGES    */
+    progress::phase(78, "Installing extra packages");
+    if checkpoint.should_run("extra_packages", true) {
+        info!("Extra packages : {:?}", config.extra_packages);
+        install(config.extra_packages.iter().map(String::as_str).collect());
+        checkpoint.complete("extra_packages");
+    }
+    /**************************/
+    eprintln!();
+    /*      USER CONFIG      */
+    progress::phase(90, "Creating users");
+    if checkpoint.should_run("users", true) {
+        if oem {
+            oem::install_firstboot();
+        } else {
+            for user in &config.users {
+                info!("Creating user : {}", user.name);
+                info!("Enabling root for user : {}", user.hasroot);
+                info!("Setting user shell : {}", user.shell);
+                users::new_user(user.name.as_str(), user.hasroot, user.password.as_str(), user.shell.to_lowercase().as_str());
+                if !user.fullname.is_empty() {
+                    strings::set_full_name(user.name.as_str(), user.fullname.as_str());
+                }
+                if !user.avatar.is_empty() {
+                    warn!("Avatars are only set on Athena Arch, {} keeps the default one", user.name);
+                }
+            }
+            if config.root_locked {
+                users::lock_root();
+            } else {
+                users::root_pass(config.rootpass.as_str());
+            }
+        }
+        checkpoint.complete("users");
+    }
+    /**************************/
+    eprintln!();
+    /*    ENABLE SERVICES    */
+    progress::phase(95, "Enabling system services");
+    if checkpoint.should_run("services", true) {
+        info!("Enabling system services...");
+        base::enable_system_services();
+        if config.bluetooth {
+            enable_service("bluetooth");
+        }
+        base::configure_power_management(&power_management);
+        tuning::write_config(&tuning_values, tuning::io_scheduler(&tuning_preset));
+        base::install_virtualization(virtualization.docker, virtualization.podman, virtualization.libvirt);
+        if let Some(user) = group_user.filter(|_| !virtualization_groups.is_empty()) {
+            users::add_groups(user, &virtualization_groups);
+        }
+        locale::configure_ntp(&config.time.ntp.to_lowercase(), &config.time.ntp_servers);
+        network::configure_network_storage(&network_storage, netstorage::root_transport(&device, config.partition.mode, &partitions));
+        network::configure_firewall(&firewall_backend, config.firewall.deny_incoming, &config.firewall.tcp_ports, &config.firewall.udp_ports);
+        checkpoint.complete("services");
+    }
+    /**************************/
+    eprintln!();
+    /*    POST-INSTALL SCRIPT    */
+    if !config.post_install_script.trim().is_empty() {
+        progress::phase(96, "Running the post-install script");
+        if checkpoint.should_run("post_install", true) {
+            post_install::run(&config.post_install_script, &["arch-chroot", "/mnt"]);
+            checkpoint.complete("post_install");
+        }
+    }
+    /**************************/
+    eprintln!();
+    progress::phase(98, "Finalizing installation");
+    checkpoint.finish();
+    logging::persist_log("/mnt/var/log/aegis-install.log");
+    if config.bootloader.r#type == "grub-efi" {
+        partition::umount("/mnt/boot");
+    }
+    partition::umount("/mnt/home");
+    partition::umount("/mnt");
+    progress::phase(100, "Installation finished");
+    eprintln!("Installation finished! You may reboot now!");
+    0
+}
@@ -0,0 +1,49 @@
+use shared::exec::{RunError, RunSpec};
+use shared::failure;
+use shared::{debug, error};
+use std::time::Duration;
+
+// A hung mirror connection must not freeze the install forever
+const INSTALL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+const INSTALL_RETRIES: u32 = 3;
+
+/// Installs packages into /mnt with the host dnf, the target release is the one of the live medium.
+/// dnf picks another mirror by itself when one fails, a failed run is simply retried.
+pub fn install(pkgs: Vec<&str>) {
+    if pkgs.is_empty() {
+        debug!("No package to install");
+        return;
+    }
+    let result = RunSpec::new("dnf")
+        .args([
+            "--installroot=/mnt",
+            "--use-host-config",
+            &format!("--releasever={}", release_version()),
+            "--assumeyes",
+            "install",
+        ])
+        .args(&pkgs)
+        .prefix("dnf")
+        .timeout(INSTALL_TIMEOUT)
+        .retries(INSTALL_RETRIES, Duration::from_secs(5))
+        .run();
+
+    if let Err(e) = result {
+        if let RunError::Timeout(_) = e {
+            failure::command_timed_out();
+        }
+        error!("The package manager failed: {}", e);
+    }
+}
+
+// The installroot has no release yet, dnf must be told which one to install
+fn release_version() -> String {
+    let output = std::process::Command::new("rpm").args(["-E", "%fedora"]).output();
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        _ => {
+            error!("Unable to read the Fedora release of the live medium, using rawhide");
+            String::from("rawhide")
+        }
+    }
+}
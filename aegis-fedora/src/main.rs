@@ -0,0 +1,156 @@
+mod functions;
+mod internal;
+use crate::functions::*;
+use shared::args::{BootloaderSubcommand, Command, Cli, Encryption, PartitionMode, UsersSubcommand};
+use shared::cleanup;
+use shared::clap::Parser;
+use shared::config_input;
+use shared::{debug, error};
+use shared::exec::check_if_root;
+use shared::human_panic;
+use shared::logging;
+use shared::osdetect;
+use shared::partition;
+use shared::progress;
+use shared::proxy;
+use shared::serde_json;
+use shared::strings::crash;
+use shared::template;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    human_panic::setup_panic!();
+    let cli = Cli::parse();
+    check_if_root(&cli);
+    let log_file_path = logging::resolve_log_file(cli.log_file);
+    logging::init(logging::level(cli.verbose, cli.quiet), cli.log_format, &log_file_path);
+    debug!("verbose: {}", cli.verbose);
+    proxy::apply(&cli.proxy);
+    if cli.show_all_disks {
+        partition::show_all_disks();
+    }
+    let works_in_chroot = matches!(
+        cli.command,
+        Command::InstallPackages(_)
+            | Command::GenFstab
+            | Command::Bootloader { .. }
+            | Command::Locale(_)
+            | Command::Networking(_)
+            | Command::Users { .. }
+            | Command::EnableServices
+    );
+    if works_in_chroot {
+        if let Err(e) = base::check_host_tools() {
+            error!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+    match cli.command {
+        Command::Partition(args) => {
+            // Same as the config reader: the initramfs and bootloader setup for LUKS is not written for Fedora
+            if args.encrypt_check || args.encrypt_boot || args.partitions.iter().any(|partition| partition.encrypt) {
+                crash("Encryption is not supported on Athena Fedora yet", 1);
+            }
+            cleanup::cleanup();
+            if args.mode == PartitionMode::EraseDisk {
+                partition::wipe_device(&args.device, args.wipe);
+            }
+            let mut partitions = args.partitions;
+            partition::partition(
+                args.device,
+                args.mode,
+                Encryption::None,
+                args.efi,
+                args.swap,
+                args.swap_size,
+                false,
+                &mut partitions,
+            );
+        }
+        Command::InstallPackages(_) => {
+            // Fedora ships a single kernel flavour, the kernel argument is not used
+            base::install_base_packages();
+            base::install_packages(Vec::new());
+        }
+        Command::GenFstab => {
+            base::genfstab();
+        }
+        Command::Bootloader { subcommand } => match subcommand {
+            BootloaderSubcommand::GrubEfi { efidir, os_prober } => {
+                base::configure_bootloader_efi(efidir, os_prober);
+            }
+            BootloaderSubcommand::GrubLegacy { device, os_prober } => {
+                base::configure_bootloader_legacy(device, os_prober);
+            }
+        }
+        Command::Locale(args) => {
+            locale::set_locale(args.locales.join(" "));
+            locale::set_keyboard(&args.virtkeyboard, &args.x11keyboard).unwrap_or_else(|e| {
+                eprintln!("Error setting keyboard: {}", e);
+            });
+            locale::set_timezone(&args.timezone);
+        }
+        Command::Networking(args) => {
+            network::create_hosts();
+            if args.ipv6 {
+                network::enable_ipv6()
+            }
+            network::set_hostname(&args.hostname);
+        }
+        Command::Users { subcommand } => match subcommand {
+            UsersSubcommand::NewUser(args) => {
+                users::new_user(
+                    &args.username,
+                    args.hasroot,
+                    &args.password,
+                    &args.shell,
+                );
+            }
+            UsersSubcommand::RootPass { password } => {
+                users::root_pass(&password);
+            }
+        },
+        Command::Config { config, config_sha256, fetch_timeout, overrides, dry, progress_json, resume, oem, jobs: _ } => {
+            if progress_json {
+                progress::enable_json();
+            }
+            let (config, _downloaded) = config_input::resolve(&config, config_sha256.as_deref(), fetch_timeout);
+            let exit_code = internal::config::read_config(config, &overrides, dry, resume, oem);
+            progress::finish(exit_code);
+            if exit_code != 0 {
+                return ExitCode::from(exit_code as u8);
+            }
+        }
+        Command::GenerateConfig { base, output } => {
+            let exit_code = internal::config::generate_config(base, output);
+            if exit_code != 0 {
+                return ExitCode::from(exit_code as u8);
+            }
+        }
+        Command::ListTemplateVars { overrides } => {
+            template::list_vars(&overrides);
+        }
+        Command::EnableServices => {
+            base::enable_system_services();
+        }
+        Command::DetectOs => {
+            println!("{}", serde_json::to_string_pretty(&osdetect::detect()).unwrap());
+        }
+        Command::ImportSettings { device } => match osdetect::import_settings(&device) {
+            Ok(settings) => println!("{}", serde_json::to_string_pretty(&settings).unwrap()),
+            Err(e) => {
+                error!("{}", e);
+                return ExitCode::from(1);
+            }
+        },
+        Command::Cleanup => {
+            cleanup::cleanup();
+        }
+        // Athena desktops, themes and tools are packaged for Arch and Nix only
+        _ => {
+            error!("This command is not supported on Athena Fedora");
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -1,7 +1,9 @@
 use crate::internal::hardware;
+use shared::encrypt;
 use shared::exec::exec;
 use shared::files;
-use shared::info;
+use shared::secureboot;
+use shared::{info, warn};
 use shared::returncode_eval::exec_eval;
 use shared::returncode_eval::files_eval;
 use shared::strings::crash;
@@ -108,6 +110,435 @@ pub fn install_bootloader_efi(efidir: PathBuf) {
     }
 }
 
+fn import_boot_module(name: &str, module: &str) {
+    import_module("modules/boot", name, module);
+}
+
+/// Writes a module to `dir`, relative to /etc/nixos, and imports it from configuration.nix.
+pub fn import_module(dir: &str, name: &str, module: &str) {
+    files_eval(
+        std::fs::write(format!("/mnt/etc/nixos/{}/{}", dir, name), module),
+        &format!("Write {} module", name),
+    );
+    files_eval(
+        files::sed_file(
+            "/mnt/etc/nixos/configuration.nix",
+            r"imports = \[",
+            &format!("$0\n    ./{}/{}", dir, name),
+        ),
+        &format!("Import {} module", name),
+    );
+}
+
+/// Enables the selected container and virtual machine stacks, `username` joins their groups.
+pub fn install_virtualization(docker: bool, podman: bool, libvirt: bool, username: Option<&str>, groups: &[&str]) {
+    let mut settings = Vec::new();
+    if docker {
+        settings.push(String::from("virtualisation.docker.enable = true;"));
+    }
+    if podman {
+        settings.push(String::from("virtualisation.podman.enable = true;"));
+        if !docker {
+            settings.push(String::from("virtualisation.podman.dockerCompat = true;"));
+        }
+    }
+    if libvirt {
+        settings.push(String::from("virtualisation.libvirtd.enable = true;"));
+        settings.push(String::from("programs.virt-manager.enable = true;"));
+    }
+    if settings.is_empty() {
+        return;
+    }
+    if let Some(username) = username.filter(|_| !groups.is_empty()) {
+        let groups: Vec<String> = groups.iter().map(|group| format!("\"{}\"", group)).collect();
+        settings.push(format!("users.users.\"{}\".extraGroups = [ {} ];", username.replace(' ', ""), groups.join(" ")));
+    }
+    let module = format!("{{ ... }}:\n{{\n  {}\n}}\n", settings.join("\n  "));
+    files_eval(files::create_directory("/mnt/etc/nixos/hosts/virtualisation"), "Create /mnt/etc/nixos/hosts/virtualisation");
+    import_module("hosts/virtualisation", "default.nix", &module);
+}
+
+/// Takes timeline snapshots of the root subvolume with snapper, keeping the last `hourly` and `daily` ones.
+pub fn install_snapper(hourly: u32, daily: u32) {
+    // The EraseDisk layout mounts @snapshots there, else snapper keeps them in a nested subvolume of @
+    if !std::path::Path::new("/mnt/.snapshots").exists() {
+        exec_eval(
+            exec("btrfs", vec![String::from("subvolume"), String::from("create"), String::from("/mnt/.snapshots")]),
+            "create /.snapshots as btrfs subvolume",
+        );
+    }
+    let module = format!(
+        r#"{{ ... }}:
+{{
+  services.snapper.configs.root = {{
+    SUBVOLUME = "/";
+    TIMELINE_CREATE = true;
+    TIMELINE_CLEANUP = true;
+    TIMELINE_LIMIT_HOURLY = {hourly};
+    TIMELINE_LIMIT_DAILY = {daily};
+    TIMELINE_LIMIT_WEEKLY = 0;
+    TIMELINE_LIMIT_MONTHLY = 0;
+    TIMELINE_LIMIT_YEARLY = 0;
+  }};
+}}
+"#
+    );
+    files_eval(files::create_directory("/mnt/etc/nixos/hosts/snapshots"), "Create /mnt/etc/nixos/hosts/snapshots");
+    import_module("hosts/snapshots", "default.nix", &module);
+}
+
+/// Revision of nixpkgs the live system was built from, the installed system starts from the same packages.
+fn installer_nixpkgs_revision() -> Option<String> {
+    let output = std::process::Command::new("nixos-version").arg("--revision").output().ok()?;
+    let revision = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !revision.is_empty() && revision.chars().all(|c| c.is_ascii_hexdigit())).then_some(revision)
+}
+
+/// Writes /etc/nixos/flake.nix with `hostname` as its nixosConfigurations output. configuration.nix stays the
+/// single module, so the desktop, design and hardware modules it imports are the ones of the non-flake install.
+/// With `home_manager`, `impermanence` and `secure_boot` the flake also brings their NixOS modules, following the
+/// same nixpkgs. Inputs are locked by flake.lock, so pure evaluation never fetches an unhashed archive.
+pub fn install_flake(hostname: &str, home_manager: bool, impermanence: bool, secure_boot: bool) {
+    let nixpkgs = match installer_nixpkgs_revision() {
+        Some(revision) => {
+            info!("Pinning nixpkgs to the installer revision {}", revision);
+            format!("github:NixOS/nixpkgs/{}", revision)
+        }
+        None => {
+            warn!("Unable to read the nixpkgs revision of the installer, the flake follows nixos-unstable");
+            String::from("github:NixOS/nixpkgs/nixos-unstable")
+        }
+    };
+    files_eval(
+        std::fs::write("/mnt/etc/nixos/flake.nix", flake_nix(&nixpkgs, hostname, home_manager, impermanence, secure_boot)),
+        "Write flake.nix",
+    );
+    files_eval(files::create_directory("/mnt/etc/nixos/hosts/flake"), "Create /mnt/etc/nixos/hosts/flake");
+    // nixos-rebuild --flake needs them on the installed system, and the registry entry keeps nix shell on the pinned nixpkgs
+    import_module(
+        "hosts/flake",
+        "default.nix",
+        "{ inputs, ... }:\n{\n  nix.settings.experimental-features = [ \"nix-command\" \"flakes\" ];\n  nix.registry.nixpkgs.flake = inputs.nixpkgs;\n}\n",
+    );
+}
+
+/// Content of flake.nix, `nixpkgs` is the flake reference of the nixpkgs input.
+pub fn flake_nix(nixpkgs: &str, hostname: &str, home_manager: bool, impermanence: bool, secure_boot: bool) -> String {
+    let mut extra_inputs = String::new();
+    let mut extra_modules = String::new();
+    if home_manager {
+        extra_inputs.push_str("\n    home-manager.url = \"github:nix-community/home-manager\";\n    home-manager.inputs.nixpkgs.follows = \"nixpkgs\";");
+        extra_modules.push_str(" inputs.home-manager.nixosModules.home-manager");
+    }
+    if impermanence {
+        extra_inputs.push_str("\n    impermanence.url = \"github:nix-community/impermanence\";");
+        extra_modules.push_str(" inputs.impermanence.nixosModules.impermanence");
+    }
+    if secure_boot {
+        extra_inputs.push_str(&format!(
+            "\n    lanzaboote.url = \"github:nix-community/lanzaboote/{}\";\n    lanzaboote.inputs.nixpkgs.follows = \"nixpkgs\";",
+            LANZABOOTE_RELEASE
+        ));
+        extra_modules.push_str(" inputs.lanzaboote.nixosModules.lanzaboote");
+    }
+    format!(
+        r#"{{
+  description = "Athena OS";
+
+  inputs = {{
+    nixpkgs.url = "{nixpkgs}";{extra_inputs}
+  }};
+
+  outputs = {{ nixpkgs, ... }}@inputs: {{
+    nixosConfigurations."{hostname}" = nixpkgs.lib.nixosSystem {{
+      specialArgs = {{ inherit inputs; }};
+      modules = [ ./configuration.nix{extra_modules} ];
+    }};
+  }};
+}}
+"#
+    )
+}
+
+/// Whether the Athena configuration already imports the home-manager NixOS module, importing it twice fails the evaluation.
+pub fn home_manager_imported() -> bool {
+    std::process::Command::new("grep")
+        .args(["-rqE", r"home-manager/nixos|nixosModules\.home-manager", "/mnt/etc/nixos"])
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+// State kept across boots with the ephemeral root, bind mounted from /persist
+const PERSISTED_DIRECTORIES: [&str; 7] = [
+    "/etc/nixos",
+    "/etc/NetworkManager/system-connections",
+    "/var/lib/nixos",
+    "/var/lib/systemd",
+    "/var/lib/bluetooth",
+    "/var/log",
+    "/home",
+];
+const PERSISTED_FILES: [&str; 5] = [
+    "/etc/machine-id",
+    "/etc/ssh/ssh_host_ed25519_key",
+    "/etc/ssh/ssh_host_ed25519_key.pub",
+    "/etc/ssh/ssh_host_rsa_key",
+    "/etc/ssh/ssh_host_rsa_key.pub",
+];
+
+/// Persists the state of the ephemeral root with the impermanence module, a flake input.
+pub fn install_impermanence() {
+    let quote = |paths: &[&str]| paths.iter().map(|path| format!("      \"{}\"", path)).collect::<Vec<String>>().join("\n");
+    let module = format!(
+        r#"{{ ... }}:
+{{
+  fileSystems."/persist".neededForBoot = true;
+  environment.persistence."/persist" = {{
+    hideMounts = true;
+    directories = [
+{directories}
+    ];
+    files = [
+{files}
+    ];
+  }};
+}}
+"#,
+        directories = quote(&PERSISTED_DIRECTORIES),
+        files = quote(&PERSISTED_FILES),
+    );
+    files_eval(files::create_directory("/mnt/etc/nixos/hosts/impermanence"), "Create /mnt/etc/nixos/hosts/impermanence");
+    import_module("hosts/impermanence", "default.nix", &module);
+}
+
+/// Copies the persisted paths written by the install to /mnt/persist, the tmpfs root they are on is gone at reboot.
+pub fn persist_state() {
+    info!("Moving the state of the ephemeral root to /persist");
+    for path in PERSISTED_DIRECTORIES.iter().chain(PERSISTED_FILES.iter()) {
+        let source = format!("/mnt{}", path);
+        if !std::path::Path::new(&source).exists() {
+            continue;
+        }
+        let parent = std::path::Path::new(path).parent().map_or(String::new(), |parent| parent.to_string_lossy().to_string());
+        files_eval(files::create_directory(&format!("/mnt/persist{}", parent)), &format!("Create /mnt/persist{}", parent));
+        exec_eval(
+            exec("cp", vec![String::from("-a"), source, format!("/mnt/persist{}", parent)]),
+            &format!("Persist {}", path),
+        );
+    }
+}
+
+/// Sets the sysctl values with boot.kernel.sysctl, and the IO scheduler rules with services.udev.extraRules.
+pub fn install_tuning(values: &std::collections::BTreeMap<String, String>, io_scheduler: bool) {
+    if values.is_empty() && !io_scheduler {
+        return;
+    }
+    let mut settings: Vec<String> = values
+        .iter()
+        .map(|(key, value)| {
+            // Numbers stay numbers, the rest (i.e., the qdisc) becomes a string. The chosen values win over the Athena ones
+            let value = if value.parse::<i64>().is_ok() { value.clone() } else { format!("\"{}\"", value.replace('"', "\\\"")) };
+            format!("boot.kernel.sysctl.\"{}\" = lib.mkForce {};", key, value)
+        })
+        .collect();
+    if io_scheduler {
+        settings.push(format!("services.udev.extraRules = ''\n{}  '';", shared::tuning::IO_SCHEDULER_RULES.lines().map(|line| format!("    {}\n", line)).collect::<String>()));
+    }
+    let module = format!("{{ lib, ... }}:\n{{\n  {}\n}}\n", settings.join("\n  "));
+    files_eval(files::create_directory("/mnt/etc/nixos/hosts/tuning"), "Create /mnt/etc/nixos/hosts/tuning");
+    import_module("hosts/tuning", "default.nix", &module);
+}
+
+/// Replaces the PipeWire audio server of Athena by PulseAudio.
+pub fn install_pulseaudio() {
+    files_eval(files::create_directory("/mnt/etc/nixos/hosts/hardware"), "Create /mnt/etc/nixos/hosts/hardware");
+    import_module(
+        "hosts/hardware",
+        "audio.nix",
+        "{ lib, ... }:\n{\n  services.pipewire.enable = lib.mkForce false;\n  services.pulseaudio.enable = true;\n}\n",
+    );
+}
+
+/// Enables the power management service of a laptop. GNOME turns power-profiles-daemon on by default, it conflicts with TLP.
+pub fn install_power_management(service: &str) {
+    let settings = match service {
+        "tlp" => "services.tlp.enable = true;\n  services.power-profiles-daemon.enable = false;",
+        "power-profiles-daemon" => "services.power-profiles-daemon.enable = true;",
+        _ => return,
+    };
+    files_eval(files::create_directory("/mnt/etc/nixos/hosts/hardware"), "Create /mnt/etc/nixos/hosts/hardware");
+    import_module("hosts/hardware", "power.nix", &format!("{{ ... }}:\n{{\n  {}\n}}\n", settings));
+}
+
+// The ESP is mounted at /efi, /boot lives inside the LUKS container that GRUB unlocks itself.
+// The LUKS and LVM devices are picked up by nixos-generate-config.
+const CRYPTODISK_MODULE: &str = r#"{ lib, ... }:
+{
+  boot.loader.grub.enableCryptodisk = true;
+  boot.loader.efi.efiSysMountPoint = lib.mkForce "/efi";
+}
+"#;
+
+/// Lets GRUB boot from the encrypted /boot of the encrypt-everything-but-ESP layout.
+pub fn install_cryptodisk() {
+    info!("Set GRUB cryptodisk for the encrypted /boot.");
+    import_boot_module("cryptodisk.nix", CRYPTODISK_MODULE);
+}
+
+/// Adds the mount options of each target mountpoint to its fileSystems entry.
+pub fn install_mount_options(mount_options: &[(String, String)]) {
+    if mount_options.is_empty() {
+        return;
+    }
+    let mut module = String::from("{ ... }:\n{\n");
+    for (mountpoint, options) in mount_options {
+        let options: Vec<String> = options.split(',').map(|option| format!("\"{}\"", option)).collect();
+        module.push_str(&format!("  fileSystems.\"{}\".options = [ {} ];\n", mountpoint, options.join(" ")));
+    }
+    module.push_str("}\n");
+    import_boot_module("mount-options.nix", &module);
+}
+
+/// Sets the initrd like the mkinitcpio HOOKS and MODULES of aegis-arch: the systemd initrd unlocks the LUKS
+/// partitions, the extra modules are loaded before the root filesystem is mounted.
+pub fn install_initrd(encrypted: bool, extra_modules: &[String]) {
+    if !encrypted && extra_modules.is_empty() {
+        return;
+    }
+    let mut module = String::from("{ ... }:\n{\n");
+    if encrypted {
+        module.push_str("  boot.initrd.systemd.enable = true;\n");
+    }
+    if !extra_modules.is_empty() {
+        info!("Initrd modules : {}", extra_modules.join(" "));
+        let modules: Vec<String> = extra_modules.iter().map(|name| format!("\"{}\"", name)).collect();
+        module.push_str(&format!("  boot.initrd.kernelModules = [ {} ];\n", modules.join(" ")));
+    }
+    module.push_str("}\n");
+    import_boot_module("initrd.nix", &module);
+}
+
+/// Encrypts the swap partitions with a new random key at every boot, replacing the swapDevices found by nixos-generate-config.
+pub fn install_random_swap() {
+    let swaps = encrypt::swap_partitions();
+    if swaps.is_empty() {
+        warn!("No swap partition found, swap encryption skipped");
+        return;
+    }
+    let mut module = String::from("{ lib, ... }:\n{\n  swapDevices = lib.mkForce [\n");
+    for (device, partuuid) in &swaps {
+        info!("Encrypting swap {} with a random key", device);
+        module.push_str(&format!(
+            "    {{ device = \"/dev/disk/by-partuuid/{}\"; randomEncryption.enable = true; }}\n",
+            partuuid
+        ));
+    }
+    module.push_str("  ];\n}\n");
+    import_boot_module("swap-encryption.nix", &module);
+}
+
+const OS_PROBER_MODULE: &str = r#"{ ... }:
+{
+  boot.loader.grub.useOSProber = true;
+}
+"#;
+
+/// Adds the other installed operating systems to the GRUB menu.
+pub fn install_os_prober() {
+    info!("Enable os-prober in GRUB.");
+    import_boot_module("os-prober.nix", OS_PROBER_MODULE);
+}
+
+/// Unlocks the LUKS partitions with the keyfile on `key_partition` and/or the TPM2 in the systemd initrd.
+/// The devices are named luks-<uuid> like in the hardware configuration generated by nixos-generate-config.
+pub fn install_luks_unlock(key_partition: Option<&str>, tpm2: bool) {
+    info!("Set LUKS unlocking (keyfile: {}, TPM2: {}).", key_partition.unwrap_or("none"), tpm2);
+    let key_uuid = key_partition.and_then(encrypt::filesystem_uuid);
+    let mut module = String::from("{ ... }:\n{\n  boot.initrd.systemd.enable = true;\n");
+    for (_, uuid) in encrypt::find_luks_partitions() {
+        let mut options = Vec::new();
+        if let Some(key_uuid) = &key_uuid {
+            module.push_str(&format!(
+                "  boot.initrd.luks.devices.\"luks-{}\".keyFile = \"{}:UUID={}\";\n",
+                uuid, encrypt::KEYFILE, key_uuid
+            ));
+            options.push("\"keyfile-timeout=10s\"");
+        }
+        if tpm2 && encrypt::tpm2_available() {
+            options.push("\"tpm2-device=auto\"");
+        }
+        if !options.is_empty() {
+            module.push_str(&format!(
+                "  boot.initrd.luks.devices.\"luks-{}\".crypttabExtraOpts = [ {} ];\n",
+                uuid, options.join(" ")
+            ));
+        }
+    }
+    module.push_str("}\n");
+    import_boot_module("luks-unlock.nix", &module);
+}
+
+// lanzaboote replaces GRUB by a signed systemd-boot stub, its keys are managed by sbctl
+const SECURE_BOOT_SETTINGS: &str = r#"  boot.loader.grub.enable = lib.mkForce false;
+  boot.loader.systemd-boot.enable = lib.mkForce false;
+  boot.lanzaboote = {
+    enable = true;
+    pkiBundle = "/var/lib/sbctl";
+  };
+}
+"#;
+
+// Tag of the lanzaboote flake input
+const LANZABOOTE_RELEASE: &str = "v0.4.1";
+
+/// Enables lanzaboote, a flake input, and creates its keys, enrolled only when the firmware is in setup mode.
+pub fn install_secure_boot() {
+    let state = secureboot::state();
+    warn!("Configuring Secure Boot with lanzaboote (firmware state: {})", state);
+    warn!("Enrolling keys can make some firmware refuse to boot, keep a way to reset the firmware keys at hand");
+    exec_eval(
+        exec(
+            "nix-shell",
+            vec![
+                String::from("-p"),
+                String::from("sbctl"),
+                String::from("--command"),
+                String::from("sbctl create-keys"),
+            ],
+        ),
+        "Create secure boot keys",
+    );
+    exec_eval(
+        exec(
+            "bash",
+            vec![
+                String::from("-c"),
+                String::from("mkdir -p /mnt/var/lib && cp -a /var/lib/sbctl /mnt/var/lib/"),
+            ],
+        ),
+        "Copy secure boot keys to the target",
+    );
+    let module = format!("{{ lib, ... }}:\n{{\n{}", SECURE_BOOT_SETTINGS);
+    import_boot_module("secureboot.nix", &module);
+    if state == secureboot::State::SetupMode {
+        // Keep the Microsoft keys, GPU and NIC option ROMs are signed with them
+        exec_eval(
+            exec(
+                "nix-shell",
+                vec![
+                    String::from("-p"),
+                    String::from("sbctl"),
+                    String::from("--command"),
+                    String::from("sbctl enroll-keys --microsoft"),
+                ],
+            ),
+            "Enroll secure boot keys",
+        );
+    } else {
+        warn!("The firmware is not in setup mode, keys are created but not enrolled. Clear the platform key in the firmware setup, then run 'sbctl enroll-keys --microsoft'");
+    }
+}
+
 pub fn install_bootloader_legacy(device: PathBuf) {
     if !device.exists() {
         crash(format!("The device {device:?} does not exist"), 1);
@@ -145,3 +576,42 @@ pub fn install_flatpak() {
         "enable flatpak",
     );
 }
+
+/// Runs `nix-instantiate --parse` on the Nix code, None when nix-instantiate is not on PATH.
+#[cfg(test)]
+pub fn parse_nix(name: &str, source: &str) -> Option<Result<(), String>> {
+    if !shared::exec::missing_programs(&["nix-instantiate"]).is_empty() {
+        eprintln!("nix-instantiate is not on PATH, skipping the parse of {}", name);
+        return None;
+    }
+    let path = std::env::temp_dir().join(format!("aegis-nix-parse-{}-{}", std::process::id(), name));
+    std::fs::write(&path, source).unwrap();
+    let output = std::process::Command::new("nix-instantiate").arg("--parse").arg(&path).output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    if output.status.success() {
+        Some(Ok(()))
+    } else {
+        Some(Err(format!("{}\n{}", String::from_utf8_lossy(&output.stderr), source)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flake_parses_with_every_input() {
+        for home_manager in [false, true] {
+            for impermanence in [false, true] {
+                for secure_boot in [false, true] {
+                    let flake = flake_nix("github:NixOS/nixpkgs/nixos-unstable", "athenaos", home_manager, impermanence, secure_boot);
+                    assert_eq!(flake.contains("inputs.home-manager.nixosModules.home-manager"), home_manager);
+                    let name = format!("flake-{}-{}-{}.nix", home_manager, impermanence, secure_boot);
+                    if let Some(parsed) = parse_nix(&name, &flake) {
+                        assert_eq!(parsed, Ok(()));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,9 @@
+use crate::functions::base;
 use shared::args::DesktopSetup;
 use shared::debug;
 use shared::files;
 use shared::returncode_eval::files_eval;
+use shared::validation;
 
 pub fn install_desktop_setup(desktop_setup: DesktopSetup) {
     debug!("Installing {:?}", desktop_setup);
@@ -96,3 +98,26 @@ fn install_mate() {
         "Set MATE",
     );
 }
+
+/// Enables hardware.bluetooth with the applet of each selected desktop, returns the settings for the summary.
+pub fn bluetooth_settings(desktops: &[String]) -> Vec<String> {
+    let mut settings = vec![String::from("hardware.bluetooth.enable = true;")];
+    let mut packages = Vec::new();
+    for applet in validation::bluetooth_applets(desktops) {
+        match applet {
+            "blueman" => settings.push(String::from("services.blueman.enable = true;")),
+            "gnome-bluetooth" => packages.push("pkgs.gnome-bluetooth"),
+            _ => packages.push("pkgs.kdePackages.bluedevil"),
+        }
+    }
+    if !packages.is_empty() {
+        settings.push(format!("environment.systemPackages = [ {} ];", packages.join(" ")));
+    }
+    settings
+}
+
+pub fn install_bluetooth(desktops: &[String]) {
+    let module = format!("{{ pkgs, ... }}:\n{{\n  {}\n}}\n", bluetooth_settings(desktops).join("\n  "));
+    files_eval(files::create_directory("/mnt/etc/nixos/hosts/hardware"), "Create /mnt/etc/nixos/hosts/hardware");
+    base::import_module("hosts/hardware", "bluetooth.nix", &module);
+}
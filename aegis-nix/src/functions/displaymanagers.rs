@@ -7,8 +7,9 @@ pub fn install_dm_setup(dm_setup: DMSetup) {
     debug!("Installing {:?}", dm_setup);
     match dm_setup {
         DMSetup::Gdm => install_gdm(),
-        DMSetup::LightDMNeon => install_lightdm_neon(),
-        DMSetup::Sddm => todo!(),
+        DMSetup::LightDM | DMSetup::LightDMNeon => install_lightdm_neon(),
+        DMSetup::Sddm => install_sddm(),
+        DMSetup::Greetd => install_greetd(),
         DMSetup::None => debug!("No display manager setup selected"),
     }
 }
@@ -34,3 +35,25 @@ fn install_lightdm_neon() {
         "Set LightDM",
     );
 }
+
+fn install_sddm() {
+    files_eval(
+        files::sed_file(
+            "/mnt/etc/nixos/configuration.nix",
+            "  dmanager =.*",
+            "  dmanager = \"sddm\";",
+        ),
+        "Set SDDM",
+    );
+}
+
+fn install_greetd() {
+    files_eval(
+        files::sed_file(
+            "/mnt/etc/nixos/configuration.nix",
+            "  dmanager =.*",
+            "  dmanager = \"greetd\";",
+        ),
+        "Set greetd",
+    );
+}
@@ -1,4 +1,6 @@
+use crate::functions::base;
 use shared::files;
+use shared::language::Language;
 use shared::returncode_eval::files_eval;
 
 pub fn set_timezone(timezone: &str) {
@@ -47,3 +49,35 @@ pub fn set_keyboard(virtkeyboard: &str, x11keyboard: &str) {
         "Set x11 Keyboard Layout",
     );
 }
+
+/// Shows messages and Firefox in the installer language, the locale and keymaps are set separately.
+pub fn set_language(language: &Language) {
+    let module = format!(
+        "{{ ... }}:\n{{\n  i18n.extraLocaleSettings.LC_MESSAGES = \"{}\";\n  programs.firefox.languagePacks = [ \"{}\" ];\n}}\n",
+        language.locale, language.firefox,
+    );
+    base::import_module("hosts/locale", "language.nix", &module);
+}
+
+/// Sets the NTP client, its servers and the hardware clock mode. Nothing is written when the Athena defaults are kept.
+pub fn configure_time(client: &str, servers: &[String], local_rtc: bool) {
+    let mut settings = Vec::new();
+    if local_rtc {
+        settings.push(String::from("time.hardwareClockInLocalTime = true;"));
+    }
+    match client {
+        // The chrony module turns timesyncd off
+        "chrony" => settings.push(String::from("services.chrony.enable = true;")),
+        "none" => settings.push(String::from("services.timesyncd.enable = false;")),
+        _ => {}
+    }
+    if !servers.is_empty() {
+        let servers: Vec<String> = servers.iter().map(|server| format!("\"{}\"", server)).collect();
+        settings.push(format!("networking.timeServers = [ {} ];", servers.join(" ")));
+    }
+    if settings.is_empty() {
+        return;
+    }
+    let module = format!("{{ ... }}:\n{{\n  {}\n}}\n", settings.join("\n  "));
+    base::import_module("hosts/locale", "time.nix", &module);
+}
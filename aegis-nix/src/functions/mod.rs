@@ -4,6 +4,7 @@ pub mod desktops;
 pub mod displaymanagers;
 pub mod locale;
 pub mod network;
+pub mod roles;
 pub mod shells;
 pub mod terminals;
 pub mod themes;
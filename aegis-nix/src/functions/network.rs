@@ -1,4 +1,8 @@
+use crate::functions::base;
 use shared::files;
+use shared::firewall;
+use shared::netstorage;
+use shared::proxy;
 use shared::returncode_eval::files_eval;
 
 pub fn set_hostname(hostname: &str) {
@@ -17,6 +21,24 @@ pub fn set_hostname(hostname: &str) {
 
 
 
+pub fn persist_proxy() {
+    for (name, value) in proxy::persisted() {
+        let option = match *name {
+            "http_proxy" => "httpProxy",
+            "https_proxy" => "httpsProxy",
+            _ => "noProxy",
+        };
+        files_eval(
+            files::sed_file(
+                "/mnt/etc/nixos/configuration.nix",
+                "networking.enableIPv6 =.*",
+                &format!("$0\n  networking.proxy.{} = \"{}\";", option, value),
+            ),
+            "persist proxy",
+        );
+    }
+}
+
 pub fn enable_ipv6() {
     files_eval(
         files::sed_file(
@@ -27,3 +49,56 @@ pub fn enable_ipv6() {
         "enable ipv6",
     );
 }
+
+/// Opens the allowed ports of networking.firewall, which already drops every other incoming connection.
+pub fn configure_firewall(tcp_ports: &[String], udp_ports: &[String]) {
+    let mut module = String::from("{ ... }:\n{\n  networking.firewall.enable = true;\n");
+    for (protocol, entries) in [("TCP", tcp_ports), ("UDP", udp_ports)] {
+        let mut ports = Vec::new();
+        let mut ranges = Vec::new();
+        for (first, last) in entries.iter().filter_map(|entry| firewall::parse_port(entry).ok()) {
+            if first == last {
+                ports.push(first.to_string());
+            } else {
+                ranges.push(format!("{{ from = {}; to = {}; }}", first, last));
+            }
+        }
+        module.push_str(&format!("  networking.firewall.allowed{}Ports = [ {} ];\n", protocol, ports.join(" ")));
+        module.push_str(&format!("  networking.firewall.allowed{}PortRanges = [ {} ];\n", protocol, ranges.join(" ")));
+    }
+    module.push_str("}\n");
+    files_eval(files::create_directory("/mnt/etc/nixos/hosts/network"), "Create /mnt/etc/nixos/hosts/network");
+    base::import_module("hosts/network", "firewall.nix", &module);
+}
+
+/// Logs in to the network storage targets at boot. iSCSI goes through services.openiscsi with the initiator name
+/// of the live system, a root on iSCSI through the initrd. NVMe over Fabrics uses nvmf-autoconnect with the
+/// discovery entries and host NQN persisted from the live system.
+pub fn configure_network_storage(targets: &[(String, &str, &str)], root_transport: Option<&str>) {
+    let mut settings = Vec::new();
+    if let Some((_, portal, target)) = targets.iter().find(|(protocol, _, _)| protocol == "iscsi") {
+        let name = netstorage::initiator_name().unwrap_or_default();
+        // services.openiscsi discovers a single portal, the first one
+        settings.push(format!("services.openiscsi = {{ enable = true; name = \"{}\"; discoverPortal = \"{}\"; enableAutoLoginOut = true; }};", name, portal));
+        if root_transport == Some("iscsi") {
+            settings.push(format!("boot.iscsi-initiator = {{ name = \"{}\"; discoverPortal = \"{}\"; target = \"{}\"; }};", name, portal, target));
+            settings.push(String::from("boot.initrd.network.enable = true;"));
+            settings.push(String::from("boot.kernelParams = [ \"ip=dhcp\" ];"));
+        }
+    }
+    let nvme: Vec<&(String, &str, &str)> = targets.iter().filter(|(protocol, _, _)| protocol.starts_with("nvme")).collect();
+    if !nvme.is_empty() {
+        for (protocol, portal, target) in &nvme {
+            netstorage::persist(protocol, portal, target);
+        }
+        settings.push(String::from("environment.systemPackages = [ pkgs.nvme-cli ];"));
+        settings.push(String::from("systemd.packages = [ pkgs.nvme-cli ];"));
+        settings.push(String::from("systemd.services.nvmf-autoconnect.wantedBy = [ \"multi-user.target\" ];"));
+    }
+    if settings.is_empty() {
+        return;
+    }
+    let module = format!("{{ pkgs, ... }}:\n{{\n  {}\n}}\n", settings.join("\n  "));
+    files_eval(files::create_directory("/mnt/etc/nixos/hosts/network"), "Create /mnt/etc/nixos/hosts/network");
+    base::import_module("hosts/network", "storage.nix", &module);
+}
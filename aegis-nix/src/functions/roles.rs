@@ -0,0 +1,27 @@
+use shared::args::RoleSetup;
+use shared::debug;
+use shared::files;
+use shared::returncode_eval::files_eval;
+
+pub fn install_role_setup(role_setup: RoleSetup) {
+    debug!("Installing {:?}", role_setup);
+    let role = match role_setup {
+        RoleSetup::RedTeam => "red",
+        RoleSetup::BlueTeam => "blue",
+        RoleSetup::Osint => "osint",
+        RoleSetup::Forensics => "forensic",
+        RoleSetup::Student => "student",
+        RoleSetup::None => {
+            debug!("No role setup selected");
+            return;
+        }
+    };
+    files_eval(
+        files::sed_file(
+            "/mnt/etc/nixos/configuration.nix",
+            "  role =.*",
+            &format!("  role = \"{}\";", role),
+        ),
+        &format!("Set {} role", role),
+    );
+}
@@ -1,6 +1,9 @@
+use shared::crypt;
 use shared::{error, info};
 use shared::files;
-use std::process::Command;
+use shared::returncode_eval::files_eval;
+use shared::strings::{self, crash};
+use crate::functions::base;
 
 pub fn new_user(username: &str, password: &str, do_hash_pass: bool) {
     let mut _password = String::new();
@@ -19,8 +22,7 @@ pub fn new_user(username: &str, password: &str, do_hash_pass: bool) {
     }
     
     if do_hash_pass {
-        let hashed_pass = hash_pass(password).stdout;
-        _password = String::from_utf8_lossy(&hashed_pass).into_owned();
+        _password = hash_pass(password);
     }
     else {
         _password = password.to_string();
@@ -37,13 +39,65 @@ pub fn new_user(username: &str, password: &str, do_hash_pass: bool) {
     }
 }
 
-pub fn hash_pass(password: &str) -> std::process::Output {
-    let output = Command::new("openssl")
-        .args(["passwd", "-6", password])
-        .output()
-        .expect("Failed to hash password");
+// The account itself is declared by configuration.nix, the description is merged into it by a module
+pub fn set_full_name(username: &str, full_name: &str) {
+    let sanitized_username = username.replace(' ', "");
+    let description = strings::gecos(full_name).replace('\\', "\\\\").replace('"', "\\\"").replace("${", "\\${");
+    let module = format!(
+        "{{ ... }}:\n{{\n  users.users.\"{}\".description = \"{}\";\n}}\n",
+        sanitized_username, description,
+    );
+    files_eval(files::create_directory("/mnt/etc/nixos/hosts/users"), "Create /mnt/etc/nixos/hosts/users");
+    base::import_module("hosts/users", &format!("{}.nix", sanitized_username), &module);
+}
+
+/// Starter home.nix of a user: the login shell and a few programs to show where the rest goes.
+pub fn home_nix(shell: &str) -> String {
+    let shell = match shell.to_lowercase().as_str() {
+        "bash" => "\n  programs.bash.enable = true;",
+        "fish" => "\n  programs.fish.enable = true;",
+        "zsh" => "\n  programs.zsh.enable = true;",
+        _ => "",
+    };
+    format!(
+        r#"{{ lib, pkgs, ... }}:
+{{
+  # Managed by home-manager: run nixos-rebuild switch after editing it
+  home.stateVersion = lib.mkDefault lib.trivial.release;
+{shell}
+  programs.git.enable = true;
+  programs.bat.enable = true;
+  programs.htop.enable = true;
+
+  home.packages = with pkgs; [
+    tree
+  ];
+}}
+"#
+    )
+}
+
+/// Host module handing the home.nix of `username` to home-manager.
+pub fn home_manager_module(username: &str) -> String {
+    format!("{{ ... }}:\n{{\n  home-manager.users.\"{0}\" = import ../../home/{0}/home.nix;\n}}\n", username)
+}
+
+/// Writes a starter home.nix for the user, under /etc/nixos/home, and hands it to home-manager.
+pub fn set_home_manager(username: &str, shell: &str) {
+    let sanitized_username = username.replace(' ', "");
+    let home_dir = format!("/mnt/etc/nixos/home/{}", sanitized_username);
+    files_eval(files::create_directory(&home_dir), &format!("Create {}", home_dir));
+    files_eval(std::fs::write(format!("{}/home.nix", home_dir), home_nix(shell)), "Write home.nix");
+    files_eval(files::create_directory("/mnt/etc/nixos/hosts/home-manager"), "Create /mnt/etc/nixos/hosts/home-manager");
+    base::import_module(
+        "hosts/home-manager",
+        &format!("{}.nix", sanitized_username),
+        &home_manager_module(&sanitized_username),
+    );
+}
 
-    output
+pub fn hash_pass(password: &str) -> String {
+    crypt::hash_password(password).unwrap_or_else(|e| crash(format!("Hash password ERROR: {}", e), 1))
 }
 
 pub fn root_pass(root_pass: &str) {
@@ -59,3 +113,36 @@ pub fn root_pass(root_pass: &str) {
         }
     }
 }
+
+// "!" matches no password, NixOS then leaves root without a way to log in
+pub fn lock_root() {
+    root_pass("!");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::base::parse_nix;
+
+    #[test]
+    fn home_nix_parses_for_every_shell() {
+        assert!(home_nix("ZSH").contains("programs.zsh.enable = true;"));
+        assert!(!home_nix("nushell").contains("programs.nushell"));
+        for shell in ["bash", "fish", "zsh", "nushell", ""] {
+            if let Some(parsed) = parse_nix(&format!("home-{}.nix", shell), &home_nix(shell)) {
+                assert_eq!(parsed, Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn home_manager_module_parses() {
+        for username in ["athena", "user-1", "first.last"] {
+            let module = home_manager_module(username);
+            assert!(module.contains(&format!("home-manager.users.\"{0}\" = import ../../home/{0}/home.nix;", username)));
+            if let Some(parsed) = parse_nix(&format!("home-manager-{}.nix", username), &module) {
+                assert_eq!(parsed, Ok(()));
+            }
+        }
+    }
+}
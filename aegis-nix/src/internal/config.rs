@@ -1,13 +1,29 @@
 use crate::functions::*;
 use crate::internal::install::install;
-use shared::args::{self, DesktopSetup, ThemeSetup, DMSetup, ShellSetup, BrowserSetup, TerminalSetup, PartitionMode};
-use shared::{debug, error, info};
-use shared::files;
+use shared::args::{self, BaseSystem, DesktopSetup, ThemeSetup, RoleSetup, DMSetup, ShellSetup, BrowserSetup, TerminalSetup, Encryption, PartitionMode, WipeMethod};
+use shared::{debug, error, info, warn};
+use shared::clap::ValueEnum;
+use shared::checkpoint::{self, Checkpoint};
+use shared::cleanup;
+use shared::config_input;
+use shared::encrypt;
+use shared::failure::{self, Category};
+use shared::firewall;
+use shared::language;
+use shared::logging;
+use shared::netstorage;
+use shared::osdetect;
 use shared::partition;
-use shared::returncode_eval::files_eval;
+use shared::post_install;
+use shared::progress;
+use shared::secureboot;
 use shared::serde::{self, Deserialize, Serialize};
 use shared::serde_json;
 use shared::strings::crash;
+use shared::template;
+use shared::tuning;
+use shared::validation::{self, Kind, Problem, Schema};
+use std::collections::BTreeMap;
 use std::path::{PathBuf};
 //use std::io::{self, BufRead, BufReader};
 //use std::process::{Command, Stdio};
@@ -18,20 +34,54 @@ use std::path::{PathBuf};
 struct Config {
     partition: Partition,
     bootloader: Bootloader,
+    #[serde(default)]
+    secure_boot: SecureBoot,
+    #[serde(default)]
+    language: String,
     locale: Locale,
+    #[serde(default)]
+    time: Time,
     networking: Networking,
+    #[serde(default)]
+    firewall: Firewall,
     users: Vec<Users>,
+    #[serde(default)]
     rootpass: String,
+    #[serde(default)]
+    root_locked: bool,
     params: InstallParams,
-    desktop: String,
+    #[serde(deserialize_with = "config_input::one_or_many")]
+    desktop: Vec<String>,
     theme: String,
+    #[serde(default)]
+    role: String,
     displaymanager: String,
     browser: String,
     terminal: String,
     flatpak: bool,
     zramd: bool,
+    #[serde(default)]
+    bluetooth: bool,
+    #[serde(default)]
+    power_management: String,
+    #[serde(default)]
+    tuning: Tuning,
+    #[serde(default)]
+    snapshots: Snapshots,
+    #[serde(default)]
+    virtualization: Virtualization,
+    #[serde(default)]
+    audio: String,
     extra_packages: Vec<String>,
+    #[serde(default)]
+    post_install_script: String,
     kernel: String,
+    #[serde(default)]
+    initramfs: Initramfs,
+    #[serde(default)]
+    flake: bool,
+    #[serde(default)]
+    impermanence: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -40,10 +90,37 @@ struct Partition {
     device: String,
     mode: PartitionMode,
     encrypt_check: bool,
+    #[serde(default)]
+    encrypt_boot: bool,
+    #[serde(default)]
+    wipe: String,
+    #[serde(default)]
+    swap_random_key: bool,
     efi: bool,
     swap: bool,
     swap_size: String,
     partitions: Vec<String>,
+    #[serde(default)]
+    encryption: LuksUnlock,
+    #[serde(default)]
+    network_storage: Vec<NetworkStorage>,
+}
+
+// iSCSI or NVMe over Fabrics target logged in before partitioning, its disks can then be used like local ones
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct NetworkStorage {
+    protocol: String,
+    portal: String,
+    target: String,
+}
+
+// Extra ways to unlock the LUKS partitions, the passphrase keyslot always stays as a fallback
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct LuksUnlock {
+    keyfile_device: String,
+    tpm2: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,6 +128,14 @@ struct Partition {
 struct Bootloader {
     r#type: String,
     location: String,
+    #[serde(default)]
+    os_prober: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct SecureBoot {
+    mode: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -62,6 +147,49 @@ struct Locale {
     timezone: String,
 }
 
+// Container and virtual machine stacks, the first user joins their groups
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Virtualization {
+    docker: bool,
+    podman: bool,
+    libvirt: bool,
+}
+
+// Timeline snapshots of the btrfs root, hourly and daily are the number of snapshots kept
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Snapshots {
+    tool: String,
+    hourly: u32,
+    daily: u32,
+}
+
+// Additions to the initrd, extra_hooks are mkinitcpio hooks and only apply to aegis-arch
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Initramfs {
+    extra_hooks: Vec<String>,
+    extra_modules: Vec<String>,
+}
+
+// Kernel tunables: a preset of sysctl values and IO scheduler rules, with sysctl overrides applied on top
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Tuning {
+    preset: String,
+    sysctl: BTreeMap<String, String>,
+}
+
+// Clock synchronisation, an empty ntp keeps the NTP client of the base system
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Time {
+    ntp: String,
+    ntp_servers: Vec<String>,
+    local_rtc: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "self::serde")] // must be below the derive attribute
 struct Networking {
@@ -69,6 +197,16 @@ struct Networking {
     ipv6: bool,
 }
 
+// Incoming traffic filter of the installed system, ports are single ports or "first-last" ranges
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "self::serde")] // must be below the derive attribute
+struct Firewall {
+    backend: String,
+    deny_incoming: bool,
+    tcp_ports: Vec<String>,
+    udp_ports: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "self::serde")] // must be below the derive attribute
 struct Users {
@@ -76,6 +214,12 @@ struct Users {
     password: String,
     hasroot: bool,
     shell: String,
+    #[serde(default)]
+    fullname: String,
+    #[serde(default)]
+    avatar: String,
+    #[serde(default)]
+    home_manager: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -85,31 +229,213 @@ struct InstallParams {
     jobs: String,
 }
 
-pub fn read_config(configpath: PathBuf) -> i32 {
+const SCHEMA: Schema = Schema {
+    required: &[
+        ("/partition", Kind::Object),
+        ("/partition/device", Kind::String),
+        ("/partition/mode", Kind::String),
+        ("/partition/encrypt_check", Kind::Bool),
+        ("/partition/efi", Kind::Bool),
+        ("/partition/swap", Kind::Bool),
+        ("/partition/swap_size", Kind::String),
+        ("/partition/partitions", Kind::Array),
+        ("/bootloader/type", Kind::String),
+        ("/bootloader/location", Kind::String),
+        ("/locale/locale", Kind::Array),
+        ("/locale/virtkeymap", Kind::String),
+        ("/locale/x11keymap", Kind::String),
+        ("/locale/timezone", Kind::String),
+        ("/networking/hostname", Kind::String),
+        ("/networking/ipv6", Kind::Bool),
+        ("/users", Kind::Array),
+        ("/rootpass", Kind::String),
+        ("/params/cores", Kind::String),
+        ("/params/jobs", Kind::String),
+        ("/desktop", Kind::StringList),
+        ("/theme", Kind::String),
+        ("/displaymanager", Kind::String),
+        ("/browser", Kind::String),
+        ("/terminal", Kind::String),
+        ("/flatpak", Kind::Bool),
+        ("/zramd", Kind::Bool),
+        ("/extra_packages", Kind::Array),
+        ("/kernel", Kind::String),
+    ],
+    choices: &[
+        ("/partition/mode", &["erasedisk", "replace", "manual"]),
+        ("/bootloader/type", &["grub-efi", "grub-legacy"]),
+        ("/audio", &["pipewire", "pulseaudio"]),
+        ("/power_management", &["tlp", "power-profiles-daemon", "none"]),
+        ("/tuning/preset", &tuning::PRESETS),
+        ("/snapshots/tool", &["snapper", "none"]),
+        ("/time/ntp", &["timesyncd", "chrony", "none"]),
+        ("/firewall/backend", &["nixos", "none"]),
+        ("/partition/wipe", &["none", "quick", "discard", "secure"]),
+        ("/secure_boot/mode", &["sbctl", "none"]),
+        ("/desktop", &["gnome", "cinnamon", "mate", "xfce refined", "xfce picom", "none"]),
+        ("/theme", &["akame", "cyborg", "graphite", "hackthebox", "samurai", "sweet", "temple", "none"]),
+        ("/role", &["red team", "blue team", "osint", "forensics", "student", "none"]),
+        ("/displaymanager", &["gdm", "lightdm", "lightdm neon", "sddm", "greetd", "none"]),
+        ("/browser", &["firefox", "none"]),
+        ("/terminal", &["alacritty", "kitty", "none"]),
+    ],
+};
+
+// Example written by generate-config. Every key of Config is present, "_comment" keys are ignored by the parser.
+// It must keep passing SCHEMA, except for the placeholder password hashes.
+const EXAMPLE_CONFIG: &str = r#"{
+  "_comment": "Aegis unattended config for Athena Nix. Run 'aegis-nix config <file> --dry' to validate it.",
+  "_comment_templates": "${NAME} in any value is replaced by a --set NAME=value, ${dmi:product_serial} and the like, or the NAME environment variable (see list-template-vars). $${NAME} is a literal ${NAME}. post_install_script is passed to the shell unchanged",
+  "partition": {
+    "_comment": "mode: EraseDisk (automatic layout on device), Replace or Manual (use the partitions list)",
+    "device": "sda",
+    "mode": "EraseDisk",
+    "encrypt_check": false,
+    "_comment_encrypt_boot": "EraseDisk with encrypt_check and efi only: encrypt /boot and swap too, the ESP is mounted at /efi (set bootloader location to /efi)",
+    "encrypt_boot": false,
+    "_comment_wipe": "EraseDisk only. wipe: none (new partition table only), quick (erase signatures), discard (SSD blkdiscard) or secure (zero the whole drive, slow)",
+    "wipe": "none",
+    "efi": true,
+    "swap": false,
+    "_comment_swap_size": "size like 8GiB, 8G, 8.5 GiB, 8GB (SI) or a percentage of the drive like 5%",
+    "swap_size": "8GiB",
+    "_comment_swap_random_key": "Encrypt the swap with a new random key at every boot (no hibernation), not with encrypt_boot",
+    "swap_random_key": false,
+    "_comment_partitions": "Manual/Replace only, entries are mountpoint:blockdevice:filesystem:encrypt[:options], options being mount options like noatime,compress=zstd (EraseDisk uses noatime for root and umask=0077 for the ESP)",
+    "partitions": [
+      "/mnt/boot:/dev/sda1:vfat:false:umask=0077",
+      "/mnt:/dev/sda2:btrfs:false:noatime,compress=zstd"
+    ],
+    "encryption": {
+      "_comment": "Optional unlocking of the encrypted partitions, the passphrase always remains as a fallback. keyfile_device: removable partition (i.e. /dev/sdb1) receiving a generated keyfile, empty for none. tpm2: unlock with the TPM2 when present (not with encrypt_boot)",
+      "keyfile_device": "",
+      "tpm2": false
+    },
+    "_comment_network_storage": "iSCSI and NVMe over Fabrics targets to log in to before partitioning, entries are {\"protocol\": \"iscsi\", \"nvme-tcp\" or \"nvme-rdma\", \"portal\": \"host[:port]\", \"target\": IQN or NQN}. The root filesystem may be on an iSCSI target, not on an NVMe over Fabrics one",
+    "network_storage": []
+  },
+  "bootloader": {
+    "_comment": "type: grub-efi (location is the EFI directory) or grub-legacy (location is the disk). os_prober: add the other installed operating systems (see detect-os) to the boot menu",
+    "type": "grub-efi",
+    "location": "/boot",
+    "os_prober": false
+  },
+  "secure_boot": {
+    "_comment": "grub-efi only. mode: sbctl (lanzaboote with custom keys, enrolled when the firmware is in setup mode, needs flake) or none",
+    "mode": "none"
+  },
+  "_comment_language": "installer language, i.e. Italiano or it: sets the messages and Firefox language and fills the locale and keymaps left empty",
+  "language": "English",
+  "locale": {
+    "_comment": "only the first locale is used by Athena Nix",
+    "locale": ["en_US.UTF-8 UTF-8"],
+    "virtkeymap": "us",
+    "x11keymap": "us",
+    "timezone": "Europe/Zurich"
+  },
+  "_comment_time": "ntp: timesyncd, chrony or none. ntp_servers replace the default servers. local_rtc keeps the hardware clock in local time, for dual boot with Windows",
+  "time": {
+    "ntp": "timesyncd",
+    "ntp_servers": [],
+    "local_rtc": false
+  },
+  "networking": {
+    "hostname": "athenaos",
+    "ipv6": false
+  },
+  "_comment_firewall": "backend: nixos (networking.firewall) or none, which keeps the Athena default. With deny_incoming, only tcp_ports and udp_ports are let in, as single ports (22) or ranges (6000-6010)",
+  "firewall": {
+    "backend": "nixos",
+    "deny_incoming": true,
+    "tcp_ports": [],
+    "udp_ports": []
+  },
+  "_comment_users": "password is a crypt hash, generate it with 'openssl passwd -6'. fullname (shown by the display manager) is optional. home_manager gives the user a starter home.nix managed by Home Manager, it needs flake",
+  "users": [
+    {
+      "name": "athena",
+      "password": "REPLACE_WITH_HASH",
+      "hasroot": true,
+      "shell": "fish",
+      "fullname": "Athena User",
+      "home_manager": false
+    }
+  ],
+  "rootpass": "REPLACE_WITH_HASH",
+  "_comment_root_locked": "Lock the root account instead of setting rootpass (which can then be left out), administration goes through the users with hasroot",
+  "root_locked": false,
+  "_comment_params": "cores and max jobs given to nixos-install",
+  "params": {
+    "cores": "0",
+    "jobs": "auto"
+  },
+  "_comment_desktop": "gnome, cinnamon, mate, xfce refined, xfce picom or none",
+  "desktop": "gnome",
+  "_comment_theme": "akame, cyborg, graphite, hackthebox, samurai, sweet, temple or none",
+  "theme": "hackthebox",
+  "_comment_role": "tools bundle added on top of extra_packages: red team, blue team, osint, forensics, student or none",
+  "role": "none",
+  "_comment_displaymanager": "gdm, lightdm, lightdm neon, sddm, greetd or none",
+  "displaymanager": "gdm",
+  "_comment_browser": "firefox or none",
+  "browser": "firefox",
+  "_comment_terminal": "alacritty, kitty or none",
+  "terminal": "kitty",
+  "flatpak": false,
+  "zramd": true,
+  "_comment_bluetooth": "Installs the Bluetooth stack with the applet of the selected desktop",
+  "bluetooth": false,
+  "_comment_power_management": "tlp, power-profiles-daemon or none, for laptops. GNOME and KDE Plasma expect power-profiles-daemon",
+  "power_management": "none",
+  "_comment_tuning": "preset: default (kernel defaults), low-latency, vm-guest or server. The presets set swappiness, cache pressure and network sysctls and pick the IO scheduler of each drive type. sysctl holds overrides, i.e., {\"vm.swappiness\": \"20\"}",
+  "tuning": {
+    "preset": "default",
+    "sysctl": {}
+  },
+  "_comment_snapshots": "btrfs root only. tool: snapper or none, keeping the last hourly and daily snapshots",
+  "snapshots": {
+    "tool": "none",
+    "hourly": 5,
+    "daily": 7
+  },
+  "_comment_virtualization": "Container and virtual machine stacks. The first user joins the docker and libvirt groups",
+  "virtualization": {
+    "docker": false,
+    "podman": false,
+    "libvirt": false
+  },
+  "_comment_audio": "pipewire or pulseaudio, the audio server",
+  "audio": "pipewire",
+  "extra_packages": [],
+  "_comment_post_install_script": "Shell script run as root inside the installed system as the last step: the script itself, an http(s) URL or a file path on the live system. A failing script does not fail the install",
+  "post_install_script": "",
+  "kernel": "",
+  "_comment_initramfs": "Advanced: kernel modules loaded by the initrd, added to the ones found by nixos-generate-config. extra_hooks only apply to aegis-arch",
+  "initramfs": {
+    "extra_hooks": [],
+    "extra_modules": []
+  },
+  "_comment_flake": "Generate a flake-based configuration: /etc/nixos/flake.nix with nixpkgs pinned to the installer release, managed with nixos-rebuild --flake",
+  "flake": false,
+  "_comment_impermanence": "Advanced: ephemeral root on a tmpfs, only /nix, /persist and /boot stay on the disk. Everything outside the persisted paths (machine-id, SSH host keys, NetworkManager connections, /home, logs) is lost at every boot. Needs the erasedisk mode and flake",
+  "impermanence": false
+}
+"#;
+
+pub fn generate_config(base: Option<BaseSystem>, output: Option<PathBuf>) -> i32 {
+    if base.is_some_and(|base| base != BaseSystem::Nix) {
+        error!("This backend generates Athena Nix configs, use aegis-arch or aegis-fedora generate-config for the other bases");
+        return 1;
+    }
+    config_input::write_example(EXAMPLE_CONFIG, output.as_deref());
+    0
+}
+
+pub fn read_config(configpath: PathBuf, overrides: &[(String, String)], dry: bool, resume: bool) -> i32 {
     let data = std::fs::read_to_string(&configpath);
     match &data {
         Ok(_) => {
-            files_eval(
-                files::sed_file(
-                    configpath.to_str().unwrap(),
-                    "\"password\":.*",
-                    "\"password\": \"*REDACTED*\",",
-                ),
-                "Redact user information",
-            );
-            files_eval(
-                files::sed_file(
-                    configpath.to_str().unwrap(),
-                    "\"rootpass\":.*",
-                    "\"rootpass\": \"*REDACTED*\",",
-                ),
-                "Redact root information",
-            );
-            // Re-read the redacted file content
-            let redacted_data = std::fs::read_to_string(&configpath).expect("Failed to read config file after redaction");
-            info!("Configuration set:\n{}", redacted_data);
-
-            debug!("[ \x1b[2;1;32mOK\x1b[0m ] Read and redacted config file {configpath:?}");
+            debug!("[ \x1b[2;1;32mOK\x1b[0m ] Read config file {configpath:?}");
         }
         Err(e) => {
             crash(
@@ -118,179 +444,495 @@ pub fn read_config(configpath: PathBuf) -> i32 {
             );
         }
     }
-    let config: std::result::Result<Config, serde_json::Error> =
+    let config: std::result::Result<serde_json::Value, serde_json::Error> =
         serde_json::from_str(&data.unwrap());
     match &config {
-        Ok(_) => {
+        Ok(config) => {
+            info!("Configuration set:\n{}", serde_json::to_string_pretty(&config_input::redact(config)).unwrap());
             debug!("[ \x1b[2;1;32mOK\x1b[0m ] Parse config file {configpath:?}",);
         }
         Err(e) => {
             crash(format!("Parse config file {configpath:?}  ERROR: {}", e), 1);
         }
     }
-    //////
-    let config: Config = config.unwrap();
-    info!("Block device to use : {}", config.partition.device);
-    info!("Partitioning mode : {:?}", config.partition.mode);
-    info!("Partitioning for EFI : {}", config.partition.efi);
-    info!("Swap partition : {}", config.partition.swap);
+    let mut config = config.unwrap();
+    let mut problems = template::substitute(&mut config, overrides);
+    problems.extend(validation::validate(&config, &SCHEMA));
+    // configuration.nix selects a single desktop
+    if config.pointer("/desktop").and_then(serde_json::Value::as_array).is_some_and(|desktops| desktops.len() > 1) {
+        problems.push(Problem::new("/desktop", "Athena Nix installs a single desktop"));
+    }
+    // Home Manager, impermanence and lanzaboote come locked by flake.lock, a channel install would fetch them unhashed
+    let flake = config.pointer("/flake").and_then(serde_json::Value::as_bool) == Some(true);
+    for (i, user) in config.pointer("/users").and_then(serde_json::Value::as_array).into_iter().flatten().enumerate() {
+        if !flake && user.pointer("/home_manager").and_then(serde_json::Value::as_bool) == Some(true) {
+            problems.push(Problem::new(&format!("/users/{}/home_manager", i), "Home Manager needs the flake install, set flake to true"));
+        }
+    }
+    if !flake && config.pointer("/secure_boot/mode").and_then(serde_json::Value::as_str).is_some_and(|mode| mode.eq_ignore_ascii_case("sbctl")) {
+        problems.push(Problem::new("/secure_boot/mode", "lanzaboote needs the flake install, set flake to true or the mode to none"));
+    }
+    if config.pointer("/impermanence").and_then(serde_json::Value::as_bool) == Some(true) {
+        if !flake {
+            problems.push(Problem::new("/impermanence", "the impermanence module needs the flake install, set flake to true"));
+        }
+        if config.pointer("/partition/mode").and_then(serde_json::Value::as_str).is_some_and(|mode| !mode.eq_ignore_ascii_case("erasedisk")) {
+            problems.push(Problem::new("/impermanence", "the ephemeral root needs the erasedisk mode, which creates the /nix and /persist subvolumes"));
+        }
+        if config.pointer("/partition/encrypt_boot").and_then(serde_json::Value::as_bool) == Some(true) {
+            problems.push(Problem::new("/impermanence", "the ephemeral root leaves no disk filesystem for an encrypted /boot"));
+        }
+        if config.pointer("/snapshots/tool").and_then(serde_json::Value::as_str).is_some_and(|tool| tool.eq_ignore_ascii_case("snapper")) {
+            problems.push(Problem::new("/impermanence", "snapper cannot snapshot a tmpfs root, set snapshots/tool to none"));
+        }
+    }
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("{}", problem);
+        }
+        error!("Config file {configpath:?} has {} problem(s)", problems.len());
+        return failure::report("Invalid config file", 1, Some(Category::InvalidConfig));
+    }
+    for warning in validation::warnings(&config) {
+        warn!("{}", warning);
+    }
+    let config_hash = checkpoint::fingerprint(&config.to_string());
+    let mut config: Config = match serde_json::from_value(config) {
+        Ok(config) => config,
+        Err(e) => crash(format!("Parse config file {configpath:?}  ERROR: {}", e), 1),
+    };
     let mut partitions: Vec<args::Partition> = Vec::new();
-    for partition in config.partition.partitions {
-        let to_encrypt: bool = partition.split(':').collect::<Vec<&str>>()[3].parse().map_err(|_| "Invalid boolean value").expect("Unable to get encrypt boolean value.");
-        partitions.push(args::Partition::new(
-            partition.split(':').collect::<Vec<&str>>()[0].to_string(),
-            partition.split(':').collect::<Vec<&str>>()[1].to_string(),
-            partition.split(':').collect::<Vec<&str>>()[2].to_string(),
-            to_encrypt,
-        ));
+    for partition in &config.partition.partitions {
+        partitions.push(
+            args::parse_partitions(partition)
+                .unwrap_or_else(|e| crash(format!("Partition \"{}\": {}", partition, e), 1)),
+        );
+    }
+    let language = language::lookup(&config.language);
+    if let Some(language) = language {
+        language::fill_defaults(language, &mut config.locale.locale, &mut config.locale.virtkeymap, &mut config.locale.x11keymap);
     }
     let device = PathBuf::from("/dev/").join(config.partition.device.as_str());
-    partition::partition(
-        device,
-        config.partition.mode,
-        config.partition.encrypt_check,
-        config.partition.efi,
-        config.partition.swap,
-        config.partition.swap_size,
-        &mut partitions,
-    );
-    println!();
-    base::install_nix_config();
-    println!();
-    info!("Installing bootloader : {}", config.bootloader.r#type);
-    info!("Installing bootloader to : {}", config.bootloader.location);
-    if config.bootloader.r#type == "grub-efi" {
-        base::install_bootloader_efi(PathBuf::from(config.bootloader.location));
-    } else if config.bootloader.r#type == "grub-legacy" {
-        base::install_bootloader_legacy(PathBuf::from(config.bootloader.location));
-    }
-    println!();
-    // Set locales at the beginning to prevent some warning messages about "Setting locale failed"
-    info!("Adding Locales : {:?}", config.locale.locale);
-    locale::set_locale(config.locale.locale.join(" "));
-    info!("Using console keymap : {}", config.locale.virtkeymap);
-    info!("Using x11 keymap : {}", config.locale.x11keymap);
-    locale::set_keyboard(config.locale.virtkeymap.as_str(), config.locale.x11keymap.as_str());
-    info!("Setting timezone : {}", config.locale.timezone);
-    locale::set_timezone(config.locale.timezone.as_str());
-    println!();
-    info!("Hostname : {}", config.networking.hostname);
-    network::set_hostname(config.networking.hostname.as_str());
-    info!("Enabling ipv6 : {}", config.networking.ipv6);
-    if config.networking.ipv6 {
-        network::enable_ipv6();
-    }
-    println!();
-    println!("---------");
-    info!("Enabling zramd : {}", config.zramd);
-    if config.zramd {
-        base::install_zram();
-    }
-    info!("Installing desktop : {:?}", config.desktop);
-    match config.desktop.to_lowercase().as_str() {
-        "gnome" => { //Note that the value on this match statement must fit the name in desktops.py of aegis-gui (then they are lowercase transformed)
-            desktops::install_desktop_setup(DesktopSetup::Gnome);
-        },
-        "cinnamon" => desktops::install_desktop_setup(DesktopSetup::Cinnamon),
-        "mate" => desktops::install_desktop_setup(DesktopSetup::Mate),
-        "xfce refined" => desktops::install_desktop_setup(DesktopSetup::XfceRefined),
-        "xfce picom" => desktops::install_desktop_setup(DesktopSetup::XfcePicom),
-        "none" => desktops::install_desktop_setup(DesktopSetup::None),
-        _ => info!("No desktop setup selected!"),
-    }
-    println!();
-    info!("Installing theme : {:?}", config.theme);
-
-    match config.theme.to_lowercase().as_str() {
-        "akame" => themes::install_theme_setup(ThemeSetup::Akame),
-        "cyborg" => themes::install_theme_setup(ThemeSetup::Cyborg),
-        "graphite" => themes::install_theme_setup(ThemeSetup::Graphite),
-        "hackthebox" => themes::install_theme_setup(ThemeSetup::HackTheBox), //Note that the value on this match statement must fit the name in themes.py of aegis-gui (then they are lowercase transformed)
-        "samurai" => themes::install_theme_setup(ThemeSetup::Samurai),
-        "sweet" => themes::install_theme_setup(ThemeSetup::Sweet),
-        "temple" => themes::install_theme_setup(ThemeSetup::Temple),
-        _ => info!("No theme setup selected!"),
-    }
-    println!();
-    info!("Installing display manager : {:?}", config.displaymanager);
-    match config.displaymanager.to_lowercase().as_str() {
-        "gdm" => {
-            displaymanagers::install_dm_setup(DMSetup::Gdm);
-        },
-        "lightdm neon" => {
-            displaymanagers::install_dm_setup(DMSetup::LightDMNeon);
-        },
-        _ => info!("No display manager setup selected!"),
+    let encryption = Encryption::from_flags(config.partition.encrypt_check, config.partition.encrypt_boot);
+    let wipe = WipeMethod::from_str(&config.partition.wipe, true).unwrap_or(WipeMethod::None);
+    // Spell out what is about to be destroyed, the dry run shows it as well
+    for line in partition::destructive_summary(&device, config.partition.mode, encryption, wipe, &partitions) {
+        warn!("{}", line);
     }
-
-    println!();
-    info!("Installing browser : {:?}", config.browser);
-    /*if let Some(browser) = &config.browser {
-        browsers::install_browser_setup(*browser);
-    }*/
-    match config.browser.to_lowercase().as_str() {
-        "firefox" => {
-            browsers::install_browser_setup(BrowserSetup::Firefox);
-        },
-        _ => info!("No browser setup selected!"),
-    }
-    println!();
-    // Terminal configuration //
-    info!("Installing terminal : {:?}", config.terminal);
-    match config.terminal.to_lowercase().as_str() {
-        "alacritty" => {
-            terminals::install_terminal_setup(TerminalSetup::Alacritty);
-        },
-        "kitty" => {
-            terminals::install_terminal_setup(TerminalSetup::Kitty);
-        },
-        _ => info!("No terminal setup selected!"),
-    }
-    // Misc Settings
-    println!();
-    info!("Installing flatpak : {}", config.flatpak);
-    if config.flatpak {
-        base::install_flatpak();
-    }
-    println!();
-    println!("---------");
-    // Users
-    for i in 0..config.users.len() {
-        info!("Creating user : {}", config.users[i].name);
-        //info!("Setting use password : {}", config.users[i].password);
-        info!("Enabling root for user : {}", config.users[i].hasroot);
-        info!("Setting user shell : {}", config.users[i].shell);
-
-        match config.users[i].shell.to_lowercase().as_str() {
-            "bash" => shells::install_shell_setup(ShellSetup::Bash),
-            "fish" => shells::install_shell_setup(ShellSetup::Fish),
-            "zsh" => shells::install_shell_setup(ShellSetup::Zsh),
-            _ => info!("No shell setup selected!"),
-        }
-        users::new_user(
-            config.users[i].name.as_str(),
-            config.users[i].password.as_str(),
-            false,
+    let firewall_backend = config.firewall.backend.to_lowercase();
+    info!("{}", firewall::summary(&firewall_backend, config.firewall.deny_incoming, &config.firewall.tcp_ports, &config.firewall.udp_ports));
+    info!("Bluetooth : {}", if config.bluetooth { desktops::bluetooth_settings(&config.desktop).join(" ") } else { String::from("false") });
+    let audio = if config.audio.is_empty() { String::from("pipewire") } else { config.audio.to_lowercase() };
+    info!("Audio : {}", audio);
+    let virtualization = &config.virtualization;
+    info!("Virtualization : docker {}, podman {}, libvirt {}", virtualization.docker, virtualization.podman, virtualization.libvirt);
+    let mut virtualization_groups = Vec::new();
+    if virtualization.docker {
+        virtualization_groups.push("docker");
+    }
+    if virtualization.libvirt {
+        virtualization_groups.push("libvirtd");
+    }
+    let group_user = config.users.first().map(|user| user.name.as_str());
+    if !virtualization_groups.is_empty() {
+        match group_user {
+            Some(user) => info!("User {} joins the groups {}", user, virtualization_groups.join(", ")),
+            None => warn!("No user is created by the install, nobody joins the groups {}", virtualization_groups.join(", ")),
+        }
+    }
+    let snapshots = if config.snapshots.tool.is_empty() { String::from("none") } else { config.snapshots.tool.to_lowercase() };
+    if snapshots == "none" {
+        info!("Snapshots : none");
+    } else {
+        info!("Snapshots : {}, keeping {} hourly and {} daily", snapshots, config.snapshots.hourly, config.snapshots.daily);
+    }
+    let network_storage: Vec<(String, &str, &str)> = config
+        .partition
+        .network_storage
+        .iter()
+        .map(|storage| (storage.protocol.to_lowercase(), storage.portal.as_str(), storage.target.as_str()))
+        .collect();
+    for (protocol, portal, target) in &network_storage {
+        info!("Network storage : {} target {} at {}", protocol, target, portal);
+    }
+    let iscsi_portal = network_storage.iter().find(|(protocol, _, _)| protocol == "iscsi").map(|(_, portal, _)| *portal);
+    for (_, portal, target) in network_storage.iter().filter(|(protocol, portal, _)| protocol == "iscsi" && Some(*portal) != iscsi_portal) {
+        warn!("services.openiscsi logs in through a single portal, {} at {} is not logged in at boot", target, portal);
+    }
+    let power_management = config.power_management.to_lowercase();
+    info!("Power management : {}", if power_management.is_empty() { "none" } else { &power_management });
+    if (power_management.is_empty() || power_management == "none") && osdetect::is_laptop() {
+        warn!("This machine runs on a battery, set power_management to tlp or power-profiles-daemon to manage it");
+    }
+    let tuning_preset = if config.tuning.preset.is_empty() { String::from("default") } else { config.tuning.preset.to_lowercase() };
+    let tuning_values = tuning::sysctl_values(&tuning_preset, &config.tuning.sysctl);
+    for line in tuning::summary(&tuning_preset, &tuning_values) {
+        info!("{}", line);
+    }
+    if !config.initramfs.extra_hooks.is_empty() {
+        warn!("initramfs/extra_hooks are mkinitcpio hooks, NixOS builds its initrd from modules and ignores them");
+    }
+    let home_manager_users: Vec<&str> = config.users.iter().filter(|user| user.home_manager).map(|user| user.name.as_str()).collect();
+    if !home_manager_users.is_empty() {
+        info!("Home Manager : {}", home_manager_users.join(", "));
+    }
+    if config.impermanence {
+        warn!("Ephemeral root: / is a tmpfs, everything outside /nix, /persist and /boot is lost at every boot");
+    }
+    let flake = config.flake.then(|| format!("/etc/nixos#{}", config.networking.hostname));
+    info!("Configuration : {}", flake.as_deref().map_or(String::from("configuration.nix"), |flake| format!("flake {}", flake)));
+    if dry {
+        info!("Config file {configpath:?} is valid");
+        return 0;
+    }
+    // os-prober mounts every partition to look for other systems, a dry run leaves the disks alone
+    if !config.time.local_rtc {
+        if let Some(windows) = osdetect::find_windows() {
+            warn!("{} found on {}, it keeps the hardware clock in local time: set time/local_rtc to avoid a clock offset between the systems", windows.name, windows.device);
+        }
+    }
+    let mut checkpoint = Checkpoint::start(config_hash, resume);
+    progress::phase(5, "Partitioning drives");
+    if checkpoint.should_run("partitioning", false) {
+        info!("Block device to use : {}", config.partition.device);
+        info!("Partitioning mode : {:?}", config.partition.mode);
+        info!("Partitioning for EFI : {}", config.partition.efi);
+        info!("Swap partition : {}", config.partition.swap);
+        // Release whatever a previous failed install left mounted or opened on the drives
+        cleanup::cleanup();
+        for (protocol, portal, target) in &network_storage {
+            if let Err(e) = netstorage::connect(protocol, portal, target) {
+                error!("{}", e);
+                return failure::report(&e, 1, Some(Category::Partitioning));
+            }
+        }
+        // The NixOS initrd can log in to an iSCSI target, it has no NVMe over Fabrics support
+        if netstorage::root_transport(&device, config.partition.mode, &partitions) == Some("nvme") {
+            let message = "The root filesystem is on NVMe over Fabrics storage, Athena Nix can only boot from a local disk or an iSCSI target";
+            error!("{}", message);
+            return failure::report(message, 1, Some(Category::InvalidConfig));
+        }
+        if config.partition.mode == PartitionMode::EraseDisk {
+            partition::wipe_device(&device, wipe);
+        }
+        partition::partition(
+            device.clone(),
+            config.partition.mode,
+            encryption,
+            config.partition.efi,
+            config.partition.swap,
+            config.partition.swap_size,
+            config.impermanence,
+            &mut partitions,
         );
-        println!("---------");
-    }
-    println!();
-    //info!("Setting root password : {}", config.rootpass);
-    users::root_pass(config.rootpass.as_str());
-    println!();
-    info!("Install Athena OS");
-    let exit_code = install(config.params.cores, config.params.jobs);
-    println!();
-    info!("Installation log file copied to /var/log/aegis.log");
-    files_eval(files::create_directory("/mnt/var/log"), "create /mnt/var/log");
-    files::copy_file("/tmp/aegis.log", "/mnt/var/log/aegis.log");
+        if !config.partition.encryption.keyfile_device.is_empty() {
+            encrypt::enroll_keyfile(&config.partition.encryption.keyfile_device);
+        }
+        if config.partition.encryption.tpm2 {
+            encrypt::enroll_tpm2();
+        }
+        encrypt::forget_passphrase();
+        checkpoint.complete("partitioning");
+    }
+    eprintln!();
+    progress::phase(15, "Fetching Athena OS configuration");
+    if checkpoint.should_run("nix_config", true) {
+        base::install_nix_config();
+        let home_manager = config.users.iter().any(|user| user.home_manager) && !base::home_manager_imported();
+        if config.flake {
+            let secure_boot = config.bootloader.r#type == "grub-efi" && config.secure_boot.mode.eq_ignore_ascii_case("sbctl");
+            base::install_flake(&config.networking.hostname, home_manager, config.impermanence, secure_boot);
+        }
+        // nixos-generate-config only keeps the subvolume option of the mounts
+        let mut mount_options: Vec<(String, String)> = if config.partition.mode == PartitionMode::EraseDisk {
+            partition::layout_mount_options(config.partition.efi, encryption, config.impermanence)
+                .into_iter()
+                .map(|(mountpoint, options)| (mountpoint.to_string(), options.to_string()))
+                .collect()
+        } else {
+            config.partition.partitions
+                .iter()
+                .filter_map(|partition| args::parse_partitions(partition).ok())
+                .filter(|partition| !partition.mount_options.is_empty())
+                .map(|partition| {
+                    let mountpoint = partition.mountpoint.trim_start_matches("/mnt").trim_end_matches('/');
+                    (format!("/{}", mountpoint.trim_start_matches('/')), partition.mount_options.join(","))
+                })
+                .collect()
+        };
+        if !network_storage.is_empty() {
+            // Mounted once the network is up
+            for mountpoint in netstorage::network_mountpoints() {
+                match mount_options.iter_mut().find(|(existing, _)| *existing == mountpoint) {
+                    Some((_, options)) => options.push_str(",_netdev"),
+                    None => mount_options.push((mountpoint, String::from("_netdev"))),
+                }
+            }
+            network::configure_network_storage(&network_storage, netstorage::root_transport(&device, config.partition.mode, &partitions));
+        }
+        base::install_mount_options(&mount_options);
+        if config.impermanence {
+            base::install_impermanence();
+        }
+        if snapshots == "snapper" {
+            base::install_snapper(config.snapshots.hourly, config.snapshots.daily);
+        }
+        if config.partition.swap_random_key {
+            base::install_random_swap();
+        }
+        checkpoint.complete("nix_config");
+    }
+    eprintln!();
+    progress::phase(25, "Configuring bootloader");
+    if checkpoint.should_run("bootloader", true) {
+        info!("Installing bootloader : {}", config.bootloader.r#type);
+        info!("Installing bootloader to : {}", config.bootloader.location);
+        info!("Secure Boot : {} (firmware state: {})", config.secure_boot.mode, secureboot::state());
+        if config.bootloader.r#type == "grub-efi" {
+            base::install_bootloader_efi(PathBuf::from(&config.bootloader.location));
+            if config.partition.encrypt_check && config.partition.encrypt_boot {
+                base::install_cryptodisk();
+            }
+            if config.secure_boot.mode.eq_ignore_ascii_case("sbctl") {
+                base::install_secure_boot();
+            }
+        } else if config.bootloader.r#type == "grub-legacy" {
+            base::install_bootloader_legacy(PathBuf::from(&config.bootloader.location));
+        }
+        if config.bootloader.os_prober {
+            base::install_os_prober();
+        }
+        let unlock = &config.partition.encryption;
+        if !unlock.keyfile_device.is_empty() || unlock.tpm2 {
+            base::install_luks_unlock(Some(unlock.keyfile_device.as_str()).filter(|device| !device.is_empty()), unlock.tpm2);
+        }
+        base::install_initrd(config.partition.encrypt_check, &config.initramfs.extra_modules);
+        checkpoint.complete("bootloader");
+    }
+    eprintln!();
+    progress::phase(30, "Configuring locales");
+    if checkpoint.should_run("locales", true) {
+        // Set locales at the beginning to prevent some warning messages about "Setting locale failed"
+        info!("Adding Locales : {:?}", config.locale.locale);
+        locale::set_locale(config.locale.locale.join(" "));
+        info!("Using console keymap : {}", config.locale.virtkeymap);
+        info!("Using x11 keymap : {}", config.locale.x11keymap);
+        locale::set_keyboard(config.locale.virtkeymap.as_str(), config.locale.x11keymap.as_str());
+        info!("Setting timezone : {}", config.locale.timezone);
+        locale::set_timezone(config.locale.timezone.as_str());
+        locale::configure_time(&config.time.ntp.to_lowercase(), &config.time.ntp_servers, config.time.local_rtc);
+        if let Some(language) = language {
+            info!("Setting language : {}", language.name);
+            locale::set_language(language);
+        }
+        checkpoint.complete("locales");
+    }
+    eprintln!();
+    progress::phase(35, "Configuring network");
+    if checkpoint.should_run("network", true) {
+        info!("Hostname : {}", config.networking.hostname);
+        network::set_hostname(config.networking.hostname.as_str());
+        info!("Enabling ipv6 : {}", config.networking.ipv6);
+        if config.networking.ipv6 {
+            network::enable_ipv6();
+        }
+        network::persist_proxy();
+        if firewall_backend == "nixos" {
+            if !config.firewall.deny_incoming {
+                warn!("networking.firewall always drops the incoming connections to ports that are not allowed");
+            }
+            network::configure_firewall(&config.firewall.tcp_ports, &config.firewall.udp_ports);
+        }
+        checkpoint.complete("network");
+    }
+    eprintln!();
+    eprintln!("---------");
+    progress::phase(40, "Configuring desktop environment");
+    if checkpoint.should_run("desktop", true) {
+        info!("Enabling zramd : {}", config.zramd);
+        if config.zramd {
+            base::install_zram();
+        }
+        info!("Installing desktop : {:?}", config.desktop);
+        match config.desktop.first().map(|desktop| desktop.to_lowercase()).unwrap_or_default().as_str() {
+            "gnome" => { //Note that the value on this match statement must fit the name in desktops.py of aegis-gui (then they are lowercase transformed)
+                desktops::install_desktop_setup(DesktopSetup::Gnome);
+            },
+            "cinnamon" => desktops::install_desktop_setup(DesktopSetup::Cinnamon),
+            "mate" => desktops::install_desktop_setup(DesktopSetup::Mate),
+            "xfce refined" => desktops::install_desktop_setup(DesktopSetup::XfceRefined),
+            "xfce picom" => desktops::install_desktop_setup(DesktopSetup::XfcePicom),
+            "none" => desktops::install_desktop_setup(DesktopSetup::None),
+            _ => info!("No desktop setup selected!"),
+        }
+        if config.bluetooth {
+            desktops::install_bluetooth(&config.desktop);
+        }
+        base::install_power_management(&power_management);
+        base::install_tuning(&tuning_values, tuning::io_scheduler(&tuning_preset));
+        if audio == "pulseaudio" {
+            base::install_pulseaudio();
+        }
+        eprintln!();
+        info!("Installing theme : {:?}", config.theme);
+
+        match config.theme.to_lowercase().as_str() {
+            "akame" => themes::install_theme_setup(ThemeSetup::Akame),
+            "cyborg" => themes::install_theme_setup(ThemeSetup::Cyborg),
+            "graphite" => themes::install_theme_setup(ThemeSetup::Graphite),
+            "hackthebox" => themes::install_theme_setup(ThemeSetup::HackTheBox), //Note that the value on this match statement must fit the name in themes.py of aegis-gui (then they are lowercase transformed)
+            "samurai" => themes::install_theme_setup(ThemeSetup::Samurai),
+            "sweet" => themes::install_theme_setup(ThemeSetup::Sweet),
+            "temple" => themes::install_theme_setup(ThemeSetup::Temple),
+            _ => info!("No theme setup selected!"),
+        }
+        eprintln!();
+        info!("Installing role : {:?}", config.role);
+        match config.role.to_lowercase().as_str() {
+            "red team" => roles::install_role_setup(RoleSetup::RedTeam),
+            "blue team" => roles::install_role_setup(RoleSetup::BlueTeam),
+            "osint" => roles::install_role_setup(RoleSetup::Osint),
+            "forensics" => roles::install_role_setup(RoleSetup::Forensics),
+            "student" => roles::install_role_setup(RoleSetup::Student),
+            _ => info!("No role setup selected!"),
+        }
+        eprintln!();
+        info!("Installing display manager : {:?}", config.displaymanager);
+        match config.displaymanager.to_lowercase().as_str() {
+            "gdm" => {
+                displaymanagers::install_dm_setup(DMSetup::Gdm);
+            },
+            "lightdm" => {
+                displaymanagers::install_dm_setup(DMSetup::LightDM);
+            },
+            "lightdm neon" => {
+                displaymanagers::install_dm_setup(DMSetup::LightDMNeon);
+            },
+            "sddm" => {
+                displaymanagers::install_dm_setup(DMSetup::Sddm);
+            },
+            "greetd" => {
+                displaymanagers::install_dm_setup(DMSetup::Greetd);
+            },
+            _ => info!("No display manager setup selected!"),
+        }
+
+        eprintln!();
+        info!("Installing browser : {:?}", config.browser);
+        /*if let Some(browser) = &config.browser {
+            browsers::install_browser_setup(*browser);
+        }*/
+        match config.browser.to_lowercase().as_str() {
+            "firefox" => {
+                browsers::install_browser_setup(BrowserSetup::Firefox);
+            },
+            _ => info!("No browser setup selected!"),
+        }
+        eprintln!();
+        // Terminal configuration //
+        info!("Installing terminal : {:?}", config.terminal);
+        match config.terminal.to_lowercase().as_str() {
+            "alacritty" => {
+                terminals::install_terminal_setup(TerminalSetup::Alacritty);
+            },
+            "kitty" => {
+                terminals::install_terminal_setup(TerminalSetup::Kitty);
+            },
+            _ => info!("No terminal setup selected!"),
+        }
+        // Misc Settings
+        eprintln!();
+        info!("Installing flatpak : {}", config.flatpak);
+        if config.flatpak {
+            base::install_flatpak();
+        }
+        checkpoint.complete("desktop");
+    }
+    eprintln!();
+    eprintln!("---------");
+    progress::phase(50, "Creating users");
+    if checkpoint.should_run("users", true) {
+        // Users
+        for i in 0..config.users.len() {
+            info!("Creating user : {}", config.users[i].name);
+            //info!("Setting use password : {}", config.users[i].password);
+            info!("Enabling root for user : {}", config.users[i].hasroot);
+            info!("Setting user shell : {}", config.users[i].shell);
+
+            match config.users[i].shell.to_lowercase().as_str() {
+                "bash" => shells::install_shell_setup(ShellSetup::Bash),
+                "fish" => shells::install_shell_setup(ShellSetup::Fish),
+                "zsh" => shells::install_shell_setup(ShellSetup::Zsh),
+                _ => info!("No shell setup selected!"),
+            }
+            users::new_user(
+                config.users[i].name.as_str(),
+                config.users[i].password.as_str(),
+                false,
+            );
+            if !config.users[i].fullname.is_empty() {
+                users::set_full_name(config.users[i].name.as_str(), config.users[i].fullname.as_str());
+            }
+            info!("Home Manager : {}", config.users[i].home_manager);
+            if config.users[i].home_manager {
+                users::set_home_manager(config.users[i].name.as_str(), config.users[i].shell.as_str());
+            }
+            if !config.users[i].avatar.is_empty() {
+                warn!("Avatars are only set on Athena Arch, {} keeps the default one", config.users[i].name);
+            }
+            eprintln!("---------");
+        }
+        eprintln!();
+        //info!("Setting root password : {}", config.rootpass);
+        if config.root_locked {
+            users::lock_root();
+        } else {
+            users::root_pass(config.rootpass.as_str());
+        }
+        base::install_virtualization(virtualization.docker, virtualization.podman, virtualization.libvirt, group_user, &virtualization_groups);
+        checkpoint.complete("users");
+    }
+    eprintln!();
+    progress::phase(55, "Installing Athena OS");
+    let mut exit_code = 0;
+    if checkpoint.should_run("install", true) {
+        info!("Install Athena OS");
+        exit_code = install(config.params.cores, config.params.jobs, flake.as_ref().map(|flake| format!("/mnt{}", flake)).as_deref());
+        if exit_code == 0 {
+            checkpoint.complete("install");
+        } else {
+            exit_code = failure::report(&format!("nixos-install failed with exit code {}", exit_code), exit_code, None);
+        }
+    }
+    if exit_code == 0 && !config.post_install_script.trim().is_empty() {
+        eprintln!();
+        progress::phase(96, "Running the post-install script");
+        if checkpoint.should_run("post_install", true) {
+            post_install::run(&config.post_install_script, &["nixos-enter", "--root", "/mnt", "--"]);
+            checkpoint.complete("post_install");
+        }
+    }
+    eprintln!();
+    progress::phase(98, "Finalizing installation");
+    if exit_code == 0 {
+        checkpoint.finish();
+    }
+    logging::persist_log("/mnt/var/log/aegis-install.log");
+    if config.impermanence {
+        base::persist_state();
+    }
     if config.bootloader.r#type == "grub-efi" {
         partition::umount("/mnt/boot");
     }
     partition::umount("/mnt/home");
     partition::umount("/mnt");
     if exit_code == 0 {
+        progress::phase(100, "Installation finished");
         info!("Installation finished! You may reboot now!");
+        if let Some(flake) = &flake {
+            info!("Flake output : {}", flake);
+            info!("Apply configuration changes with : sudo nixos-rebuild switch --flake {}", flake);
+        }
     }
     else {
         error!("Installation failed. Exit code: {}", exit_code);
@@ -309,7 +951,7 @@ pub fn read_config(configpath: PathBuf) -> i32 {
 /*
 // Prompt the user to generate logs and return true if the answer is 'Y'
 fn prompt_user_for_logs() -> bool {
-    println!("\nDo you want to generate logs of the failed install to communicate to the team? (Y/n)");
+    eprintln!("\nDo you want to generate logs of the failed install to communicate to the team? (Y/n)");
 
     let mut input = String::new();
     io::stdin().read_line(&mut input).expect("Failed to read user input");
@@ -325,7 +967,7 @@ fn prompt_user_for_logs() -> bool {
 fn run_logs_command() {
     // Create a new command to run the specified shell command
     let mut logs_command = Command::new("sh")
-        .args(["-c", "cat /tmp/aegis.log | nc termbin.com 9999"])
+        .args(["-c", &format!("cat '{}' | nc termbin.com 9999", logging::log_file().display())])
         .stdout(Stdio::piped())  // Redirect standard output to a pipe
         .stderr(Stdio::piped())  // Redirect standard error to a pipe
         .spawn()  // Start the command as a new process
@@ -1,11 +1,17 @@
 use shared::{error, info};
+use shared::failure;
 use std::io::{BufRead, BufReader};
 use std::process::{Command, Stdio};
 
-pub fn install(cores: String, jobs: String) -> i32 {
+/// Runs nixos-install, from the flake output `flake` (i.e., /mnt/etc/nixos#athenaos) when one is given.
+pub fn install(cores: String, jobs: String, flake: Option<&str>) -> i32 {
     // The init logging is called at the beginning of main.rs
 
-    let install_nixos_args = format!("nixos-install --no-root-password --cores {} --max-jobs {} --keep-going", cores, jobs);
+    let mut install_nixos_args = format!("nixos-install --no-root-password --cores {} --max-jobs {} --keep-going", cores, jobs);
+    if let Some(flake) = flake {
+        // The Athena configuration is written for channels, --impure lets it keep reading them
+        install_nixos_args.push_str(&format!(" --impure --flake {}", flake));
+    }
     let install_args = vec![
         "-p",
         "nixos-install-tools",
@@ -25,6 +31,7 @@ pub fn install(cores: String, jobs: String) -> i32 {
         let reader = BufReader::new(stdout_handle);
         for line in reader.lines().map_while(Result::ok) {
             info!("{}", line);
+            failure::record_output(&line);
         }
     });
 
@@ -33,6 +40,7 @@ pub fn install(cores: String, jobs: String) -> i32 {
         let reader = BufReader::new(stderr_handle);
         for line in reader.lines().map_while(Result::ok) {
             info!("{}", line);
+            failure::record_output(&line);
         }
     });
 
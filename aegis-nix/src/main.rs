@@ -1,43 +1,69 @@
 mod functions;
 mod internal;
 use crate::functions::*;
-use shared::args::{BootloaderSubcommand, Command, Cli, UsersSubcommand};
+use shared::args::{BootloaderSubcommand, Command, Cli, Encryption, PartitionMode, UsersSubcommand};
+use shared::cleanup;
 use shared::clap::Parser;
+use shared::config_input;
+use shared::encrypt;
+use shared::{debug, error};
 use shared::exec::check_if_root;
 use shared::human_panic;
 use shared::logging;
+use shared::osdetect;
 use shared::partition;
+use shared::progress;
+use shared::proxy;
+use shared::serde_json;
+use shared::template;
+use std::process::ExitCode;
 
-fn main() -> Result<(), i32> {
-    check_if_root();
+fn main() -> ExitCode {
     human_panic::setup_panic!();
     let cli = Cli::parse();
-    println!("verbose: {}", cli.verbose);
-    let log_file_path = "/tmp/aegis";
-    logging::init(cli.verbose, log_file_path);
+    check_if_root(&cli);
+    let log_file_path = logging::resolve_log_file(cli.log_file);
+    logging::init(logging::level(cli.verbose, cli.quiet), cli.log_format, &log_file_path);
+    debug!("verbose: {}", cli.verbose);
+    proxy::apply(&cli.proxy);
+    if cli.show_all_disks {
+        partition::show_all_disks();
+    }
     // menu choice
     match cli.command {
         Command::Partition(args) => {
+            cleanup::cleanup();
+            if args.mode == PartitionMode::EraseDisk {
+                partition::wipe_device(&args.device, args.wipe);
+            }
             let mut partitions = args.partitions;
             partition::partition(
                 args.device,
                 args.mode,
-                args.encrypt_check,
+                Encryption::from_flags(args.encrypt_check, args.encrypt_boot),
                 args.efi,
                 args.swap,
                 args.swap_size,
+                false,
                 &mut partitions,
             );
+            encrypt::forget_passphrase();
         }
         Command::InstallBase => {
             base::install_nix_config();
         }
         Command::Bootloader { subcommand } => match subcommand {
-            BootloaderSubcommand::GrubEfi { efidir } => {
+            BootloaderSubcommand::GrubEfi { efidir, os_prober } => {
                 base::install_bootloader_efi(efidir);
+                if os_prober {
+                    base::install_os_prober();
+                }
             }
-            BootloaderSubcommand::GrubLegacy { device } => {
+            BootloaderSubcommand::GrubLegacy { device, os_prober } => {
                 base::install_bootloader_legacy(device);
+                if os_prober {
+                    base::install_os_prober();
+                }
             }
         }
         Command::Locale(args) => {
@@ -68,20 +94,43 @@ fn main() -> Result<(), i32> {
             }
         },
         Command::InstallParams(args) => {
-            internal::install::install(args.cores, args.jobs);
+            internal::install::install(args.cores, args.jobs, None);
+        }
+        Command::Config { config, config_sha256, fetch_timeout, overrides, dry, progress_json, resume, oem, jobs: _ } => {
+            // Every nix install step edits configuration.nix, so they always run one after the other
+            if progress_json {
+                progress::enable_json();
+            }
+            if oem {
+                // The user account is declared in configuration.nix, a wizard cannot add it after the install
+                error!("OEM mode is not available on Athena Nix");
+                return ExitCode::FAILURE;
+            }
+            let (config, _downloaded) = config_input::resolve(&config, config_sha256.as_deref(), fetch_timeout);
+            let exit_code = internal::config::read_config(config, &overrides, dry, resume);
+            progress::finish(exit_code);
+            if exit_code != 0 {
+                return ExitCode::from(exit_code as u8);
+            }
         }
-        Command::Config { config } => {
-            let exit_code = internal::config::read_config(config);
+        Command::GenerateConfig { base, output } => {
+            let exit_code = internal::config::generate_config(base, output);
             if exit_code != 0 {
-                return Err(exit_code);
+                return ExitCode::from(exit_code as u8);
             }
         }
+        Command::ListTemplateVars { overrides } => {
+            template::list_vars(&overrides);
+        }
         Command::Desktops { desktop } => {
             desktops::install_desktop_setup(desktop);
         }
         Command::Themes { theme } => {
             themes::install_theme_setup(theme);
         }
+        Command::Roles { role } => {
+            roles::install_role_setup(role);
+        }
         Command::DisplayManagers { displaymanager } => {
             displaymanagers::install_dm_setup(displaymanager);
         }
@@ -94,7 +143,20 @@ fn main() -> Result<(), i32> {
         Command::Terminals { terminal } => {
             terminals::install_terminal_setup(terminal);
         },
+        Command::DetectOs => {
+            println!("{}", serde_json::to_string_pretty(&osdetect::detect()).unwrap());
+        }
+        Command::ImportSettings { device } => match osdetect::import_settings(&device) {
+            Ok(settings) => println!("{}", serde_json::to_string_pretty(&settings).unwrap()),
+            Err(e) => {
+                error!("{}", e);
+                return ExitCode::from(1);
+            }
+        },
+        Command::Cleanup => {
+            cleanup::cleanup();
+        },
         _ => todo!() //Do nothing for all those Command:: specified in shared/args.rs but not specifically implemented in athena-nix (because useless)
     }
-    Ok(())
+    ExitCode::SUCCESS
 }
@@ -7,13 +7,60 @@ use std::path::PathBuf;
 #[command(author=env!("CARGO_PKG_AUTHORS"))]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(about = env!("CARGO_PKG_DESCRIPTION"), long_about = None)]
-
 pub struct Cli {
     #[command(subcommand)]
     pub command: Command,
 
-    #[arg(short, long, action = clap::ArgAction::Count)]
+    /// Log more details, repeat for more (-v debug, -vv trace)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
     pub verbose: u8,
+
+    /// Log less, repeat for less (-q warnings, -qq errors only)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub quiet: u8,
+
+    /// Log file path (defaults to $AEGIS_LOG, then /tmp/aegis.log)
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+
+    /// Format of the log file entries
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Accept every block device as install target, loop devices and the disk of the live system included
+    #[arg(long, global = true)]
+    pub show_all_disks: bool,
+
+    #[command(flatten)]
+    pub proxy: ProxyArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ProxyArgs {
+    /// Proxy for HTTP connections, i.e. http://proxy.example.com:3128
+    #[arg(long, global = true)]
+    pub http_proxy: Option<String>,
+
+    /// Proxy for HTTPS connections
+    #[arg(long, global = true)]
+    pub https_proxy: Option<String>,
+
+    /// Comma-separated hosts reached without the proxy
+    #[arg(long, global = true)]
+    pub no_proxy: Option<String>,
+
+    /// Also configure the proxy in the installed system
+    #[arg(long, global = true)]
+    pub persist_proxy: bool,
+}
+
+#[derive(Debug, ValueEnum, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+pub enum LogFormat {
+    #[value(name = "text")]
+    Text,
+
+    #[value(name = "json")]
+    Json,
 }
 
 #[derive(Debug, ValueEnum, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
@@ -28,6 +75,18 @@ pub enum PackageManager {
     None,
 }
 
+#[derive(Debug, ValueEnum, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+pub enum BaseSystem {
+    #[value(name = "arch")]
+    Arch,
+
+    #[value(name = "nix")]
+    Nix,
+
+    #[value(name = "fedora")]
+    Fedora,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Command {
     /// Partition the install destination
@@ -46,12 +105,6 @@ pub enum Command {
     #[command(name = "genfstab")]
     GenFstab,
     
-    /// Setup Snapper
-    /*
-    #[command(name = "setup-snapper")]
-    SetupSnapper,
-    */
-
     /// Install the bootloader
     #[command(name = "bootloader")]
     Bootloader {
@@ -93,8 +146,60 @@ pub enum Command {
     /// Read Aegis installation config
     #[command(name = "config")]
     Config {
-        /// The config file to read
+        /// The config file to read, either a local path, an http(s) URL or - for stdin
         config: PathBuf,
+
+        /// Expected SHA-256 checksum of the config file
+        #[arg(long)]
+        config_sha256: Option<String>,
+
+        /// Timeout in seconds when downloading the config file
+        #[arg(long, default_value_t = 30)]
+        fetch_timeout: u64,
+
+        /// Value for a ${KEY} placeholder in the config, overriding DMI and environment values
+        #[arg(long = "set", value_name = "KEY=VALUE", value_parser = crate::template::parse_override)]
+        overrides: Vec<(String, String)>,
+
+        /// Only validate the config file and report every problem found, without installing
+        #[arg(long)]
+        dry: bool,
+
+        /// Print the install progress to stdout as one JSON object per line
+        #[arg(long)]
+        progress_json: bool,
+
+        /// Skip the phases completed by an interrupted install of the same config
+        #[arg(long)]
+        resume: bool,
+
+        /// OEM install: no users and root password, the end user creates them in a first-boot wizard
+        #[arg(long)]
+        oem: bool,
+
+        /// Number of independent install steps to run at the same time
+        #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: u16,
+    },
+
+    /// Write a fully commented example config
+    #[command(name = "generate-config")]
+    GenerateConfig {
+        /// The base system the example is for (defaults to the one of this backend)
+        #[arg(long, value_enum)]
+        base: Option<BaseSystem>,
+
+        /// Where to write the example (defaults to stdout)
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+
+    /// List the config template variables available on this machine
+    #[command(name = "list-template-vars")]
+    ListTemplateVars {
+        /// Value for a ${KEY} placeholder, overriding DMI and environment values
+        #[arg(long = "set", value_name = "KEY=VALUE", value_parser = crate::template::parse_override)]
+        overrides: Vec<(String, String)>,
     },
 
     /// Install a graphical desktop
@@ -113,6 +218,14 @@ pub enum Command {
         theme: ThemeSetup,
     },
 
+    /// Install the tools of a role
+    #[command(name = "roles")]
+    Roles {
+        /// The role setup to use
+        #[arg(value_enum)]
+        role: RoleSetup,
+    },
+
     /// Install a display manager
     #[command(name = "displaymanagers")]
     DisplayManagers {
@@ -148,6 +261,21 @@ pub enum Command {
     /// Enable services
     #[command(name = "enable-services")]
     EnableServices,
+
+    /// List the operating systems already installed, as JSON
+    #[command(name = "detect-os")]
+    DetectOs,
+
+    /// Read the hostname, users, timezone, locale and /home partition of an existing Linux install, as JSON
+    #[command(name = "import-settings")]
+    ImportSettings {
+        /// Root partition of the existing install, i.e., /dev/sda2. It is only mounted read-only
+        device: String,
+    },
+
+    /// Release mounts, swap and LUKS mappings left by a failed install
+    #[command(name = "cleanup")]
+    Cleanup,
 }
 
 #[derive(Debug, Args)]
@@ -166,6 +294,14 @@ pub struct PartitionArgs {
     #[arg(long)]
     pub encrypt_check: bool,
 
+    /// Encrypt everything except the ESP in Erase Disk mode (needs --encrypt-check and --efi)
+    #[arg(long, requires = "encrypt_check")]
+    pub encrypt_boot: bool,
+
+    /// How to wipe the device before partitioning it in Erase Disk mode
+    #[arg(long, value_enum, default_value_t = WipeMethod::None)]
+    pub wipe: WipeMethod,
+
     /// If the install destination should be partitioned with EFI
     #[arg(long)]
     pub efi: bool,
@@ -174,7 +310,7 @@ pub struct PartitionArgs {
     #[arg(long)]
     pub swap: bool,
 
-    /// Swap partition size
+    /// Swap partition size, i.e. 8GiB, 8G, 8GB (SI) or 5% of the drive
     #[arg(long)]
     pub swap_size: String,
 
@@ -183,6 +319,26 @@ pub struct PartitionArgs {
     pub partitions: Vec<Partition>,
 }
 
+/// What the Erase Disk layout encrypts
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encryption {
+    None,
+    /// The root partition only, /boot stays on the plaintext ESP
+    Root,
+    /// Root and swap in a single LUKS container, only the ESP stays plaintext
+    AllButEsp,
+}
+
+impl Encryption {
+    pub fn from_flags(encrypt_check: bool, encrypt_boot: bool) -> Self {
+        match (encrypt_check, encrypt_boot) {
+            (false, _) => Encryption::None,
+            (true, false) => Encryption::Root,
+            (true, true) => Encryption::AllButEsp,
+        }
+    }
+}
+
 #[derive(Debug, Args)]
 pub struct InstallPackagesArgs {
     #[clap(long)]
@@ -195,30 +351,76 @@ pub struct Partition {
     pub blockdevice: String,
     pub filesystem: String,
     pub encrypt: bool,
+    pub mount_options: Vec<String>,
 }
 
 impl Partition {
-    pub fn new(mountpoint: String, blockdevice: String, filesystem: String, encrypt: bool) -> Self {
+    pub fn new(mountpoint: String, blockdevice: String, filesystem: String, encrypt: bool, mount_options: Vec<String>) -> Self {
         Self {
             mountpoint,
             blockdevice,
             filesystem,
             encrypt,
+            mount_options,
         }
     }
 }
 
-pub fn parse_partitions(s: &str) -> Result<Partition, &'static str> { // to rewrite
-    println!("{}", s);
-    let to_encrypt: bool = s.split(':').collect::<Vec<&str>>()[3].parse().map_err(|_| "Invalid boolean value")?;
+/// Parses a mountpoint:blockdevice:filesystem:encrypt[:options] partition entry,
+/// options being a comma-separated mount option list like noatime,compress=zstd.
+pub fn parse_partitions(s: &str) -> Result<Partition, &'static str> {
+    let fields: Vec<&str> = s.split(':').collect();
+    if fields.len() != 4 && fields.len() != 5 {
+        return Err("Expected mountpoint:blockdevice:filesystem:encrypt[:options]");
+    }
+    let to_encrypt: bool = fields[3].parse().map_err(|_| "Invalid boolean value")?;
+    let mount_options = match fields.get(4) {
+        Some(options) => parse_mount_options(options)?,
+        None => Vec::new(),
+    };
     Ok(Partition::new(
-        s.split(':').collect::<Vec<&str>>()[0].to_string(),
-        s.split(':').collect::<Vec<&str>>()[1].to_string(),
-        s.split(':').collect::<Vec<&str>>()[2].to_string(),
+        fields[0].to_string(),
+        fields[1].to_string(),
+        fields[2].to_string(),
         to_encrypt,
+        mount_options,
     ))
 }
 
+/// Splits a comma-separated mount option list, rejecting empty options and whitespace.
+pub fn parse_mount_options(s: &str) -> Result<Vec<String>, &'static str> {
+    if s.is_empty() {
+        return Ok(Vec::new());
+    }
+    s.split(',')
+        .map(|option| {
+            if option.is_empty() {
+                Err("Empty mount option, check the commas")
+            } else if option.contains(char::is_whitespace) {
+                Err("Mount options cannot contain whitespace")
+            } else {
+                Ok(option.to_string())
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, ValueEnum, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+pub enum WipeMethod {
+    /// Only write a new partition table
+    #[value(name = "none")]
+    None,
+    /// Erase the filesystem, RAID and partition table signatures
+    #[value(name = "quick")]
+    Quick,
+    /// Discard every block, SSDs only
+    #[value(name = "discard")]
+    Discard,
+    /// Overwrite the whole drive with zeros, slow on large disks
+    #[value(name = "secure")]
+    Secure,
+}
+
 #[derive(Debug, ValueEnum, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
 pub enum PartitionMode {
     #[value(name = "erase")]
@@ -236,6 +438,10 @@ pub enum BootloaderSubcommand {
     GrubEfi {
         /// The directory to install the EFI bootloader to
         efidir: PathBuf,
+
+        /// Add the other installed operating systems to the boot menu
+        #[arg(long)]
+        os_prober: bool,
     },
 
     /// Install GRUB in legacy (BIOS) mode
@@ -243,6 +449,10 @@ pub enum BootloaderSubcommand {
     GrubLegacy {
         /// The device to install the bootloader to
         device: PathBuf,
+
+        /// Add the other installed operating systems to the boot menu
+        #[arg(long)]
+        os_prober: bool,
     },
 }
 
@@ -392,17 +602,44 @@ pub enum ThemeSetup {
     None,
 }
 
+#[derive(Debug, ValueEnum, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+pub enum RoleSetup {
+    #[value(name = "red-team")]
+    RedTeam,
+
+    #[value(name = "blue-team")]
+    BlueTeam,
+
+    #[value(name = "osint")]
+    Osint,
+
+    #[value(name = "forensics")]
+    Forensics,
+
+    #[value(name = "student")]
+    Student,
+
+    #[value(name = "None")]
+    None,
+}
+
 #[derive(Debug, ValueEnum, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
 pub enum DMSetup {
     #[value(name = "gdm")]
     Gdm,
 
+    #[value(name = "lightdm")]
+    LightDM,
+
     #[value(name = "lightdm-neon")]
     LightDMNeon,
 
     #[value(name = "sddm")]
     Sddm,
 
+    #[value(name = "greetd")]
+    Greetd,
+
     #[value(name = "None")]
     None,
 }
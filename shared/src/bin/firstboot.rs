@@ -0,0 +1,102 @@
+//! First-boot wizard of an OEM install: creates the account of the end user on tty1 before any login prompt.
+//! Installed as /usr/bin/aegis-firstboot by `aegis-<distro> config --oem`, started by aegis-firstboot.service.
+
+use shared::crypt::hash_password;
+use shared::validation::check_username;
+use std::io::{self, BufRead, Write};
+use std::process::{Command, ExitCode, Stdio};
+
+// Groups of a user with root access, as given by the installer to hasroot users
+const ADMIN_GROUPS: &str = "wheel";
+
+fn prompt(question: &str) -> io::Result<String> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the console was closed"));
+    }
+    Ok(answer.trim_end_matches(['\r', '\n']).to_string())
+}
+
+// The terminal does not echo the password while it is typed
+fn prompt_secret(question: &str) -> io::Result<String> {
+    let echo = |flag: &str| Command::new("stty").arg(flag).status();
+    echo("-echo")?;
+    let answer = prompt(question);
+    echo("echo")?;
+    println!();
+    answer
+}
+
+fn ask_password(owner: &str, allow_empty: bool) -> io::Result<String> {
+    loop {
+        let password = prompt_secret(&format!("Password for {}: ", owner))?;
+        if password.is_empty() && !allow_empty {
+            println!("The password must not be empty.");
+            continue;
+        }
+        if prompt_secret("Repeat the password: ")? == password {
+            return Ok(password);
+        }
+        println!("The passwords do not match.");
+    }
+}
+
+fn run(program: &str, args: &[&str], input: Option<&str>) -> io::Result<()> {
+    let mut child = Command::new(program).args(args).stdin(Stdio::piped()).spawn()?;
+    if let (Some(input), Some(stdin)) = (input, child.stdin.as_mut()) {
+        stdin.write_all(input.as_bytes())?;
+    }
+    drop(child.stdin.take());
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} exited with {}", program, status)));
+    }
+    Ok(())
+}
+
+// chpasswd -e reads the hash on stdin, the password never appears in a command line
+fn set_password(user: &str, password: &str) -> io::Result<()> {
+    run("chpasswd", &["-e"], Some(&format!("{}:{}\n", user, hash_password(password)?)))
+}
+
+fn create_user() -> io::Result<()> {
+    let name = loop {
+        let name = prompt("User name: ")?;
+        match check_username(&name) {
+            Ok(()) => break name,
+            Err(e) => println!("The user name {}.", e),
+        }
+    };
+    let password = ask_password(&name, false)?;
+    // A wizard interrupted after useradd is run again at the next boot with the account already there
+    let exists = Command::new("id").arg(&name).stdout(Stdio::null()).stderr(Stdio::null()).status()?.success();
+    if !exists {
+        run("useradd", &["-m", "-G", ADMIN_GROUPS, &name], None)?;
+    }
+    set_password(&name, &password)?;
+    println!();
+    println!("Leave the root password empty to keep root locked, {} can use sudo.", name);
+    let root_password = ask_password("root", true)?;
+    if !root_password.is_empty() {
+        set_password("root", &root_password)?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    println!("Welcome to Athena OS. Create your account to finish the setup.");
+    println!();
+    match create_user() {
+        Ok(()) => {
+            println!("Setup complete.");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            // The unit stays enabled, the wizard runs again at the next boot
+            eprintln!("Setup failed: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::failure;
+use crate::log::{info, warn};
+use crate::strings::crash;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Written on the target after each completed phase, so an interrupted install can be resumed
+const STATE_FILE: &str = "/mnt/.aegis-state.json";
+
+#[derive(Serialize, Deserialize)]
+struct State {
+    config_hash: String,
+    phases: Vec<PhaseRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PhaseRecord {
+    name: String,
+    started: u64,
+    finished: u64,
+}
+
+pub struct Checkpoint {
+    state: State,
+    resume: bool,
+    started: u64,
+}
+
+/// Stable fingerprint of a config (64-bit FNV-1a), used to refuse resuming with a different config.
+pub fn fingerprint(config: &str) -> String {
+    let hash = config.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl Checkpoint {
+    /// Starts a fresh install, or loads the phases completed by a previous run of the same config when resuming.
+    pub fn start(config_hash: String, resume: bool) -> Self {
+        let mut state = State { config_hash, phases: Vec::new() };
+        if resume {
+            let previous: State = match fs::read_to_string(STATE_FILE).map(|s| serde_json::from_str(&s)) {
+                Ok(Ok(previous)) => previous,
+                Ok(Err(e)) => crash(format!("Nothing to resume, {} is invalid: {}", STATE_FILE, e), 1),
+                Err(e) => crash(
+                    format!("Nothing to resume, {} cannot be read (is the target still mounted on /mnt?): {}", STATE_FILE, e),
+                    1,
+                ),
+            };
+            if previous.config_hash != state.config_hash {
+                crash("The config changed since the interrupted install, start over without --resume", 1);
+            }
+            let done: Vec<&str> = previous.phases.iter().map(|phase| phase.name.as_str()).collect();
+            info!("Resuming install, completed phases: {}", done.join(", "));
+            state.phases = previous.phases;
+        }
+        Checkpoint { state, resume, started: now() }
+    }
+
+    /// Whether a phase has to run. Phases completed by a previous run are skipped when resuming,
+    /// and a non resumable phase (i.e., partitioning) is never run again by --resume.
+    pub fn should_run(&mut self, phase: &str, resumable: bool) -> bool {
+        if self.state.phases.iter().any(|record| record.name == phase) {
+            info!("Skipping {}, completed by a previous run", phase);
+            return false;
+        }
+        if self.resume && !resumable {
+            crash(format!("Cannot resume: {} did not complete and is not safe to run again", phase), 1);
+        }
+        self.started = now();
+        failure::enter_phase(phase);
+        true
+    }
+
+    /// Records a completed phase on the target.
+    pub fn complete(&mut self, phase: &str) {
+        self.state.phases.push(PhaseRecord {
+            name: phase.to_string(),
+            started: self.started,
+            finished: now(),
+        });
+        let state = serde_json::to_string_pretty(&self.state).unwrap();
+        if let Err(e) = fs::write(STATE_FILE, state) {
+            warn!("Unable to write checkpoint {}: {}", STATE_FILE, e);
+        }
+    }
+
+    /// Removes the checkpoint once the install is complete, it must not end up in the installed system.
+    pub fn finish(&self) {
+        if let Err(e) = fs::remove_file(STATE_FILE) {
+            warn!("Unable to remove checkpoint {}: {}", STATE_FILE, e);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::exec::exec;
+use crate::log::{info, warn};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+// Every mount, swap device, LVM volume group, LUKS mapping and temporary target file of the installer is recorded here,
+// so that a cleanup after a failed install only releases resources that belong to us.
+const STATE_FILE: &str = "/tmp/aegis-activated";
+
+pub fn track_mount(mountpoint: &str) {
+    track("mount", mountpoint);
+}
+
+pub fn track_swap(blockdevice: &str) {
+    track("swap", blockdevice);
+}
+
+pub fn track_volume_group(name: &str) {
+    track("vg", name);
+}
+
+pub fn track_luks(cryptlabel: &str) {
+    track("luks", cryptlabel);
+}
+
+/// A file written on the target for the duration of a step only, removed by a cleanup after a crash or kill.
+pub fn track_file(path: &str) {
+    track("file", path);
+}
+
+/// LUKS mappings opened by this install, the containers it created.
+pub fn tracked_luks() -> Vec<String> {
+    fs::read_to_string(STATE_FILE)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.strip_prefix("luks "))
+        .map(str::to_string)
+        .collect()
+}
+
+fn track(kind: &str, value: &str) {
+    let file = OpenOptions::new().create(true).append(true).open(STATE_FILE);
+    match file {
+        Ok(mut file) => {
+            if let Err(e) = writeln!(file, "{} {}", kind, value) {
+                warn!("Unable to track {} {}: {}", kind, value, e);
+            }
+        }
+        Err(e) => warn!("Unable to open {}: {}", STATE_FILE, e),
+    }
+}
+
+/// Removes the temporary target files, then releases swap, mounts, LVM volume groups and LUKS mappings left active by a previous (failed) install.
+/// Resources that are already released are skipped, so it is safe to run before every install.
+pub fn cleanup() {
+    let state = match fs::read_to_string(STATE_FILE) {
+        Ok(state) => state,
+        Err(_) => {
+            info!("No resources from a previous install to clean up");
+            return;
+        }
+    };
+    let entries: Vec<(&str, &str)> = state
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .collect();
+
+    // Removed while the target is still mounted
+    for (_, path) in entries.iter().filter(|(kind, _)| *kind == "file") {
+        if Path::new(path).exists() {
+            match fs::remove_file(path) {
+                Ok(_) => info!("Remove {}", path),
+                Err(e) => warn!("Remove {} ERROR: {}", path, e),
+            }
+        }
+    }
+    for (_, blockdevice) in entries.iter().filter(|(kind, _)| *kind == "swap") {
+        if is_listed("/proc/swaps", blockdevice) {
+            release("swapoff", vec![String::from(*blockdevice)], format!("Disable swap on {}", blockdevice));
+        }
+    }
+    // Unmount in reverse order so nested mountpoints are released before their parents
+    for (_, mountpoint) in entries.iter().rev().filter(|(kind, _)| *kind == "mount") {
+        let mountpoint = mountpoint.trim_end_matches('/');
+        let mountpoint = if mountpoint.is_empty() { "/" } else { mountpoint };
+        if is_listed("/proc/mounts", mountpoint) {
+            release("umount", vec![String::from(mountpoint)], format!("Unmount {}", mountpoint));
+        }
+    }
+    // Logical volumes keep the LUKS mapping busy until their volume group is deactivated
+    for (_, name) in entries.iter().filter(|(kind, _)| *kind == "vg") {
+        if Path::new(&format!("/dev/{}", name)).exists() {
+            release(
+                "vgchange",
+                vec![String::from("-an"), String::from(*name)],
+                format!("Deactivate LVM volume group {}", name),
+            );
+        }
+    }
+    for (_, cryptlabel) in entries.iter().filter(|(kind, _)| *kind == "luks") {
+        if Path::new(&format!("/dev/mapper/{}", cryptlabel)).exists() {
+            release(
+                "cryptsetup",
+                vec![String::from("close"), String::from(*cryptlabel)],
+                format!("Close LUKS mapping {}", cryptlabel),
+            );
+        }
+    }
+
+    if let Err(e) = fs::remove_file(STATE_FILE) {
+        warn!("Unable to remove {}: {}", STATE_FILE, e);
+    }
+}
+
+// Cleanup failures are reported but never fatal: the remaining resources must still be released
+fn release(command: &str, args: Vec<String>, logmsg: String) {
+    match exec(command, args) {
+        Ok(status) if status.success() => info!("{}", logmsg),
+        Ok(status) => warn!("{} ERROR: exit code {}", logmsg, status.code().unwrap_or(-1)),
+        Err(e) => warn!("{} ERROR: {}", logmsg, e),
+    }
+}
+
+// Check whether a /proc table lists the given mountpoint (/proc/mounts) or device (/proc/swaps)
+fn is_listed(proc_file: &str, entry: &str) -> bool {
+    let column = if proc_file == "/proc/mounts" { 1 } else { 0 };
+    fs::read_to_string(proc_file)
+        .map(|table| {
+            table
+                .lines()
+                .any(|line| line.split_whitespace().nth(column) == Some(entry))
+        })
+        .unwrap_or(false)
+}
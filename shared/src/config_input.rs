@@ -0,0 +1,235 @@
+use crate::files::SecretFile;
+use crate::log::info;
+use crate::strings::crash;
+use serde::{Deserialize, Deserializer};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const DOWNLOAD_RETRIES: u32 = 3;
+// Keys whose values never reach the logs, at any depth of the config
+const SECRET_KEYS: [&str; 5] = ["password", "rootpass", "passphrase", "psk", "private_key"];
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Deserializes a key given either as a single string or as an array of strings.
+/// Older configs hold a single value where the key has since become a list.
+pub fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// Returns a copy of the config with every secret replaced by "*REDACTED*", for logging.
+/// The config file itself is left untouched, the installer keeps working on the real values.
+pub fn redact(config: &serde_json::Value) -> serde_json::Value {
+    match config {
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    if SECRET_KEYS.contains(&key.as_str()) && !value.is_null() {
+                        (key.clone(), serde_json::Value::from("*REDACTED*"))
+                    } else {
+                        (key.clone(), redact(value))
+                    }
+                })
+                .collect(),
+        ),
+        serde_json::Value::Array(values) => serde_json::Value::Array(values.iter().map(redact).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Turns the config argument into a local file path, downloading it first when it is an
+/// http(s) URL or reading it from stdin when it is "-", and verifies its SHA-256 checksum
+/// when one is given. A downloaded or piped config is returned with the SecretFile that
+/// shreds it once the caller is done.
+pub fn resolve(config: &Path, sha256: Option<&str>, timeout: u64) -> (PathBuf, Option<SecretFile>) {
+    let source = config.to_string_lossy().to_string();
+    let destination = PathBuf::from(format!("/tmp/aegis-config-{}.json", std::process::id()));
+    let temporary = if source.starts_with("http://") || source.starts_with("https://") {
+        // curl truncates the file created here and keeps its mode
+        drop(create_private(&destination));
+        let temporary = SecretFile::new(&destination);
+        if let Err(e) = download(&source, &destination, timeout) {
+            crash(e, 1);
+        }
+        Some(temporary)
+    } else if source == "-" {
+        let file = create_private(&destination);
+        let temporary = SecretFile::new(&destination);
+        if let Err(e) = read_stdin(file) {
+            crash(format!("Read config file from stdin  ERROR: {}", e), 1);
+        }
+        Some(temporary)
+    } else {
+        None
+    };
+    let path = match &temporary {
+        Some(temporary) => temporary.path().to_path_buf(),
+        None => config.to_path_buf(),
+    };
+    if let Some(expected) = sha256 {
+        verify_sha256(&path, &source, expected);
+    }
+    (path, temporary)
+}
+
+/// Writes a generated example config to `output`, or to stdout when no path is given.
+pub fn write_example(example: &str, output: Option<&Path>) {
+    match output {
+        Some(path) => match fs::write(path, example) {
+            Ok(_) => info!("Example config written to {}", path.display()),
+            Err(e) => crash(format!("Write example config {}  ERROR: {}", path.display(), e), 1),
+        },
+        None => print!("{}", example),
+    }
+}
+
+// The config holds password hashes: only root reads it, and a file or symlink planted at the path in the
+// world-writable /tmp makes the install stop instead of being followed
+fn create_private(path: &Path) -> File {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .unwrap_or_else(|e| crash(format!("Create {}  ERROR: {}", path.display(), e), 1))
+}
+
+// The config is stored in a file because read_config works on paths (logging, resume)
+fn read_stdin(mut file: File) -> io::Result<()> {
+    if io::stdin().is_terminal() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no config was piped on stdin"));
+    }
+    let mut data = String::new();
+    io::stdin().read_to_string(&mut data)?;
+    file.write_all(data.as_bytes())?;
+    info!("Read config file from stdin");
+    Ok(())
+}
+
+fn download(url: &str, destination: &Path, timeout: u64) -> Result<(), String> {
+    info!("Downloading config file from {}", url);
+    let output = Command::new("curl")
+        .args([
+            "--silent",
+            "--show-error",
+            "--location",
+            "--fail",
+            "--max-time",
+            &timeout.to_string(),
+            "--retry",
+            &DOWNLOAD_RETRIES.to_string(),
+            "--write-out",
+            "%{http_code}",
+            "--output",
+        ])
+        .arg(destination)
+        .arg(url)
+        .output()
+        .map_err(|e| format!("Download config file {}  ERROR: {}", url, e))?;
+
+    let http_code = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() {
+        info!("Downloaded config file from {} (HTTP {})", url, http_code);
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if http_code.is_empty() || http_code == "000" {
+        Err(format!("Download config file {}  ERROR: {}", url, stderr))
+    } else {
+        Err(format!("Download config file {}  ERROR: HTTP status {}", url, http_code))
+    }
+}
+
+fn verify_sha256(path: &Path, source: &str, expected: &str) {
+    let output = Command::new("sha256sum").arg(path).output();
+    let actual = match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        Ok(output) => crash(
+            format!("Checksum of config file {}  ERROR: {}", source, String::from_utf8_lossy(&output.stderr).trim()),
+            1,
+        ),
+        Err(e) => crash(format!("Checksum of config file {}  ERROR: {}", source, e), 1),
+    };
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        crash(
+            format!("Checksum of config file {} does not match: expected {}, got {}", source, expected, actual),
+            1,
+        );
+    }
+    info!("Config file {} matches the expected SHA-256 checksum", source);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{Cli, Command};
+    use clap::Parser;
+
+    fn config_argument(argv: &[&str]) -> PathBuf {
+        match Cli::try_parse_from(argv).unwrap().command {
+            Command::Config { config, .. } => config,
+            command => panic!("parsed as {:?}", command),
+        }
+    }
+
+    #[test]
+    fn dash_is_stdin() {
+        assert_eq!(config_argument(&["aegis", "config", "-"]), PathBuf::from("-"));
+        assert_eq!(config_argument(&["aegis", "config", "--dry", "-"]), PathBuf::from("-"));
+    }
+
+    #[test]
+    fn double_dash_ends_the_options() {
+        assert_eq!(config_argument(&["aegis", "config", "--", "-"]), PathBuf::from("-"));
+        assert_eq!(config_argument(&["aegis", "config", "--dry", "--", "--config.json"]), PathBuf::from("--config.json"));
+    }
+
+    #[test]
+    fn local_path_is_used_in_place() {
+        let (path, temporary) = resolve(Path::new("/etc/aegis.json"), None, 30);
+        assert_eq!(path, PathBuf::from("/etc/aegis.json"));
+        assert!(temporary.is_none());
+    }
+
+    #[test]
+    fn secrets_are_redacted_at_any_depth() {
+        let config = serde_json::json!({
+            "rootpass": "$6$root$hash",
+            "users": [{ "name": "athena", "password": "$6$user$hash", "shell": "bash" }],
+            "partition": { "encryption": { "passphrase": "correct horse", "tpm2": false } },
+            "networking": { "wifi": [{ "ssid": "home", "psk": "secret" }], "wireguard": { "private_key": "key" } },
+        });
+        let redacted = redact(&config);
+        for pointer in ["/rootpass", "/users/0/password", "/partition/encryption/passphrase", "/networking/wifi/0/psk", "/networking/wireguard/private_key"] {
+            assert_eq!(redacted.pointer(pointer), Some(&serde_json::Value::from("*REDACTED*")), "{}", pointer);
+        }
+        assert_eq!(redacted.pointer("/users/0/name"), Some(&serde_json::Value::from("athena")));
+        assert_eq!(redacted.pointer("/partition/encryption/tpm2"), Some(&serde_json::Value::from(false)));
+        assert!(!redacted.to_string().contains("hash"));
+        // The config itself keeps the real values
+        assert_eq!(config.pointer("/users/0/password"), Some(&serde_json::Value::from("$6$user$hash")));
+    }
+
+    #[test]
+    fn absent_secrets_stay_null() {
+        let config = serde_json::json!({ "rootpass": null, "password": "" });
+        let redacted = redact(&config);
+        assert_eq!(redacted.pointer("/rootpass"), Some(&serde_json::Value::Null));
+        assert_eq!(redacted.pointer("/password"), Some(&serde_json::Value::from("*REDACTED*")));
+    }
+}
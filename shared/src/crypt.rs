@@ -0,0 +1,291 @@
+use std::fs::File;
+use std::io::Read;
+
+// SHA-crypt default rounds, so the hash carries no rounds= parameter
+const ROUNDS: usize = 5000;
+// Bounds of an explicit rounds= parameter, values outside are clamped
+const ROUNDS_MIN: usize = 1000;
+const ROUNDS_MAX: usize = 999_999_999;
+const SALT_LENGTH: usize = 16;
+const CRYPT_ALPHABET: &[u8; 64] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Hashes a password into a $6$ (SHA-512 crypt) hash with a random salt, as accepted by useradd -p and chpasswd -e.
+/// Computed in-process, so the password never appears in a command line or on a pipe.
+pub fn hash_password(password: &str) -> Result<String, std::io::Error> {
+    let mut random = [0u8; SALT_LENGTH];
+    File::open("/dev/urandom")?.read_exact(&mut random)?;
+    let salt: String = random.iter().map(|byte| CRYPT_ALPHABET[(byte % 64) as usize] as char).collect();
+    Ok(sha512_crypt(password.as_bytes(), salt.as_bytes()))
+}
+
+/// SHA-512 crypt as specified by Ulrich Drepper (https://www.akkadia.org/drepper/SHA-crypt.txt), default rounds.
+pub fn sha512_crypt(password: &[u8], salt: &[u8]) -> String {
+    sha512_crypt_rounds(password, salt, None)
+}
+
+// With explicit rounds the hash carries them as $6$rounds=N$salt$...
+fn sha512_crypt_rounds(password: &[u8], salt: &[u8], rounds: Option<usize>) -> String {
+    let salt = &salt[..salt.len().min(SALT_LENGTH)];
+
+    let mut alternate = Sha512::new();
+    alternate.update(password);
+    alternate.update(salt);
+    alternate.update(password);
+    let alternate = alternate.finish();
+
+    let mut digest = Sha512::new();
+    digest.update(password);
+    digest.update(salt);
+    digest.update(&repeat(&alternate, password.len()));
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            digest.update(&alternate);
+        } else {
+            digest.update(password);
+        }
+        length >>= 1;
+    }
+    let mut result = digest.finish();
+
+    let mut p = Sha512::new();
+    for _ in 0..password.len() {
+        p.update(password);
+    }
+    let p = repeat(&p.finish(), password.len());
+
+    let mut s = Sha512::new();
+    for _ in 0..16 + result[0] as usize {
+        s.update(salt);
+    }
+    let s = repeat(&s.finish(), salt.len());
+
+    for round in 0..rounds.map_or(ROUNDS, |rounds| rounds.clamp(ROUNDS_MIN, ROUNDS_MAX)) {
+        let mut c = Sha512::new();
+        if round % 2 == 1 {
+            c.update(&p);
+        } else {
+            c.update(&result);
+        }
+        if round % 3 != 0 {
+            c.update(&s);
+        }
+        if round % 7 != 0 {
+            c.update(&p);
+        }
+        if round % 2 == 1 {
+            c.update(&result);
+        } else {
+            c.update(&p);
+        }
+        result = c.finish();
+    }
+
+    // The digest bytes are encoded in groups of three, in this fixed order
+    const ORDER: [(usize, usize, usize); 21] = [
+        (0, 21, 42), (22, 43, 1), (44, 2, 23), (3, 24, 45), (25, 46, 4), (47, 5, 26), (6, 27, 48),
+        (28, 49, 7), (50, 8, 29), (9, 30, 51), (31, 52, 10), (53, 11, 32), (12, 33, 54), (34, 55, 13),
+        (56, 14, 35), (15, 36, 57), (37, 58, 16), (59, 17, 38), (18, 39, 60), (40, 61, 19), (62, 20, 41),
+    ];
+    let mut encoded = String::new();
+    for (a, b, c) in ORDER {
+        encode(&mut encoded, ((result[a] as u32) << 16) | ((result[b] as u32) << 8) | result[c] as u32, 4);
+    }
+    encode(&mut encoded, result[63] as u32, 2);
+
+    let rounds = rounds.map_or(String::new(), |rounds| format!("rounds={}$", rounds.clamp(ROUNDS_MIN, ROUNDS_MAX)));
+    format!("$6${}{}${}", rounds, String::from_utf8_lossy(salt), encoded)
+}
+
+// The first `length` bytes of the endless repetition of `block`
+fn repeat(block: &[u8; 64], length: usize) -> Vec<u8> {
+    block.iter().copied().cycle().take(length).collect()
+}
+
+fn encode(output: &mut String, mut value: u32, characters: usize) {
+    for _ in 0..characters {
+        output.push(CRYPT_ALPHABET[(value & 0x3f) as usize] as char);
+        value >>= 6;
+    }
+}
+
+const K: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc, 0x3956c25bf348b538,
+    0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118, 0xd807aa98a3030242, 0x12835b0145706fbe,
+    0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2, 0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235,
+    0xc19bf174cf692694, 0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5, 0x983e5152ee66dfab,
+    0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4, 0xc6e00bf33da88fc2, 0xd5a79147930aa725,
+    0x06ca6351e003826f, 0x142929670a0e6e70, 0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df, 0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30, 0xd192e819d6ef5218,
+    0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8, 0x19a4c116b8d2d0c8, 0x1e376c085141ab53,
+    0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8, 0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3, 0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b, 0xca273eceea26619c,
+    0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178, 0x06f067aa72176fba, 0x0a637dc5a2c898a6,
+    0x113f9804bef90dae, 0x1b710b35131c471b, 0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c, 0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
+
+// Minimal streaming SHA-512, only what sha512_crypt needs
+struct Sha512 {
+    state: [u64; 8],
+    buffer: Vec<u8>,
+    length: u128,
+}
+
+impl Sha512 {
+    fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+                0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+            ],
+            buffer: Vec::with_capacity(128),
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.length += data.len() as u128;
+        self.buffer.extend_from_slice(data);
+        let blocks = self.buffer.len() / 128;
+        for i in 0..blocks {
+            let block: [u8; 128] = self.buffer[i * 128..(i + 1) * 128].try_into().unwrap();
+            self.compress(&block);
+        }
+        self.buffer.drain(..blocks * 128);
+    }
+
+    fn finish(mut self) -> [u8; 64] {
+        let bits = self.length * 8;
+        let mut padding = vec![0x80u8];
+        padding.resize((239 - self.buffer.len()) % 128 + 1, 0);
+        padding.extend_from_slice(&bits.to_be_bytes());
+        let length = self.length;
+        self.update(&padding);
+        self.length = length;
+        let mut digest = [0u8; 64];
+        for (chunk, word) in digest.chunks_mut(8).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 128]) {
+        let mut w = [0u64; 80];
+        for (i, chunk) in block.chunks(8).enumerate() {
+            w[i] = u64::from_be_bytes(chunk.try_into().unwrap());
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..80 {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test vectors of https://www.akkadia.org/drepper/SHA-crypt.txt
+    #[test]
+    fn default_rounds() {
+        assert_eq!(
+            sha512_crypt(b"Hello world!", b"saltstring"),
+            "$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1"
+        );
+    }
+
+    #[test]
+    fn explicit_rounds() {
+        let vectors: [(&[u8], &[u8], usize, &str); 5] = [
+            (
+                b"Hello world!",
+                b"saltstringsaltstring",
+                10000,
+                "$6$rounds=10000$saltstringsaltst$OW1/O6BYHV6BcXZu8QVeXbDWra3Oeqh0sbHbbMCVNSnCM/UrjmM0Dp8vOuZeHBy/YTBmSK6H9qs/y3RnOaw5v.",
+            ),
+            (
+                b"This is just a test",
+                b"toolongsaltstring",
+                5000,
+                "$6$rounds=5000$toolongsaltstrin$lQ8jolhgVRVhY4b5pZKaysCLi0QBxGoNeKQzQ3glMhwllF7oGDZxUhx1yxdYcz/e1JSbq3y6JMxxl8audkUEm0",
+            ),
+            (
+                b"a very much longer text to encrypt.  This one even stretches over morethan one line.",
+                b"anotherlongsaltstring",
+                1400,
+                "$6$rounds=1400$anotherlongsalts$POfYwTEok97VWcjxIiSOjiykti.o/pQs.wPvMxQ6Fm7I6IoYN3CmLs66x9t0oSwbtEW7o7UmJEiDwGqd8p4ur1",
+            ),
+            (
+                b"we have a short salt string but not a short password",
+                b"short",
+                77777,
+                "$6$rounds=77777$short$WuQyW2YR.hBNpjjRhpYD/ifIw05xdfeEyQoMxIXbkvr0gge1a1x3yRULJ5CCaUeOxFmtlcGZelFl5CxtgfiAc0",
+            ),
+            (
+                b"a short string",
+                b"asaltof16chars..",
+                123456,
+                "$6$rounds=123456$asaltof16chars..$BtCwjqMJGx5hrJhZywWvt0RLE8uZ4oPwcelCjmw2kSYu.Ec6ycULevoBK25fs2xXgMNrCzIMVcgEJAstJeonj1",
+            ),
+        ];
+        for (password, salt, rounds, expected) in vectors {
+            assert_eq!(sha512_crypt_rounds(password, salt, Some(rounds)), expected);
+        }
+    }
+
+    #[test]
+    fn rounds_below_minimum_are_clamped() {
+        assert_eq!(
+            sha512_crypt_rounds(b"the minimum number is still observed", b"roundstoolow", Some(10)),
+            "$6$rounds=1000$roundstoolow$kUMsbe306n21p9R.FRkW3IGn.S9NPN0x50YhH1xhLsPuWGsUSklZt58jaTfF4ZEQpyUNGc0dqbpBYYBaHHrsX."
+        );
+    }
+
+    // Same result as glibc crypt("", "$6$saltstring")
+    #[test]
+    fn empty_password() {
+        assert_eq!(
+            sha512_crypt(b"", b"saltstring"),
+            "$6$saltstring$kyGrqt6gmjAdtFLPrflEFifSYLCWWq1pyx95SvqinLDy2UHmj0sTF0MSLMwxPFZc3tu5kQckI8fks0zOPda3n1"
+        );
+    }
+
+    #[test]
+    fn salt_truncated_to_16_characters() {
+        assert_eq!(sha512_crypt(b"password", b"toolongsaltstring"), sha512_crypt(b"password", b"toolongsaltstrin"));
+        assert!(sha512_crypt(b"password", b"toolongsaltstring").starts_with("$6$toolongsaltstrin$"));
+    }
+
+    #[test]
+    fn random_salt() {
+        let hash = hash_password("secret").unwrap();
+        assert!(hash.starts_with("$6$"));
+        assert_eq!(hash.split('$').nth(2).map(str::len), Some(SALT_LENGTH));
+        assert_ne!(hash, hash_password("secret").unwrap());
+    }
+}
@@ -1,49 +1,158 @@
+use crate::cleanup;
+use crate::exec::{exec, RunSpec};
+use crate::files;
+use crate::log::{info, warn};
+use crate::returncode_eval::{exec_eval, files_eval, run_eval};
 use std::fs;
+use std::path::Path;
 use std::process::Command;
 
-pub fn find_luks_partitions() -> Vec<(String, String)> {
-    let mut luks_partitions = Vec::new();
-
-    // Iterate over block devices in /dev
-    if let Ok(entries) = fs::read_dir("/dev") {
-        for entry in entries.flatten() {
-            if let Some(device_name) = entry.file_name().to_str() {
-                // Check if the device is a block device
-                if device_name.starts_with("sd") || device_name.starts_with("nvme") || device_name.starts_with("mmcblk") {
-                    let device_path = format!("/dev/{}", device_name);
-
-                    // Check if the device is a LUKS partition
-                    let output = Command::new("cryptsetup")
-                        .arg("luksDump")
-                        .arg(&device_path)
-                        .output()
-                        .expect("Failed to execute cryptsetup");
-                        
-                    // Check if the output contains LUKS header information
-                    if output.status.success() {
-                        if let Some(uuid) = parse_uuid_from_output(&output.stdout) {
-                            luks_partitions.push((device_path, uuid));
-                        }
-                    }
-                }
-            }
-        }
+/// Passphrase written by the frontend, used to format, open and enroll the LUKS partitions.
+pub const PASSPHRASE_FILE: &str = "/tmp/luks";
+/// Path of the generated keyfile on the removable key device.
+pub const KEYFILE: &str = "/crypto_keyfile";
+const KEY_MOUNTPOINT: &str = "/tmp/aegis-keydevice";
+const TPM_DEVICE: &str = "/dev/tpmrm0";
+// Without the key device the boot falls back to the passphrase prompt after this delay
+const KEYFILE_TIMEOUT: &str = "10s";
+
+/// Shreds the passphrase file once every LUKS partition is formatted and enrolled.
+pub fn forget_passphrase() {
+    files::shred_file(Path::new(PASSPHRASE_FILE));
+}
+
+/// Adds a random keyfile stored on `key_partition` as a new keyslot of every LUKS partition of the install.
+/// The passphrase keyslot is kept as a fallback.
+pub fn enroll_keyfile(key_partition: &str) {
+    info!("Enrolling a keyfile stored on {}", key_partition);
+    files_eval(files::create_directory(KEY_MOUNTPOINT), "create the key device mountpoint");
+    exec_eval(
+        exec("mount", vec![String::from(key_partition), String::from(KEY_MOUNTPOINT)]),
+        format!("mount key device {}", key_partition).as_str(),
+    );
+    let keyfile = format!("{}{}", KEY_MOUNTPOINT, KEYFILE);
+    exec_eval(
+        exec(
+            "dd",
+            vec![
+                String::from("if=/dev/urandom"),
+                format!("of={}", keyfile),
+                String::from("bs=512"),
+                String::from("count=8"),
+            ],
+        ),
+        "generate the LUKS keyfile",
+    );
+    exec_eval(
+        exec("chmod", vec![String::from("0400"), String::from(&keyfile)]),
+        "restrict the LUKS keyfile permissions",
+    );
+    for (device_path, _) in find_luks_partitions() {
+        run_eval(
+            RunSpec::new("cryptsetup").args(["luksAddKey", &device_path, &keyfile, "-d", PASSPHRASE_FILE]).run(),
+            format!("add the keyfile to {}", device_path).as_str(),
+        );
     }
+    exec_eval(
+        exec("umount", vec![String::from(KEY_MOUNTPOINT)]),
+        format!("unmount key device {}", key_partition).as_str(),
+    );
+}
 
-    luks_partitions
+/// Whether the machine has a TPM2 usable by systemd-cryptenroll.
+pub fn tpm2_available() -> bool {
+    Path::new(TPM_DEVICE).exists()
 }
 
-fn parse_uuid_from_output(output: &[u8]) -> Option<String> {
-    let output_str = String::from_utf8_lossy(output);
+/// Binds every LUKS partition of the install to the TPM2 (PCR 7, the Secure Boot state) for unattended unlocking.
+/// The passphrase keyslot is kept as a fallback. Skipped with a warning when there is no TPM2.
+pub fn enroll_tpm2() {
+    if !tpm2_available() {
+        warn!("No TPM2 found at {}, TPM2 auto-unlock is skipped", TPM_DEVICE);
+        return;
+    }
+    for (device_path, _) in find_luks_partitions() {
+        run_eval(
+            RunSpec::new("systemd-cryptenroll")
+                .arg(&format!("--unlock-key-file={}", PASSPHRASE_FILE))
+                .args(["--tpm2-device=auto", "--tpm2-pcrs=7", &device_path])
+                .run(),
+            format!("enroll the TPM2 on {}", device_path).as_str(),
+        );
+    }
+}
+
+/// Swap partitions activated by the partitioning, with their PARTUUID.
+/// The PARTUUID survives the swap being re-created with a random key at every boot, a filesystem UUID does not.
+pub fn swap_partitions() -> Vec<(String, String)> {
+    fs::read_to_string("/proc/swaps")
+        .unwrap_or_default()
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|device| device.starts_with("/dev/") && !device.starts_with("/dev/mapper/") && !device.starts_with("/dev/dm-") && !device.starts_with("/dev/zram"))
+        .filter_map(|device| {
+            let output = Command::new("blkid").args(["-s", "PARTUUID", "-o", "value", device]).output().ok()?;
+            let partuuid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            (!partuuid.is_empty()).then(|| (device.to_string(), partuuid))
+        })
+        .collect()
+}
 
-    for line in output_str.lines() {
-        if line.starts_with("UUID:") {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if let Some(uuid) = parts.get(1) {
-                return Some(uuid.to_string());
-            }
+/// UUID of the filesystem on `device`, as reported by blkid.
+pub fn filesystem_uuid(device: &str) -> Option<String> {
+    let output = Command::new("blkid")
+        .args(["-s", "UUID", "-o", "value", device])
+        .output()
+        .ok()?;
+    let uuid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !uuid.is_empty()).then_some(uuid)
+}
+
+/// Kernel parameters for systemd's sd-encrypt to unlock every LUKS partition with the
+/// keyfile on `key_partition` and/or the TPM2, falling back to the passphrase. Only the partitions of the install get one.
+pub fn unlock_kernel_params(key_partition: Option<&str>, tpm2: bool) -> String {
+    let key_uuid = key_partition.and_then(filesystem_uuid);
+    let mut params = String::new();
+    for (_, uuid) in find_luks_partitions() {
+        let mut options = Vec::new();
+        if let Some(key_uuid) = &key_uuid {
+            params.push_str(&format!("rd.luks.key={}={}:UUID={} ", uuid, KEYFILE, key_uuid));
+            options.push(format!("keyfile-timeout={}", KEYFILE_TIMEOUT));
+        }
+        if tpm2 && tpm2_available() {
+            options.push(String::from("tpm2-device=auto"));
+        }
+        if !options.is_empty() {
+            params.push_str(&format!("rd.luks.options={}={} ", uuid, options.join(",")));
         }
     }
+    params
+}
 
-    None
-}
\ No newline at end of file
+/// LUKS partitions created by this install, with their UUID. Only the containers opened by the partitioning
+/// (recorded by cleanup) are listed, the encrypted disks of other systems on the machine are never touched.
+pub fn find_luks_partitions() -> Vec<(String, String)> {
+    cleanup::tracked_luks()
+        .iter()
+        .filter_map(|cryptlabel| {
+            let device = backing_device(cryptlabel)?;
+            let output = Command::new("cryptsetup").args(["luksUUID", &device]).output().ok()?;
+            let uuid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            (output.status.success() && !uuid.is_empty()).then_some((device, uuid))
+        })
+        .collect()
+}
+
+// cryptsetup status prints the partition under the mapping on a "device:" line
+fn backing_device(cryptlabel: &str) -> Option<String> {
+    let output = Command::new("cryptsetup").args(["status", cryptlabel]).output().ok()?;
+    if !output.status.success() {
+        warn!("LUKS mapping {} is not open, it is left out", cryptlabel);
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("device:"))
+        .map(|device| device.trim().to_string())
+}
@@ -1,7 +1,29 @@
-use std::process::Command;
+use crate::args;
+use crate::failure;
+use crate::log::{info, warn};
+use crate::logging;
+use crate::progress;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// When stdout carries JSON progress objects, the output of child processes goes to stderr instead
+fn child_stdout() -> Stdio {
+    if progress::json_enabled() {
+        Stdio::from(io::stderr())
+    } else {
+        Stdio::inherit()
+    }
+}
 
 pub fn exec(command: &str, args: Vec<String>) -> Result<std::process::ExitStatus, std::io::Error> {
-    let returncode = Command::new(command).args(args).status();
+    let returncode = Command::new(command).args(args).stdout(child_stdout()).status();
     returncode
 }
 
@@ -14,10 +36,21 @@ pub fn exec_chroot(
             "-c",
             format!("arch-chroot /mnt {} {}", command, args.join(" ")).as_str(),
         ])
+        .stdout(child_stdout())
         .status();
     returncode
 }
 
+/// The programs not found in any directory of PATH.
+pub fn missing_programs<'a>(programs: &[&'a str]) -> Vec<&'a str> {
+    let paths = std::env::var_os("PATH").unwrap_or_default();
+    programs
+        .iter()
+        .filter(|program| !std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .copied()
+        .collect()
+}
+
 pub fn exec_workdir(
     command: &str,
     workdir: &str,
@@ -26,11 +59,265 @@ pub fn exec_workdir(
     let returncode = Command::new(command)
         .args(args)
         .current_dir(workdir)
+        .stdout(child_stdout())
         .status();
     returncode
 }
 
-pub fn check_if_root() -> bool {
+/// Decides from an output line whether a failed attempt is transient (i.e., a mirror timeout) and worth retrying.
+pub type Classifier = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// A command to run with a timeout and retries, streaming its output to the log line by line.
+pub struct RunSpec {
+    program: String,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    workdir: Option<PathBuf>,
+    timeout: Option<Duration>,
+    retries: u32,
+    backoff: Duration,
+    expected_codes: Vec<i32>,
+    prefix: String,
+    classifier: Option<Classifier>,
+}
+
+/// Outcome of a successful run.
+pub struct RunOutput {
+    pub code: i32,
+    pub attempts: u32,
+}
+
+/// Why a run failed, after its last attempt.
+pub enum RunError {
+    Spawn(io::Error),
+    Timeout(Duration),
+    ExitCode(i32),
+    Signal,
+}
+
+pub type RunResult = Result<RunOutput, RunError>;
+
+impl RunError {
+    /// The exit code to crash with, 124 on timeout like timeout(1).
+    pub fn code(&self) -> i32 {
+        match self {
+            RunError::Spawn(e) => e.raw_os_error().unwrap_or(1),
+            RunError::Timeout(_) => 124,
+            RunError::ExitCode(code) => *code,
+            RunError::Signal => 1,
+        }
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Spawn(e) => write!(f, "{}", e),
+            RunError::Timeout(timeout) => write!(f, "timed out after {}s", timeout.as_secs()),
+            RunError::ExitCode(code) => write!(f, "exit code {}", code),
+            RunError::Signal => write!(f, "killed by a signal"),
+        }
+    }
+}
+
+impl RunSpec {
+    pub fn new(program: &str) -> Self {
+        Self {
+            program: program.to_string(),
+            args: Vec::new(),
+            env: Vec::new(),
+            workdir: None,
+            timeout: None,
+            retries: 0,
+            backoff: Duration::from_secs(5),
+            expected_codes: vec![0],
+            prefix: program.to_string(),
+            classifier: None,
+        }
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    pub fn args<S: AsRef<str>>(mut self, args: impl IntoIterator<Item = S>) -> Self {
+        self.args.extend(args.into_iter().map(|arg| arg.as_ref().to_string()));
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn workdir(mut self, workdir: &str) -> Self {
+        self.workdir = Some(PathBuf::from(workdir));
+        self
+    }
+
+    /// Kill the command if an attempt runs longer than this.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retry a failed attempt up to `retries` times, waiting `backoff` doubled at each retry.
+    pub fn retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Exit codes considered a success, only 0 by default.
+    pub fn expect_codes(mut self, codes: &[i32]) -> Self {
+        self.expected_codes = codes.to_vec();
+        self
+    }
+
+    /// Tag logged output lines with this prefix instead of the program name.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Only retry failed attempts (or timeouts) whose output contains a line matched by `classifier`.
+    /// Without a classifier every failure is retried.
+    pub fn retry_when(mut self, classifier: Classifier) -> Self {
+        self.classifier = Some(classifier);
+        self
+    }
+
+    pub fn run(&self) -> RunResult {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let transient = Arc::new(AtomicBool::new(self.classifier.is_none()));
+            let error = match self.attempt(&transient) {
+                Ok(code) if self.expected_codes.contains(&code) => {
+                    return Ok(RunOutput { code, attempts: attempt });
+                }
+                Ok(code) => RunError::ExitCode(code),
+                Err(error) => error,
+            };
+            let retryable = match error {
+                RunError::Spawn(_) => false,
+                RunError::Timeout(_) => true,
+                _ => transient.load(Ordering::Relaxed),
+            };
+            if !retryable || attempt > self.retries {
+                return Err(error);
+            }
+            let wait = self.backoff * 2u32.saturating_pow(attempt - 1);
+            warn!(
+                "[{}] attempt {} failed ({}), retrying in {}s",
+                self.prefix,
+                attempt,
+                error,
+                wait.as_secs()
+            );
+            thread::sleep(wait);
+        }
+    }
+
+    fn attempt(&self, transient: &Arc<AtomicBool>) -> Result<i32, RunError> {
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .envs(self.env.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // A group of its own, so a timeout kills what the command started too (arch-chroot, pacman, ...).
+            // Outside the foreground group, it must not read the terminal.
+            .process_group(0);
+        if let Some(workdir) = &self.workdir {
+            command.current_dir(workdir);
+        }
+        let mut child = command.spawn().map_err(RunError::Spawn)?;
+
+        let readers = [
+            self.spawn_reader(child.stdout.take().unwrap(), transient),
+            self.spawn_reader(child.stderr.take().unwrap(), transient),
+        ];
+
+        let started = Instant::now();
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) => {}
+                Err(e) => break Err(RunError::Spawn(e)),
+            }
+            if let Some(timeout) = self.timeout {
+                if started.elapsed() >= timeout {
+                    kill_group(child.id());
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break Err(RunError::Timeout(timeout));
+                }
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+        // Children of a killed command may still hold the pipes open, don't wait for them on timeout
+        if status.is_ok() {
+            for reader in readers {
+                let _ = reader.join();
+            }
+        }
+
+        status?.code().ok_or(RunError::Signal)
+    }
+
+    fn spawn_reader<R: Read + Send + 'static>(
+        &self,
+        pipe: R,
+        transient: &Arc<AtomicBool>,
+    ) -> thread::JoinHandle<()> {
+        let prefix = self.prefix.clone();
+        let classifier = self.classifier.clone();
+        let transient = Arc::clone(transient);
+        let step = logging::current_step();
+        thread::spawn(move || {
+            logging::set_step(step);
+            for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+                info!("[{}] {}", prefix, line);
+                failure::record_output(&line);
+                // The classifier may have side effects (i.e., rotating a mirror), run it until the first match
+                if let Some(classifier) = &classifier {
+                    if !transient.load(Ordering::Relaxed) && classifier(&line) {
+                        transient.store(true, Ordering::Relaxed);
+                    }
+                }
+            }
+        })
+    }
+}
+
+// The group id is the pid of its leader, kill takes it negated
+fn kill_group(leader: u32) {
+    let killed = Command::new("kill").args(["-KILL", "--", &format!("-{}", leader)]).status();
+    if !killed.is_ok_and(|status| status.success()) {
+        warn!("Unable to kill the process group {}", leader);
+    }
+}
+
+/// Whether a command changes the system. Dry runs and the config helpers only read files
+/// and print, so they run unprivileged.
+pub fn needs_root(command: &args::Command) -> bool {
+    !matches!(
+        command,
+        args::Command::Config { dry: true, .. }
+            | args::Command::GenerateConfig { .. }
+            | args::Command::ListTemplateVars { .. }
+    )
+}
+
+/// Exits with the command to run again through sudo when the parsed command needs root.
+/// --help and --version never get here, clap handles them while parsing.
+pub fn check_if_root(cli: &args::Cli) -> bool {
+    if !needs_root(&cli.command) {
+        return false;
+    }
     let output = Command::new("id")
         .arg("-u")
         .output()
@@ -39,11 +326,124 @@ pub fn check_if_root() -> bool {
     if let Ok(euid_str) = String::from_utf8(output.stdout) {
         let euid: u32 = euid_str.trim().parse().unwrap_or(1);
         if euid != 0 {
-            eprintln!("You must be root to perform this operation.");
+            let rerun: Vec<String> = std::env::args()
+                .map(|arg| {
+                    if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || "'\"$`\\".contains(c)) {
+                        format!("'{}'", arg.replace('\'', "'\\''"))
+                    } else {
+                        arg
+                    }
+                })
+                .collect();
+            eprintln!("This operation partitions disks and installs packages, it must run as root.");
+            eprintln!("Run it again with:\n  sudo {}", rerun.join(" "));
+            eprintln!("Validating a config with --dry and generate-config work without root.");
             std::process::exit(1);
         }
         return true; // Return true if running as root
     }
 
     false // If there's an error, return false
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn success_on_first_attempt() {
+        let result = RunSpec::new("true").run();
+        assert!(matches!(result, Ok(RunOutput { code: 0, attempts: 1 })));
+    }
+
+    #[test]
+    fn failure_is_retried() {
+        let started = Instant::now();
+        let result = RunSpec::new("false").retries(2, Duration::ZERO).run();
+        assert!(matches!(result, Err(RunError::ExitCode(1))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn retry_until_success() {
+        // Fails until the marker file exists, the first attempt creates it
+        let marker = std::env::temp_dir().join(format!("aegis-exec-retry-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let script = format!("test -e {0} || {{ touch {0}; exit 3; }}", marker.display());
+        let result = RunSpec::new("sh").args(["-c", &script]).retries(3, Duration::ZERO).run();
+        assert!(matches!(result, Ok(RunOutput { code: 0, attempts: 2 })));
+        std::fs::remove_file(marker).unwrap();
+    }
+
+    #[test]
+    fn expected_codes() {
+        let result = RunSpec::new("sh").args(["-c", "exit 3"]).expect_codes(&[0, 3]).run();
+        assert!(matches!(result, Ok(RunOutput { code: 3, attempts: 1 })));
+    }
+
+    #[test]
+    fn classifier_limits_the_retries() {
+        let transient: Classifier = Arc::new(|line: &str| line.contains("mirror timeout"));
+        let permanent = RunSpec::new("sh").args(["-c", "echo no such package; exit 1"]).retries(2, Duration::ZERO).retry_when(transient.clone()).run();
+        assert!(matches!(permanent, Err(RunError::ExitCode(1))));
+        let counter = std::env::temp_dir().join(format!("aegis-exec-classifier-{}", std::process::id()));
+        let _ = std::fs::remove_file(&counter);
+        let script = format!("echo x >> {}; echo mirror timeout; exit 1", counter.display());
+        let retried = RunSpec::new("sh").args(["-c", &script]).retries(2, Duration::ZERO).retry_when(transient).run();
+        assert!(matches!(retried, Err(RunError::ExitCode(1))));
+        assert_eq!(std::fs::read_to_string(&counter).unwrap().lines().count(), 3);
+        std::fs::remove_file(counter).unwrap();
+    }
+
+    #[test]
+    fn timeout_kills_the_command() {
+        let started = Instant::now();
+        let result = RunSpec::new("sleep").arg("30").timeout(Duration::from_millis(300)).run();
+        assert!(matches!(result, Err(RunError::Timeout(_))));
+        assert_eq!(RunError::Timeout(Duration::from_millis(300)).code(), 124);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn timeout_kills_the_whole_process_group() {
+        // The background sleep keeps the output pipes open unless it is killed with its parent
+        let marker = std::env::temp_dir().join(format!("aegis-exec-group-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let script = format!("(sleep 2; touch {}) & sleep 30", marker.display());
+        let result = RunSpec::new("sh").args(["-c", &script]).timeout(Duration::from_millis(300)).run();
+        assert!(matches!(result, Err(RunError::Timeout(_))));
+        thread::sleep(Duration::from_secs(3));
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn missing_program_is_not_retried() {
+        let result = RunSpec::new("aegis-no-such-program").retries(3, Duration::from_secs(60)).run();
+        assert!(matches!(result, Err(RunError::Spawn(_))));
+    }
+
+    fn command(argv: &[&str]) -> args::Command {
+        use clap::Parser;
+        args::Cli::try_parse_from([&["aegis"], argv].concat()).unwrap().command
+    }
+
+    #[test]
+    fn needs_root_matrix() {
+        let matrix: [(&[&str], bool); 11] = [
+            (&["config", "aegis.json", "--dry"], false),
+            (&["config", "-", "--dry", "--set", "HOST=athena"], false),
+            (&["generate-config"], false),
+            (&["generate-config", "--base", "nix", "--output", "aegis.json"], false),
+            (&["list-template-vars"], false),
+            (&["config", "aegis.json"], true),
+            (&["config", "aegis.json", "--resume"], true),
+            (&["genfstab"], true),
+            (&["enable-services"], true),
+            (&["cleanup"], true),
+            (&["import-settings", "/dev/sda2"], true),
+        ];
+        for (argv, expected) in matrix {
+            assert_eq!(needs_root(&command(argv)), expected, "{}", argv.join(" "));
+        }
+    }
+}
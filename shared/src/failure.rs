@@ -0,0 +1,159 @@
+use regex::Regex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+// Number of command output lines kept for the failure report
+const OUTPUT_TAIL: usize = 30;
+
+static PHASE: Mutex<Option<String>> = Mutex::new(None);
+static TIMED_OUT: Mutex<bool> = Mutex::new(false);
+static OUTPUT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static FAILURE: Mutex<Option<Failure>> = Mutex::new(None);
+
+/// What went wrong, so a frontend can show a targeted message and decide whether a retry makes sense.
+/// The exit code of the backend follows the category:
+/// 2 invalid config, 10 partitioning, 20 package install, 21 network timeout, 30 bootloader,
+/// 40 system configuration, 50 users. Failures outside of an install phase (category "other")
+/// keep the exit code of the failed operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    InvalidConfig,
+    Partitioning,
+    PackageInstall,
+    NetworkTimeout,
+    Bootloader,
+    SystemConfiguration,
+    Users,
+    Other,
+}
+
+impl Category {
+    fn exit_code(self, code: i32) -> i32 {
+        match self {
+            Category::InvalidConfig => 2,
+            Category::Partitioning => 10,
+            Category::PackageInstall => 20,
+            Category::NetworkTimeout => 21,
+            Category::Bootloader => 30,
+            Category::SystemConfiguration => 40,
+            Category::Users => 50,
+            Category::Other => code,
+        }
+    }
+
+    // Running the same install again can only help when the cause was outside of the machine
+    fn retryable(self) -> bool {
+        matches!(self, Category::PackageInstall | Category::NetworkTimeout)
+    }
+
+    fn hint(self) -> &'static str {
+        match self {
+            Category::InvalidConfig => "Fix the reported config problems and start the install again.",
+            Category::Partitioning => "Check that the disk is not in use or mounted, and that the partition layout fits on it.",
+            Category::PackageInstall => "A package could not be installed, the mirrors may be out of sync. Retry in a few minutes.",
+            Category::NetworkTimeout => "A download timed out, check the network connection and retry.",
+            Category::Bootloader => "Check the EFI partition or the boot device chosen for the bootloader.",
+            Category::SystemConfiguration => "The system configuration failed, the log has the details.",
+            Category::Users => "Check the user names and password hashes in the config.",
+            Category::Other => "The log has the details.",
+        }
+    }
+
+    // Install phases, as named by the checkpoints, mapped to what failing in them means
+    fn from_phase(phase: &str, timed_out: bool) -> Self {
+        match phase {
+            "partitioning" => Category::Partitioning,
+            "base" | "packages" | "desktop" | "extra_packages" | "install" if timed_out => Category::NetworkTimeout,
+            "base" | "packages" | "desktop" | "extra_packages" | "install" => Category::PackageInstall,
+            "bootloader" => Category::Bootloader,
+            "users" => Category::Users,
+            _ => Category::SystemConfiguration,
+        }
+    }
+}
+
+/// The report of a failed run, written to the status file and the final --progress-json line.
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+    pub category: Category,
+    pub exit_code: i32,
+    pub phase: Option<String>,
+    pub message: String,
+    pub package: Option<String>,
+    pub output_tail: Vec<String>,
+    pub retryable: bool,
+    pub hint: &'static str,
+}
+
+/// Marks the start of an install phase, failures from now on are attributed to it.
+pub fn enter_phase(phase: &str) {
+    *PHASE.lock().unwrap_or_else(|e| e.into_inner()) = Some(phase.to_string());
+    *TIMED_OUT.lock().unwrap_or_else(|e| e.into_inner()) = false;
+}
+
+/// Notes that a command of the current phase was killed by its timeout.
+pub fn command_timed_out() {
+    *TIMED_OUT.lock().unwrap_or_else(|e| e.into_inner()) = true;
+}
+
+/// Keeps the last lines printed by commands, they end up in the failure report.
+pub fn record_output(line: &str) {
+    let mut output = OUTPUT.lock().unwrap_or_else(|e| e.into_inner());
+    if output.len() == OUTPUT_TAIL {
+        output.pop_front();
+    }
+    output.push_back(line.to_string());
+}
+
+/// Records why the run failed and returns the exit code to leave with.
+/// Without a category, it is deduced from the phase running at the time of the failure.
+pub fn report(message: &str, code: i32, category: Option<Category>) -> i32 {
+    let phase = PHASE.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let timed_out = *TIMED_OUT.lock().unwrap_or_else(|e| e.into_inner());
+    let category = category.unwrap_or_else(|| match &phase {
+        Some(phase) => Category::from_phase(phase, timed_out),
+        None => Category::Other,
+    });
+    let output_tail: Vec<String> = OUTPUT.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect();
+    let package = match category {
+        Category::PackageInstall | Category::NetworkTimeout => failing_package(&output_tail),
+        _ => None,
+    };
+    let failure = Failure {
+        category,
+        exit_code: category.exit_code(code),
+        phase,
+        message: message.to_string(),
+        package,
+        output_tail,
+        retryable: category.retryable(),
+        hint: category.hint(),
+    };
+    // With parallel steps only the first failure is the cause, the others follow from it
+    FAILURE.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert(failure).exit_code
+}
+
+/// The failure reported by this run, if any.
+pub fn last() -> Option<Failure> {
+    FAILURE.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+// Package named by the error lines of pacman, dnf or nix
+fn failing_package(output: &[String]) -> Option<String> {
+    let patterns = [
+        r"target not found: (\S+)",
+        r"failed retrieving file '([a-zA-Z0-9@._+\-]+?)-[0-9][^']*'",
+        r"No match for argument: (\S+)",
+        r"builder for '/nix/store/[a-z0-9]+-([^']+?)\.drv' failed",
+    ];
+    for line in output.iter().rev() {
+        for pattern in patterns {
+            if let Some(captures) = Regex::new(pattern).unwrap().captures(line) {
+                return Some(captures[1].to_string());
+            }
+        }
+    }
+    None
+}
@@ -1,9 +1,67 @@
-use crate::log::{info};
+use crate::log::{info, warn};
 use crate::strings::crash;
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, Write, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use regex::Regex;
 
+// Every live SecretFile, so crash can shred them before exiting without running the destructors
+static SECRET_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// A file holding secrets (passphrase, config with password hashes), shredded when dropped.
+pub struct SecretFile {
+    path: PathBuf,
+}
+
+impl SecretFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        SECRET_FILES.lock().unwrap_or_else(|e| e.into_inner()).push(path.clone());
+        SecretFile { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SecretFile {
+    fn drop(&mut self) {
+        shred_file(&self.path);
+        SECRET_FILES.lock().unwrap_or_else(|e| e.into_inner()).retain(|path| path != &self.path);
+    }
+}
+
+/// Shreds every file still held by a SecretFile, for exits that skip the destructors.
+pub fn shred_secrets() {
+    let paths = std::mem::take(&mut *SECRET_FILES.lock().unwrap_or_else(|e| e.into_inner()));
+    for path in paths {
+        shred_file(&path);
+    }
+}
+
+/// Overwrites a file with zeros before removing it, so its content does not linger on the tmpfs.
+/// Best effort: failures are only logged, a missing file is not an error.
+pub fn shred_file(path: &Path) {
+    if !path.exists() {
+        return;
+    }
+    let overwrite = || -> io::Result<()> {
+        let length = fs::metadata(path)?.len();
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.write_all(&vec![0u8; length as usize])?;
+        file.sync_all()
+    };
+    if let Err(e) = overwrite() {
+        warn!("Overwrite {}: Failed with error {}", path.display(), e);
+    }
+    match fs::remove_file(path) {
+        Ok(_) => info!("Shred {}", path.display()),
+        Err(e) => warn!("Remove {}: Failed with error {}", path.display(), e),
+    }
+}
+
 pub fn create_file(path: &str) {
     let returncode = File::create(path);
     match returncode {
@@ -98,3 +156,50 @@ pub fn replace_line_in_file(path: &str, search: &str, replacement: &str) -> io::
 pub fn create_directory(path: &str) -> std::io::Result<()> { // Create all missing dirs in the specified path
     std::fs::create_dir_all(path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("aegis-files-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    // One test, shred_secrets would shred the SecretFiles of tests running in parallel
+    #[test]
+    fn secret_files_are_shredded() {
+        let directory = scratch("secrets");
+
+        let dropped = directory.join("dropped.json");
+        fs::write(&dropped, "{\"rootpass\": \"$6$x$y\"}").unwrap();
+        // A second name of the same inode shows what the secret was overwritten with
+        let witness = directory.join("witness");
+        fs::hard_link(&dropped, &witness).unwrap();
+        let secret = SecretFile::new(&dropped);
+        assert_eq!(secret.path(), dropped.as_path());
+        drop(secret);
+        assert!(!dropped.exists());
+        assert_eq!(fs::read(&witness).unwrap(), vec![0u8; 22]);
+
+        let crashed = directory.join("crashed.json");
+        fs::write(&crashed, "secret").unwrap();
+        let secret = SecretFile::new(&crashed);
+        shred_secrets();
+        assert!(!crashed.exists());
+        assert!(SECRET_FILES.lock().unwrap().is_empty());
+        // Already shredded, dropping it later is harmless
+        drop(secret);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn shredding_a_missing_file_is_harmless() {
+        let directory = scratch("missing");
+        shred_file(&directory.join("never-written"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
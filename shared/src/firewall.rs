@@ -0,0 +1,77 @@
+/// Parses an allowed port entry, a port from 1 to 65535 or a "first-last" range of them, into its bounds.
+pub fn parse_port(entry: &str) -> Result<(u16, u16), String> {
+    let port = |value: &str| match value.trim().parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(format!("\"{}\" is not a port between 1 and 65535", value.trim())),
+    };
+    match entry.split_once('-') {
+        Some((first, last)) => {
+            let (first, last) = (port(first)?, port(last)?);
+            if first > last {
+                return Err(format!("range \"{}\" ends before it starts", entry));
+            }
+            Ok((first, last))
+        }
+        None => port(entry).map(|port| (port, port)),
+    }
+}
+
+fn nft_set(ports: &[String]) -> String {
+    let ports: Vec<String> = ports
+        .iter()
+        .filter_map(|entry| parse_port(entry).ok())
+        .map(|(first, last)| if first == last { first.to_string() } else { format!("{}-{}", first, last) })
+        .collect();
+    format!("{{ {} }}", ports.join(", "))
+}
+
+/// Ruleset for /etc/nftables.conf. Loopback, ICMP and the replies to outgoing traffic are always let in,
+/// with deny_incoming everything else that does not reach an allowed port is dropped.
+pub fn nftables_ruleset(deny_incoming: bool, tcp_ports: &[String], udp_ports: &[String]) -> String {
+    let policy = if deny_incoming { "drop" } else { "accept" };
+    let mut allowed = String::new();
+    if !tcp_ports.is_empty() {
+        allowed.push_str(&format!("    tcp dport {} accept\n", nft_set(tcp_ports)));
+    }
+    if !udp_ports.is_empty() {
+        allowed.push_str(&format!("    udp dport {} accept\n", nft_set(udp_ports)));
+    }
+    format!(
+        r#"#!/usr/bin/nft -f
+# Written by aegis
+
+flush ruleset
+
+table inet filter {{
+  chain input {{
+    type filter hook input priority filter; policy {policy};
+    ct state invalid drop
+    ct state {{ established, related }} accept
+    iif lo accept
+    meta l4proto {{ icmp, ipv6-icmp }} accept
+{allowed}  }}
+  chain forward {{
+    type filter hook forward priority filter; policy {policy};
+  }}
+  chain output {{
+    type filter hook output priority filter; policy accept;
+  }}
+}}
+"#
+    )
+}
+
+/// Human-readable description of the firewall, shown with the config summary.
+pub fn summary(backend: &str, deny_incoming: bool, tcp_ports: &[String], udp_ports: &[String]) -> String {
+    if backend.is_empty() || backend.eq_ignore_ascii_case("none") {
+        return String::from("Firewall : none, all incoming connections are accepted");
+    }
+    let list = |ports: &[String]| if ports.is_empty() { String::from("none") } else { ports.join(", ") };
+    format!(
+        "Firewall : {}, incoming connections {}, allowed TCP ports: {}, allowed UDP ports: {}",
+        backend.to_lowercase(),
+        if deny_incoming { "denied" } else { "accepted" },
+        list(tcp_ports),
+        list(udp_ports),
+    )
+}
@@ -0,0 +1,121 @@
+/// Defaults derived from the installer language. They only fill values the config leaves empty.
+pub struct Language {
+    pub name: &'static str,
+    pub code: &'static str,
+    pub locale: &'static str,
+    // Console keymap and X11 layout, which are named differently for some languages
+    pub keymap: &'static str,
+    pub layout: &'static str,
+    pub timezone_region: &'static str,
+    // Mozilla language pack code, i.e. es-ES
+    pub firefox: &'static str,
+    // Suffixes of the Arch libreoffice-fresh-* and hunspell-* packages, when they exist
+    pub libreoffice: Option<&'static str>,
+    pub hunspell: Option<&'static str>,
+}
+
+pub const LANGUAGES: [Language; 12] = [
+    Language { name: "English", code: "en", locale: "en_US.UTF-8", keymap: "us", layout: "us", timezone_region: "America", firefox: "en-US", libreoffice: None, hunspell: Some("en_us") },
+    Language { name: "Italiano", code: "it", locale: "it_IT.UTF-8", keymap: "it", layout: "it", timezone_region: "Europe", firefox: "it", libreoffice: Some("it"), hunspell: Some("it") },
+    Language { name: "Deutsch", code: "de", locale: "de_DE.UTF-8", keymap: "de", layout: "de", timezone_region: "Europe", firefox: "de", libreoffice: Some("de"), hunspell: Some("de") },
+    Language { name: "Français", code: "fr", locale: "fr_FR.UTF-8", keymap: "fr", layout: "fr", timezone_region: "Europe", firefox: "fr", libreoffice: Some("fr"), hunspell: Some("fr") },
+    Language { name: "Español", code: "es", locale: "es_ES.UTF-8", keymap: "es", layout: "es", timezone_region: "Europe", firefox: "es-ES", libreoffice: Some("es"), hunspell: Some("es_es") },
+    Language { name: "Português", code: "pt", locale: "pt_PT.UTF-8", keymap: "pt-latin1", layout: "pt", timezone_region: "Europe", firefox: "pt-PT", libreoffice: Some("pt"), hunspell: None },
+    Language { name: "Português do Brasil", code: "pt_br", locale: "pt_BR.UTF-8", keymap: "br-abnt2", layout: "br", timezone_region: "America", firefox: "pt-BR", libreoffice: Some("pt-br"), hunspell: None },
+    Language { name: "Nederlands", code: "nl", locale: "nl_NL.UTF-8", keymap: "nl", layout: "nl", timezone_region: "Europe", firefox: "nl", libreoffice: Some("nl"), hunspell: Some("nl") },
+    Language { name: "Polski", code: "pl", locale: "pl_PL.UTF-8", keymap: "pl", layout: "pl", timezone_region: "Europe", firefox: "pl", libreoffice: Some("pl"), hunspell: None },
+    Language { name: "Русский", code: "ru", locale: "ru_RU.UTF-8", keymap: "ru", layout: "ru", timezone_region: "Europe", firefox: "ru", libreoffice: Some("ru"), hunspell: None },
+    Language { name: "日本語", code: "ja", locale: "ja_JP.UTF-8", keymap: "jp106", layout: "jp", timezone_region: "Asia", firefox: "ja", libreoffice: Some("ja"), hunspell: None },
+    Language { name: "中文", code: "zh_cn", locale: "zh_CN.UTF-8", keymap: "us", layout: "us", timezone_region: "Asia", firefox: "zh-CN", libreoffice: Some("zh-cn"), hunspell: None },
+];
+
+/// Finds a language by its displayed name or its code, case-insensitively.
+pub fn lookup(language: &str) -> Option<&'static Language> {
+    let language = language.trim().to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|candidate| candidate.name.to_lowercase() == language || candidate.code == language)
+}
+
+/// Names accepted by lookup, for error messages.
+pub fn names() -> Vec<&'static str> {
+    LANGUAGES.iter().map(|language| language.name).collect()
+}
+
+/// Fills the locale and keyboard values left empty in the config, set values are always kept.
+pub fn fill_defaults(language: &Language, locales: &mut Vec<String>, virtkeymap: &mut String, x11keymap: &mut String) {
+    if locales.is_empty() {
+        locales.push(format!("{} UTF-8", language.locale));
+    }
+    if virtkeymap.is_empty() {
+        *virtkeymap = language.keymap.to_string();
+    }
+    if x11keymap.is_empty() {
+        *x11keymap = language.layout.to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_by_name_or_code() {
+        assert_eq!(lookup("Deutsch").map(|language| language.locale), Some("de_DE.UTF-8"));
+        assert_eq!(lookup(" italiano ").map(|language| language.locale), Some("it_IT.UTF-8"));
+        assert_eq!(lookup("PT_BR").map(|language| language.locale), Some("pt_BR.UTF-8"));
+        assert_eq!(lookup("日本語").map(|language| language.keymap), Some("jp106"));
+        assert!(lookup("Klingon").is_none());
+        assert!(lookup("").is_none());
+    }
+
+    #[test]
+    fn locale_matches_the_language_code() {
+        for language in &LANGUAGES {
+            let (territory, charset) = language.locale.split_once('.').unwrap();
+            assert_eq!(charset, "UTF-8", "{}", language.name);
+            let (code, country) = territory.split_once('_').unwrap();
+            assert!(
+                language.code == code || language.code == territory.to_lowercase(),
+                "{} has code {} and locale {}",
+                language.name,
+                language.code,
+                language.locale
+            );
+            assert_eq!(country, country.to_uppercase(), "{}", language.name);
+        }
+    }
+
+    #[test]
+    fn names_and_codes_are_unique() {
+        for (i, language) in LANGUAGES.iter().enumerate() {
+            for other in &LANGUAGES[i + 1..] {
+                assert_ne!(language.code, other.code);
+                assert_ne!(language.name.to_lowercase(), other.name.to_lowercase());
+            }
+        }
+        assert_eq!(names().len(), LANGUAGES.len());
+    }
+
+    #[test]
+    fn keyboard_names_differ_between_console_and_x11() {
+        let portuguese = lookup("pt").unwrap();
+        assert_eq!((portuguese.keymap, portuguese.layout), ("pt-latin1", "pt"));
+        let brazilian = lookup("pt_br").unwrap();
+        assert_eq!((brazilian.keymap, brazilian.layout), ("br-abnt2", "br"));
+    }
+
+    #[test]
+    fn defaults_fill_only_empty_values() {
+        let french = lookup("fr").unwrap();
+        let (mut locales, mut virtkeymap, mut x11keymap) = (Vec::new(), String::new(), String::new());
+        fill_defaults(french, &mut locales, &mut virtkeymap, &mut x11keymap);
+        assert_eq!(locales, vec![String::from("fr_FR.UTF-8 UTF-8")]);
+        assert_eq!((virtkeymap.as_str(), x11keymap.as_str()), ("fr", "fr"));
+
+        let (mut locales, mut virtkeymap, mut x11keymap) = (vec![String::from("en_GB.UTF-8 UTF-8")], String::from("uk"), String::new());
+        fill_defaults(french, &mut locales, &mut virtkeymap, &mut x11keymap);
+        assert_eq!(locales, vec![String::from("en_GB.UTF-8 UTF-8")]);
+        assert_eq!((virtkeymap.as_str(), x11keymap.as_str()), ("uk", "fr"));
+    }
+}
@@ -1,40 +1,210 @@
-use flexi_logger::{style, DeferredNow, FileSpec, LogSpecification, Logger, Duplicate};
-use log::LevelFilter;
-use std::fs;
+use crate::args::LogFormat;
+use flexi_logger::{
+    style, Cleanup, Criterion, DeferredNow, Duplicate, FileSpec, LogSpecification, Logger, Naming,
+};
+use log::{info, warn, LevelFilter};
+use serde_json::json;
+use std::env;
+use std::fs::{self, OpenOptions};
 use std::io::Write;
-use crate::files;
-
-pub fn init(verbosity: u8, log_file_path: &str) {
-    let log_specification = match verbosity {
-        0 => LogSpecification::builder()
-            .default(LevelFilter::Info)
-            .build(),
-        1 => LogSpecification::builder()
-            .default(LevelFilter::Debug)
-            .build(),
-        _ => LogSpecification::builder()
-            .default(LevelFilter::Trace)
-            .build(),
+use std::path::{Path, PathBuf};
+use std::cell::RefCell;
+use std::process::exit;
+use std::sync::OnceLock;
+
+// The log lives on the live medium tmpfs and pacman output can grow it to gigabytes.
+// It is rotated by size, and a symlink with the usual name always points to the active file.
+const ROTATE_SIZE: u64 = 32 * 1024 * 1024;
+const ROTATE_KEEP: usize = 4;
+
+pub const DEFAULT_LOG_FILE: &str = "/tmp/aegis.log";
+// Child processes (i.e., a backend started by a frontend) read the log path from here
+pub const LOG_FILE_ENV: &str = "AEGIS_LOG";
+
+static LOG_FILE: OnceLock<PathBuf> = OnceLock::new();
+// Index of the first segment rotated by this process, the older ones belong to previous runs
+static FIRST_SEGMENT: OnceLock<u32> = OnceLock::new();
+
+thread_local! {
+    // Name of the install step running on this thread, prefixed to its log lines
+    static STEP: RefCell<Option<&'static str>> = const { RefCell::new(None) };
+}
+
+pub fn set_step(step: Option<&'static str>) {
+    STEP.with(|current| *current.borrow_mut() = step);
+}
+
+pub fn current_step() -> Option<&'static str> {
+    STEP.with(|current| *current.borrow())
+}
+
+/// The log file given by --log-file, else by $AEGIS_LOG, else /tmp/aegis.log.
+pub fn resolve_log_file(log_file: Option<PathBuf>) -> PathBuf {
+    log_file
+        .or_else(|| env::var_os(LOG_FILE_ENV).filter(|path| !path.is_empty()).map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_LOG_FILE))
+}
+
+/// The log file the logger was started with.
+pub fn log_file() -> &'static Path {
+    LOG_FILE.get().map(PathBuf::as_path).unwrap_or(Path::new(DEFAULT_LOG_FILE))
+}
+
+/// Maps the -v and -q counts to a level, starting from info.
+pub fn level(verbose: u8, quiet: u8) -> LevelFilter {
+    match i16::from(verbose) - i16::from(quiet) {
+        i16::MIN..=-2 => LevelFilter::Error,
+        -1 => LevelFilter::Warn,
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+pub fn init(level: LevelFilter, format: LogFormat, log_file: &Path) {
+    let log_specification = LogSpecification::builder().default(level).build();
+
+    // The log of a previous run is kept: the logger rotates its active segment out when it starts, and a
+    // plain file at the log path (written by hand or before the log was rotated) is moved to <log>.old
+    let moved = match fs::symlink_metadata(log_file) {
+        Ok(metadata) if metadata.file_type().is_symlink() => fs::remove_file(log_file).map(|_| None),
+        Ok(_) => {
+            let old = PathBuf::from(format!("{}.old", log_file.display()));
+            fs::rename(log_file, &old).map(|_| Some(old))
+        }
+        Err(_) => Ok(None),
     };
+    let moved = moved.unwrap_or_else(|e| {
+        eprintln!("Cannot replace the log file {}: {}", log_file.display(), e);
+        exit(1);
+    });
 
-    // Check if the log file already exists
-    if fs::metadata(log_file_path).is_ok() {
-        // If an old log file exists, remove it
-        files::remove_file(log_file_path);
+    // The logger is not running yet, so a log file that cannot be written is reported on stderr
+    let writable = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .and_then(|_| fs::remove_file(log_file));
+    if let Err(e) = writable {
+        eprintln!("Cannot write the log file {}: {}", log_file.display(), e);
+        eprintln!("Choose another path with --log-file or the {} environment variable.", LOG_FILE_ENV);
+        exit(1);
     }
-    
+    env::set_var(LOG_FILE_ENV, log_file);
+    let _ = LOG_FILE.set(log_file.to_path_buf());
+
+    let (directory, basename, suffix) = split_log_path(log_file);
+
+    let file_format = match format {
+        LogFormat::Text => format_log_entry,
+        LogFormat::Json => format_json_entry,
+    };
+
     // Create a file-based logger and specify the log file path
     Logger::with(log_specification)
         .log_to_file(
             FileSpec::default()
-                .basename(log_file_path)
-                .suffix("log") // log file extension. Not using .log because does not apply ANSI color code
+                .directory(directory)
+                .basename(basename)
+                .suffix(suffix)
                 .suppress_timestamp(),
         )
+        .rotate(
+            Criterion::Size(ROTATE_SIZE),
+            Naming::Numbers, // the active file is aegis_rCURRENT.log, older ones are aegis_r00000.log, ...
+            Cleanup::KeepLogFiles(ROTATE_KEEP),
+        )
+        .create_symlink(log_file) // readers and persist_log keep using the configured path
         .duplicate_to_stderr(Duplicate::All) // Duplicate logs to stderr for console output
-        .format(format_log_entry)
+        .format_for_files(file_format)
+        .format_for_stderr(if plain_console() { format_plain_entry } else { format_log_entry })
         .start() // It will create the new log file
         .unwrap();
+    let _ = FIRST_SEGMENT.set(rotated_segments(log_file).last().map_or(0, |(index, _)| index + 1));
+    if let Some(old) = moved {
+        info!("The previous log was moved to {}", old.display());
+    }
+}
+
+fn split_log_path(log_file: &Path) -> (&Path, String, String) {
+    let directory = log_file.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let basename = log_file.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let suffix = log_file.extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or_default();
+    (directory, basename, suffix)
+}
+
+// Rotated segments next to the log, basename_r00000.suffix, basename_r00001.suffix, ... oldest first
+fn rotated_segments(log_file: &Path) -> Vec<(u32, PathBuf)> {
+    let (directory, basename, suffix) = split_log_path(log_file);
+    let prefix = format!("{}_r", basename);
+    let extension = format!(".{}", suffix);
+    let mut segments: Vec<(u32, PathBuf)> = fs::read_dir(directory)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let index = name.strip_prefix(&prefix)?.strip_suffix(&extension)?.parse().ok()?;
+            Some((index, entry.path()))
+        })
+        .collect();
+    segments.sort();
+    segments
+}
+
+// The segments of this run in the order they were written, the symlink to the active one last
+fn log_segments(log_file: &Path) -> Vec<PathBuf> {
+    let first = FIRST_SEGMENT.get().copied().unwrap_or(0);
+    let mut segments: Vec<PathBuf> =
+        rotated_segments(log_file).into_iter().filter(|(index, _)| *index >= first).map(|(_, path)| path).collect();
+    segments.push(log_file.to_path_buf());
+    segments
+}
+
+fn concatenate(segments: &[PathBuf], destination: &str) -> std::io::Result<()> {
+    let mut output = fs::File::create(destination)?;
+    for segment in segments {
+        std::io::copy(&mut fs::File::open(segment)?, &mut output)?;
+    }
+    output.sync_all()
+}
+
+/// Copies the installation log to `destination`, creating its parent directories. The segments rotated
+/// during the install are written first, so the copy reads as one log from the start.
+/// A read-only or full destination is only reported, it must never abort an install.
+pub fn persist_log(destination: &str) -> bool {
+    let segments = log_segments(log_file());
+    if let Some(parent) = Path::new(destination).parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            warn!("Unable to create {}: {}", parent.display(), e);
+            return false;
+        }
+    }
+    match concatenate(&segments, destination) {
+        Ok(()) => {
+            info!("Installation log saved to {}", destination);
+            true
+        }
+        Err(e) => {
+            warn!("Unable to save installation log to {}: {}", destination, e);
+            false
+        }
+    }
+}
+
+/// Whether the console cannot render colors: NO_COLOR is set, TERM is dumb or a VT100, or stderr is a serial line.
+pub fn plain_console() -> bool {
+    if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        return true;
+    }
+    if env::var("TERM").is_ok_and(|term| matches!(term.as_str(), "dumb" | "vt100" | "vt102" | "vt220")) {
+        return true;
+    }
+    // ttyS* are the 8250 UARTs, ttyAMA* and ttyUSB* the serial consoles of ARM boards and adapters
+    fs::read_link("/proc/self/fd/2").is_ok_and(|tty| {
+        let tty = tty.to_string_lossy();
+        ["/dev/ttyS", "/dev/ttyAMA", "/dev/ttyUSB"].iter().any(|serial| tty.starts_with(serial))
+    })
 }
 
 /// Formats a log entry with color
@@ -43,7 +213,28 @@ fn format_log_entry(
     now: &mut DeferredNow,
     record: &log::Record,
 ) -> std::io::Result<()> {
-    let msg = record.args().to_string();
+    write_entry(w, now, record, true)
+}
+
+/// Formats a log entry without escape sequences, for consoles that cannot render them
+fn format_plain_entry(
+    w: &mut dyn Write,
+    now: &mut DeferredNow,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    write_entry(w, now, record, false)
+}
+
+fn write_entry(
+    w: &mut dyn Write,
+    now: &mut DeferredNow,
+    record: &log::Record,
+    colored: bool,
+) -> std::io::Result<()> {
+    let msg = match current_step() {
+        Some(step) => format!("[{}] {}", step, record.args()),
+        None => record.args().to_string(),
+    };
     let level = record.level();
     
     // Get the current time as a NaiveTime
@@ -52,11 +243,87 @@ fn format_log_entry(
     // Format the time as a string
     let time_str = time.format("%H:%M:%S").to_string();
     
+    let level_str = if colored { style(level).paint(level.to_string()).to_string() } else { level.to_string() };
     write!(
         w,
         "[ {} ] {} {}",
-        style(level).paint(level.to_string()),
+        level_str,
         time_str,
         msg
     )
 }
+
+/// Formats a log entry as a JSON object on a single line
+fn format_json_entry(
+    w: &mut dyn Write,
+    now: &mut DeferredNow,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    let entry = json!({
+        "time": now.now().format("%Y-%m-%dT%H:%M:%S%.3f%:z").to_string(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "step": current_step(),
+        "message": record.args().to_string(),
+    });
+    write!(w, "{}", entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of its own per test, tests run in parallel
+    fn scratch(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("aegis-logging-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn rotated_segments_oldest_first() {
+        let directory = scratch("segments");
+        for name in ["aegis_r00010.log", "aegis_r00002.log", "aegis_rCURRENT.log", "aegis_r00000.log", "other_r00001.log", "aegis_r00001.txt"] {
+            fs::write(directory.join(name), name).unwrap();
+        }
+        let segments: Vec<u32> = rotated_segments(&directory.join("aegis.log")).into_iter().map(|(index, _)| index).collect();
+        assert_eq!(segments, vec![0, 2, 10]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn persisted_log_concatenates_the_segments() {
+        let directory = scratch("persist");
+        let log_file = directory.join("aegis.log");
+        fs::write(directory.join("aegis_r00001.log"), "second\n").unwrap();
+        fs::write(directory.join("aegis_r00000.log"), "first\n").unwrap();
+        fs::write(directory.join("aegis_rCURRENT.log"), "current\n").unwrap();
+        std::os::unix::fs::symlink(directory.join("aegis_rCURRENT.log"), &log_file).unwrap();
+        let segments = log_segments(&log_file);
+        assert_eq!(segments.last(), Some(&log_file));
+        let destination = directory.join("saved/aegis.log");
+        fs::create_dir_all(destination.parent().unwrap()).unwrap();
+        concatenate(&segments, &destination.to_string_lossy()).unwrap();
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "first\nsecond\ncurrent\n");
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn log_path_parts() {
+        let (directory, basename, suffix) = split_log_path(Path::new("/var/log/aegis.log"));
+        assert_eq!((directory, basename.as_str(), suffix.as_str()), (Path::new("/var/log"), "aegis", "log"));
+        let (directory, basename, _) = split_log_path(Path::new("aegis.log"));
+        assert_eq!((directory, basename.as_str()), (Path::new("."), "aegis"));
+    }
+
+    #[test]
+    fn verbosity_levels() {
+        assert_eq!(level(0, 0), LevelFilter::Info);
+        assert_eq!(level(1, 0), LevelFilter::Debug);
+        assert_eq!(level(3, 0), LevelFilter::Trace);
+        assert_eq!(level(0, 1), LevelFilter::Warn);
+        assert_eq!(level(0, 5), LevelFilter::Error);
+        assert_eq!(level(2, 1), LevelFilter::Debug);
+    }
+}
@@ -1,11 +1,31 @@
 pub mod args;
+pub mod checkpoint;
+pub mod cleanup;
+pub mod config_input;
+pub mod crypt;
 pub mod encrypt;
 pub mod exec;
+pub mod failure;
 pub mod files;
+pub mod firewall;
+pub mod language;
 pub mod logging;
+pub mod netstorage;
+pub mod oem;
+pub mod osdetect;
 pub mod partition;
+pub mod post_install;
+pub mod progress;
+pub mod proxy;
 pub mod returncode_eval;
+pub mod secureboot;
+pub mod services;
+pub mod size;
+pub mod steps;
 pub mod strings;
+pub mod template;
+pub mod tuning;
+pub mod validation;
 pub use clap;
 pub use human_panic;
 pub use log::{self, *};
@@ -0,0 +1,241 @@
+use crate::args::{self, PartitionMode};
+use crate::exec::exec;
+use crate::files;
+use crate::log::info;
+use crate::returncode_eval::{exec_eval, files_eval};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+pub const PROTOCOLS: [&str; 3] = ["iscsi", "nvme-tcp", "nvme-rdma"];
+
+/// Splits a portal, "host" or "host:port" ("[address]:port" for IPv6), falling back to the default port of the protocol.
+pub fn parse_portal(protocol: &str, portal: &str) -> Result<(String, u16), String> {
+    let default_port = if protocol == "iscsi" { 3260 } else { 4420 };
+    let (host, port) = match portal.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, port)) => (host, Some(port.strip_prefix(':').ok_or_else(|| format!("\"{}\" is not a host:port portal", portal))?)),
+            None => return Err(format!("\"{}\" misses the closing ]", portal)),
+        },
+        None => match portal.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (portal, None),
+        },
+    };
+    if host.is_empty() || host.contains(char::is_whitespace) {
+        return Err(format!("\"{}\" is not a valid portal host", portal));
+    }
+    let port = match port {
+        Some(port) => match port.parse::<u16>() {
+            Ok(port) if port > 0 => port,
+            _ => return Err(format!("\"{}\" is not a port between 1 and 65535", port)),
+        },
+        None => default_port,
+    };
+    Ok((host.to_string(), port))
+}
+
+/// Checks the target name: an iSCSI qualified name (iqn., eui. or naa.) or an NVMe qualified name (nqn.).
+pub fn check_target(protocol: &str, target: &str) -> Result<(), String> {
+    let prefixes: &[&str] = if protocol == "iscsi" { &["iqn.", "eui.", "naa."] } else { &["nqn."] };
+    if !prefixes.iter().any(|prefix| target.starts_with(prefix)) || target.contains(char::is_whitespace) {
+        return Err(format!("\"{}\" is not a valid {} name, expected it to start with {}", target, protocol, prefixes.join(", ")));
+    }
+    Ok(())
+}
+
+fn address(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+// The raw output of the tool ends up in the error, the target side usually explains a refused login there
+fn run(program: &str, args: &[&str]) -> Result<(), String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("{} {}: {}", program, args.join(" "), e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let raw: Vec<String> = [&output.stdout, &output.stderr]
+        .iter()
+        .map(|stream| String::from_utf8_lossy(stream).trim().to_string())
+        .filter(|stream| !stream.is_empty())
+        .collect();
+    Err(format!("{} {} failed: {}", program, args.join(" "), raw.join("\n")))
+}
+
+/// Logs in to the target on the live system, its LUNs or namespaces show up as block devices.
+pub fn connect(protocol: &str, portal: &str, target: &str) -> Result<(), String> {
+    let (host, port) = parse_portal(protocol, portal)?;
+    info!("Connecting {} target {} at {}", protocol, target, address(&host, port));
+    if protocol == "iscsi" {
+        let portal = address(&host, port);
+        run("systemctl", &["start", "iscsid"])?;
+        run("iscsiadm", &["-m", "discovery", "-t", "sendtargets", "-p", &portal])?;
+        run("iscsiadm", &["-m", "node", "-T", target, "-p", &portal, "--login"])?;
+        // The node records are copied to the installed system, it logs in again at boot
+        run("iscsiadm", &["-m", "node", "-T", target, "-p", &portal, "-o", "update", "-n", "node.startup", "-v", "automatic"])?;
+    } else {
+        let transport = protocol.trim_start_matches("nvme-");
+        run("modprobe", &[&format!("nvme-{}", transport)])?;
+        run("nvme", &["connect", "-t", transport, "-a", &host, "-s", &port.to_string(), "-n", target])?;
+    }
+    // Wait for the new block devices before partitioning
+    run("udevadm", &["settle"])
+}
+
+/// Makes the installed system log in again at boot with the identity the target grants access to:
+/// the iSCSI initiator name and node records, or the NVMe host NQN and a discovery entry for nvmf-autoconnect.
+pub fn persist(protocol: &str, portal: &str, target: &str) {
+    let Ok((host, port)) = parse_portal(protocol, portal) else {
+        return;
+    };
+    info!("Logging in to {} at boot", target);
+    if protocol == "iscsi" {
+        files_eval(files::create_directory("/mnt/etc/iscsi"), "create /mnt/etc/iscsi");
+        files::copy_file("/etc/iscsi/initiatorname.iscsi", "/mnt/etc/iscsi/initiatorname.iscsi");
+        for records in ["/etc/iscsi/nodes", "/etc/iscsi/send_targets"] {
+            if Path::new(records).exists() {
+                exec_eval(
+                    exec("cp", vec![String::from("-a"), String::from(records), String::from("/mnt/etc/iscsi/")]),
+                    &format!("copy {} to the installed system", records),
+                );
+            }
+        }
+    } else {
+        files_eval(files::create_directory("/mnt/etc/nvme"), "create /mnt/etc/nvme");
+        for identity in ["/etc/nvme/hostnqn", "/etc/nvme/hostid"] {
+            if Path::new(identity).exists() {
+                files::copy_file(identity, &format!("/mnt{}", identity));
+            }
+        }
+        if !Path::new("/mnt/etc/nvme/discovery.conf").exists() {
+            files::create_file("/mnt/etc/nvme/discovery.conf");
+        }
+        files_eval(
+            files::append_file(
+                "/mnt/etc/nvme/discovery.conf",
+                &format!("--transport={} --traddr={} --trsvcid={}", protocol.trim_start_matches("nvme-"), host, port),
+            ),
+            "add the NVMe over Fabrics discovery entry",
+        );
+    }
+}
+
+/// Initiator name of the live system, the one the iSCSI targets were logged in with.
+pub fn initiator_name() -> Option<String> {
+    fs::read_to_string("/etc/iscsi/initiatorname.iscsi")
+        .ok()?
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("InitiatorName=").map(str::to_string))
+}
+
+/// dracut kernel parameters bringing up the network and logging in to the target before the root is mounted.
+pub fn dracut_args(protocol: &str, portal: &str, target: &str) -> Vec<String> {
+    let Ok((host, port)) = parse_portal(protocol, portal) else {
+        return Vec::new();
+    };
+    let host = if host.contains(':') { format!("[{}]", host) } else { host };
+    if protocol == "iscsi" {
+        let mut params = vec![format!("netroot=iscsi:{}::{}::{}", host, port, target)];
+        if let Some(name) = initiator_name() {
+            params.push(format!("rd.iscsi.initiator={}", name));
+        }
+        params
+    } else {
+        vec![format!("rd.nvmf.discover={},{},,{}", protocol.trim_start_matches("nvme-"), host, port)]
+    }
+}
+
+// A controller reached over TCP, RDMA or FC, with native multipath the namespace hangs off the subsystem
+fn nvme_over_fabrics(disk: &str) -> bool {
+    let device = Path::new("/sys/block").join(disk).join("device");
+    let mut transports = vec![device.join("transport")];
+    if let Ok(controllers) = fs::read_dir(&device) {
+        transports.extend(controllers.flatten().map(|controller| controller.path().join("transport")));
+    }
+    transports
+        .iter()
+        .filter_map(|transport| fs::read_to_string(transport).ok())
+        .any(|transport| transport.trim() != "pcie")
+}
+
+/// Disks reached through an iSCSI session or an NVMe over Fabrics controller, with their transport ("iscsi" or "nvme").
+pub fn network_disks() -> Vec<(String, &'static str)> {
+    let Ok(entries) = fs::read_dir("/sys/block") else {
+        return Vec::new();
+    };
+    let session = Regex::new(r"/session\d+/").unwrap();
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = fs::canonicalize(entry.path()).ok()?.to_string_lossy().to_string();
+            if session.is_match(&path) {
+                Some((name, "iscsi"))
+            } else if name.starts_with("nvme") && nvme_over_fabrics(&name) {
+                Some((name, "nvme"))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Transport of the network storage a block device sits on, directly or under a partition or a LUKS mapping.
+pub fn network_transport(device: &str) -> Option<&'static str> {
+    let output = Command::new("lsblk").args(["-nrso", "NAME,TYPE", device]).output().ok()?;
+    let network = network_disks();
+    String::from_utf8_lossy(&output.stdout).lines().find_map(|line| match line.split_once(' ') {
+        Some((name, "disk")) => network.iter().find(|(disk, _)| disk == name).map(|(_, transport)| *transport),
+        _ => None,
+    })
+}
+
+/// Transport of the network storage holding the root filesystem of the install: the erased disk, or the partition mounted at /mnt.
+/// None when the root is on a local disk.
+pub fn root_transport(device: &Path, mode: PartitionMode, partitions: &[args::Partition]) -> Option<&'static str> {
+    match mode {
+        PartitionMode::EraseDisk => network_transport(&device.to_string_lossy()),
+        _ => partitions
+            .iter()
+            .find(|partition| partition.mountpoint.trim_end_matches('/') == "/mnt")
+            .and_then(|partition| network_transport(&partition.blockdevice)),
+    }
+}
+
+/// Mountpoints of the target (relative to it, i.e., /srv) whose filesystem is on network storage.
+pub fn network_mountpoints() -> Vec<String> {
+    let Ok(output) = Command::new("findmnt").args(["-rno", "TARGET,SOURCE", "-R", "/mnt"]).output() else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        // btrfs sources carry the subvolume, i.e., /dev/sdb2[/@home]
+        .filter(|(_, source)| network_transport(source.split('[').next().unwrap_or(source)).is_some())
+        .map(|(target, _)| format!("/{}", target.trim_start_matches("/mnt").trim_start_matches('/')))
+        .collect()
+}
+
+/// Adds _netdev to the fstab entries of the filesystems on network storage, systemd mounts them once the network is up.
+pub fn add_netdev_options(fstab: &str) {
+    for mountpoint in network_mountpoints() {
+        info!("Mounting {} after the network", mountpoint);
+        files_eval(
+            files::sed_file(
+                fstab,
+                &format!(r"(?m)^(\S+\s+{}\s+\S+\s+)(\S+)", regex::escape(&mountpoint)),
+                "${1}${2},_netdev",
+            ),
+            &format!("add _netdev to the {} fstab entry", mountpoint),
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use crate::exec::exec_chroot;
+use crate::files;
+use crate::log::info;
+use crate::returncode_eval::{exec_eval, files_eval};
+use crate::strings::crash;
+use crate::validation::Problem;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+// Present on the target until the first-boot wizard completed successfully
+const MARKER: &str = "/var/lib/aegis/oem";
+// Built with the installer (the aegis-firstboot binary of shared) and copied to the target
+const WIZARD: &str = "/usr/bin/aegis-firstboot";
+const UNIT: &str = "aegis-firstboot.service";
+
+/// Drops the problems about users and the root password, which the end user provides at first boot.
+pub fn skip_credentials(problems: &mut Vec<Problem>) {
+    problems.retain(|problem| !problem.path.starts_with("/users") && !problem.path.starts_with("/rootpass"));
+}
+
+/// Sets up the target for an end user: the first-boot wizard runs on tty1 before any login prompt,
+/// and root stays locked until the wizard created the accounts. The wizard unit disables itself on success.
+pub fn install_firstboot() {
+    info!("Preparing the first-boot wizard for OEM mode");
+    install_wizard();
+    let unit = format!(
+        "[Unit]
+Description=Athena OS first-boot setup
+ConditionPathExists={marker}
+ConditionPathExists={wizard}
+After=systemd-user-sessions.service
+Before=getty@tty1.service display-manager.service
+
+[Service]
+Type=oneshot
+ExecStart={wizard}
+ExecStartPost=/usr/bin/rm -f {marker}
+ExecStartPost=/usr/bin/systemctl disable {unit}
+StandardInput=tty
+StandardOutput=tty
+TTYPath=/dev/tty1
+TTYReset=yes
+TTYVHangup=yes
+
+[Install]
+WantedBy=multi-user.target
+",
+        marker = MARKER,
+        wizard = WIZARD,
+        unit = UNIT,
+    );
+    files_eval(
+        std::fs::write(format!("/mnt/etc/systemd/system/{}", UNIT), unit),
+        "write the first-boot wizard unit",
+    );
+    files_eval(files::create_directory("/mnt/var/lib/aegis"), "create /mnt/var/lib/aegis");
+    files::create_file(&format!("/mnt{}", MARKER));
+    exec_eval(
+        exec_chroot("usermod", vec![String::from("--lock"), String::from("root")]),
+        "lock the root account until first boot",
+    );
+    exec_eval(
+        exec_chroot("systemctl", vec![String::from("enable"), String::from(UNIT)]),
+        "enable the first-boot wizard",
+    );
+}
+
+// The wizard installed next to the installer binary, or in /usr/bin of the live system
+fn wizard_source() -> Option<PathBuf> {
+    let name = Path::new(WIZARD).file_name()?;
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(name)))
+        .into_iter()
+        .chain([PathBuf::from(WIZARD)])
+        .find(|path| path.is_file())
+}
+
+// Without the wizard the end user could never log in, root is locked only once it is on the target
+fn install_wizard() {
+    let target = format!("/mnt{}", WIZARD);
+    match wizard_source() {
+        Some(source) => {
+            files::copy_file(&source.to_string_lossy(), &target);
+            files_eval(
+                std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o755)),
+                "make the first-boot wizard executable",
+            );
+        }
+        None => crash(format!("OEM mode needs the first-boot wizard, {} was not found on the live system", WIZARD), 1),
+    }
+    if !Path::new(&target).is_file() {
+        crash(format!("OEM mode needs the first-boot wizard, {} is missing on the target", target), 1);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::log::{debug, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Candidate partitions are mounted here read-only, one at a time
+const PROBE_MOUNTPOINT: &str = "/tmp/aegis-osprobe";
+// Regular accounts start at this UID, nobody (65534) is not one of them
+const FIRST_USER_UID: u32 = 1000;
+const NOBODY_UID: u32 = 65534;
+
+#[derive(Debug, Serialize)]
+pub struct DetectedOs {
+    pub device: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExistingUser {
+    pub name: String,
+    pub uid: u32,
+    pub fullname: String,
+    pub shell: String,
+}
+
+/// Settings of an existing Linux install that can prefill a new config. Every field is optional,
+/// a missing or unreadable file leaves it empty.
+#[derive(Debug, Serialize)]
+pub struct ExistingSettings {
+    pub device: String,
+    pub hostname: Option<String>,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+    pub users: Vec<ExistingUser>,
+    // Partition holding the old /home, as a partitions entry that mounts it without formatting
+    pub home_partition: Option<String>,
+}
+
+/// Lists the operating systems installed on the block devices of this machine.
+/// os-prober is used when the live medium ships it, else every unmounted partition is probed.
+/// Partitions are only ever mounted read-only, without journal replay.
+pub fn detect() -> Vec<DetectedOs> {
+    if let Some(systems) = os_prober() {
+        return systems;
+    }
+    debug!("os-prober not available, probing the partitions directly");
+    let output = match Command::new("lsblk").args(["-rno", "PATH,TYPE,FSTYPE"]).output() {
+        Ok(output) if output.status.success() => output,
+        _ => {
+            warn!("Unable to list the block devices with lsblk");
+            return Vec::new();
+        }
+    };
+    let mounted = fs::read_to_string("/proc/mounts").unwrap_or_default();
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| match line.split_whitespace().collect::<Vec<&str>>()[..] {
+            [path, "part", fstype] => Some((path.to_string(), fstype.to_string())),
+            _ => None,
+        })
+        .filter(|(_, fstype)| !matches!(fstype.as_str(), "swap" | "crypto_LUKS" | "LVM2_member"))
+        .filter(|(path, _)| !mounted.lines().any(|mount| mount.split_whitespace().next() == Some(path)))
+        .filter_map(|(path, fstype)| probe_partition(&path, &fstype).map(|name| DetectedOs { device: path, name }))
+        .collect()
+}
+
+/// The Windows install found on this machine, if any. Windows keeps the hardware clock in local time.
+pub fn find_windows() -> Option<DetectedOs> {
+    detect().into_iter().find(|system| system.name.to_lowercase().contains("windows"))
+}
+
+/// Whether this machine runs on a battery, laptops have a BAT* power supply.
+pub fn is_laptop() -> bool {
+    fs::read_dir("/sys/class/power_supply")
+        .map(|entries| entries.flatten().any(|entry| entry.file_name().to_string_lossy().starts_with("BAT")))
+        .unwrap_or(false)
+}
+
+// os-prober lines are device:long name:short name:type, an EFI device is followed by @/path/to/loader
+fn os_prober() -> Option<Vec<DetectedOs>> {
+    let output = Command::new("os-prober").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split(':').collect();
+                let device = fields.first()?.split('@').next()?;
+                Some(DetectedOs { device: device.to_string(), name: fields.get(1)?.to_string() })
+            })
+            .collect(),
+    )
+}
+
+// Mounting read-only still replays the journal of ext3/ext4 and xfs unless told not to
+fn readonly_options(fstype: &str) -> &'static str {
+    match fstype {
+        "ext3" | "ext4" => "ro,noload",
+        "xfs" => "ro,norecovery",
+        _ => "ro",
+    }
+}
+
+fn mount_readonly(path: &str, fstype: &str) -> bool {
+    fs::create_dir_all(PROBE_MOUNTPOINT).is_ok()
+        && Command::new("mount")
+            .args(["-o", readonly_options(fstype), path, PROBE_MOUNTPOINT])
+            .output()
+            .is_ok_and(|output| output.status.success())
+}
+
+fn probe_partition(path: &str, fstype: &str) -> Option<String> {
+    if !mount_readonly(path, fstype) {
+        debug!("Unable to mount {} read-only, skipping it", path);
+        return None;
+    }
+    let root = Path::new(PROBE_MOUNTPOINT);
+    let name = if root.join("EFI/Microsoft").is_dir() {
+        Some(String::from("Windows Boot Manager"))
+    } else if root.join("Windows/System32").is_dir() {
+        Some(String::from("Windows"))
+    } else {
+        fs::read_to_string(root.join("etc/os-release")).ok().and_then(|release| {
+            release
+                .lines()
+                .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+                .map(|name| name.trim_matches('"').to_string())
+        })
+    };
+    let _ = Command::new("umount").arg(PROBE_MOUNTPOINT).output();
+    name
+}
+
+/// Reads the hostname, regular users, timezone, locale and /home partition of the Linux install on `device`.
+/// The partition is mounted read-only without journal replay and unmounted before returning, nothing on it is modified.
+pub fn import_settings(device: &str) -> Result<ExistingSettings, String> {
+    let fstype = Command::new("blkid")
+        .args(["-o", "value", "-s", "TYPE", device])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .map_err(|e| format!("blkid {}: {}", device, e))?;
+    if fstype.is_empty() || matches!(fstype.as_str(), "swap" | "crypto_LUKS" | "LVM2_member") {
+        return Err(format!("{} holds no readable filesystem, unlock or activate it first", device));
+    }
+    if !mount_readonly(device, &fstype) {
+        return Err(format!("Unable to mount {} read-only", device));
+    }
+    let mounted = Path::new(PROBE_MOUNTPOINT);
+    // The btrfs layouts keep the root in the @ subvolume
+    let root = if !mounted.join("etc").is_dir() && mounted.join("@/etc").is_dir() { mounted.join("@") } else { mounted.to_path_buf() };
+    let settings = if root.join("etc/passwd").is_file() {
+        Ok(read_settings(device, &root))
+    } else {
+        Err(format!("{} is not the root partition of a Linux install, it has no /etc/passwd", device))
+    };
+    let _ = Command::new("umount").arg(PROBE_MOUNTPOINT).output();
+    settings
+}
+
+fn read_settings(device: &str, root: &Path) -> ExistingSettings {
+    let read = |file: &str| fs::read_to_string(root.join(file)).ok();
+    let hostname = read("etc/hostname").map(|hostname| hostname.trim().to_string()).filter(|hostname| !hostname.is_empty());
+    // /etc/localtime links to the zone file, i.e., ../usr/share/zoneinfo/Europe/Rome
+    let timezone = fs::read_link(root.join("etc/localtime")).ok().and_then(|target| {
+        let target = target.to_string_lossy().to_string();
+        target.split_once("zoneinfo/").map(|(_, zone)| zone.to_string())
+    });
+    let locale = read("etc/locale.conf").and_then(|conf| {
+        conf.lines().find_map(|line| line.trim().strip_prefix("LANG=")).map(|lang| lang.trim_matches('"').to_string())
+    });
+    let users = read("etc/passwd")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            let uid = fields.get(2)?.parse::<u32>().ok()?;
+            if uid < FIRST_USER_UID || uid == NOBODY_UID {
+                return None;
+            }
+            Some(ExistingUser {
+                name: fields[0].to_string(),
+                uid,
+                fullname: fields.get(4)?.split(',').next().unwrap_or("").to_string(),
+                shell: fields.get(6).and_then(|shell| shell.rsplit('/').next()).unwrap_or("").to_string(),
+            })
+        })
+        .collect();
+    let home_partition = read("etc/fstab").and_then(|fstab| home_partition(device, &fstab));
+    ExistingSettings { device: device.to_string(), hostname, timezone, locale, users, home_partition }
+}
+
+// A /home of its own in the old fstab, on another partition than the root. A btrfs subvolume of the
+// root cannot be kept, the root partition is formatted by the new install.
+fn home_partition(root_device: &str, fstab: &str) -> Option<String> {
+    let fields: Vec<&str> = fstab
+        .lines()
+        .map(str::split_whitespace)
+        .map(Iterator::collect::<Vec<&str>>)
+        .find(|fields| fields.len() > 3 && !fields[0].starts_with('#') && fields[1] == "/home")?;
+    let source = match fields[0].split_once('=') {
+        Some((tag @ ("UUID" | "PARTUUID" | "LABEL" | "PARTLABEL"), value)) => {
+            let output = Command::new("findfs").arg(format!("{}={}", tag, value.trim_matches('"'))).output().ok()?;
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        _ => fields[0].to_string(),
+    };
+    if source.is_empty() || source == root_device || fields[3].split(',').any(|option| option.starts_with("subvol")) {
+        return None;
+    }
+    Some(format!("/mnt/home:{}:noformat:false", source))
+}
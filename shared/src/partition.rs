@@ -1,29 +1,65 @@
 use crate::args;
-use crate::args::PartitionMode;
+use crate::args::{Encryption, PartitionMode, WipeMethod};
+use crate::cleanup;
+use crate::encrypt;
 use crate::exec::exec;
 use crate::exec::exec_workdir;
+use crate::exec::RunSpec;
 use crate::files;
-use crate::log::debug;
+use crate::log::{debug, info, warn};
 use crate::returncode_eval::exec_eval;
 use crate::returncode_eval::files_eval;
+use crate::returncode_eval::run_eval;
+use crate::size;
 use crate::strings::crash;
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set by --show-all-disks, unsuitable_device then accepts every block device
+static SHOW_ALL_DISKS: AtomicBool = AtomicBool::new(false);
+
+/// Accepts every block device as install target, loop devices and the disk of the live system included.
+pub fn show_all_disks() {
+    SHOW_ALL_DISKS.store(true, Ordering::Relaxed);
+}
 
 /*mkfs.bfs mkfs.cramfs mkfs.ext3  mkfs.fat mkfs.msdos  mkfs.xfs
 mkfs.btrfs mkfs.ext2  mkfs.ext4  mkfs.minix mkfs.vfat mkfs.f2fs */
 
-fn encrypt_blockdevice(blockdevice: &str, cryptlabel: &str) {
+// Mount options of the automatic layout, written to the target fstab by genfstab
+pub const ROOT_MOUNT_OPTIONS: &str = "noatime";
+pub const ESP_MOUNT_OPTIONS: &str = "umask=0077";
+// The ephemeral root lives in memory, anything not persisted is gone at the next boot
+pub const TMPFS_ROOT_MOUNT_OPTIONS: &str = "defaults,size=25%,mode=755";
+
+/// Mountpoints (relative to the target root) and mount options set up by the EraseDisk layout.
+pub fn layout_mount_options(efi: bool, encryption: Encryption, ephemeral_root: bool) -> Vec<(&'static str, &'static str)> {
+    let mut options = if ephemeral_root {
+        vec![("/", TMPFS_ROOT_MOUNT_OPTIONS), ("/nix", ROOT_MOUNT_OPTIONS), ("/persist", ROOT_MOUNT_OPTIONS)]
+    } else {
+        vec![("/", ROOT_MOUNT_OPTIONS), ("/home", ROOT_MOUNT_OPTIONS), ("/.snapshots", ROOT_MOUNT_OPTIONS)]
+    };
+    match (efi, encryption) {
+        (true, Encryption::AllButEsp) => options.push(("/efi", ESP_MOUNT_OPTIONS)),
+        (true, _) => options.push(("/boot", ESP_MOUNT_OPTIONS)),
+        (false, _) => {}
+    }
+    options
+}
+
+/// LVM volume group holding root and swap inside the LUKS container of Encryption::AllButEsp
+pub const VOLUME_GROUP: &str = "athena";
+
+// GRUB cryptodisk can read LUKS1 containers, LUKS2 only with PBKDF2 and not with the default argon2id
+fn encrypt_blockdevice(blockdevice: &str, cryptlabel: &str, luks1: bool) {
+    let mut format_args = vec![String::from("luksFormat"), String::from("-q")];
+    if luks1 {
+        format_args.extend([String::from("--type"), String::from("luks1")]);
+    }
+    format_args.extend([String::from(blockdevice), String::from("-d"), String::from(encrypt::PASSPHRASE_FILE)]);
     exec_eval(
-        exec(
-            "cryptsetup",
-            vec![
-                String::from("luksFormat"),
-                String::from("-q"),
-                String::from(blockdevice),
-                String::from("-d"),
-                String::from("/tmp/luks"),
-            ],
-        ),
+        exec("cryptsetup", format_args),
         "Format LUKS partition",
     );
     exec_eval(
@@ -34,29 +70,22 @@ fn encrypt_blockdevice(blockdevice: &str, cryptlabel: &str) {
                 String::from(blockdevice),
                 String::from(cryptlabel),
                 String::from("-d"),
-                String::from("/tmp/luks"),
+                String::from(encrypt::PASSPHRASE_FILE),
             ],
         ),
         "Open LUKS format",
     );
-    exec_eval(
-        exec(
-            "rm",
-            vec![
-                String::from("-rf"),
-                String::from("/tmp/luks"),
-            ],
-        ),
-        "Remove luks key",
-    );
+    cleanup::track_luks(cryptlabel);
+    // The passphrase file is kept for the other encrypted partitions and the unlock enrollment,
+    // the caller removes it with encrypt::forget_passphrase
 }
 
-pub fn fmt_mount(mountpoint: &str, filesystem: &str, blockdevice: &str, encryption: bool) {
+pub fn fmt_mount(mountpoint: &str, filesystem: &str, blockdevice: &str, encryption: bool, mount_options: &[String]) {
     let mut bdevice = String::from(blockdevice);
     // Extract the block device name (i.e., sda3)
     let cryptlabel = format!("{}crypted",bdevice.trim_start_matches("/dev/")); // i.e., sda3crypted
     if encryption {
-        encrypt_blockdevice(&bdevice, &cryptlabel);
+        encrypt_blockdevice(&bdevice, &cryptlabel, false);
         bdevice = format!("/dev/mapper/{cryptlabel}");
     }
     match filesystem {
@@ -119,6 +148,7 @@ pub fn fmt_mount(mountpoint: &str, filesystem: &str, blockdevice: &str, encrypti
                 exec("swapon", vec![String::from(&bdevice)]),
                 format!("Activate {bdevice} swap device").as_str(),
             );
+            cleanup::track_swap(&bdevice);
         }
         "don't format" => {
             debug!("Not formatting {}", bdevice);
@@ -137,53 +167,243 @@ pub fn fmt_mount(mountpoint: &str, filesystem: &str, blockdevice: &str, encrypti
         exec("mkdir", vec![String::from("-p"), String::from(mountpoint)]),
         format!("Creating mountpoint {mountpoint} for {bdevice}").as_str(),
     );
-    mount(&bdevice, mountpoint, "");
+    mount(&bdevice, mountpoint, &mount_options.join(","));
+}
+
+// Rough sequential write speed used for the secure wipe time estimate
+const WIPE_BYTES_PER_SECOND: u64 = 150 * 1024 * 1024;
+
+/// Wipes the whole device before the Erase Disk layout is written to it.
+/// A discard on a rotational drive falls back to a quick wipe.
+pub fn wipe_device(device: &Path, method: WipeMethod) {
+    if method == WipeMethod::None {
+        return;
+    }
+    if !device.exists() {
+        crash(format!("The device {device:?} doesn't exist"), 1);
+    }
+    let device = device.to_string_lossy().to_string();
+    let method = if method == WipeMethod::Discard && lsblk_field(&device, "ROTA").as_deref() != Some("0") {
+        warn!("{} is not an SSD, using a quick wipe instead of a discard", device);
+        WipeMethod::Quick
+    } else {
+        method
+    };
+    match method {
+        WipeMethod::None => {}
+        WipeMethod::Quick => exec_eval(
+            exec("wipefs", vec![String::from("-a"), String::from(&device)]),
+            format!("Erase the signatures on {}", device).as_str(),
+        ),
+        WipeMethod::Discard => exec_eval(
+            exec("blkdiscard", vec![String::from("-f"), String::from(&device)]),
+            format!("Discard every block of {}", device).as_str(),
+        ),
+        WipeMethod::Secure => {
+            let size: u64 = lsblk_field(&device, "SIZE").and_then(|size| size.parse().ok()).unwrap_or(0);
+            warn!(
+                "Zeroing {} ({} GiB) takes about {} minutes, interrupt the installer to stop it",
+                device,
+                size / (1024 * 1024 * 1024),
+                size / WIPE_BYTES_PER_SECOND / 60 + 1
+            );
+            // shred -v reports its progress on a new line every few seconds
+            run_eval(
+                RunSpec::new("shred").args(["-v", "-n", "0", "-z"]).arg(&device).prefix("wipe").run(),
+                format!("Overwrite {} with zeros", device).as_str(),
+            );
+        }
+    }
+    info!("{} wiped", device);
 }
 
+/// Human-readable list of what partitioning destroys and keeps, destructive lines are in capitals.
+pub fn destructive_summary(
+    device: &Path,
+    mode: PartitionMode,
+    encryption: Encryption,
+    wipe: WipeMethod,
+    partitions: &[args::Partition],
+) -> Vec<String> {
+    let mut summary = Vec::new();
+    match mode {
+        PartitionMode::EraseDisk => {
+            let device = device.to_string_lossy().to_string();
+            let field = |column: &str| lsblk_field(&device, column).filter(|value| !value.is_empty());
+            let size = field("SIZE")
+                .and_then(|size| size.parse::<u64>().ok())
+                .map(|size| format!("{} GiB", size / (1024 * size::MIB)))
+                .unwrap_or_else(|| String::from("unknown size"));
+            summary.push(format!(
+                "ERASE ALL DATA on {} ({}, serial {}, {})",
+                device,
+                field("MODEL").unwrap_or_else(|| String::from("unknown model")),
+                field("SERIAL").unwrap_or_else(|| String::from("unknown")),
+                size
+            ));
+            if wipe != WipeMethod::None {
+                summary.push(format!("WIPE {} with the {:?} method", device, wipe));
+            }
+            summary.push(match encryption {
+                Encryption::None => String::from("Encryption: none"),
+                Encryption::Root => String::from("Encryption: root partition"),
+                Encryption::AllButEsp => String::from("Encryption: everything except the EFI system partition"),
+            });
+        }
+        PartitionMode::Manual | PartitionMode::Replace => {
+            for partition in partitions {
+                if partition.filesystem == "noformat" || partition.filesystem == "don't format" {
+                    summary.push(format!("Keep {} mounted at {}", partition.blockdevice, partition.mountpoint));
+                } else {
+                    summary.push(format!(
+                        "FORMAT {} as {} for {}{}",
+                        partition.blockdevice,
+                        partition.filesystem,
+                        partition.mountpoint,
+                        if partition.encrypt { ", encrypted" } else { "" }
+                    ));
+                }
+            }
+        }
+    }
+    summary
+}
+
+// Single lsblk column of the whole device, sizes in bytes
+fn lsblk_field(device: &str, column: &str) -> Option<String> {
+    let output = Command::new("lsblk").args(["-bdno", column, device]).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Partitions the drives. With `ephemeral_root` the EraseDisk layout mounts a tmpfs as root and keeps
+/// only /nix and /persist on the disk, the /boot partition stays as it is.
+#[allow(clippy::too_many_arguments)]
 pub fn partition(
     device: PathBuf,
     mode: PartitionMode,
-    encrypt_check: bool,
+    encryption: Encryption,
     efi: bool,
     swap: bool,
     swap_size: String,
+    ephemeral_root: bool,
     partitions: &mut Vec<args::Partition>,
 ) {
-    println!("{:?}", mode);
+    debug!("{:?}", mode);
     match mode {
         PartitionMode::EraseDisk => {
             if !device.exists() {
                 crash(format!("The device {device:?} doesn't exist"), 1);
             }
+            if let Some(reason) = unsuitable_device(&device) {
+                crash(format!("Cannot install on {device:?}: {reason}"), 1);
+            }
             debug!("Erase disk partitioning {device:?}");
-            if efi {
-                partition_with_efi(&device, swap, swap_size);
+            if encryption == Encryption::AllButEsp {
+                if ephemeral_root {
+                    crash("An ephemeral root leaves no disk filesystem for an encrypted /boot", 1);
+                }
+                if !efi {
+                    crash("Encrypting everything except the ESP requires an EFI install", 1);
+                }
+                // An ESP followed by a single partition for the LUKS container
+                partition_with_efi(&device, false, swap_size.clone());
+                part_disk_container(&device, swap, &swap_size);
             } else {
-                partition_no_efi(&device, swap, swap_size);
+                if efi {
+                    partition_with_efi(&device, swap, swap_size);
+                } else {
+                    partition_no_efi(&device, swap, swap_size);
+                }
+                part_disk(&device, efi, encryption == Encryption::Root, swap, ephemeral_root);
             }
-            part_disk(&device, efi, encrypt_check, swap);
         }
         PartitionMode::Manual | PartitionMode::Replace => {
             debug!("Manual/Replace partitioning");
-            partitions.sort_by(|a, b| a.mountpoint.len().cmp(&b.mountpoint.len()));
+            partitions.sort_by_key(|a| a.mountpoint.len());
             for i in 0..partitions.len() {
-                println!("{:?}", partitions);
-                println!("{}", partitions.len());
-                println!("{}", &partitions[i].mountpoint);
-                println!("{}", &partitions[i].filesystem);
-                println!("{}", &partitions[i].blockdevice);
-                println!("{}", partitions[i].encrypt);
+                debug!("{:?}", partitions);
+                debug!("{}", partitions.len());
+                debug!("{}", &partitions[i].mountpoint);
+                debug!("{}", &partitions[i].filesystem);
+                debug!("{}", &partitions[i].blockdevice);
+                debug!("{}", partitions[i].encrypt);
                 fmt_mount(
                     &partitions[i].mountpoint,
                     &partitions[i].filesystem,
                     &partitions[i].blockdevice,
                     partitions[i].encrypt,
+                    &partitions[i].mount_options,
                 );
             }
         }
     }
 }
 
+// Optical drives and zram swap cannot hold an install, neither can the disk the live system runs from.
+// Loop devices back the squashfs of the live images, installing on an image file needs --show-all-disks.
+fn unsuitable_device(device: &Path) -> Option<&'static str> {
+    if SHOW_ALL_DISKS.load(Ordering::Relaxed) {
+        return None;
+    }
+    let name = device.file_name()?.to_string_lossy().to_string();
+    if name.starts_with("sr") || name.starts_with("zram") {
+        return Some("optical drives and zram devices cannot hold an install");
+    }
+    if name.starts_with("loop") {
+        return Some("loop devices are not offered as install targets, pass --show-all-disks to install on an image file");
+    }
+    if live_disks().contains(&name) {
+        return Some("it hosts the running live system");
+    }
+    None
+}
+
+// Disks under the boot medium mounts of the Arch, Fedora and NixOS live images, and under / when it is a block device
+fn live_disks() -> Vec<String> {
+    let mut disks = Vec::new();
+    for mountpoint in ["/run/archiso/bootmnt", "/run/initramfs/live", "/iso", "/"] {
+        // -v drops the [/subvolume] suffix btrfs sources carry
+        let Ok(output) = Command::new("findmnt").args(["-nvo", "SOURCE", mountpoint]).output() else {
+            continue;
+        };
+        let source = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !source.starts_with("/dev/") {
+            continue;
+        }
+        // With -s, lsblk lists the devices the source sits on, down to its disk
+        let Ok(output) = Command::new("lsblk").args(["-nrso", "NAME,TYPE", &source]).output() else {
+            continue;
+        };
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if let Some((name, "disk")) = line.split_once(' ') {
+                disks.push(name.to_string());
+            }
+        }
+    }
+    disks
+}
+
+// Every mkpart runs with "-a optimal": boundaries are MiB/GiB values, so partitions start on
+// 1MiB boundaries and stay aligned on 4Kn drives as well as on 512-byte sector ones
+// The swap partition follows the 512MiB boot partition and is swap_size long, rounded down to whole MiB
+fn swap_boundary(device: &str, swap_size: &str) -> String {
+    let device_size = lsblk_field(device, "SIZE").and_then(|size| size.parse().ok()).unwrap_or(0);
+    let swap_mib = swap_mib(swap_size, device_size);
+    // Leave at least 8GiB for the root partition
+    if device_size > 0 && (512 + swap_mib + 8 * 1024) * size::MIB > device_size {
+        crash(format!("A {} swap does not leave enough space for the root partition on {}", swap_size, device), 1);
+    }
+    format!("{}MiB", 512 + swap_mib)
+}
+
+fn swap_mib(swap_size: &str, available: u64) -> u64 {
+    match size::parse_size(swap_size, available) {
+        Ok(bytes) if bytes >= size::MIB => bytes / size::MIB,
+        Ok(_) => crash(format!("Swap size {} is smaller than 1MiB", swap_size), 1),
+        Err(e) => crash(format!("Swap size: {}", e), 1),
+    }
+}
+
 fn partition_with_efi(device: &Path, swap: bool, swap_size: String) {
     let device = device.to_string_lossy().to_string();
     exec_eval(
@@ -204,6 +424,8 @@ fn partition_with_efi(device: &Path, swap: bool, swap_size: String) {
             "parted",
             vec![
                 String::from("-s"),
+                String::from("-a"),
+                String::from("optimal"),
                 String::from(&device),
                 String::from("--"),
                 String::from("mkpart"),
@@ -231,7 +453,7 @@ fn partition_with_efi(device: &Path, swap: bool, swap_size: String) {
         "enable EFI system partition",
     );
     let boundary_partition_size = if swap {
-        swap_size
+        swap_boundary(&device, &swap_size)
     } else {
         String::from("512MiB")
     };
@@ -241,6 +463,8 @@ fn partition_with_efi(device: &Path, swap: bool, swap_size: String) {
                 "parted",
                 vec![
                     String::from("-s"),
+                    String::from("-a"),
+                    String::from("optimal"),
                     String::from(&device),
                     String::from("--"),
                     String::from("mkpart"),
@@ -258,6 +482,8 @@ fn partition_with_efi(device: &Path, swap: bool, swap_size: String) {
             "parted",
             vec![
                 String::from("-s"),
+                String::from("-a"),
+                String::from("optimal"),
                 String::from(&device),
                 String::from("--"),
                 String::from("mkpart"),
@@ -292,6 +518,8 @@ fn partition_no_efi(device: &Path, swap: bool, swap_size: String) {
             "parted",
             vec![
                 String::from("-s"),
+                String::from("-a"),
+                String::from("optimal"),
                 String::from(&device),
                 String::from("--"),
                 String::from("mkpart"),
@@ -304,7 +532,7 @@ fn partition_no_efi(device: &Path, swap: bool, swap_size: String) {
         "create bios boot partition",
     );
     let boundary_partition_size = if swap {
-        swap_size
+        swap_boundary(&device, &swap_size)
     } else {
         String::from("512MiB")
     };
@@ -315,6 +543,8 @@ fn partition_no_efi(device: &Path, swap: bool, swap_size: String) {
                 "parted",
                 vec![
                     String::from("-s"),
+                    String::from("-a"),
+                    String::from("optimal"),
                     String::from(&device),
                     String::from("--"),
                     String::from("mkpart"),
@@ -334,6 +564,8 @@ fn partition_no_efi(device: &Path, swap: bool, swap_size: String) {
             "parted",
             vec![
                 String::from("-s"),
+                String::from("-a"),
+                String::from("optimal"),
                 String::from(&device),
                 String::from("--"),
                 String::from("mkpart"),
@@ -363,15 +595,20 @@ fn partition_no_efi(device: &Path, swap: bool, swap_size: String) {
     );
 }
 
-fn part_disk(device: &Path, efi: bool, encrypt_check: bool, swap: bool) {
-    let device = device.to_string_lossy().to_string(); // i.e., /dev/sda
-
-    let dsuffix = if device.contains("nvme") || device.contains("mmcblk") || device.contains("loop") {
+// Partitions of nvme0n1, mmcblk0 and loop0 are nvme0n1p1, ..., those of sda are sda1, ...
+fn partition_suffix(device: &str) -> &'static str {
+    if device.contains("nvme") || device.contains("mmcblk") || device.contains("loop") {
         "p"
     }
     else {
         ""
-    };
+    }
+}
+
+fn part_disk(device: &Path, efi: bool, encrypt_check: bool, swap: bool, ephemeral_root: bool) {
+    let device = device.to_string_lossy().to_string(); // i.e., /dev/sda
+
+    let dsuffix = partition_suffix(&device);
 
     let bdsuffix = if swap {
         format!("{}3", dsuffix)
@@ -412,6 +649,7 @@ fn part_disk(device: &Path, efi: bool, encrypt_check: bool, swap: bool) {
             ),
             format!("activate {}{}2 swap device", device, dsuffix).as_str(),
         );
+        cleanup::track_swap(&format!("{}{}2", device, dsuffix));
     }
 
     let mut root_blockdevice = format!("{device}{bdsuffix}"); // i.e., /dev/sda3
@@ -419,10 +657,96 @@ fn part_disk(device: &Path, efi: bool, encrypt_check: bool, swap: bool) {
 
     if encrypt_check {
         let cryptlabel = format!("{root_blockdevice_name}crypted"); // i.e., sda3crypted will be the name of the opened LUKS partition
-        encrypt_blockdevice(&root_blockdevice, &cryptlabel);
+        encrypt_blockdevice(&root_blockdevice, &cryptlabel, false);
         root_blockdevice =  format!("/dev/mapper/{cryptlabel}");
     }
 
+    if ephemeral_root {
+        format_ephemeral_root(&root_blockdevice);
+    } else {
+        format_root(&root_blockdevice);
+    }
+    let boot_options = if efi { ESP_MOUNT_OPTIONS } else { "" };
+    mount(format!("{}{}1", device, dsuffix).as_str(), "/mnt/boot", boot_options);
+}
+
+// Erase Disk layout of Encryption::AllButEsp: the ESP (mounted at /efi) and a LUKS container
+// with an LVM volume group for swap and root, so /boot is encrypted too
+fn part_disk_container(device: &Path, swap: bool, swap_size: &str) {
+    let device = device.to_string_lossy().to_string(); // i.e., /dev/sda
+    let dsuffix = partition_suffix(&device);
+    let esp = format!("{}{}1", device, dsuffix);
+    let container = format!("{}{}2", device, dsuffix);
+
+    exec_eval(
+        exec(
+            "mkfs.fat",
+            vec![String::from("-F"), String::from("32"), String::from("-n"), String::from("BOOT"), String::from(&esp)],
+        ),
+        format!("format {} as fat32", esp).as_str(),
+    );
+
+    warn!("/boot is encrypted: GRUB cryptodisk needs a LUKS1 container and asks the passphrase before the initramfs asks it again");
+    let cryptlabel = format!("{}crypted", container.trim_start_matches("/dev/")); // i.e., sda2crypted
+    encrypt_blockdevice(&container, &cryptlabel, true);
+    let mapper = format!("/dev/mapper/{cryptlabel}");
+
+    exec_eval(
+        exec("pvcreate", vec![String::from(&mapper)]),
+        format!("create LVM physical volume on {}", mapper).as_str(),
+    );
+    exec_eval(
+        exec("vgcreate", vec![String::from(VOLUME_GROUP), String::from(&mapper)]),
+        format!("create LVM volume group {}", VOLUME_GROUP).as_str(),
+    );
+    cleanup::track_volume_group(VOLUME_GROUP);
+    if swap {
+        let swap_volume = format!("/dev/{}/swap", VOLUME_GROUP);
+        exec_eval(
+            exec(
+                "lvcreate",
+                vec![
+                    String::from("-L"),
+                    format!("{}m", swap_mib(swap_size, lsblk_field(&device, "SIZE").and_then(|size| size.parse().ok()).unwrap_or(0))),
+                    String::from("-n"),
+                    String::from("swap"),
+                    String::from(VOLUME_GROUP),
+                ],
+            ),
+            "create swap logical volume",
+        );
+        exec_eval(
+            exec("mkswap", vec![String::from("-L"), String::from("swap"), String::from(&swap_volume)]),
+            format!("make {} as swap volume", swap_volume).as_str(),
+        );
+        exec_eval(
+            exec("swapon", vec![String::from(&swap_volume)]),
+            format!("activate {} swap device", swap_volume).as_str(),
+        );
+        cleanup::track_swap(&swap_volume);
+    }
+    exec_eval(
+        exec(
+            "lvcreate",
+            vec![
+                String::from("-l"),
+                String::from("100%FREE"),
+                String::from("-n"),
+                String::from("root"),
+                String::from(VOLUME_GROUP),
+            ],
+        ),
+        "create root logical volume",
+    );
+
+    format_root(&format!("/dev/{}/root", VOLUME_GROUP));
+    files_eval(files::create_directory("/mnt/efi"), "create /mnt/efi");
+    mount(&esp, "/mnt/efi", ESP_MOUNT_OPTIONS);
+}
+
+// Format the root block device as btrfs with the @, @home and @snapshots subvolumes, mounted at /mnt, /mnt/home
+// and /mnt/.snapshots. Keeping the snapshots out of @ lets snapper roll the root back without losing them.
+fn format_root(root_blockdevice: &str) {
     /* Format root partition */
     exec_eval(
         exec(
@@ -431,7 +755,7 @@ fn part_disk(device: &Path, efi: bool, encrypt_check: bool, swap: bool) {
         ),
         format!("format {} as btrfs", root_blockdevice).as_str(),
     );
-    mount(&root_blockdevice, "/mnt", "");
+    mount(root_blockdevice, "/mnt", "");
     exec_eval(
         exec_workdir(
             "btrfs",
@@ -456,20 +780,77 @@ fn part_disk(device: &Path, efi: bool, encrypt_check: bool, swap: bool) {
         ),
         "Create btrfs subvolume @home",
     );
+    exec_eval(
+        exec_workdir(
+            "btrfs",
+            "/mnt",
+            vec![
+                String::from("subvolume"),
+                String::from("create"),
+                String::from("@snapshots"),
+            ],
+        ),
+        "Create btrfs subvolume @snapshots",
+    );
     umount("/mnt");
-    mount(&root_blockdevice, "/mnt/", "subvol=@");
+    mount(root_blockdevice, "/mnt/", &format!("{},subvol=@", ROOT_MOUNT_OPTIONS));
     files_eval(files::create_directory("/mnt/boot"), "create /mnt/boot");
     files_eval(files::create_directory("/mnt/home"), "create /mnt/home");
     mount(
-        &root_blockdevice,
+        root_blockdevice,
         "/mnt/home",
-        "subvol=@home",
+        &format!("{},subvol=@home", ROOT_MOUNT_OPTIONS),
     );
+    files_eval(files::create_directory("/mnt/.snapshots"), "create /mnt/.snapshots");
+    mount(
+        root_blockdevice,
+        "/mnt/.snapshots",
+        &format!("{},subvol=@snapshots", ROOT_MOUNT_OPTIONS),
+    );
+}
 
-    mount(format!("{}{}1", device, dsuffix).as_str(), "/mnt/boot", "");
+// Ephemeral root layout: the root block device holds the @nix and @persist btrfs subvolumes, mounted at /mnt/nix
+// and /mnt/persist, while /mnt itself is a tmpfs. The system is rebuilt from /nix at every boot.
+fn format_ephemeral_root(root_blockdevice: &str) {
+    exec_eval(
+        exec(
+            "mkfs.btrfs",
+            vec![String::from("-L"), String::from("athenaos"), String::from("-f"), String::from(root_blockdevice)],
+        ),
+        format!("format {} as btrfs", root_blockdevice).as_str(),
+    );
+    mount(root_blockdevice, "/mnt", "");
+    for subvolume in ["@nix", "@persist"] {
+        exec_eval(
+            exec_workdir("btrfs", "/mnt", vec![String::from("subvolume"), String::from("create"), String::from(subvolume)]),
+            &format!("Create btrfs subvolume {}", subvolume),
+        );
+    }
+    umount("/mnt");
+    cleanup::track_mount("/mnt");
+    exec_eval(
+        exec(
+            "mount",
+            vec![
+                String::from("-t"),
+                String::from("tmpfs"),
+                String::from("-o"),
+                String::from(TMPFS_ROOT_MOUNT_OPTIONS),
+                String::from("none"),
+                String::from("/mnt"),
+            ],
+        ),
+        "mount a tmpfs at /mnt",
+    );
+    files_eval(files::create_directory("/mnt/boot"), "create /mnt/boot");
+    for (mountpoint, subvolume) in [("/mnt/nix", "@nix"), ("/mnt/persist", "@persist")] {
+        files_eval(files::create_directory(mountpoint), &format!("create {}", mountpoint));
+        mount(root_blockdevice, mountpoint, &format!("{},subvol={}", ROOT_MOUNT_OPTIONS, subvolume));
+    }
 }
 
 pub fn mount(partition: &str, mountpoint: &str, options: &str) {
+    cleanup::track_mount(mountpoint);
     if !options.is_empty() {
         exec_eval(
             exec(
@@ -504,3 +885,35 @@ pub fn umount(mountpoint: &str) {
         format!("unmount command processed on {}", mountpoint).as_str(),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1024 * size::MIB;
+
+    #[test]
+    fn swap_rounds_down_to_whole_mib() {
+        assert_eq!(swap_mib("4GiB", 500 * GIB), 4096);
+        assert_eq!(swap_mib("1.5G", 500 * GIB), 1536);
+        // 10% of 1TB is 95367.43MiB
+        assert_eq!(swap_mib("10%", 1_000_000_000_000), 95367);
+        assert_eq!(swap_mib("4GB", 500 * GIB), 3814);
+    }
+
+    #[test]
+    fn swap_boundary_aligned_on_512_and_4096_byte_sectors() {
+        for (swap_size, available) in [("4GiB", 500 * GIB), ("10%", 1_000_000_000_000), ("4GB", 500 * GIB), ("3%", 123_456_789_000)] {
+            let boundary = (512 + swap_mib(swap_size, available)) * size::MIB;
+            for sector in [512, 4096] {
+                assert_eq!(boundary % sector, 0, "{} of {} bytes on {}-byte sectors", swap_size, available, sector);
+            }
+        }
+    }
+
+    #[test]
+    fn swap_boundary_follows_the_boot_partition() {
+        // lsblk knows no such device, the size check is skipped
+        assert_eq!(swap_boundary("/dev/aegis-no-such-disk", "8GiB"), "8704MiB");
+    }
+}
@@ -0,0 +1,58 @@
+use crate::exec::RunSpec;
+use crate::files;
+use crate::log::{info, warn};
+use crate::progress;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+// Location of the script in the target, as seen from inside the chroot and from the live system
+const SCRIPT: &str = "/root/aegis-post-install.sh";
+const SCRIPT_ON_TARGET: &str = "/mnt/root/aegis-post-install.sh";
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+const SCRIPT_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Runs the user's post-install script inside the target with `enter` (i.e., ["arch-chroot", "/mnt"]), then removes it.
+/// `script` is an http(s) URL, the path of a file on the live system or the script itself.
+/// A failing script does not fail the install, it is reported as a warning of the completed install.
+pub fn run(script: &str, enter: &[&str]) {
+    let script = script.trim();
+    if let Err(e) = write_script(script) {
+        warn!("Post-install script not run: {}", e);
+        progress::completion_warning(&format!("the post-install script was not run: {}", e));
+        return;
+    }
+    info!("Running the post-install script");
+    let result = RunSpec::new(enter[0])
+        .args(&enter[1..])
+        .args(["/bin/sh", SCRIPT])
+        .prefix("post-install")
+        .timeout(SCRIPT_TIMEOUT)
+        .run();
+    // The script may hold credentials (i.e., joining a domain)
+    files::shred_file(Path::new(SCRIPT_ON_TARGET));
+    match result {
+        Ok(_) => info!("Post-install script completed"),
+        Err(e) => {
+            warn!("Post-install script failed: {}", e);
+            progress::completion_warning(&format!("the post-install script failed: {}", e));
+        }
+    }
+}
+
+fn write_script(script: &str) -> Result<(), String> {
+    if script.starts_with("http://") || script.starts_with("https://") {
+        info!("Downloading the post-install script from {}", script);
+        RunSpec::new("curl")
+            .args(["--silent", "--show-error", "--location", "--fail", "--retry", "3", "--output", SCRIPT_ON_TARGET, script])
+            .timeout(DOWNLOAD_TIMEOUT)
+            .run()
+            .map_err(|e| format!("download {}: {}", script, e))?;
+    } else if !script.contains('\n') && Path::new(script).is_file() {
+        fs::copy(script, SCRIPT_ON_TARGET).map_err(|e| format!("copy {}: {}", script, e))?;
+    } else {
+        fs::write(SCRIPT_ON_TARGET, format!("{}\n", script)).map_err(|e| format!("write {}: {}", SCRIPT_ON_TARGET, e))?;
+    }
+    fs::set_permissions(SCRIPT_ON_TARGET, fs::Permissions::from_mode(0o700)).map_err(|e| e.to_string())
+}
@@ -0,0 +1,136 @@
+use crate::failure;
+use crate::log::{info, warn};
+use serde_json::json;
+use std::fs;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+// Frontends (aegis-tui, aegis-gui) scan the log for lines containing this marker
+// followed by a percentage and a phase description, i.e.:
+// AEGIS-PROGRESS: 42 Installing desktop environment
+pub const PROGRESS_MARKER: &str = "AEGIS-PROGRESS:";
+
+// Every phase opens a section of the log with a delimiter line, i.e.:
+// ===== AEGIS STEP: Installing desktop environment =====
+// so a frontend can jump between steps and show the end of the one that failed.
+pub const STEP_MARKER: &str = "===== AEGIS STEP:";
+const STEP_MARKER_END: &str = "=====";
+
+// With --progress-json every phase is also printed to stdout as one JSON object per line, i.e.:
+// {"phase":"installing_desktop_environment","percent":42,"message":"Installing desktop environment"}
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+// Final status of the last run, read by frontends that do not follow the JSON progress, i.e.:
+// {"status":"failure","exit_code":21,"failure":{"category":"network_timeout","retryable":true,...}}
+pub const STATUS_FILE: &str = "/tmp/aegis-status.json";
+
+// Problems that did not stop the install, a successful run with warnings is reported as completed with warnings
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn enable_json() {
+    JSON_OUTPUT.store(true, Ordering::Relaxed);
+}
+
+/// Whether stdout is reserved to JSON progress objects.
+pub fn json_enabled() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Announce the start of an installation phase with its overall completion percentage.
+pub fn phase(percent: u8, description: &str) {
+    let percent = percent.min(100);
+    info!("{} {} {}", STEP_MARKER, description, STEP_MARKER_END);
+    info!("{} {} {}", PROGRESS_MARKER, percent, description);
+    if json_enabled() {
+        let phase = description.to_lowercase().replace(' ', "_");
+        emit(json!({ "phase": phase, "percent": percent, "message": description }));
+    }
+}
+
+/// Record a problem the user must know about once the install completes, without failing it.
+pub fn completion_warning(message: &str) {
+    WARNINGS.lock().unwrap_or_else(|e| e.into_inner()).push(message.to_string());
+}
+
+/// Report the final status of the run. It must be called exactly once, right before exiting.
+/// The status is also written to STATUS_FILE, with the failure report when the run failed.
+pub fn finish(exit_code: i32) {
+    let warnings = WARNINGS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let status = match exit_code {
+        0 if warnings.is_empty() => "success",
+        0 => "success_with_warnings",
+        _ => "failure",
+    };
+    let object = json!({ "status": status, "exit_code": exit_code, "warnings": warnings, "failure": failure::last() });
+    if let Err(e) = fs::write(STATUS_FILE, serde_json::to_string_pretty(&object).unwrap()) {
+        warn!("Unable to write the install status {}: {}", STATUS_FILE, e);
+    }
+    if json_enabled() {
+        emit(object);
+    }
+}
+
+/// Name of the last step of a log and its last `lines` lines, the step a failed install stopped in.
+/// Lines logged before the first delimiter belong to no step, None is returned for a log without one.
+pub fn last_step_excerpt(log: &str, lines: usize) -> Option<(String, Vec<String>)> {
+    let mut step = None;
+    let mut section: Vec<&str> = Vec::new();
+    for line in log.lines() {
+        if let Some(start) = line.find(STEP_MARKER) {
+            let name = line[start + STEP_MARKER.len()..].trim().trim_end_matches(STEP_MARKER_END).trim();
+            step = Some(name.to_string());
+            section.clear();
+        } else if step.is_some() {
+            section.push(line);
+        }
+    }
+    let skip = section.len().saturating_sub(lines);
+    step.map(|step| (step, section[skip..].iter().map(|line| line.to_string()).collect()))
+}
+
+fn emit(object: serde_json::Value) {
+    let mut stdout = io::stdout().lock();
+    // A closed stdout (i.e., the frontend went away) must not abort the install
+    let _ = writeln!(stdout, "{}", object);
+    let _ = stdout.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAILED_INSTALL: &str = include_str!("../tests/fixtures/failed-install.log");
+
+    #[test]
+    fn excerpt_of_the_failed_step() {
+        let (step, lines) = last_step_excerpt(FAILED_INSTALL, 3).unwrap();
+        assert_eq!(step, "Installing base packages");
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("warning: failed to retrieve some files"));
+        assert!(lines[2].ends_with("Install base packages ERROR: exit code 1"));
+    }
+
+    #[test]
+    fn excerpt_shorter_than_asked() {
+        let (step, lines) = last_step_excerpt(FAILED_INSTALL, 100).unwrap();
+        assert_eq!(step, "Installing base packages");
+        assert_eq!(lines.len(), 7);
+        assert!(lines[0].contains("AEGIS-PROGRESS: 10"));
+    }
+
+    #[test]
+    fn excerpt_stops_at_the_next_step() {
+        let partitioning: String = FAILED_INSTALL.lines().take(6).map(|line| format!("{}\n", line)).collect();
+        let (step, lines) = last_step_excerpt(&partitioning, 10).unwrap();
+        assert_eq!(step, "Partitioning drives");
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn log_without_steps() {
+        let before_any_step: String = FAILED_INSTALL.lines().take(2).map(|line| format!("{}\n", line)).collect();
+        assert!(last_step_excerpt(&before_any_step, 10).is_none());
+        assert!(last_step_excerpt("", 10).is_none());
+    }
+}
@@ -0,0 +1,49 @@
+use crate::args::ProxyArgs;
+use crate::files;
+use crate::log::info;
+use crate::returncode_eval::files_eval;
+use std::env;
+use std::sync::OnceLock;
+
+// Proxy variables to write into the installed system, only set with --persist-proxy
+static PERSISTED: OnceLock<Vec<(&'static str, String)>> = OnceLock::new();
+
+/// Exports the proxy settings to the environment, so every command spawned by the installer
+/// (pacstrap, pacman, curl, nix, ...) inherits them.
+pub fn apply(args: &ProxyArgs) {
+    let vars: Vec<(&'static str, String)> = [
+        ("http_proxy", &args.http_proxy),
+        ("https_proxy", &args.https_proxy),
+        ("no_proxy", &args.no_proxy),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| value.clone().map(|value| (name, value)))
+    .collect();
+
+    for (name, value) in &vars {
+        info!("Using {} : {}", name, value);
+        // Some tools only read the uppercase variant
+        env::set_var(name, value);
+        env::set_var(name.to_uppercase(), value);
+    }
+    if args.persist_proxy {
+        let _ = PERSISTED.set(vars);
+    }
+}
+
+/// The proxy variables (http_proxy, https_proxy, no_proxy) to keep in the installed system.
+pub fn persisted() -> &'static [(&'static str, String)] {
+    PERSISTED.get().map(Vec::as_slice).unwrap_or(&[])
+}
+
+/// Writes the persisted proxy variables, lowercase and uppercase, to /etc/environment of the target.
+pub fn persist() {
+    for (name, value) in persisted() {
+        for name in [name.to_string(), name.to_uppercase()] {
+            files_eval(
+                files::append_file("/mnt/etc/environment", &format!("{}={}", name, value)),
+                "persist proxy",
+            );
+        }
+    }
+}
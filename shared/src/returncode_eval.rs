@@ -1,3 +1,5 @@
+use crate::exec::{RunError, RunResult};
+use crate::failure;
 use crate::log::{info};
 use crate::strings::crash;
 
@@ -32,3 +34,17 @@ pub fn files_eval(return_code: std::result::Result<(), std::io::Error>, logmsg:
         }
     }
 }
+
+pub fn run_eval(result: RunResult, logmsg: &str) {
+    match result {
+        Ok(_) => {
+            info!("{}", logmsg);
+        }
+        Err(e) => {
+            if let RunError::Timeout(_) = e {
+                failure::command_timed_out();
+            }
+            crash(format!("{}  ERROR: {}", logmsg, e), e.code());
+        }
+    }
+}
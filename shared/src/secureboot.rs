@@ -0,0 +1,48 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// EFI global variables, the first 4 bytes of an efivars file are its attributes
+const SECURE_BOOT_VAR: &str = "/sys/firmware/efi/efivars/SecureBoot-8be4df61-93ca-11d2-aa0d-00e098032b8c";
+const SETUP_MODE_VAR: &str = "/sys/firmware/efi/efivars/SetupMode-8be4df61-93ca-11d2-aa0d-00e098032b8c";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Legacy BIOS boot or firmware without Secure Boot
+    Unsupported,
+    Disabled,
+    Enabled,
+    /// No platform key enrolled, custom keys can be enrolled from the OS
+    SetupMode,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            State::Unsupported => "unsupported",
+            State::Disabled => "disabled",
+            State::Enabled => "enabled",
+            State::SetupMode => "setup mode",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+fn efi_flag(path: &str) -> Option<bool> {
+    fs::read(path).ok().and_then(|value| value.get(4).map(|flag| *flag == 1))
+}
+
+/// Reads the Secure Boot state of the running firmware from efivars.
+pub fn state() -> State {
+    if !Path::new("/sys/firmware/efi").exists() {
+        return State::Unsupported;
+    }
+    if efi_flag(SETUP_MODE_VAR) == Some(true) {
+        return State::SetupMode;
+    }
+    match efi_flag(SECURE_BOOT_VAR) {
+        Some(true) => State::Enabled,
+        Some(false) => State::Disabled,
+        None => State::Unsupported,
+    }
+}
@@ -0,0 +1,96 @@
+use crate::exec::exec_chroot;
+use crate::firewall;
+use crate::log::debug;
+use crate::returncode_eval::{exec_eval, files_eval};
+
+/// Package names of the virtualization stacks, which differ between the bases.
+pub struct VirtualizationPackages {
+    pub docker: &'static [&'static str],
+    pub libvirt: &'static [&'static str],
+}
+
+pub fn enable(unit: &str) {
+    debug!("Enabling {}", unit);
+    exec_eval(
+        exec_chroot("systemctl", vec![String::from("enable"), String::from(unit)]),
+        &format!("Enable {}", unit),
+    );
+}
+
+/// Installs the selected container and virtual machine stacks with `install` and enables their daemons.
+/// podman-docker provides the docker command when Docker itself is not installed.
+pub fn install_virtualization(packages: &VirtualizationPackages, docker: bool, podman: bool, libvirt: bool, install: impl Fn(Vec<&str>)) {
+    let mut selected = Vec::new();
+    if docker {
+        selected.extend(packages.docker);
+    }
+    if podman {
+        selected.push("podman");
+        if !docker {
+            selected.push("podman-docker");
+        }
+    }
+    if libvirt {
+        selected.extend(packages.libvirt);
+    }
+    install(selected);
+    if docker {
+        enable("docker");
+    }
+    if libvirt {
+        enable("libvirtd");
+    }
+}
+
+/// Installs and enables the power management service of a laptop. TLP handles the radios itself, the rfkill units are masked.
+pub fn configure_power_management(service: &str, install: impl Fn(Vec<&str>)) {
+    match service {
+        "tlp" => {
+            install(vec!["tlp"]);
+            enable("tlp");
+            for unit in ["systemd-rfkill.service", "systemd-rfkill.socket"] {
+                exec_eval(
+                    exec_chroot("systemctl", vec![String::from("mask"), String::from(unit)]),
+                    &format!("Mask {}", unit),
+                );
+            }
+        }
+        "power-profiles-daemon" => {
+            install(vec!["power-profiles-daemon"]);
+            enable("power-profiles-daemon");
+        }
+        _ => {}
+    }
+}
+
+/// Installs and enables the chosen firewall. firewalld is set up offline, its daemon does not run in the chroot.
+pub fn configure_firewall(backend: &str, deny_incoming: bool, tcp_ports: &[String], udp_ports: &[String], install: impl Fn(Vec<&str>)) {
+    match backend {
+        "nftables" => {
+            install(vec!["nftables"]);
+            files_eval(
+                std::fs::write("/mnt/etc/nftables.conf", firewall::nftables_ruleset(deny_incoming, tcp_ports, udp_ports)),
+                "Write nftables ruleset",
+            );
+            enable("nftables");
+        }
+        "firewalld" => {
+            install(vec!["firewalld"]);
+            // public only lets in ssh and dhcpv6-client, ssh has to be listed among the ports to stay open
+            let zone = if deny_incoming { "public" } else { "trusted" };
+            let mut args = vec![format!("--zone={}", zone)];
+            if deny_incoming {
+                args.push(String::from("--remove-service=ssh"));
+            }
+            args.extend(tcp_ports.iter().map(|port| format!("--add-port={}/tcp", port)));
+            args.extend(udp_ports.iter().map(|port| format!("--add-port={}/udp", port)));
+            exec_eval(
+                exec_chroot("firewall-offline-cmd", vec![format!("--set-default-zone={}", zone)]),
+                "Set firewalld default zone",
+            );
+            exec_eval(exec_chroot("firewall-offline-cmd", args), "Open firewalld ports");
+            enable("firewalld");
+        }
+        _ => {}
+    }
+}
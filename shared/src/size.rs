@@ -0,0 +1,85 @@
+pub const MIB: u64 = 1024 * 1024;
+
+/// Parses a size given as a decimal value and a unit, a percentage of `available` or "rest" (all of `available`).
+/// B, K/KiB, M/MiB, G/GiB and T/TiB are binary units, KB, MB, GB and TB are SI ones, all case-insensitive.
+/// The result is rounded down to a whole byte, callers round it down further to their own granularity.
+pub fn parse_size(input: &str, available: u64) -> Result<u64, String> {
+    let size = input.trim();
+    if size.eq_ignore_ascii_case("rest") {
+        return Ok(available);
+    }
+    if let Some(percent) = size.strip_suffix('%') {
+        let percent: f64 = percent.trim().parse().map_err(|_| format!("invalid percentage \"{}\"", input))?;
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(format!("percentage \"{}\" is not between 0 and 100", input));
+        }
+        return Ok((available as f64 * percent / 100.0) as u64);
+    }
+    let unit_start = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .ok_or_else(|| format!("size \"{}\" has no unit, i.e. 8GiB", input))?;
+    let (value, unit) = size.split_at(unit_start);
+    let value: f64 = value.parse().map_err(|_| format!("invalid size \"{}\"", input))?;
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "b" => 1,
+        "k" | "kib" => 1024,
+        "m" | "mib" => MIB,
+        "g" | "gib" => 1024 * MIB,
+        "t" | "tib" => 1024 * 1024 * MIB,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => return Err(format!("unknown unit \"{}\" in \"{}\"", unit.trim(), input)),
+    };
+    Ok((value * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1024 * MIB;
+
+    #[test]
+    fn binary_units() {
+        assert_eq!(parse_size("512MiB", 0), Ok(512 * MIB));
+        assert_eq!(parse_size("512M", 0), Ok(512 * MIB));
+        assert_eq!(parse_size("8GiB", 0), Ok(8 * GIB));
+        assert_eq!(parse_size("8 gib", 0), Ok(8 * GIB));
+        assert_eq!(parse_size("1.5G", 0), Ok(1536 * MIB));
+        assert_eq!(parse_size("2T", 0), Ok(2048 * GIB));
+        assert_eq!(parse_size("4096B", 0), Ok(4096));
+    }
+
+    #[test]
+    fn si_units() {
+        assert_eq!(parse_size("500MB", 0), Ok(500_000_000));
+        assert_eq!(parse_size("8GB", 0), Ok(8_000_000_000));
+    }
+
+    #[test]
+    fn percentages() {
+        assert_eq!(parse_size("50%", 10 * GIB), Ok(5 * GIB));
+        assert_eq!(parse_size(" 12.5 %", 8 * GIB), Ok(GIB));
+        assert_eq!(parse_size("0%", 10 * GIB), Ok(0));
+        assert_eq!(parse_size("100%", 10 * GIB), Ok(10 * GIB));
+        assert!(parse_size("101%", 10 * GIB).is_err());
+        assert!(parse_size("-5%", 10 * GIB).is_err());
+        assert!(parse_size("half%", 10 * GIB).is_err());
+    }
+
+    #[test]
+    fn remaining_space() {
+        assert_eq!(parse_size("rest", 42 * GIB), Ok(42 * GIB));
+        assert_eq!(parse_size(" REST ", 42 * GIB), Ok(42 * GIB));
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        assert!(parse_size("8", 0).unwrap_err().contains("no unit"));
+        assert!(parse_size("8PiB", 0).unwrap_err().contains("unknown unit"));
+        assert!(parse_size("1.2.3G", 0).is_err());
+        assert!(parse_size("", 0).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::log::info;
+use crate::logging;
+use crate::strings::crash;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+
+/// An install step with the names of the steps that must complete before it starts.
+pub struct Step<'a> {
+    name: &'static str,
+    after: &'static [&'static str],
+    run: Box<dyn FnOnce() + Send + 'a>,
+}
+
+impl<'a> Step<'a> {
+    pub fn new(name: &'static str, after: &'static [&'static str], run: impl FnOnce() + Send + 'a) -> Self {
+        Step { name, after, run: Box::new(run) }
+    }
+}
+
+/// Runs the steps as soon as their prerequisites are done, at most `jobs` at a time.
+/// With a single job they run one after the other on the calling thread, in the given order when possible.
+/// The log lines of each step are prefixed by its name so interleaved output stays readable.
+pub fn run_steps(steps: Vec<Step<'_>>, jobs: usize) {
+    for step in &steps {
+        for prerequisite in step.after {
+            if !steps.iter().any(|other| other.name == *prerequisite) {
+                crash(format!("Step {} depends on unknown step {}", step.name, prerequisite), 1);
+            }
+        }
+    }
+
+    let mut pending: Vec<Option<Step>> = steps.into_iter().map(Some).collect();
+    let mut done: Vec<&'static str> = Vec::new();
+    let mut running = 0;
+    let jobs = jobs.max(1);
+
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        loop {
+            while running < jobs {
+                let ready = pending.iter().position(|step| {
+                    step.as_ref()
+                        .is_some_and(|step| step.after.iter().all(|prerequisite| done.contains(prerequisite)))
+                });
+                let Some(index) = ready else { break };
+                let step = pending[index].take().unwrap();
+                if jobs == 1 {
+                    run_step(step.name, step.run);
+                    done.push(step.name);
+                    continue;
+                }
+                let sender = sender.clone();
+                running += 1;
+                scope.spawn(move || {
+                    run_step(step.name, step.run);
+                    sender.send(step.name).unwrap();
+                });
+            }
+            if running == 0 {
+                break;
+            }
+            done.push(receiver.recv().unwrap());
+            running -= 1;
+        }
+    });
+
+    let stuck: Vec<&str> = pending.iter().flatten().map(|step| step.name).collect();
+    if !stuck.is_empty() {
+        crash(format!("Steps {} have circular dependencies", stuck.join(", ")), 1);
+    }
+}
+
+fn run_step(name: &'static str, run: Box<dyn FnOnce() + Send + '_>) {
+    logging::set_step(Some(name));
+    info!("Starting step");
+    // A panicking step must not leave the other steps waiting for it forever
+    if panic::catch_unwind(AssertUnwindSafe(run)).is_err() {
+        crash(format!("Step {} failed", name), 1);
+    }
+    info!("Step done");
+    logging::set_step(None);
+}
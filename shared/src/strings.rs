@@ -1,7 +1,41 @@
+use crate::exec::exec_chroot;
 use crate::log::{error};
+use crate::returncode_eval::exec_eval;
 use std::process::exit;
 
+/// Makes a full name safe for the GECOS field of /etc/passwd, where ':' separates the fields,
+/// ',' the subfields (and '=' is refused by chfn) and a newline ends the entry.
+pub fn gecos(full_name: &str) -> String {
+    full_name
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if matches!(c, ':' | ',' | '=') { ' ' } else { c })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Sets the GECOS full name of a user of the target, shown by the display managers.
+pub fn set_full_name(username: &str, full_name: &str) {
+    let sanitized_username = username.replace(' ', "");
+    exec_eval(
+        exec_chroot(
+            "usermod",
+            vec![
+                String::from("-c"),
+                // exec_chroot runs the command through bash, the name is single-quoted
+                format!("'{}'", gecos(full_name).replace('\'', "'\\''")),
+                sanitized_username.clone(),
+            ],
+        ),
+        format!("Set full name of user {}", sanitized_username).as_str(),
+    );
+}
+
 pub fn crash<S: AsRef<str>>(a: S, b: i32) -> ! {
     error!("{}", a.as_ref());
-    exit(b);
+    crate::files::shred_secrets();
+    let code = crate::failure::report(a.as_ref(), b, None);
+    crate::progress::finish(code);
+    exit(code);
 }
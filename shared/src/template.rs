@@ -0,0 +1,123 @@
+use crate::validation::Problem;
+use regex::{Captures, Regex};
+use serde_json::Value;
+use std::fs;
+
+const DMI_DIR: &str = "/sys/class/dmi/id";
+
+// DMI fields that make sense as per-host values; product_serial and friends are only readable by root
+const DMI_FIELDS: [&str; 9] = [
+    "product_serial",
+    "product_name",
+    "product_uuid",
+    "product_version",
+    "sys_vendor",
+    "board_serial",
+    "board_name",
+    "chassis_serial",
+    "bios_version",
+];
+
+// Shell code, its ${NAME} are expanded by the shell when the script runs
+const VERBATIM_FIELDS: [&str; 1] = ["/post_install_script"];
+
+/// Replaces ${NAME} placeholders in every string value of the config. NAME is looked up in
+/// the --set overrides first, then as dmi:<field> in /sys/class/dmi/id or as an environment
+/// variable. Placeholders that cannot be resolved are reported with the path of their field.
+/// $${NAME} is written as a literal ${NAME}; "_comment" keys and VERBATIM_FIELDS are left as is.
+pub fn substitute(config: &mut Value, overrides: &[(String, String)]) -> Vec<Problem> {
+    let placeholder = Regex::new(r"\$(\$?)\{([^}]+)\}").unwrap();
+    let mut problems = Vec::new();
+    substitute_value(config, String::new(), &placeholder, overrides, &mut problems);
+    problems
+}
+
+fn substitute_value(
+    value: &mut Value,
+    path: String,
+    placeholder: &Regex,
+    overrides: &[(String, String)],
+    problems: &mut Vec<Problem>,
+) {
+    match value {
+        Value::String(string) => {
+            let replaced = placeholder.replace_all(string, |caps: &Captures| {
+                if !caps[1].is_empty() {
+                    return format!("${{{}}}", &caps[2]);
+                }
+                match lookup(&caps[2], overrides) {
+                    Some(resolved) => resolved,
+                    None => {
+                        problems.push(Problem::new(&path, format!("unresolved placeholder {}", &caps[0])));
+                        caps[0].to_string()
+                    }
+                }
+            });
+            *string = replaced.into_owned();
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                substitute_value(item, format!("{}/{}", path, i), placeholder, overrides, problems);
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                let field = format!("{}/{}", path, key);
+                if key.starts_with("_comment") || VERBATIM_FIELDS.contains(&field.as_str()) {
+                    continue;
+                }
+                substitute_value(item, field, placeholder, overrides, problems);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn lookup(name: &str, overrides: &[(String, String)]) -> Option<String> {
+    if let Some((_, value)) = overrides.iter().rev().find(|(key, _)| key == name) {
+        return Some(value.clone());
+    }
+    match name.strip_prefix("dmi:") {
+        Some(field) => read_dmi(field),
+        None => std::env::var(name).ok(),
+    }
+}
+
+fn read_dmi(field: &str) -> Option<String> {
+    if !DMI_FIELDS.contains(&field) {
+        return None;
+    }
+    fs::read_to_string(format!("{}/{}", DMI_DIR, field))
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Prints the template variables that would be substituted on this machine.
+pub fn list_vars(overrides: &[(String, String)]) {
+    println!("Overrides (--set):");
+    if overrides.is_empty() {
+        println!("  (none)");
+    }
+    for (key, value) in overrides {
+        println!("  ${{{}}} = {}", key, value);
+    }
+    println!("DMI ({}):", DMI_DIR);
+    for field in DMI_FIELDS {
+        match read_dmi(field) {
+            Some(value) => println!("  ${{dmi:{}}} = {}", field, value),
+            None => println!("  ${{dmi:{}}} (unavailable)", field),
+        }
+    }
+    println!("Environment:");
+    println!("  ${{NAME}} = value of the NAME environment variable, if set");
+    println!("Write $${{NAME}} for a literal ${{NAME}}, post_install_script is never substituted.");
+}
+
+/// Parses a KEY=VALUE pair given to --set.
+pub fn parse_override(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("invalid KEY=VALUE: no '=' found in '{}'", s)),
+    }
+}
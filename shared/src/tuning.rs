@@ -0,0 +1,155 @@
+use crate::files;
+use crate::log::info;
+use crate::returncode_eval::files_eval;
+use std::collections::BTreeMap;
+use std::fs;
+
+pub const PRESETS: [&str; 4] = ["default", "low-latency", "vm-guest", "server"];
+
+// Values of a kernel without any sysctl.d file, the summary only lists what the install changes
+const KERNEL_DEFAULTS: [(&str, &str); 9] = [
+    ("vm.swappiness", "60"),
+    ("vm.vfs_cache_pressure", "100"),
+    ("vm.dirty_ratio", "20"),
+    ("vm.dirty_background_ratio", "10"),
+    ("net.core.default_qdisc", "pfifo_fast"),
+    ("net.core.somaxconn", "4096"),
+    ("net.ipv4.tcp_congestion_control", "cubic"),
+    ("net.ipv4.tcp_fastopen", "1"),
+    ("net.ipv4.tcp_mtu_probing", "0"),
+];
+
+// The default preset keeps the kernel defaults and the scheduler the kernel picks
+fn preset_values(preset: &str) -> &'static [(&'static str, &'static str)] {
+    match preset {
+        // Keeps the page cache and the desktop in memory, small writeback bursts avoid stalls
+        "low-latency" => &[
+            ("vm.swappiness", "10"),
+            ("vm.vfs_cache_pressure", "50"),
+            ("vm.dirty_ratio", "10"),
+            ("vm.dirty_background_ratio", "5"),
+            ("net.core.default_qdisc", "fq_codel"),
+            ("net.ipv4.tcp_fastopen", "3"),
+        ],
+        // The host caches the disk already, the guest writes back early and rarely swaps
+        "vm-guest" => &[
+            ("vm.swappiness", "10"),
+            ("vm.dirty_ratio", "10"),
+            ("vm.dirty_background_ratio", "5"),
+            ("net.core.default_qdisc", "fq_codel"),
+        ],
+        "server" => &[
+            ("vm.swappiness", "10"),
+            ("net.core.default_qdisc", "fq"),
+            ("net.core.somaxconn", "8192"),
+            ("net.ipv4.tcp_congestion_control", "bbr"),
+            ("net.ipv4.tcp_fastopen", "3"),
+            ("net.ipv4.tcp_mtu_probing", "1"),
+        ],
+        _ => &[],
+    }
+}
+
+/// Checks a manual override: a sysctl key like vm.swappiness and a single-line value.
+pub fn check_sysctl(key: &str, value: &str) -> Result<(), String> {
+    let valid_key = key.split('.').count() > 1
+        && key.split('.').all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'));
+    if !valid_key {
+        return Err(format!("\"{}\" is not a sysctl key like vm.swappiness", key));
+    }
+    if value.trim().is_empty() || value.contains('\n') {
+        return Err(format!("{} needs a single-line value", key));
+    }
+    Ok(())
+}
+
+/// Sysctl values of the preset with the manual overrides applied on top, sorted by key.
+pub fn sysctl_values(preset: &str, overrides: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    let mut values: BTreeMap<String, String> =
+        preset_values(preset).iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+    for (key, value) in overrides {
+        values.insert(key.clone(), value.trim().to_string());
+    }
+    values
+}
+
+/// Content of /etc/sysctl.d/99-aegis-tuning.conf.
+pub fn sysctl_conf(values: &BTreeMap<String, String>) -> String {
+    let mut conf = String::from("# Written by aegis\n");
+    for (key, value) in values {
+        conf.push_str(&format!("{} = {}\n", key, value));
+    }
+    conf
+}
+
+/// udev rules picking the IO scheduler from the drive type: none for NVMe and virtio disks,
+/// mq-deadline for SSDs and bfq for spinning disks.
+pub const IO_SCHEDULER_RULES: &str = r#"# Written by aegis
+ACTION=="add|change", KERNEL=="nvme[0-9]*n[0-9]*|vd[a-z]*", ATTR{queue/scheduler}="none"
+ACTION=="add|change", KERNEL=="sd[a-z]*|mmcblk[0-9]*", ATTR{queue/rotational}=="0", ATTR{queue/scheduler}="mq-deadline"
+ACTION=="add|change", KERNEL=="sd[a-z]*", ATTR{queue/rotational}=="1", ATTR{queue/scheduler}="bfq"
+"#;
+
+/// Whether the preset installs the IO scheduler rules.
+pub fn io_scheduler(preset: &str) -> bool {
+    !preset.is_empty() && preset != "default"
+}
+
+/// Writes the sysctl.d file and the IO scheduler rules of the target, read by systemd at every boot.
+pub fn write_config(values: &BTreeMap<String, String>, io_scheduler: bool) {
+    if !values.is_empty() {
+        info!("Writing the sysctl tuning");
+        files_eval(files::create_directory("/mnt/etc/sysctl.d"), "create /mnt/etc/sysctl.d");
+        files_eval(fs::write("/mnt/etc/sysctl.d/99-aegis-tuning.conf", sysctl_conf(values)), "write the sysctl tuning");
+    }
+    if io_scheduler {
+        info!("Writing the IO scheduler rules");
+        files_eval(files::create_directory("/mnt/etc/udev/rules.d"), "create /mnt/etc/udev/rules.d");
+        files_eval(fs::write("/mnt/etc/udev/rules.d/60-aegis-ioscheduler.rules", IO_SCHEDULER_RULES), "write the IO scheduler rules");
+    }
+}
+
+// Same choice as IO_SCHEDULER_RULES, for the drives of this machine
+fn scheduler_for(disk: &str) -> Option<&'static str> {
+    if disk.starts_with("nvme") || disk.starts_with("vd") {
+        return Some("none");
+    }
+    if !disk.starts_with("sd") && !disk.starts_with("mmcblk") {
+        return None;
+    }
+    let rotational = fs::read_to_string(format!("/sys/block/{}/queue/rotational", disk)).ok()?;
+    match (rotational.trim(), disk.starts_with("sd")) {
+        ("1", true) => Some("bfq"),
+        ("0", _) => Some("mq-deadline"),
+        _ => None,
+    }
+}
+
+/// Human-readable lines of the summary: every sysctl value differing from the kernel default, and the
+/// scheduler each detected drive gets.
+pub fn summary(preset: &str, values: &BTreeMap<String, String>) -> Vec<String> {
+    let preset = if preset.is_empty() { "default" } else { preset };
+    let mut lines = vec![format!("System tuning : {}", preset)];
+    for (key, value) in values {
+        match KERNEL_DEFAULTS.iter().find(|(default_key, _)| default_key == key) {
+            Some((_, default)) if default == value => {}
+            Some((_, default)) => lines.push(format!("sysctl {} = {} (kernel default {})", key, value, default)),
+            None => lines.push(format!("sysctl {} = {}", key, value)),
+        }
+    }
+    if io_scheduler(preset) {
+        let mut disks: Vec<String> = fs::read_dir("/sys/block")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        disks.sort();
+        for disk in disks {
+            if let Some(scheduler) = scheduler_for(&disk) {
+                lines.push(format!("IO scheduler of {} : {}", disk, scheduler));
+            }
+        }
+    }
+    lines
+}
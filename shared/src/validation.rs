@@ -0,0 +1,646 @@
+use serde_json::Value;
+use std::fmt;
+
+const FILESYSTEMS: [&str; 15] = [
+    "vfat", "bfs", "cramfs", "ext3", "fat", "msdos", "xfs", "btrfs", "ext2", "ext4", "minix", "f2fs",
+    "linux-swap", "don't format", "noformat",
+];
+
+const SHELLS: [&str; 3] = ["bash", "fish", "zsh"];
+
+// System accounts created by the base packages of Arch, Fedora and NixOS, useradd refuses them
+const RESERVED_USERNAMES: [&str; 24] = [
+    "root", "bin", "daemon", "adm", "lp", "sync", "shutdown", "halt", "mail", "operator", "games", "ftp",
+    "nobody", "dbus", "polkitd", "avahi", "colord", "rtkit", "git", "http", "uucp", "tss", "nixbld", "sshd",
+];
+// Longest name useradd accepts
+const USERNAME_MAX_LENGTH: usize = 32;
+
+// Desktops whose session relies on PipeWire as the audio server
+const PIPEWIRE_DESKTOPS: [&str; 3] = ["gnome", "onyx", "hyprland"];
+
+// Window managers without a session manager of their own
+const TILING_WMS: [&str; 6] = ["awesome", "bspwm", "herbstluftwm", "hyprland", "i3", "sway"];
+
+// mkinitcpio hooks shipped by mkinitcpio and the Arch and Athena packages, others are likely typos
+const KNOWN_HOOKS: [&str; 24] = [
+    "base", "udev", "systemd", "autodetect", "microcode", "modconf", "kms", "keyboard", "keymap", "consolefont",
+    "sd-vconsole", "block", "encrypt", "sd-encrypt", "lvm2", "mdadm_udev", "filesystems", "fsck", "resume",
+    "plymouth", "grub-btrfs-overlayfs", "shutdown", "sd-shutdown", "openswap",
+];
+// Hooks of the busybox initramfs, replaced by systemd, sd-encrypt and sd-vconsole in the generated HOOKS
+const BUSYBOX_HOOKS: [&str; 5] = ["udev", "encrypt", "resume", "keymap", "consolefont"];
+// Kernel modules commonly wanted early: GPU drivers, virtual machine devices, storage and filesystems
+const KNOWN_MODULES: [&str; 30] = [
+    "amdgpu", "radeon", "i915", "xe", "nouveau", "nvidia", "nvidia_modeset", "nvidia_uvm", "nvidia_drm",
+    "virtio_gpu", "virtio_blk", "virtio_pci", "virtio_scsi", "virtio_net", "vmwgfx", "vsock",
+    "vmw_vsock_vmci_transport", "vmw_balloon", "vmw_vmci", "qxl", "bochs", "hv_vmbus", "hv_storvsc",
+    "nvme", "ahci", "usb_storage", "uas", "btrfs", "ext4", "xfs",
+];
+
+/// Password hash used by generated example configs, rejected until replaced by a real hash.
+pub const PLACEHOLDER_HASH: &str = "REPLACE_WITH_HASH";
+
+/// A config error, located by the JSON pointer of the offending value (i.e., /users/0/name).
+pub struct Problem {
+    pub path: String,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn new(path: &str, message: impl Into<String>) -> Self {
+        Self {
+            path: path.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    String,
+    Bool,
+    Array,
+    Object,
+    // A single string or an array of strings, see config_input::one_or_many
+    StringList,
+}
+
+impl Kind {
+    fn matches(&self, value: &Value) -> bool {
+        match self {
+            Kind::String => value.is_string(),
+            Kind::Bool => value.is_boolean(),
+            Kind::Array => value.is_array(),
+            Kind::Object => value.is_object(),
+            Kind::StringList => {
+                value.is_string() || value.as_array().is_some_and(|values| values.iter().all(Value::is_string))
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::String => "a string",
+            Kind::Bool => "a boolean",
+            Kind::Array => "an array",
+            Kind::Object => "an object",
+            Kind::StringList => "a string or an array of strings",
+        }
+    }
+}
+
+/// Keys a backend config must contain and the values accepted by its selection keys.
+/// Selection values are compared lowercase, and an empty string means nothing was selected.
+pub struct Schema {
+    pub required: &'static [(&'static str, Kind)],
+    pub choices: &'static [(&'static str, &'static [&'static str])],
+}
+
+/// Checks a parsed config against the schema and returns every problem found, not just the first one.
+pub fn validate(config: &Value, schema: &Schema) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    for (path, kind) in schema.required {
+        match config.pointer(path) {
+            None => problems.push(Problem::new(path, "missing")),
+            Some(value) if !kind.matches(value) => {
+                problems.push(Problem::new(path, format!("expected {}", kind.name())))
+            }
+            Some(_) => {}
+        }
+    }
+
+    for (path, allowed) in schema.choices {
+        let values: Vec<(String, &str)> = match config.pointer(path) {
+            Some(Value::String(value)) => vec![(path.to_string(), value.as_str())],
+            Some(Value::Array(values)) => values
+                .iter()
+                .enumerate()
+                .filter_map(|(i, value)| value.as_str().map(|value| (format!("{}/{}", path, i), value)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        for (path, value) in values {
+            let value = value.to_lowercase();
+            if !value.is_empty() && !allowed.contains(&value.as_str()) {
+                problems.push(Problem::new(
+                    &path,
+                    format!("unknown value \"{}\", expected one of: {}", value, allowed.join(", ")),
+                ));
+            }
+        }
+    }
+
+    check_partitions(config, &mut problems);
+    check_users(config, &mut problems);
+    check_snapshots(config, &mut problems);
+
+    if let Some(Value::Array(desktops)) = config.pointer("/desktop") {
+        let none = desktops.iter().any(|desktop| desktop.as_str().is_some_and(|desktop| desktop.eq_ignore_ascii_case("none")));
+        if none && desktops.len() > 1 {
+            problems.push(Problem::new("/desktop", "\"none\" cannot be combined with other desktops"));
+        }
+    }
+
+    if config.pointer("/aur_packages").and_then(Value::as_array).is_some_and(|packages| !packages.is_empty()) {
+        let helper = config.pointer("/aur_helper").and_then(Value::as_str).unwrap_or("").to_lowercase();
+        if helper.is_empty() || helper == "none" {
+            problems.push(Problem::new("/aur_packages", "needs an aur_helper to build them"));
+        }
+    }
+
+    for protocol in ["tcp", "udp"] {
+        let path = format!("/firewall/{}_ports", protocol);
+        for (i, entry) in config.pointer(&path).and_then(Value::as_array).into_iter().flatten().enumerate() {
+            let result = entry.as_str().ok_or_else(|| String::from("expected a string")).and_then(|entry| crate::firewall::parse_port(entry).map(|_| ()));
+            if let Err(e) = result {
+                problems.push(Problem::new(&format!("{}/{}", path, i), e));
+            }
+        }
+    }
+
+    for (i, storage) in config.pointer("/partition/network_storage").and_then(Value::as_array).into_iter().flatten().enumerate() {
+        let path = format!("/partition/network_storage/{}", i);
+        let field = |key: &str| storage.pointer(&format!("/{}", key)).and_then(Value::as_str).unwrap_or("");
+        let protocol = field("protocol").to_lowercase();
+        if !crate::netstorage::PROTOCOLS.contains(&protocol.as_str()) {
+            problems.push(Problem::new(
+                &format!("{}/protocol", path),
+                format!("unknown value \"{}\", expected one of: {}", protocol, crate::netstorage::PROTOCOLS.join(", ")),
+            ));
+            continue;
+        }
+        if let Err(e) = crate::netstorage::parse_portal(&protocol, field("portal")) {
+            problems.push(Problem::new(&format!("{}/portal", path), e));
+        }
+        if let Err(e) = crate::netstorage::check_target(&protocol, field("target")) {
+            problems.push(Problem::new(&format!("{}/target", path), e));
+        }
+    }
+
+    for (key, value) in config.pointer("/tuning/sysctl").and_then(Value::as_object).into_iter().flatten() {
+        let result = value.as_str().ok_or_else(|| String::from("expected a string")).and_then(|value| crate::tuning::check_sysctl(key, value));
+        if let Err(e) = result {
+            problems.push(Problem::new(&format!("/tuning/sysctl/{}", key), e));
+        }
+    }
+
+    if config.pointer("/audio").and_then(Value::as_str).is_some_and(|audio| audio.eq_ignore_ascii_case("pulseaudio")) {
+        let desktops = config.pointer("/desktop").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str);
+        for desktop in desktops.filter(|desktop| PIPEWIRE_DESKTOPS.contains(&desktop.to_lowercase().as_str())) {
+            problems.push(Problem::new("/audio", format!("{} needs PipeWire, it cannot run with PulseAudio", desktop)));
+        }
+    }
+
+    for (i, server) in config.pointer("/time/ntp_servers").and_then(Value::as_array).into_iter().flatten().enumerate() {
+        let result = server.as_str().ok_or_else(|| String::from("expected a string")).and_then(check_ntp_server);
+        if let Err(e) = result {
+            problems.push(Problem::new(&format!("/time/ntp_servers/{}", i), e));
+        }
+    }
+
+    if let Some(Value::String(language)) = config.pointer("/language") {
+        if !language.is_empty() && crate::language::lookup(language).is_none() {
+            problems.push(Problem::new(
+                "/language",
+                format!("unknown language \"{}\", expected one of: {}", language, crate::language::names().join(", ")),
+            ));
+        }
+    }
+
+    // The theme list belongs to SDDM, another display manager would silently ignore it
+    let theme = config.pointer("/displaymanager_theme").and_then(Value::as_str).unwrap_or("");
+    let displaymanager = config.pointer("/displaymanager").and_then(Value::as_str).unwrap_or("none");
+    if !theme.is_empty() && !displaymanager.eq_ignore_ascii_case("sddm") {
+        problems.push(Problem::new("/displaymanager_theme", "only applies to the sddm display manager"));
+    }
+
+    // Secure Boot signs EFI binaries, there is nothing to sign with a legacy bootloader
+    let secure_boot = config.pointer("/secure_boot/mode").and_then(Value::as_str).unwrap_or("none");
+    let bootloader = config.pointer("/bootloader/type").and_then(Value::as_str).unwrap_or("");
+    if !secure_boot.is_empty() && !secure_boot.eq_ignore_ascii_case("none") && bootloader != "grub-efi" {
+        problems.push(Problem::new("/secure_boot/mode", "Secure Boot requires the grub-efi bootloader"));
+    }
+
+    // The encrypted /boot layout is an EraseDisk layout with the ESP mounted at /efi
+    if config.pointer("/partition/encrypt_boot").and_then(Value::as_bool).unwrap_or(false) {
+        let flag = |path: &str| config.pointer(path).and_then(Value::as_bool).unwrap_or(false);
+        let mode = config.pointer("/partition/mode").and_then(Value::as_str).unwrap_or("");
+        if !flag("/partition/encrypt_check") {
+            problems.push(Problem::new("/partition/encrypt_boot", "requires encrypt_check"));
+        }
+        if !flag("/partition/efi") || bootloader != "grub-efi" {
+            problems.push(Problem::new("/partition/encrypt_boot", "requires efi and the grub-efi bootloader"));
+        }
+        if !mode.eq_ignore_ascii_case("erasedisk") {
+            problems.push(Problem::new("/partition/encrypt_boot", "only applies to the EraseDisk mode"));
+        }
+        if config.pointer("/bootloader/location").and_then(Value::as_str) != Some("/efi") {
+            problems.push(Problem::new("/bootloader/location", "must be /efi when encrypt_boot is set"));
+        }
+    }
+
+    if config.pointer("/partition/swap").and_then(Value::as_bool).unwrap_or(false) {
+        if let Some(Err(e)) = config.pointer("/partition/swap_size").and_then(Value::as_str).map(|size| crate::size::parse_size(size, 0)) {
+            problems.push(Problem::new("/partition/swap_size", e));
+        }
+    }
+
+    // A random-key swap needs a swap partition of its own, the encrypt_boot swap is already inside LUKS
+    if config.pointer("/partition/swap_random_key").and_then(Value::as_bool).unwrap_or(false) {
+        let swap_partition = config
+            .pointer("/partition/partitions")
+            .and_then(Value::as_array)
+            .is_some_and(|partitions| partitions.iter().filter_map(Value::as_str).any(|p| p.split(':').nth(2) == Some("linux-swap")));
+        let erase_disk = config.pointer("/partition/mode").and_then(Value::as_str).is_some_and(|mode| mode.eq_ignore_ascii_case("erasedisk"));
+        let swap = config.pointer("/partition/swap").and_then(Value::as_bool).unwrap_or(false);
+        let has_swap = if erase_disk { swap } else { swap_partition };
+        if !has_swap {
+            problems.push(Problem::new("/partition/swap_random_key", "requires a swap partition"));
+        }
+        if config.pointer("/partition/encrypt_boot").and_then(Value::as_bool).unwrap_or(false) {
+            problems.push(Problem::new("/partition/swap_random_key", "not needed with encrypt_boot, the swap is already encrypted"));
+        }
+    }
+
+    // Wiping only makes sense on the drive the automatic layout takes over
+    let wipe = config.pointer("/partition/wipe").and_then(Value::as_str).unwrap_or("none");
+    let mode = config.pointer("/partition/mode").and_then(Value::as_str).unwrap_or("");
+    if !wipe.is_empty() && !wipe.eq_ignore_ascii_case("none") && !mode.eq_ignore_ascii_case("erasedisk") {
+        problems.push(Problem::new("/partition/wipe", "only applies to the EraseDisk mode"));
+    }
+
+    // Extra unlock methods are keyslots added next to the passphrase of an encrypted partition
+    let keyfile = config.pointer("/partition/encryption/keyfile_device").and_then(Value::as_str).unwrap_or("");
+    let tpm2 = config.pointer("/partition/encryption/tpm2").and_then(Value::as_bool).unwrap_or(false);
+    if !keyfile.is_empty() || tpm2 {
+        let encrypt_check = config.pointer("/partition/encrypt_check").and_then(Value::as_bool).unwrap_or(false);
+        let encrypted_partition = config
+            .pointer("/partition/partitions")
+            .and_then(Value::as_array)
+            .is_some_and(|partitions| partitions.iter().filter_map(Value::as_str).any(|p| p.split(':').nth(3) == Some("true")));
+        if !encrypt_check && !encrypted_partition {
+            problems.push(Problem::new("/partition/encryption", "needs encrypt_check or an encrypted partition"));
+        }
+        if !keyfile.is_empty() && !keyfile.starts_with("/dev/") {
+            problems.push(Problem::new("/partition/encryption/keyfile_device", "expected a partition path like /dev/sdb1"));
+        }
+    }
+    // systemd-cryptenroll only writes LUKS2 headers, the encrypted /boot container is LUKS1 for GRUB
+    if tpm2 && config.pointer("/partition/encrypt_boot").and_then(Value::as_bool).unwrap_or(false) {
+        problems.push(Problem::new("/partition/encryption/tpm2", "not available with encrypt_boot"));
+    }
+
+    if let Some(Value::String(rootpass)) = config.pointer("/rootpass") {
+        if rootpass.is_empty() {
+            problems.push(Problem::new("/rootpass", "root password hash is empty"));
+        } else if rootpass == PLACEHOLDER_HASH {
+            problems.push(Problem::new("/rootpass", "placeholder hash, generate one with `openssl passwd -6`"));
+        }
+    }
+    // A locked root has no password, administration goes through the sudo users
+    if config.pointer("/root_locked").and_then(Value::as_bool).unwrap_or(false) {
+        problems.retain(|problem| !problem.path.starts_with("/rootpass"));
+        let users = config.pointer("/users").and_then(Value::as_array);
+        if !users.is_some_and(|users| users.iter().any(|user| user.get("hasroot").and_then(Value::as_bool).unwrap_or(false))) {
+            problems.push(Problem::new("/users", "root_locked needs at least one user with hasroot"));
+        }
+    }
+
+    problems
+}
+
+/// Selections that install fine but are likely not what was meant, reported without stopping the install.
+pub fn warnings(config: &Value) -> Vec<Problem> {
+    let mut warnings = Vec::new();
+    check_power_management(config, &mut warnings);
+    let virtualization = |name: &str| config.pointer(&format!("/virtualization/{}", name)).and_then(Value::as_bool).unwrap_or(false);
+    if virtualization("docker") && virtualization("podman") {
+        warnings.push(Problem::new("/virtualization", "Docker and Podman are both selected, the docker command stays Docker's and Podman gets no docker alias"));
+    }
+    check_initramfs(config, &mut warnings);
+    // Without @snapshots, snapper keeps the snapshots inside @ and a rollback of @ takes them along.
+    // The EraseDisk layout has it, manual layouts need a partition entry mounting it.
+    let erase_disk = config.pointer("/partition/mode").and_then(Value::as_str).is_some_and(|mode| mode.eq_ignore_ascii_case("erasedisk"));
+    if snapshot_tool(config) == "snapper" && !erase_disk && root_filesystem(config).as_deref() == Some("btrfs")
+        && !partition_fields(config).iter().any(|fields| fields[0].trim_end_matches('/') == "/mnt/.snapshots")
+    {
+        warnings.push(Problem::new("/partition/partitions", "no @snapshots subvolume is mounted at /mnt/.snapshots, snapper keeps the snapshots inside the root subvolume"));
+    }
+    let desktops: Vec<String> = match config.pointer("/desktop") {
+        Some(Value::String(desktop)) => vec![desktop.to_lowercase()],
+        Some(Value::Array(desktops)) => desktops.iter().filter_map(Value::as_str).map(str::to_lowercase).collect(),
+        _ => return warnings,
+    };
+    let no_desktop = desktops.iter().all(|desktop| desktop == "none" || desktop.is_empty());
+    let displaymanager = config.pointer("/displaymanager").and_then(Value::as_str).unwrap_or("none").to_lowercase();
+    let no_displaymanager = displaymanager == "none" || displaymanager.is_empty();
+
+    if no_desktop && !no_displaymanager {
+        warnings.push(Problem::new("/displaymanager", "no desktop is selected, there is no session to log into"));
+    }
+    if no_displaymanager {
+        for desktop in desktops.iter().filter(|desktop| TILING_WMS.contains(&desktop.as_str())) {
+            warnings.push(Problem::new(
+                "/displaymanager",
+                format!("no display manager, {} has to be started from the console (startx for X11 window managers)", desktop),
+            ));
+        }
+    }
+    let theme = config.pointer("/theme").and_then(Value::as_str).unwrap_or("none").to_lowercase();
+    if no_desktop && theme != "none" && !theme.is_empty() {
+        warnings.push(Problem::new("/theme", "no desktop is selected, the theme has nothing to style"));
+    }
+    warnings
+}
+
+// TLP and power-profiles-daemon both tune the same settings, the packages conflict with each other
+fn check_power_management(config: &Value, warnings: &mut Vec<Problem>) {
+    let power = config.pointer("/power_management").and_then(Value::as_str).unwrap_or("").to_lowercase();
+    let selected = |key: &str, name: &str| {
+        config
+            .pointer(key)
+            .and_then(Value::as_array)
+            .is_some_and(|values| values.iter().filter_map(Value::as_str).any(|value| value.eq_ignore_ascii_case(name)))
+    };
+    if power == "tlp" && selected("/extra_packages", "power-profiles-daemon") {
+        warnings.push(Problem::new("/power_management", "TLP conflicts with power-profiles-daemon from extra_packages"));
+    }
+    if power == "power-profiles-daemon" && selected("/extra_packages", "tlp") {
+        warnings.push(Problem::new("/power_management", "power-profiles-daemon conflicts with tlp from extra_packages"));
+    }
+    if power == "tlp" && (selected("/desktop", "gnome") || selected("/desktop", "kde plasma")) {
+        warnings.push(Problem::new("/power_management", "GNOME and KDE Plasma power settings expect power-profiles-daemon, not TLP"));
+    }
+}
+
+fn snapshot_tool(config: &Value) -> String {
+    config.pointer("/snapshots/tool").and_then(Value::as_str).unwrap_or("none").to_lowercase()
+}
+
+// Fields of the well-formed manual partition entries, check_partitions reports the others
+fn partition_fields(config: &Value) -> Vec<Vec<&str>> {
+    config
+        .pointer("/partition/partitions")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(|partition| partition.split(':').collect::<Vec<&str>>())
+        .filter(|fields| fields.len() >= 4)
+        .collect()
+}
+
+// Filesystem of the target root: btrfs for the EraseDisk layout, else the one of the partition mounted at /mnt
+fn root_filesystem(config: &Value) -> Option<String> {
+    let mode = config.pointer("/partition/mode").and_then(Value::as_str).unwrap_or("");
+    if mode.eq_ignore_ascii_case("erasedisk") {
+        return Some(String::from("btrfs"));
+    }
+    partition_fields(config)
+        .into_iter()
+        .find(|fields| fields[0].trim_end_matches('/') == "/mnt")
+        .map(|fields| fields[2].to_string())
+}
+
+// Snapshots are btrfs subvolumes, other root filesystems cannot have them
+fn check_snapshots(config: &Value, problems: &mut Vec<Problem>) {
+    let tool = snapshot_tool(config);
+    if tool.is_empty() || tool == "none" {
+        return;
+    }
+    if let Some(filesystem) = root_filesystem(config).filter(|filesystem| filesystem != "btrfs") {
+        problems.push(Problem::new("/snapshots/tool", format!("{} needs a btrfs root, the root filesystem is {}", tool, filesystem)));
+    }
+}
+
+// Overrides are passed to mkinitcpio as they are, a wrong name only shows up when the image is built
+fn check_initramfs(config: &Value, warnings: &mut Vec<Problem>) {
+    for (key, known, what) in [("hooks", &KNOWN_HOOKS[..], "hook"), ("modules", &KNOWN_MODULES[..], "kernel module")] {
+        let Some(Value::Array(entries)) = config.pointer(&format!("/initramfs/extra_{}", key)) else {
+            continue;
+        };
+        for (i, entry) in entries.iter().enumerate() {
+            let name = entry.as_str().unwrap_or_default();
+            if key == "hooks" && BUSYBOX_HOOKS.contains(&name) {
+                warnings.push(Problem::new(
+                    &format!("/initramfs/extra_hooks/{}", i),
+                    format!("\"{}\" is a busybox hook, the systemd initramfs already covers it", name),
+                ));
+            } else if !known.contains(&name) {
+                warnings.push(Problem::new(
+                    &format!("/initramfs/extra_{}/{}", key, i),
+                    format!("\"{}\" is not a known initramfs {}, check its spelling", name, what),
+                ));
+            }
+        }
+    }
+}
+
+// Manual partitions are "mountpoint:blockdevice:filesystem:encrypt" strings
+fn check_partitions(config: &Value, problems: &mut Vec<Problem>) {
+    let Some(Value::Array(partitions)) = config.pointer("/partition/partitions") else {
+        return;
+    };
+    for (i, partition) in partitions.iter().enumerate() {
+        let path = format!("/partition/partitions/{}", i);
+        let Some(partition) = partition.as_str() else {
+            problems.push(Problem::new(&path, "expected a string"));
+            continue;
+        };
+        let fields: Vec<&str> = partition.split(':').collect();
+        if fields.len() != 4 && fields.len() != 5 {
+            problems.push(Problem::new(
+                &path,
+                format!("\"{}\" is not in the form mountpoint:blockdevice:filesystem:encrypt[:options]", partition),
+            ));
+            continue;
+        }
+        if !fields[0].starts_with('/') || fields[0].contains(char::is_whitespace) {
+            problems.push(Problem::new(&path, format!("invalid mount point \"{}\"", fields[0])));
+        }
+        if !fields[1].starts_with("/dev/") {
+            problems.push(Problem::new(&path, format!("invalid block device \"{}\"", fields[1])));
+        }
+        if !FILESYSTEMS.contains(&fields[2]) {
+            problems.push(Problem::new(&path, format!("unknown filesystem \"{}\"", fields[2])));
+        }
+        if fields[3].parse::<bool>().is_err() {
+            problems.push(Problem::new(&path, format!("encrypt flag \"{}\" is not true or false", fields[3])));
+        }
+        if let Some(Err(e)) = fields.get(4).map(|options| crate::args::parse_mount_options(options)) {
+            problems.push(Problem::new(&path, format!("mount options \"{}\": {}", fields[4], e)));
+        }
+    }
+}
+
+/// Checks a user name against the portable useradd rules ([a-z_][a-z0-9_-]*[$]?, at most 32 characters)
+/// and the system accounts, so a bad name is reported before the install rather than by useradd.
+pub fn check_username(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(String::from("must not be empty"));
+    }
+    if name.len() > USERNAME_MAX_LENGTH {
+        return Err(format!("must be at most {} characters long", USERNAME_MAX_LENGTH));
+    }
+    if !name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_') {
+        return Err(String::from("must start with a lowercase letter or '_'"));
+    }
+    let body = name.strip_suffix('$').unwrap_or(name);
+    if !body.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-') {
+        return Err(String::from("may only contain lowercase letters, digits, '_' and '-' (and end with '$')"));
+    }
+    if RESERVED_USERNAMES.contains(&name) {
+        return Err(format!("\"{}\" is a system account", name));
+    }
+    Ok(())
+}
+
+fn check_users(config: &Value, problems: &mut Vec<Problem>) {
+    let Some(Value::Array(users)) = config.pointer("/users") else {
+        return;
+    };
+    if users.is_empty() {
+        problems.push(Problem::new("/users", "at least one user is required"));
+    }
+    for (i, user) in users.iter().enumerate() {
+        for key in ["name", "password", "shell"] {
+            let path = format!("/users/{}/{}", i, key);
+            match user.get(key) {
+                None => problems.push(Problem::new(&path, "missing")),
+                Some(Value::String(value)) if value.is_empty() && key != "shell" => {
+                    problems.push(Problem::new(&path, "must not be empty"))
+                }
+                Some(Value::String(_)) => {}
+                Some(_) => problems.push(Problem::new(&path, "expected a string")),
+            }
+        }
+        // An empty name is already reported above
+        if let Some(Value::String(name)) = user.get("name").filter(|name| name.as_str() != Some("")) {
+            let path = format!("/users/{}/name", i);
+            if let Err(e) = check_username(name) {
+                problems.push(Problem::new(&path, e));
+            } else if let Some(first) = users[..i].iter().position(|other| other.get("name") == user.get("name")) {
+                problems.push(Problem::new(&path, format!("\"{}\" is already the name of /users/{}", name, first)));
+            }
+        }
+        if let Some(Value::String(avatar)) = user.get("avatar") {
+            if avatar.contains('/') || avatar == ".." {
+                problems.push(Problem::new(&format!("/users/{}/avatar", i), "expected the file name of an installed avatar"));
+            }
+        }
+        if user.get("password").and_then(Value::as_str) == Some(PLACEHOLDER_HASH) {
+            problems.push(Problem::new(
+                &format!("/users/{}/password", i),
+                "placeholder hash, generate one with `openssl passwd -6`",
+            ));
+        }
+        let path = format!("/users/{}/hasroot", i);
+        match user.get("hasroot") {
+            None => problems.push(Problem::new(&path, "missing")),
+            Some(value) if !value.is_boolean() => problems.push(Problem::new(&path, "expected a boolean")),
+            Some(_) => {}
+        }
+        if let Some(Value::String(shell)) = user.get("shell") {
+            let shell = shell.to_lowercase();
+            if !shell.is_empty() && !SHELLS.contains(&shell.as_str()) {
+                problems.push(Problem::new(
+                    &format!("/users/{}/shell", i),
+                    format!("unknown value \"{}\", expected one of: {}", shell, SHELLS.join(", ")),
+                ));
+            }
+        }
+    }
+}
+
+/// Bluetooth applet of each selected desktop (blueman, gnome-bluetooth or bluedevil), without duplicates.
+/// It is derived from the desktop list so that changing the desktop changes the applet with it.
+pub fn bluetooth_applets(desktops: &[String]) -> Vec<&'static str> {
+    let mut applets = Vec::new();
+    for desktop in desktops {
+        let applet = match desktop.to_lowercase().as_str() {
+            "gnome" | "budgie" | "onyx" => "gnome-bluetooth",
+            "kde plasma" => "bluedevil",
+            "none" | "" => continue,
+            _ => "blueman",
+        };
+        if !applets.contains(&applet) {
+            applets.push(applet);
+        }
+    }
+    applets
+}
+
+/// Checks an NTP server, either an IP address or a host name made of 1 to 63 character labels
+/// (letters, digits and '-', not at either end of a label), at most 253 characters long.
+pub fn check_ntp_server(server: &str) -> Result<(), String> {
+    if server.parse::<std::net::IpAddr>().is_ok() {
+        return Ok(());
+    }
+    let valid_label = |label: &str| {
+        (1..=63).contains(&label.len())
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    };
+    if server.len() > 253 || !server.split('.').all(valid_label) {
+        return Err(format!("\"{}\" is not a valid host name or IP address", server));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_usernames() {
+        for name in ["athena", "a", "_build", "user1", "web-admin", "snake_case", "machine$", &"a".repeat(USERNAME_MAX_LENGTH)] {
+            assert_eq!(check_username(name), Ok(()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn invalid_usernames() {
+        let cases: [(&str, &str); 11] = [
+            ("", "empty"),
+            ("1user", "start with"),
+            ("-user", "start with"),
+            ("$", "start with"),
+            ("Athena", "start with"),
+            ("athEna", "may only contain"),
+            ("john doe", "may only contain"),
+            ("user.name", "may only contain"),
+            ("us$er", "may only contain"),
+            ("élodie", "start with"),
+            ("user$$", "may only contain"),
+        ];
+        for (name, reason) in cases {
+            let error = check_username(name).unwrap_err();
+            assert!(error.contains(reason), "{:?}: {}", name, error);
+        }
+    }
+
+    #[test]
+    fn length_limit() {
+        let too_long = "a".repeat(USERNAME_MAX_LENGTH + 1);
+        assert!(check_username(&too_long).unwrap_err().contains("at most 32"));
+        // The trailing $ counts toward the limit
+        assert!(check_username(&format!("{}$", "a".repeat(USERNAME_MAX_LENGTH))).is_err());
+    }
+
+    #[test]
+    fn system_accounts_are_reserved() {
+        for name in ["root", "nobody", "nixbld", "sshd"] {
+            assert!(check_username(name).unwrap_err().contains("system account"), "{}", name);
+        }
+        assert_eq!(check_username("rootless"), Ok(()));
+    }
+}